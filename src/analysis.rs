@@ -0,0 +1,366 @@
+use crate::compat::*;
+use crate::palette::ColorDistance;
+use crate::prelude::*;
+
+/// Size of the blocks checked by [detect_dither_regions]
+const DITHER_BLOCK: usize = 4;
+/// Fraction of neighbouring pixels that must differ for a block to count as dithered
+const DITHER_THRESHOLD: f32 = 0.75;
+/// Minimum width of each band for [detect_banding]
+const MIN_BAND_WIDTH: usize = 3;
+/// Minimum number of bands in a row for [detect_banding]
+const MIN_BANDS: usize = 3;
+/// Maximum difference between neighbouring bands for [detect_banding]
+const MAX_BAND_STEP: f32 = 64.0;
+
+/// Find areas that use a two color checkerboard or ordered dither pattern
+///
+/// The image is checked in 4x4 blocks, neighbouring dithered blocks are merged into one rect
+pub fn detect_dither_regions(image: &IndexedImage) -> Vec<IciRect> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.get_pixels();
+    let mut spans = vec![];
+    for block_y in 0..height.div_ceil(DITHER_BLOCK) {
+        let mut run_start = None;
+        for block_x in 0..=width.div_ceil(DITHER_BLOCK) {
+            let dithered = block_x < width.div_ceil(DITHER_BLOCK)
+                && is_dithered(pixels, width, height, block_x, block_y);
+            match (dithered, run_start) {
+                (true, None) => run_start = Some(block_x),
+                (false, Some(start)) => {
+                    spans.push((block_y, start, block_x));
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    merge_spans(&spans)
+        .into_iter()
+        .filter_map(|(x, y, w, h)| {
+            IciRect::new(
+                (x * DITHER_BLOCK) as u8,
+                (y * DITHER_BLOCK) as u8,
+                (w * DITHER_BLOCK).min(255) as u8,
+                (h * DITHER_BLOCK).min(255) as u8,
+            )
+            .clip(image.width(), image.height())
+        })
+        .collect()
+}
+
+fn is_dithered(pixels: &[u8], width: usize, height: usize, block_x: usize, block_y: usize) -> bool {
+    let x0 = block_x * DITHER_BLOCK;
+    let y0 = block_y * DITHER_BLOCK;
+    let x1 = (x0 + DITHER_BLOCK).min(width);
+    let y1 = (y0 + DITHER_BLOCK).min(height);
+    if x1 - x0 < 2 || y1 - y0 < 2 {
+        return false;
+    }
+    let get = |x: usize, y: usize| pixels[x + y * width];
+    let mut colors = vec![];
+    let mut pairs = 0;
+    let mut different = 0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let idx = get(x, y);
+            if !colors.contains(&idx) {
+                colors.push(idx);
+                if colors.len() > 2 {
+                    return false;
+                }
+            }
+            for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                if nx < x1 && ny < y1 {
+                    pairs += 1;
+                    if get(nx, ny) != idx {
+                        different += 1;
+                    }
+                }
+            }
+        }
+    }
+    colors.len() == 2 && different as f32 / pairs as f32 >= DITHER_THRESHOLD
+}
+
+/// Find areas that look like smooth gradients that have been reduced to a few colors
+///
+/// These are 3 or more similar colored bands, each at least 3 pixels wide, getting steadily lighter or darker,
+/// both horizontally and vertically
+pub fn detect_banding(image: &IndexedImage) -> Vec<IciRect> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.get_pixels();
+    let palette = image.get_palette();
+    let mut row_spans = vec![];
+    for (y, row) in pixels.chunks_exact(width).enumerate() {
+        for (start, end) in banded_segments(row, palette) {
+            row_spans.push((y, start, end));
+        }
+    }
+    let mut column_spans = vec![];
+    for x in 0..width {
+        let column: Vec<u8> = (0..height).map(|y| pixels[x + y * width]).collect();
+        for (start, end) in banded_segments(&column, palette) {
+            column_spans.push((x, start, end));
+        }
+    }
+    let rows = merge_spans(&row_spans)
+        .into_iter()
+        .map(|(x, y, w, h)| IciRect::new(x as u8, y as u8, w as u8, h as u8));
+    let columns = merge_spans(&column_spans)
+        .into_iter()
+        .map(|(y, x, h, w)| IciRect::new(x as u8, y as u8, w as u8, h as u8));
+    rows.chain(columns).collect()
+}
+
+/// Returns start and end (exclusive) of banded sections of `line`
+fn banded_segments(line: &[u8], palette: &[Color]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize, u8)> = vec![];
+    for (i, idx) in line.iter().enumerate() {
+        match runs.last_mut() {
+            Some((_, len, last)) if last == idx => *len += 1,
+            _ => runs.push((i, 1, *idx)),
+        }
+    }
+    let color = |idx: u8| palette.get(idx as usize).copied().unwrap_or(TRANSPARENT);
+    let mut segments = vec![];
+    let mut chain_start = 0;
+    let mut direction = 0.0_f32;
+    for i in 0..runs.len() {
+        let continues = i > chain_start && {
+            let (prev, current) = (color(runs[i - 1].2), color(runs[i].2));
+            let step = ColorDistance::EuclideanAlpha.distance(prev, current);
+            let change = (current.brightness() - prev.brightness()).signum();
+            let ok = runs[i].1 >= MIN_BAND_WIDTH
+                && step <= MAX_BAND_STEP
+                && (direction == 0.0 || change == direction);
+            if ok {
+                direction = change;
+            }
+            ok
+        };
+        if !continues {
+            if i - chain_start >= MIN_BANDS {
+                segments.push(chain_end(&runs, chain_start, i));
+            }
+            chain_start = if runs[i].1 >= MIN_BAND_WIDTH {
+                i
+            } else {
+                i + 1
+            };
+            direction = 0.0;
+        }
+    }
+    if runs.len() > chain_start && runs.len() - chain_start >= MIN_BANDS {
+        segments.push(chain_end(&runs, chain_start, runs.len()));
+    }
+    segments
+}
+
+fn chain_end(runs: &[(usize, usize, u8)], first: usize, end: usize) -> (usize, usize) {
+    let last = runs[end - 1];
+    (runs[first].0, last.0 + last.1)
+}
+
+/// Merges spans (line, start, end) on consecutive lines with the same start and end
+///
+/// Returns (start, first line, length, line count)
+fn merge_spans(spans: &[(usize, usize, usize)]) -> Vec<(usize, usize, usize, usize)> {
+    let mut output: Vec<(usize, usize, usize, usize)> = vec![];
+    for (line, start, end) in spans {
+        let existing = output.iter_mut().find(|(s, first, len, count)| {
+            *s == *start && *s + *len == *end && *first + *count == *line
+        });
+        match existing {
+            Some((_, _, _, count)) => *count += 1,
+            None => output.push((*start, *line, end - start, 1)),
+        }
+    }
+    output
+}
+
+/// Number of pixels using each palette index
+fn histogram(pixels: &[u8]) -> [usize; 256] {
+    let mut counts = [0; 256];
+    for idx in pixels {
+        counts[*idx as usize] += 1;
+    }
+    counts
+}
+
+impl IndexedImage {
+    /// Number of pixels using `idx`
+    pub fn count_index(&self, idx: u8) -> usize {
+        self.get_pixels()
+            .iter()
+            .filter(|pixel| **pixel == idx)
+            .count()
+    }
+
+    /// Number of pixels using each palette index
+    pub fn histogram(&self) -> [usize; 256] {
+        histogram(self.get_pixels())
+    }
+
+    /// Split the image into each group of connected (horizontally or vertically) pixels that aren't `transparent_idx`
+    ///
+    /// Each group is returned with its bounds in this image and as an image of that size, pixels in the bounds that
+    /// aren't part of the group are set to `transparent_idx`. Groups are ordered by their first pixel, row by row
+    pub fn connected_components(&self, transparent_idx: u8) -> Vec<(IciRect, IndexedImage)> {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let pixels = self.get_pixels();
+        let mut visited = vec![false; pixels.len()];
+        let mut components = vec![];
+        for start in 0..pixels.len() {
+            if visited[start] || pixels[start] == transparent_idx {
+                continue;
+            }
+            visited[start] = true;
+            let mut members = vec![];
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                members.push(i);
+                let (x, y) = (i % width, i / width);
+                let neighbours = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < width).then(|| i + 1),
+                    (y > 0).then(|| i - width),
+                    (y + 1 < height).then(|| i + width),
+                ];
+                for n in neighbours.into_iter().flatten() {
+                    if !visited[n] && pixels[n] != transparent_idx {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+            let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+            for i in &members {
+                let (x, y) = (i % width, i / width);
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x);
+                bottom = bottom.max(y);
+            }
+            let (part_width, part_height) = (right - left + 1, bottom - top + 1);
+            let mut part = vec![transparent_idx; part_width * part_height];
+            for i in members {
+                let (x, y) = (i % width, i / width);
+                part[(x - left) + (y - top) * part_width] = pixels[i];
+            }
+            let rect = IciRect::new(left as u8, top as u8, part_width as u8, part_height as u8);
+            let image =
+                IndexedImage::from_parts(rect.width, rect.height, self.get_palette().clone(), part);
+            components.push((rect, image));
+        }
+        components
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Number of pixels in frame `frame` using `idx`
+    pub fn count_index_in_frame(&self, frame: u16, idx: u8) -> Result<usize, IndexedImageError> {
+        Ok(self
+            .get_frame_pixels(frame)?
+            .iter()
+            .filter(|pixel| **pixel == idx)
+            .count())
+    }
+
+    /// Number of pixels in frame `frame` using each palette index
+    pub fn frame_histogram(&self, frame: u16) -> Result<[usize; 256], IndexedImageError> {
+        Ok(histogram(self.get_frame_pixels(frame)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dither() {
+        let pixels = (0..64)
+            .map(|i| {
+                let (x, y) = (i % 8, i / 8);
+                if x < 4 {
+                    0
+                } else {
+                    ((x + y) % 2) as u8
+                }
+            })
+            .collect();
+        let image = IndexedImage::new(8, 8, vec![RED, BLUE], pixels).unwrap();
+        assert_eq!(
+            detect_dither_regions(&image),
+            vec![IciRect::new(4, 0, 4, 8)]
+        );
+        let flat = IndexedImage::new(8, 8, vec![RED, BLUE], vec![0; 64]).unwrap();
+        assert!(detect_dither_regions(&flat).is_empty());
+    }
+
+    #[test]
+    fn banding() {
+        let palette: Vec<Color> = (0..4).map(|i| Color::gray(100 + i * 20)).collect();
+        let pixels = (0..24).map(|i| ((i % 12) / 3) as u8).collect();
+        let image = IndexedImage::new(12, 2, palette.clone(), pixels).unwrap();
+        assert_eq!(detect_banding(&image), vec![IciRect::new(0, 0, 12, 2)]);
+
+        let pixels = (0..24).map(|i| ((i % 12) % 2) as u8).collect();
+        let noisy = IndexedImage::new(12, 2, palette, pixels).unwrap();
+        assert!(detect_banding(&noisy).is_empty());
+    }
+
+    #[test]
+    fn components() {
+        #[rustfmt::skip]
+        let image = IndexedImage::new(5, 4, vec![TRANSPARENT, RED, BLUE], vec![
+            1, 1, 0, 0, 2,
+            0, 1, 0, 2, 2,
+            0, 0, 0, 0, 0,
+            2, 0, 1, 2, 0,
+        ]).unwrap();
+        let components = image.connected_components(0);
+        let rects: Vec<IciRect> = components.iter().map(|(rect, _)| *rect).collect();
+        assert_eq!(
+            rects,
+            vec![
+                IciRect::new(0, 0, 2, 2),
+                IciRect::new(3, 0, 2, 2),
+                IciRect::new(0, 3, 1, 1),
+                IciRect::new(2, 3, 2, 1),
+            ]
+        );
+        assert_eq!(components[0].1.get_pixels(), &[1, 1, 0, 1]);
+        assert_eq!(components[1].1.get_pixels(), &[0, 2, 2, 2]);
+        assert_eq!(components[3].1.get_pixels(), &[1, 2]);
+        assert_eq!(components[3].1.get_palette(), image.get_palette());
+
+        assert_eq!(image.connected_components(3).len(), 1);
+        let empty = IndexedImage::blank(3, 3, vec![TRANSPARENT]);
+        assert!(empty.connected_components(0).is_empty());
+    }
+
+    #[test]
+    fn counts() {
+        let image = IndexedImage::new(2, 2, vec![RED, GREEN, BLUE], vec![2, 1, 2, 0]).unwrap();
+        assert_eq!(image.count_index(2), 2);
+        assert_eq!(image.count_index(5), 0);
+        let histogram = image.histogram();
+        assert_eq!(&histogram[..4], &[1, 1, 2, 0]);
+        assert_eq!(histogram.iter().sum::<usize>(), 4);
+
+        let image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 1],
+            PlayType::Loops,
+        )
+        .unwrap();
+        assert_eq!(image.count_index_in_frame(1, 1).unwrap(), 2);
+        assert_eq!(&image.frame_histogram(0).unwrap()[..2], &[1, 1]);
+        assert!(image.frame_histogram(2).is_err());
+    }
+}
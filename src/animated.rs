@@ -1,12 +1,29 @@
 use crate::animated::PlayType::*;
+use crate::compat::*;
 use crate::errors::IndexedImageError;
 use crate::errors::IndexedImageError::*;
+use crate::errors::{pixel_violations, InvariantViolation};
 use crate::file::FileType::Animated;
-use crate::file::{verify_format, HEADER};
-use crate::image::IndexedImage;
+use crate::file::{
+    append_checksum, read_header, verify_format_version, wrong_file_type, HEADER, HEADER_V2,
+};
+use crate::image::{draw_indexes_to_buffer, indexes_to_rgba, HighestIdx, IndexedImage};
+use crate::metadata;
 use crate::palette;
-use crate::palette::FilePalette;
+use crate::palette::{nearest_index_by, ColorDistance, FilePalette, Palette};
+use crate::playback;
+use crate::playback::PlaybackState;
 use crate::prelude::*;
+use crate::reader::ByteReader;
+use crate::tags;
+use crate::tags::FrameTag;
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::mem;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum PlayType {
@@ -24,26 +41,43 @@ pub enum PlayType {
     LoopsReversed,
     /// Play from 0 to end to 0 repeatedly
     LoopsBoth,
+    /// Play from 0 to end to 0 once
+    /// Must call [set_animate(true)] before the image will play
+    /// Once it finishes will call [reset]
+    OnceBoth,
+}
+
+/// Returned by [AnimatedIndexedImage::update_with_events] when the frame changes
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AnimationEvent {
+    /// Moved to this frame
+    FrameChanged(u16),
+    /// Wrapped around (or changed direction for [LoopsBoth] and [OnceBoth]) and is now on this frame
+    Looped(u16),
+    /// [Once], [OnceReversed] or [OnceBoth] finished, the animation has been [reset] and stopped
+    Completed,
 }
 
 impl PlayType {
-    pub fn to_byte(&self) -> u8 {
+    pub const fn to_byte(&self) -> u8 {
         match self {
             Once => 0,
             OnceReversed => 1,
             Loops => 2,
             LoopsReversed => 3,
             LoopsBoth => 4,
+            OnceBoth => 5,
         }
     }
 
-    pub fn from_byte(value: u8) -> Option<PlayType> {
+    pub const fn from_byte(value: u8) -> Option<PlayType> {
         match value {
             0 => Some(Once),
             1 => Some(OnceReversed),
             2 => Some(Loops),
             3 => Some(LoopsReversed),
             4 => Some(LoopsBoth),
+            5 => Some(OnceBoth),
             _ => None,
         }
     }
@@ -59,18 +93,24 @@ pub struct AnimatedIndexedImage {
     width: u8,
     height: u8,
     per_frame: f64,
-    palette: Vec<Color>,
-    /// max allowed is 255
+    palette: Palette,
+    /// max allowed is 65535
     frame_count: usize,
     frame_size: usize,
     pixels: Vec<u8>,
     current_frame: usize,
     next_frame_time: f64,
-    highest_palette_idx: u8,
+    highest_palette_idx: HighestIdx,
     animate: bool,
     play_type: PlayType,
     /// used with [LoopsBoth] to know whether increasing or decreasing
     loop_increasing: bool,
+    metadata: BTreeMap<String, String>,
+    /// Original colors for [AnimatedIndexedImage::reset_palette_to_base], not saved in files
+    base_palette: Option<Palette>,
+    tags: BTreeMap<String, FrameTag>,
+    /// frames being played, all frames if `None`
+    segment: Option<FrameTag>,
 }
 
 impl AnimatedIndexedImage {
@@ -78,7 +118,7 @@ impl AnimatedIndexedImage {
         width: u8,
         height: u8,
         per_frame: f64,
-        frame_count: u8,
+        frame_count: u16,
         palette: Vec<Color>,
         pixels: Vec<u8>,
         play_type: PlayType,
@@ -89,21 +129,22 @@ impl AnimatedIndexedImage {
         if height == 0 {
             return Err(HeightIsZero);
         }
-        if palette.is_empty() {
-            return Err(PaletteIsEmpty);
-        }
+        let palette = Palette::new(palette)?;
         if per_frame < 0.0 {
             return Err(NegativePerFrame(per_frame));
         }
+        if frame_count == 0 {
+            return Err(NoFrames);
+        }
         let frame_size = width as usize * height as usize;
         if pixels.len() != frame_size * frame_count as usize {
             return Err(MissingData(pixels.len(), frame_size * frame_count as usize));
         }
-        let highest_palette_idx = *pixels
-            .iter()
-            .max()
-            .expect("Unable to get highest color index");
-        let frame_size = width as usize * height as usize;
+        let highest_palette_idx = HighestIdx::new(&pixels);
+        let highest = highest_palette_idx.get(&pixels);
+        if highest as usize >= palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
         let animate = matches!(play_type, Loops | LoopsReversed | LoopsBoth);
         Ok(Self {
             width,
@@ -119,60 +160,147 @@ impl AnimatedIndexedImage {
             frame_size,
             play_type,
             loop_increasing: true,
+            metadata: BTreeMap::new(),
+            base_palette: None,
+            tags: BTreeMap::new(),
+            segment: None,
         })
     }
 }
 
+#[cfg(feature = "std")]
+impl AnimatedIndexedImage {
+    /// Create an animation from image files, one frame per file in the order given
+    ///
+    /// Files can be ICI images or, if the `png` feature is enabled, PNGs
+    /// All files must be the same size, their palettes will be merged and must not exceed 256 colors in total
+    pub fn from_files(
+        paths: &[PathBuf],
+        per_frame: f64,
+        play_type: PlayType,
+    ) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        if paths.is_empty() {
+            return Err(NoFrames);
+        }
+        if paths.len() > u16::MAX as usize {
+            return Err(TooManyFrames(paths.len()));
+        }
+        let frames = paths
+            .iter()
+            .map(|path| read_frame(path))
+            .collect::<Result<Vec<IndexedImage>, IndexedImageError>>()?;
+        let (width, height) = frames[0].size();
+        let mut palette: Vec<Color> = vec![];
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * frames.len());
+        for frame in &frames {
+            if frame.size() != (width, height) {
+                return Err(InvalidImageSize);
+            }
+            let frame_palette = frame.get_palette();
+            let mut mapping: [Option<u8>; 256] = [None; 256];
+            for &idx in frame.get_pixels() {
+                let merged = match mapping[idx as usize] {
+                    Some(merged) => merged,
+                    None => {
+                        let color = frame_palette.get(idx).ok_or(IndexOutOfRange(
+                            idx as usize,
+                            frame_palette.len(),
+                            "palette",
+                        ))?;
+                        let merged = match palette.iter().position(|c| *c == color) {
+                            Some(pos) => pos,
+                            None => {
+                                palette.push(color);
+                                palette.len() - 1
+                            }
+                        };
+                        if merged > 255 {
+                            return Err(PaletteTooManyColors);
+                        }
+                        mapping[idx as usize] = Some(merged as u8);
+                        merged as u8
+                    }
+                };
+                pixels.push(merged);
+            }
+        }
+        AnimatedIndexedImage::new(
+            width,
+            height,
+            per_frame,
+            frames.len() as u16,
+            palette,
+            pixels,
+            play_type,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_frame(path: &Path) -> Result<IndexedImage, IndexedImageError> {
+    let bytes =
+        std::fs::read(path).map_err(|e| FileIo(path.display().to_string(), e.to_string()))?;
+    if bytes.starts_with(&HEADER[..3]) {
+        return IndexedImage::from_file_contents(&bytes).map(|(image, _)| image);
+    }
+    #[cfg(feature = "png")]
+    {
+        crate::import::from_png_bytes(&bytes, 256, crate::dither::Dithering::None)
+    }
+    #[cfg(not(feature = "png"))]
+    {
+        Err(NotIciFile)
+    }
+}
+
 impl AnimatedIndexedImage {
     /// Replace palette for image
-    /// Will only return an error if the new palette has less colors than the image needs
+    /// Will return an error if the new palette has less colors than the image needs, is empty or has more than 256 colors
     pub fn set_palette(&mut self, palette: &[Color]) -> Result<(), IndexedImageError> {
-        assert!(!palette.is_empty());
-        if palette.len() < self.highest_palette_idx as usize {
-            return Err(PaletteTooFewColors(self.highest_palette_idx));
+        let palette = Palette::try_from(palette)?;
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
+        if palette.len() <= highest as usize {
+            return Err(PaletteTooFewColors(highest));
         }
-        self.palette = palette.to_vec();
+        self.palette = palette;
         Ok(())
     }
 
     /// Replace palette for image, any pixels outside the new palette will be replaced with `id`
-    /// Will only return an error if id is outside the new palette
+    /// Will return an error if id is outside the new palette, or the palette is empty or has more than 256 colors
     pub fn set_palette_replace_id(
         &mut self,
         palette: &[Color],
         id: u8,
     ) -> Result<(), IndexedImageError> {
-        let new_palette_len = palette.len() as u8;
-        assert!(!palette.is_empty());
-        if new_palette_len <= id {
+        let palette = Palette::try_from(palette)?;
+        let new_palette_len = palette.len();
+        if new_palette_len <= id as usize {
             return Err(IdOutsideOfNewPalette);
         }
-        self.palette = palette.to_vec();
+        self.palette = palette;
         for i in self.pixels.iter_mut() {
-            if *i >= new_palette_len {
+            if *i as usize >= new_palette_len {
                 *i = id;
             }
         }
-        self.highest_palette_idx = *self
-            .pixels
-            .iter()
-            .max()
-            .expect("Unable to get highest color index");
+        self.highest_palette_idx = HighestIdx::new(&self.pixels);
         Ok(())
     }
 
     /// Replace palette for image, any color indexes outside the palette will be expanded with `color`
+    /// This never fails, colors after the first 256 are dropped
     pub fn set_palette_replace_color<C: Into<Color> + Copy>(
         &mut self,
         palette: &[Color],
         color: C,
     ) {
-        assert!(!palette.is_empty());
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
         let mut tmp_pal = palette.to_vec();
-        while tmp_pal.len() <= self.highest_palette_idx as usize {
+        while tmp_pal.len() <= highest as usize {
             tmp_pal.push(color.into());
         }
-        self.palette = tmp_pal;
+        self.palette = Palette::from_vec_lossy(tmp_pal);
     }
 
     #[inline]
@@ -180,35 +308,50 @@ impl AnimatedIndexedImage {
         (self.width, self.height)
     }
 
-    /// This is unchecked and if color_idx > min_palette_size_supported
-    /// will crash when rendering
     #[inline]
     pub fn set_pixel(
         &mut self,
-        frame: u8,
+        frame: u16,
         pixel_idx: usize,
         color_idx: u8,
     ) -> Result<(), IndexedImageError> {
-        if frame >= self.frame_count as u8 {
+        if frame as usize >= self.frame_count {
             return Err(IndexOutOfRange(frame as usize, self.frame_count, "frames"));
         }
         if pixel_idx >= self.frame_size {
             return Err(IndexOutOfRange(pixel_idx, self.frame_size, "pixels"));
         }
+        if color_idx as usize >= self.palette.len() {
+            return Err(IndexOutOfRange(
+                color_idx as usize,
+                self.palette.len(),
+                "palette",
+            ));
+        }
         let idx = (frame as usize * self.frame_size) + pixel_idx;
-        self.pixels[idx] = color_idx;
-        self.highest_palette_idx = self.highest_palette_idx.max(color_idx);
+        let old = mem::replace(&mut self.pixels[idx], color_idx);
+        self.highest_palette_idx.write(old, color_idx);
         Ok(())
     }
 
+    /// # Safety
+    ///
+    /// Out of bounds may occur
+    #[inline]
+    pub unsafe fn set_pixel_unchecked(&mut self, frame: u16, pixel_idx: usize, color_idx: u8) {
+        let idx = frame as usize * self.frame_size + pixel_idx;
+        let old = mem::replace(&mut self.pixels[idx], color_idx);
+        self.highest_palette_idx.write(old, color_idx);
+    }
+
     #[inline]
     pub fn get_pixels(&self) -> &[u8] {
         &self.pixels
     }
 
     #[inline]
-    pub fn get_frame_pixels(&self, idx: u8) -> Result<&[u8], IndexedImageError> {
-        if idx >= self.frame_count as u8 {
+    pub fn get_frame_pixels(&self, idx: u16) -> Result<&[u8], IndexedImageError> {
+        if idx as usize >= self.frame_count {
             return Err(IndexOutOfRange(idx as usize, self.frame_count, "frames"));
         }
         let start = self.frame_size * idx as usize;
@@ -218,12 +361,60 @@ impl AnimatedIndexedImage {
 
     #[inline]
     pub fn get_current_frame_pixels(&self) -> &[u8] {
-        self.get_frame_pixels(self.current_frame as u8).unwrap()
+        let start = self.frame_size * self.current_frame;
+        self.pixels
+            .get(start..start + self.frame_size)
+            .unwrap_or_default()
+    }
+
+    /// RGBA8 pixel data (`width * height * 4` bytes) of the current frame, for uploading to textures, etc
+    pub fn current_frame_rgba(&self) -> Vec<u8> {
+        indexes_to_rgba(&self.palette, self.get_current_frame_pixels(), false)
+    }
+
+    /// Like [AnimatedIndexedImage::current_frame_rgba] but with RGB multiplied by alpha
+    pub fn current_frame_rgba_premultiplied(&self) -> Vec<u8> {
+        indexes_to_rgba(&self.palette, self.get_current_frame_pixels(), true)
+    }
+
+    /// Copy the current frame into a framebuffer, see [IndexedImage::draw_to_buffer]
+    pub fn draw_to_buffer(
+        &self,
+        buffer: &mut [u32],
+        buffer_width: usize,
+        x: isize,
+        y: isize,
+        format: PixelFormat,
+    ) {
+        draw_indexes_to_buffer(
+            &self.palette,
+            self.get_current_frame_pixels(),
+            (self.width as usize, self.height as usize),
+            buffer,
+            buffer_width,
+            (x, y),
+            format,
+        );
+    }
+
+    /// RGBA8 pixel data of every frame side by side (`width * frame_count` by `height`), for sprite sheets and texture atlases
+    pub fn strip_rgba(&self) -> Vec<u8> {
+        let row_len = self.width as usize;
+        let frames: Vec<&[u8]> = self.pixels.chunks_exact(self.frame_size).collect();
+        let strip: Vec<u8> = (0..self.height as usize)
+            .flat_map(|y| {
+                frames
+                    .iter()
+                    .flat_map(move |frame| &frame[y * row_len..(y + 1) * row_len])
+            })
+            .copied()
+            .collect();
+        indexes_to_rgba(&self.palette, &strip, false)
     }
 
     #[inline]
-    pub fn get_pixel(&self, frame: u8, pixel_idx: usize) -> Result<u8, IndexedImageError> {
-        if frame >= self.frame_count as u8 {
+    pub fn get_pixel(&self, frame: u16, pixel_idx: usize) -> Result<u8, IndexedImageError> {
+        if frame as usize >= self.frame_count {
             return Err(IndexOutOfRange(frame as usize, self.frame_count, "frames"));
         }
         if pixel_idx >= self.frame_size {
@@ -244,27 +435,62 @@ impl AnimatedIndexedImage {
     }
 
     pub fn as_images(&self) -> Vec<IndexedImage> {
-        let mut output = vec![];
-        for i in 0..self.frame_count {
-            output.push(self.get_frame(i));
-        }
-        output
+        self.pixels
+            .chunks_exact(self.frame_size)
+            .map(|pixels| {
+                IndexedImage::from_parts(
+                    self.width,
+                    self.height,
+                    self.palette.clone(),
+                    pixels.to_vec(),
+                )
+            })
+            .collect()
     }
 
-    pub fn get_frame(&self, idx: usize) -> IndexedImage {
-        let pixels = self
-            .pixels
-            .iter()
-            .skip(self.frame_size * idx)
-            .take(self.frame_size)
-            .cloned()
-            .collect();
-        IndexedImage::new(self.width, self.height, self.palette.clone(), pixels).unwrap()
+    /// Copy of frame `idx` as an image
+    pub fn get_frame(&self, idx: usize) -> Result<IndexedImage, IndexedImageError> {
+        let pixels = self.get_frame_pixels(
+            u16::try_from(idx).map_err(|_| IndexOutOfRange(idx, self.frame_count, "frames"))?,
+        )?;
+        Ok(IndexedImage::from_parts(
+            self.width,
+            self.height,
+            self.palette.clone(),
+            pixels.to_vec(),
+        ))
+    }
+
+    /// Copy of frame `idx` as an image, using this animation's palette
+    pub fn frame(&self, idx: u16) -> Result<IndexedImage, IndexedImageError> {
+        self.get_frame(idx as usize)
+    }
+
+    /// Replace the pixels of frame `idx` with `image`
+    ///
+    /// `image` must be the same size as this animation and only use indexes within this animation's palette,
+    /// the palette of `image` is ignored
+    pub fn set_frame(&mut self, idx: u16, image: &IndexedImage) -> Result<(), IndexedImageError> {
+        let idx = idx as usize;
+        if idx >= self.frame_count {
+            return Err(IndexOutOfRange(idx, self.frame_count, "frames"));
+        }
+        if image.size() != self.size() {
+            return Err(InvalidImageSize);
+        }
+        let highest = image.min_palette_size_supported();
+        if highest as usize >= self.palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
+        let start = idx * self.frame_size;
+        self.pixels[start..start + self.frame_size].copy_from_slice(image.get_pixels());
+        self.highest_palette_idx = HighestIdx::new(&self.pixels);
+        Ok(())
     }
 
     #[inline]
     pub fn get_color(&self, idx: u8) -> Result<Color, IndexedImageError> {
-        if idx >= self.palette.len() as u8 {
+        if idx as usize >= self.palette.len() {
             return Err(IndexOutOfRange(idx as usize, self.palette.len(), "palette"));
         }
         Ok(self.palette[idx as usize])
@@ -272,21 +498,142 @@ impl AnimatedIndexedImage {
 
     #[inline]
     pub fn set_color(&mut self, idx: u8, color: Color) -> Result<(), IndexedImageError> {
-        if idx >= self.palette.len() as u8 {
+        if idx as usize >= self.palette.len() {
             return Err(IndexOutOfRange(idx as usize, self.palette.len(), "palette"));
         }
         self.palette[idx as usize] = color;
         Ok(())
     }
 
+    /// Set several palette colors at once, if any index is outside the palette then no colors are changed
     #[inline]
-    pub fn get_palette(&self) -> &[Color] {
+    pub fn set_colors(&mut self, colors: &[(u8, Color)]) -> Result<(), IndexedImageError> {
+        self.palette.set_colors(colors)
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &Palette {
         &self.palette
     }
 
+    /// Store the current palette so it can be restored with [AnimatedIndexedImage::reset_palette_to_base]
+    ///
+    /// See [IndexedImage::set_base_palette]
+    pub fn set_base_palette(&mut self) {
+        self.base_palette = Some(self.palette.clone());
+    }
+
+    /// Palette stored by [AnimatedIndexedImage::set_base_palette]
+    #[inline]
+    pub fn base_palette(&self) -> Option<&Palette> {
+        self.base_palette.as_ref()
+    }
+
+    pub fn clear_base_palette(&mut self) {
+        self.base_palette = None;
+    }
+
+    /// Replace the palette with the one stored by [AnimatedIndexedImage::set_base_palette], does nothing if there isn't one
+    ///
+    /// Will return an error if the base palette has fewer colors than the image needs
+    pub fn reset_palette_to_base(&mut self) -> Result<(), IndexedImageError> {
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
+        if let Some(base) = &self.base_palette {
+            if base.len() <= highest as usize {
+                return Err(PaletteTooFewColors(highest));
+            }
+            self.palette.clone_from(base);
+        }
+        Ok(())
+    }
+
+    /// Remove palette colors that no pixel uses, shifting the indexes of later colors down
+    ///
+    /// Returns the number of colors removed
+    pub fn prune_unused_colors(&mut self) -> usize {
+        let remap = palette::prune_unused(&mut self.palette, &self.pixels);
+        for idx in self.pixels.iter_mut() {
+            *idx = remap.get(*idx as usize).copied().flatten().unwrap_or(*idx);
+        }
+        self.refresh_highest_palette_idx();
+        if let Some(base) = &self.base_palette {
+            self.base_palette = Palette::new(palette::retain_remapped(base, &remap)).ok();
+        }
+        remap.iter().filter(|new| new.is_none()).count()
+    }
+
+    /// Colors can be changed but not added or removed
+    #[inline]
+    pub(crate) fn palette_mut(&mut self) -> &mut Palette {
+        &mut self.palette
+    }
+
+    /// Key/value data saved with the file, see [metadata](crate::metadata) for common keys
+    #[inline]
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Set a metadata value, returning the previous value
+    ///
+    /// `key` must be 1..=255 bytes and `value` at most 65535 bytes
+    pub fn set_metadata(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<Option<String>, IndexedImageError> {
+        metadata::insert(&mut self.metadata, key, value)
+    }
+
+    #[inline]
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.metadata.remove(key)
+    }
+
+    /// Highest color index used by the pixels, the palette must have more colors than this
+    ///
+    /// Tracked as pixels are changed, the pixels are only rescanned if the last use of the highest index was overwritten
     #[inline]
     pub fn min_palette_size_supported(&self) -> u8 {
-        self.highest_palette_idx
+        self.highest_palette_idx.get(&self.pixels)
+    }
+
+    /// Check the internal state is valid, for use in tests and debug builds after using `_unchecked` methods
+    /// or building an animation from parts
+    ///
+    /// All problems found are returned
+    pub fn debug_validate(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations = vec![];
+        if self.width == 0 || self.height == 0 {
+            violations.push(InvariantViolation::ZeroSize(self.width, self.height));
+        }
+        let frame_size = self.width as usize * self.height as usize;
+        if self.frame_size != frame_size {
+            violations.push(InvariantViolation::FrameSize(self.frame_size, frame_size));
+        }
+        if !(1..=u16::MAX as usize).contains(&self.frame_count) {
+            violations.push(InvariantViolation::FrameCount(self.frame_count));
+        }
+        if self.current_frame >= self.frame_count {
+            violations.push(InvariantViolation::CurrentFrame(
+                self.current_frame,
+                self.frame_count,
+            ));
+        }
+        if self.per_frame < 0.0 || self.per_frame.is_nan() {
+            violations.push(InvariantViolation::PerFrame(self.per_frame));
+        }
+        violations.extend(pixel_violations(
+            &self.pixels,
+            frame_size * self.frame_count,
+            self.palette.len(),
+            self.highest_palette_idx,
+        ));
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
     }
 
     #[inline]
@@ -320,8 +667,48 @@ impl AnimatedIndexedImage {
     }
 
     #[inline]
-    pub fn frame_count(&self) -> u8 {
-        self.frame_count as u8
+    pub fn frame_count(&self) -> u16 {
+        self.frame_count as u16
+    }
+
+    /// Index of the frame currently being shown
+    #[inline]
+    pub fn get_current_frame(&self) -> u16 {
+        self.current_frame as u16
+    }
+
+    /// Jump to frame `idx` and restart the frame timer
+    pub fn set_current_frame(&mut self, idx: u16) -> Result<(), IndexedImageError> {
+        if idx as usize >= self.frame_count {
+            return Err(IndexOutOfRange(idx as usize, self.frame_count, "frames"));
+        }
+        self.current_frame = idx as usize;
+        self.next_frame_time = self.per_frame;
+        Ok(())
+    }
+
+    /// Current frame, frame timer, direction and segment
+    pub fn playback_state(&self) -> PlaybackState {
+        PlaybackState {
+            current_frame: self.current_frame as u16,
+            next_frame_time: self.next_frame_time,
+            animate: self.animate,
+            increasing: self.loop_increasing,
+            segment: self.segment,
+        }
+    }
+
+    /// Resume playback from `state`, i.e. from [AnimatedIndexedImage::playback_state] before saving
+    ///
+    /// The frame must be in the animation and inside the segment, if there is one
+    pub fn set_playback_state(&mut self, state: PlaybackState) -> Result<(), IndexedImageError> {
+        state.validate(self.frame_count())?;
+        self.current_frame = state.current_frame as usize;
+        self.next_frame_time = state.next_frame_time;
+        self.animate = state.animate;
+        self.loop_increasing = state.increasing;
+        self.segment = state.segment;
+        Ok(())
     }
 
     /// Doesn't go to next frame until [update] is called
@@ -347,14 +734,19 @@ impl AnimatedIndexedImage {
     /// - Looping - Frame to 0, playing to true
     /// - LoopingReversed - Frame to end, playing to true
     /// - LoopingBoth - Frame to 0, playing to true
+    /// - OnceBoth - Frame to 0, playing to false
+    ///
+    /// When playing a segment the first and last frames of the segment are used instead
     #[inline]
     pub fn reset(&mut self) {
+        let (first, last) = self.frame_range();
         let (idx, animated) = match self.play_type {
-            Once => (0, false),
-            OnceReversed => (self.frame_count - 1, false),
-            _ => (0, true),
+            Once | OnceBoth => (first, false),
+            OnceReversed => (last, false),
+            _ => (first, true),
         };
         self.current_frame = idx;
+        self.loop_increasing = true;
         self.animate = animated;
         self.next_frame_time = self.per_frame;
     }
@@ -373,14 +765,14 @@ impl AnimatedIndexedImage {
     /// Changes play type {
     /// Once <-> OnceReversed
     /// Loops <-> LoopsReversed
-    /// LoopsBoth swaps direction
+    /// LoopsBoth and OnceBoth swap direction
     pub fn reverse(&mut self) {
         match self.play_type {
             Once => self.play_type = OnceReversed,
             OnceReversed => self.play_type = Once,
             Loops => self.play_type = LoopsReversed,
             LoopsReversed => self.play_type = Loops,
-            LoopsBoth => self.loop_increasing = !self.loop_increasing,
+            LoopsBoth | OnceBoth => self.loop_increasing = !self.loop_increasing,
         }
     }
 }
@@ -390,160 +782,987 @@ impl AnimatedIndexedImage {
     ///
     /// * `delta` - Time delta, e.g. `timing.fixed_time_step`
     pub fn update(&mut self, delta: f64) {
+        self.update_with_events(delta);
+    }
+
+    /// Like [update] but returns what happened if the frame changed, i.e. to remove an effect once it's finished
+    pub fn update_with_events(&mut self, delta: f64) -> Option<AnimationEvent> {
+        let mut event = None;
         if self.animate {
             if self.next_frame_time < 0.0 {
                 self.next_frame_time = self.per_frame;
-                match self.play_type {
-                    Once => {
-                        self.current_frame += 1;
-                        if self.current_frame >= self.frame_count {
-                            self.reset();
-                        }
-                    }
-                    OnceReversed => {
-                        if self.current_frame > 0 {
-                            self.current_frame -= 1;
-                        } else {
-                            self.reset();
-                        }
-                    }
-                    Loops => {
-                        self.current_frame += 1;
-                        if self.current_frame >= self.frame_count {
-                            self.current_frame = 0;
-                        }
-                    }
-                    LoopsReversed => {
-                        if self.current_frame > 0 {
-                            self.current_frame -= 1;
-                        } else {
-                            self.current_frame = self.frame_count - 1;
-                        }
+                event = Some(self.advance_frame());
+            }
+            self.next_frame_time -= delta;
+        }
+        event
+    }
+
+    /// Move to the next frame depending on the play type
+    fn advance_frame(&mut self) -> AnimationEvent {
+        let (first, last) = self.frame_range();
+        let mut looped = false;
+        match self.play_type {
+            Once => {
+                self.current_frame += 1;
+                if self.current_frame > last {
+                    self.reset();
+                    return AnimationEvent::Completed;
+                }
+            }
+            OnceReversed => {
+                if self.current_frame > first {
+                    self.current_frame -= 1;
+                } else {
+                    self.reset();
+                    return AnimationEvent::Completed;
+                }
+            }
+            Loops => {
+                self.current_frame += 1;
+                if self.current_frame > last {
+                    self.current_frame = first;
+                    looped = true;
+                }
+            }
+            LoopsReversed => {
+                if self.current_frame > first {
+                    self.current_frame -= 1;
+                } else {
+                    self.current_frame = last;
+                    looped = true;
+                }
+            }
+            LoopsBoth => {
+                if self.loop_increasing {
+                    self.current_frame += 1;
+                    if self.current_frame > last {
+                        self.loop_increasing = false;
+                        self.current_frame = last;
+                        looped = true;
                     }
-                    LoopsBoth => {
-                        if self.loop_increasing {
-                            self.current_frame += 1;
-                            if self.current_frame >= self.frame_count {
-                                self.loop_increasing = false;
-                                self.current_frame = self.frame_count - 1;
-                            }
-                        } else if self.current_frame > 0 {
-                            self.current_frame -= 1;
-                        } else {
-                            self.loop_increasing = true;
-                        }
+                } else if self.current_frame > first {
+                    self.current_frame -= 1;
+                } else {
+                    self.loop_increasing = true;
+                    looped = true;
+                }
+            }
+            OnceBoth => {
+                if self.loop_increasing {
+                    self.current_frame += 1;
+                    if self.current_frame > last {
+                        self.loop_increasing = false;
+                        self.current_frame = last;
+                        looped = true;
                     }
+                } else if self.current_frame > first {
+                    self.current_frame -= 1;
+                } else {
+                    self.reset();
+                    return AnimationEvent::Completed;
                 }
             }
-            self.next_frame_time -= delta;
         }
+        let frame = self.current_frame as u16;
+        if looped {
+            AnimationEvent::Looped(frame)
+        } else {
+            AnimationEvent::FrameChanged(frame)
+        }
+    }
+
+    /// Set the frame and frame timer to where they would be if the animation had been [reset] and then
+    /// [update]d for `seconds`
+    ///
+    /// Whether the animation is playing isn't changed, unless a [Once], [OnceReversed] or [OnceBoth] animation
+    /// would have finished in which case it's [reset]
+    /// If the per frame time is 0 the animation is just [reset]
+    pub fn seek(&mut self, seconds: f64) {
+        let animate = self.animate;
+        self.reset();
+        let seconds = seconds.max(0.0);
+        if self.per_frame <= 0.0 || seconds == 0.0 {
+            self.animate = animate;
+            return;
+        }
+        // frames change once the timer has gone past per frame
+        let mut steps = ((seconds / self.per_frame).ceil() - 1.0).max(0.0);
+        let residual = (steps + 1.0) * self.per_frame - seconds;
+        let (first, last) = self.frame_range();
+        let count = (last - first + 1) as f64;
+        let finished = match self.play_type {
+            Once | OnceReversed => steps >= count,
+            OnceBoth => steps >= count * 2.0,
+            Loops | LoopsReversed => {
+                steps %= count;
+                false
+            }
+            LoopsBoth => {
+                steps %= count * 2.0;
+                false
+            }
+        };
+        if finished {
+            self.reset();
+            return;
+        }
+        for _ in 0..steps as usize {
+            self.advance_frame();
+        }
+        self.next_frame_time = residual;
+        self.animate = animate;
     }
 }
 
 impl AnimatedIndexedImage {
+    /// Writes a version 1 file, unless there are more than 255 frames then version 2 is used
+    ///
     /// Errors will only be returned if you [FilePalette::Name] and the len is invalid
     pub fn to_file_contents(&self, palette: &FilePalette) -> Result<Vec<u8>, IndexedImageError> {
+        self.write_contents(palette, false)
+    }
+
+    /// Same as `to_file_contents` but the [PlaybackState] is included, and restored when reading
+    ///
+    /// For save games, where the animation should continue from the current frame
+    pub fn to_file_contents_with_state(
+        &self,
+        palette: &FilePalette,
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        self.write_contents(palette, true)
+    }
+
+    fn write_contents(
+        &self,
+        palette: &FilePalette,
+        with_state: bool,
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        let v2 =
+            self.frame_count > u8::MAX as usize || palette::needs_v2(palette, self.get_palette());
         let mut output = vec![];
-        output.extend_from_slice(&HEADER);
+        output.extend_from_slice(if v2 { &HEADER_V2 } else { &HEADER });
         output.push(Animated.to_byte());
 
-        palette::write(palette, self.get_palette(), &mut output)?;
-        output.push(self.width);
-        output.push(self.height);
-        output.push(self.play_type.to_byte());
-        output.push(self.frame_count as u8);
+        palette::write(palette, self.get_palette(), v2, &mut output)?;
+        if v2 {
+            output.extend_from_slice(&(self.width as u16).to_be_bytes());
+            output.extend_from_slice(&(self.height as u16).to_be_bytes());
+            output.push(self.play_type.to_byte());
+            output.extend_from_slice(&(self.frame_count as u16).to_be_bytes());
+        } else {
+            output.push(self.width);
+            output.push(self.height);
+            output.push(self.play_type.to_byte());
+            output.push(self.frame_count as u8);
+        }
         output.extend_from_slice(&self.per_frame.to_be_bytes());
         output.extend_from_slice(&self.pixels);
+        metadata::write(&self.metadata, &mut output);
+        tags::write(&self.tags, &mut output);
+        if with_state {
+            playback::write(&self.playback_state(), &mut output);
+        }
 
         Ok(output)
     }
 
-    /// Create an [AnimatedIndexedImage], image palette will be filled with transparency unless file contains colors
+    /// Same as `to_file_contents` but a CRC-32 checksum is appended, this is verified when reading
+    pub fn to_file_contents_with_checksum(
+        &self,
+        palette: &FilePalette,
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        let mut output = self.to_file_contents(palette)?;
+        append_checksum(&mut output);
+        Ok(output)
+    }
+
+    /// Create an [AnimatedIndexedImage] from a version 1 or 2 file, image palette will be filled with transparency unless file contains colors
     /// use `image.set_palette*` to replace the palette
     pub fn from_file_contents(
         bytes: &[u8],
     ) -> Result<(AnimatedIndexedImage, FilePalette), IndexedImageError> {
-        let file_type = verify_format(bytes)?;
+        Self::read_file(bytes).map(|(image, pal_type, _)| (image, pal_type))
+    }
+
+    /// Like [AnimatedIndexedImage::from_file_contents] but calls `progress` with 0.0..=1.0 as frames are read,
+    /// ending with 1.0 once the animation has been created
+    pub fn from_file_contents_with_progress(
+        bytes: &[u8],
+        mut progress: impl FnMut(f32),
+    ) -> Result<(AnimatedIndexedImage, FilePalette), IndexedImageError> {
+        let parts = AnimationParts::read(bytes)?;
+        let mut pixels = Vec::with_capacity(parts.pixels.len());
+        for (i, frame) in parts.frames().enumerate() {
+            progress(i as f32 / parts.frame_count as f32);
+            pixels.extend_from_slice(frame);
+        }
+        let (image, pal_type, _) = parts.build(pixels)?;
+        progress(1.0);
+        Ok((image, pal_type))
+    }
+
+    /// Like [AnimatedIndexedImage::from_file_contents_with_progress] but yields to the executor
+    /// while reading frames so other tasks (such as drawing a progress bar) can run
+    pub async fn from_file_contents_async(
+        bytes: &[u8],
+        mut progress: impl FnMut(f32),
+    ) -> Result<(AnimatedIndexedImage, FilePalette), IndexedImageError> {
+        let parts = AnimationParts::read(bytes)?;
+        // yield roughly every 1%
+        let yield_every = (parts.frame_count as usize / 100).max(1);
+        let mut pixels = Vec::with_capacity(parts.pixels.len());
+        for (i, frame) in parts.frames().enumerate() {
+            if i % yield_every == 0 {
+                progress(i as f32 / parts.frame_count as f32);
+                YieldNow(false).await;
+            }
+            pixels.extend_from_slice(frame);
+        }
+        let (image, pal_type, _) = parts.build(pixels)?;
+        progress(1.0);
+        Ok((image, pal_type))
+    }
+
+    /// Also returns the offset of the data after the pixels
+    pub(crate) fn read_file(
+        bytes: &[u8],
+    ) -> Result<(AnimatedIndexedImage, FilePalette, usize), IndexedImageError> {
+        Self::read_file_into(bytes, Vec::with_capacity)
+    }
+
+    /// Pixels are copied into the buffer returned by `take`, which is only called (with the
+    /// number of pixels) once the file has been read successfully
+    pub(crate) fn read_file_into(
+        bytes: &[u8],
+        take: impl FnOnce(usize) -> Vec<u8>,
+    ) -> Result<(AnimatedIndexedImage, FilePalette, usize), IndexedImageError> {
+        let parts = AnimationParts::read(bytes)?;
+        if let Some(state) = &parts.state {
+            state.validate(parts.frame_count)?;
+        }
+        let mut buffer = take(parts.pixels.len());
+        buffer.clear();
+        buffer.extend_from_slice(parts.pixels);
+        parts.build(buffer)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn into_pixels(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    /// First and last frame to play
+    fn frame_range(&self) -> (usize, usize) {
+        match self.segment {
+            Some(segment) => (segment.start as usize, segment.end as usize),
+            None => (0, self.frame_count - 1),
+        }
+    }
+
+    pub(crate) fn tags_mut(&mut self) -> &mut BTreeMap<String, FrameTag> {
+        &mut self.tags
+    }
+
+    pub(crate) fn tags_ref(&self) -> &BTreeMap<String, FrameTag> {
+        &self.tags
+    }
+
+    pub(crate) fn set_segment(&mut self, segment: Option<FrameTag>) {
+        self.segment = segment;
+    }
+
+    pub(crate) fn segment(&self) -> Option<FrameTag> {
+        self.segment
+    }
+
+    /// Copy of everything but the pixels, which must be set with [AnimatedIndexedImage::set_raw_pixels] before use
+    pub(crate) fn clone_without_pixels(&self) -> Self {
+        Self {
+            palette: self.palette.clone(),
+            pixels: vec![],
+            metadata: self.metadata.clone(),
+            base_palette: self.base_palette.clone(),
+            tags: self.tags.clone(),
+            ..*self
+        }
+    }
+
+    /// Callers must make sure `pixels` is `frame_size * frame_count` long and valid for the palette
+    pub(crate) fn set_raw_pixels(&mut self, pixels: Vec<u8>) {
+        self.pixels = pixels;
+    }
+
+    /// Direct pixel access for frame `idx`
+    ///
+    /// Callers must only write indexes that are valid for the palette and call
+    /// [AnimatedIndexedImage::refresh_highest_palette_idx] if needed
+    pub(crate) fn frame_pixels_mut(&mut self, idx: u16) -> Result<&mut [u8], IndexedImageError> {
+        if idx as usize >= self.frame_count {
+            return Err(IndexOutOfRange(idx as usize, self.frame_count, "frames"));
+        }
+        let start = self.frame_size * idx as usize;
+        Ok(&mut self.pixels[start..start + self.frame_size])
+    }
+
+    pub(crate) fn refresh_highest_palette_idx(&mut self) {
+        self.highest_palette_idx = HighestIdx::new(&self.pixels);
+    }
+}
+
+/// Parsed animation file, before the pixels have been copied
+pub(crate) struct AnimationParts<'a> {
+    pub(crate) pal_type: FilePalette,
+    pub(crate) colors: Option<Vec<Color>>,
+    pub(crate) width: u8,
+    pub(crate) height: u8,
+    pub(crate) play_type: PlayType,
+    pub(crate) frame_count: u16,
+    pub(crate) per_frame: f64,
+    pub(crate) pixels: &'a [u8],
+    pixels_end: usize,
+    pub(crate) metadata: BTreeMap<String, String>,
+    pub(crate) tags: BTreeMap<String, FrameTag>,
+    pub(crate) state: Option<PlaybackState>,
+}
+
+impl<'a> AnimationParts<'a> {
+    pub(crate) fn read(bytes: &'a [u8]) -> Result<Self, IndexedImageError> {
+        AnimationParts::read_from(bytes, &mut ByteReader::new(bytes))
+    }
+
+    /// Like [AnimationParts::read] but reading with `reader`, which must be at the start of `bytes`
+    pub(crate) fn read_from(
+        bytes: &'a [u8],
+        reader: &mut ByteReader<'a>,
+    ) -> Result<Self, IndexedImageError> {
+        let (version, file_type) = verify_format_version(bytes)?;
         if file_type != Animated {
-            return Err(InvalidFileFormat(
-                0,
-                format!(
-                    "Expected Animated Image file but found {}",
-                    file_type.name()
-                ),
+            return Err(wrong_file_type(Animated.name(), &file_type));
+        }
+        let v2 = version == HEADER_V2[3];
+        read_header(reader)?;
+        let (pal_type, colors) = palette::read(reader)?;
+
+        reader.set_section(FileSection::Dimensions);
+        let width = reader.size(v2, "width")?;
+        let height = reader.size(v2, "height")?;
+        if width > 255 || height > 255 {
+            return Err(ImageTooLarge(width, height));
+        }
+        if width == 0 {
+            return Err(WidthIsZero);
+        }
+        if height == 0 {
+            return Err(HeightIsZero);
+        }
+        reader.set_section(FileSection::Animation);
+        let play_type_offset = reader.offset();
+        let play_type_byte = reader.u8("play type")?;
+        let Some(play_type) = PlayType::from_byte(play_type_byte) else {
+            return Err(reader.invalid(play_type_offset, "play type", "0..=5", play_type_byte));
+        };
+        let frame_count_offset = reader.offset();
+        let frame_count = reader.size(v2, "frame count")?;
+        if frame_count == 0 {
+            return Err(reader.invalid(frame_count_offset, "frame count", "at least 1", 0));
+        }
+        let per_frame_offset = reader.offset();
+        let per_frame = reader.f64("per frame time")?;
+        if !(per_frame > 0.0 && per_frame.is_finite()) {
+            return Err(reader.invalid(
+                per_frame_offset,
+                "per frame time",
+                "finite and greater than 0",
+                per_frame,
             ));
         }
-        let idx = HEADER.len() + 1;
-        let (skip, pal_type, colors) = palette::read(idx, bytes)?;
+        reader.set_section(FileSection::Pixels);
+        let palette_len = colors.as_ref().map(Vec::len);
+        let pixels = reader.pixels(width * height * frame_count, palette_len, "frame data")?;
+        let pixels_end = reader.offset();
+        let metadata = metadata::read(pixels_end, reader.remaining())?;
+        let tags = tags::read(pixels_end, reader.remaining(), frame_count)?;
+        let state = playback::read(pixels_end, reader.remaining(), frame_count as u16)?;
+        Ok(Self {
+            pal_type,
+            colors,
+            width: width as u8,
+            height: height as u8,
+            play_type,
+            frame_count: frame_count as u16,
+            per_frame,
+            pixels,
+            pixels_end,
+            metadata,
+            tags,
+            state,
+        })
+    }
+
+    fn frames(&self) -> core::slice::ChunksExact<'a, u8> {
+        self.pixels
+            .chunks_exact(self.width as usize * self.height as usize)
+    }
+
+    fn build(
+        self,
+        pixels: Vec<u8>,
+    ) -> Result<(AnimatedIndexedImage, FilePalette, usize), IndexedImageError> {
+        let highest = pixels.iter().max().copied().unwrap_or_default() as usize;
+        let colors = match self.colors {
+            None => vec![TRANSPARENT; highest + 1],
+            Some(colors) => colors,
+        };
+
+        let mut image = AnimatedIndexedImage::new(
+            self.width,
+            self.height,
+            self.per_frame,
+            self.frame_count,
+            colors,
+            pixels,
+            self.play_type,
+        )?;
+        image.metadata = self.metadata;
+        image.tags = self.tags;
+        if let Some(state) = self.state {
+            image.set_playback_state(state)?;
+        }
+        Ok((image, self.pal_type, self.pixels_end))
+    }
+}
+
+/// Returns pending once so the executor can run other tasks
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Insert a frame before `idx`, an `idx` of `frame_count()` adds the frame at the end
+    ///
+    /// `pixels` must be `width * height` long and only use indexes within the palette
+    /// If the frame is inserted before the current frame, the current frame is kept
+    pub fn insert_frame(&mut self, idx: u16, pixels: &[u8]) -> Result<(), IndexedImageError> {
+        let idx = idx as usize;
+        if idx > self.frame_count {
+            return Err(IndexOutOfRange(idx, self.frame_count + 1, "frames"));
+        }
+        if self.frame_count >= u16::MAX as usize {
+            return Err(TooManyFrames(self.frame_count + 1));
+        }
+        if pixels.len() != self.frame_size {
+            return Err(MissingData(pixels.len(), self.frame_size));
+        }
+        let highest = pixels.iter().max().copied().unwrap_or_default();
+        if highest as usize >= self.palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
+        let start = idx * self.frame_size;
+        self.pixels.splice(start..start, pixels.iter().copied());
+        self.frame_count += 1;
+        self.highest_palette_idx.include(highest);
+        if idx <= self.current_frame && self.frame_count > 1 {
+            self.current_frame += 1;
+        }
+        for tag in self.tags.values_mut().chain(&mut self.segment) {
+            tag.frame_inserted(idx as u16);
+        }
+        Ok(())
+    }
+
+    /// Remove frame `idx` and return its pixels
+    ///
+    /// Animations must have at least one frame, so the last frame can't be removed
+    /// If the current frame is removed the next frame (or the new last frame) will be shown
+    pub fn remove_frame(&mut self, idx: u16) -> Result<Vec<u8>, IndexedImageError> {
+        let idx = idx as usize;
+        if idx >= self.frame_count {
+            return Err(IndexOutOfRange(idx, self.frame_count, "frames"));
+        }
+        if self.frame_count == 1 {
+            return Err(NoFrames);
+        }
+        let start = idx * self.frame_size;
+        let removed = self.pixels.drain(start..start + self.frame_size).collect();
+        self.frame_count -= 1;
+        if idx < self.current_frame {
+            self.current_frame -= 1;
+        }
+        self.current_frame = self.current_frame.min(self.frame_count - 1);
+        self.highest_palette_idx = HighestIdx::new(&self.pixels);
+        self.tags.retain(|_, tag| tag.frame_removed(idx as u16));
+        if let Some(segment) = &mut self.segment {
+            if !segment.frame_removed(idx as u16) {
+                self.segment = None;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Swap frames `first` and `second`, the current frame moves with its pixels
+    pub fn swap_frames(&mut self, first: u16, second: u16) -> Result<(), IndexedImageError> {
+        let (first, second) = (first as usize, second as usize);
+        for idx in [first, second] {
+            if idx >= self.frame_count {
+                return Err(IndexOutOfRange(idx, self.frame_count, "frames"));
+            }
+        }
+        if first == second {
+            return Ok(());
+        }
+        let (low, high) = (first.min(second), first.max(second));
+        let (start, end) = self.pixels.split_at_mut(high * self.frame_size);
+        start[low * self.frame_size..(low + 1) * self.frame_size]
+            .swap_with_slice(&mut end[..self.frame_size]);
+        if self.current_frame == first {
+            self.current_frame = second;
+        } else if self.current_frame == second {
+            self.current_frame = first;
+        }
+        Ok(())
+    }
+
+    /// Insert a copy of frame `idx` after it
+    pub fn duplicate_frame(&mut self, idx: u16) -> Result<(), IndexedImageError> {
+        let pixels = self.get_frame_pixels(idx)?.to_vec();
+        self.insert_frame(idx + 1, &pixels)
+    }
+
+    /// Pixels that are different between frames `from` and `to` as `(offset in frame, index in from, index in to)`
+    ///
+    /// Applying the result to `from` with [AnimatedIndexedImage::apply_delta] makes it match `to`
+    pub fn frame_delta(
+        &self,
+        from: u16,
+        to: u16,
+    ) -> Result<Vec<(usize, u8, u8)>, IndexedImageError> {
+        let from = self.get_frame_pixels(from)?;
+        let to = self.get_frame_pixels(to)?;
+        Ok(from
+            .iter()
+            .zip(to)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(i, (old, new))| (i, *old, *new))
+            .collect())
+    }
+
+    /// Apply a delta from [AnimatedIndexedImage::frame_delta] to frame `idx`
+    ///
+    /// Every offset must be in the frame, every new index must be in the palette and every old index must
+    /// match the current pixel, otherwise an error is returned and the frame is unchanged
+    pub fn apply_delta(
+        &mut self,
+        idx: u16,
+        delta: &[(usize, u8, u8)],
+    ) -> Result<(), IndexedImageError> {
+        let start = self.frame_size * idx as usize;
+        let frame = self.get_frame_pixels(idx)?;
+        for (offset, old, new) in delta {
+            let Some(current) = frame.get(*offset) else {
+                return Err(IndexOutOfRange(*offset, self.frame_size, "frame"));
+            };
+            if current != old {
+                return Err(DeltaMismatch(*offset, *old, *current));
+            }
+            if *new as usize >= self.palette.len() {
+                return Err(IndexOutOfRange(
+                    *new as usize,
+                    self.palette.len(),
+                    "palette",
+                ));
+            }
+        }
+        for (offset, old, new) in delta {
+            self.pixels[start + offset] = *new;
+            self.highest_palette_idx.write(*old, *new);
+        }
+        Ok(())
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Create a new animation where each frame has faint copies of its neighbouring frames
+    /// blended in, giving a baked motion blur look
+    ///
+    /// `strength` is clamped to 0.0..=1.0, at 1.0 each neighbour is weighted half as much as the frame itself
+    /// Looping animations blend the first and last frames together
+    ///
+    /// Blended colors are matched to the closest existing palette color, so the palette is unchanged
+    pub fn bake_motion_blur(&self, strength: f32) -> AnimatedIndexedImage {
+        let weight = strength.clamp(0.0, 1.0) * 0.5;
+        let loops = matches!(self.play_type, Loops | LoopsReversed | LoopsBoth);
+        let count = self.frame_count;
+        let neighbour = |frame: usize, offset: isize| -> Option<usize> {
+            let idx = frame as isize + offset;
+            if loops && count > 2 {
+                Some(idx.rem_euclid(count as isize) as usize)
+            } else if idx >= 0 && (idx as usize) < count {
+                Some(idx as usize)
+            } else {
+                None
+            }
+        };
+        let color = |idx: u8| self.palette.get(idx).unwrap_or(TRANSPARENT);
+        let mut cache: BTreeMap<(u8, Option<u8>, Option<u8>), u8> = BTreeMap::new();
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for frame in 0..count {
+            let prev = neighbour(frame, -1);
+            let next = neighbour(frame, 1);
+            for i in 0..self.frame_size {
+                let current = self.pixels[frame * self.frame_size + i];
+                let prev = prev.map(|f| self.pixels[f * self.frame_size + i]);
+                let next = next.map(|f| self.pixels[f * self.frame_size + i]);
+                let idx = *cache.entry((current, prev, next)).or_insert_with(|| {
+                    let mut total = [0.0_f32; 4];
+                    let mut total_weight = 0.0;
+                    let colors = [Some(current), prev, next];
+                    for (n, idx) in colors.into_iter().enumerate() {
+                        let Some(idx) = idx else {
+                            continue;
+                        };
+                        let w = if n == 0 { 1.0 } else { weight };
+                        let c = color(idx);
+                        total[0] += c.r as f32 * w;
+                        total[1] += c.g as f32 * w;
+                        total[2] += c.b as f32 * w;
+                        total[3] += c.a as f32 * w;
+                        total_weight += w;
+                    }
+                    let blended = Color::new(
+                        (total[0] / total_weight).round() as u8,
+                        (total[1] / total_weight).round() as u8,
+                        (total[2] / total_weight).round() as u8,
+                        (total[3] / total_weight).round() as u8,
+                    );
+                    nearest_index_by(&self.palette, blended, ColorDistance::EuclideanAlpha)
+                });
+                pixels.push(idx);
+            }
+        }
+        let mut output = self.clone();
+        output.pixels = pixels;
+        output.highest_palette_idx = HighestIdx::new(&output.pixels);
+        output.reset();
+        output
+    }
+
+    /// Smaller copy of the animation for previews, the palette and play type are kept
+    ///
+    /// The frames are shrunk (keeping the aspect ratio) to fit in `max_width`x`max_height` using nearest neighbour,
+    /// if there are more than `max_frames` frames then evenly spaced frames are picked and the
+    /// per frame time is increased so the animation lasts about as long
+    pub fn thumbnail_animation(
+        &self,
+        max_width: u8,
+        max_height: u8,
+        max_frames: u16,
+    ) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        if max_width == 0 {
+            return Err(WidthIsZero);
+        }
+        if max_height == 0 {
+            return Err(HeightIsZero);
+        }
+        if max_frames == 0 {
+            return Err(NoFrames);
+        }
+        let scale = (max_width as f64 / self.width as f64)
+            .min(max_height as f64 / self.height as f64)
+            .min(1.0);
+        let width = ((self.width as f64 * scale).round() as usize).clamp(1, max_width as usize);
+        let height = ((self.height as f64 * scale).round() as usize).clamp(1, max_height as usize);
+        let frame_count = self.frame_count.min(max_frames as usize);
+        let mut pixels = Vec::with_capacity(width * height * frame_count);
+        for i in 0..frame_count {
+            let frame = i * self.frame_count / frame_count;
+            let start = frame * self.frame_size;
+            for y in 0..height {
+                let src_y = y * self.height as usize / height;
+                for x in 0..width {
+                    let src_x = x * self.width as usize / width;
+                    pixels.push(self.pixels[start + src_x + src_y * self.width as usize]);
+                }
+            }
+        }
+        let per_frame = if frame_count == self.frame_count {
+            self.per_frame
+        } else {
+            self.per_frame * self.frame_count as f64 / frame_count as f64
+        };
+        AnimatedIndexedImage::new(
+            width as u8,
+            height as u8,
+            per_frame,
+            frame_count as u16,
+            self.palette.to_vec(),
+            pixels,
+            self.play_type,
+        )
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Apply `f` to a copy of every frame, in parallel if the `rayon` feature is enabled
+    fn map_frames<T, F>(&self, f: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(&IndexedImage) -> T + Send + Sync,
+    {
+        let frames = self.as_images();
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            frames.par_iter().map(f).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            frames.iter().map(f).collect()
+        }
+    }
+
+    /// RGBA8 pixel data of every frame, see [IndexedImage::to_rgba_bytes]
+    pub fn frames_rgba(&self) -> Vec<Vec<u8>> {
+        self.map_frames(|frame| frame.to_rgba_bytes())
+    }
+
+    /// Create a copy of this animation using `palette`, each pixel will be replaced with the closest color
+    ///
+    /// Playback state and metadata are kept, the base palette is cleared
+    ///
+    /// Will only return an error if `palette` is empty or has more than 256 colors
+    pub fn remap_to_palette(
+        &self,
+        palette: &[Color],
+        dithering: Dithering,
+    ) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        let palette = Palette::try_from(palette)?;
+        let frames = self
+            .map_frames(|frame| frame.remap_to_palette(&palette, dithering))
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut output = self.with_frames(frames);
+        output.palette = palette;
+        output.base_palette = None;
+        Ok(output)
+    }
+
+    /// Copy of this animation with the frames replaced by `frames`, which must all be the same size
+    fn with_frames(&self, frames: Vec<IndexedImage>) -> AnimatedIndexedImage {
+        let (width, height) = frames
+            .first()
+            .map(|frame| frame.size())
+            .unwrap_or(self.size());
+        let mut output = self.clone();
+        output.width = width;
+        output.height = height;
+        output.frame_size = width as usize * height as usize;
+        output.pixels = frames
+            .into_iter()
+            .flat_map(|frame| frame.into_pixels())
+            .collect();
+        output.highest_palette_idx = HighestIdx::new(&output.pixels);
+        output
+    }
+
+    /// Scale every frame, see [IndexedImage::scale]
+    ///
+    /// Playback state and metadata are kept
+    pub fn scale(&self, algo: Scaling) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        let frames = self
+            .map_frames(|frame| frame.scale(algo.clone()))
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.with_frames(frames))
+    }
+
+    /// Rotate every frame 90° clockwise
+    ///
+    /// Playback state and metadata are kept
+    pub fn rotate_cw(&self) -> AnimatedIndexedImage {
+        let frames = self.map_frames(|frame| frame.rotate_cw());
+        self.with_frames(frames)
+    }
+
+    /// Rotate every frame 90° counter clockwise
+    ///
+    /// Playback state and metadata are kept
+    pub fn rotate_ccw(&self) -> AnimatedIndexedImage {
+        let frames = self.map_frames(|frame| frame.rotate_ccw());
+        self.with_frames(frames)
+    }
+
+    /// Flip every frame vertically
+    ///
+    /// Playback state and metadata are kept
+    pub fn flip_vertical(&self) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        let frames = self
+            .map_frames(|frame| frame.flip_vertical())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.with_frames(frames))
+    }
+
+    /// Flip every frame horizontally
+    ///
+    /// Playback state and metadata are kept
+    pub fn flip_horizontal(&self) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        let frames = self
+            .map_frames(|frame| frame.flip_horizontal())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.with_frames(frames))
+    }
+}
+
+impl Tint for AnimatedIndexedImage {
+    /// Tint every palette color
+    fn tint_add(&mut self, r_diff: isize, g_diff: isize, b_diff: isize, a_diff: isize) {
+        for color in self.palette.iter_mut() {
+            color.tint_add(r_diff, g_diff, b_diff, a_diff);
+        }
+    }
+
+    /// Tint every palette color
+    fn tint_mul(&mut self, r_diff: f32, g_diff: f32, b_diff: f32, a_diff: f32) {
+        for color in self.palette.iter_mut() {
+            color.tint_mul(r_diff, g_diff, b_diff, a_diff);
+        }
+    }
+}
+
+impl HueShift for AnimatedIndexedImage {
+    /// Shift the hue of every palette color
+    fn tint_hue_shift(&mut self, degrees: f32) {
+        for color in self.palette.iter_mut() {
+            color.tint_hue_shift(degrees);
+        }
+    }
+}
+
+/// Build an [AnimatedIndexedImage] one frame at a time
+///
+/// Problems are reported by [AnimatedIndexedImageBuilder::build]
+///
+/// Defaults to 0.1 seconds per frame and [PlayType::Loops]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedIndexedImageBuilder {
+    width: u8,
+    height: u8,
+    per_frame: f64,
+    play_type: PlayType,
+    palette: Option<Vec<Color>>,
+    pixels: Vec<u8>,
+    frame_count: usize,
+    /// first invalid frame, as (frame, pixel count)
+    invalid_frame: Option<(usize, usize)>,
+}
+
+impl AnimatedIndexedImageBuilder {
+    pub fn new(width: u8, height: u8) -> Self {
+        Self {
+            width,
+            height,
+            per_frame: 0.1,
+            play_type: Loops,
+            palette: None,
+            pixels: vec![],
+            frame_count: 0,
+            invalid_frame: None,
+        }
+    }
+
+    /// Set the palette, if not set the palette of the first image added with
+    /// [add_frame_image](AnimatedIndexedImageBuilder::add_frame_image) is used
+    pub fn palette(&mut self, palette: &[Color]) -> &mut Self {
+        self.palette = Some(palette.to_vec());
+        self
+    }
+
+    pub fn per_frame(&mut self, seconds: f64) -> &mut Self {
+        self.per_frame = seconds;
+        self
+    }
+
+    pub fn play_type(&mut self, play_type: PlayType) -> &mut Self {
+        self.play_type = play_type;
+        self
+    }
+
+    /// Add a frame, `pixels` must be `width * height` long
+    pub fn add_frame(&mut self, pixels: &[u8]) -> &mut Self {
+        let expected = self.width as usize * self.height as usize;
+        if pixels.len() != expected && self.invalid_frame.is_none() {
+            self.invalid_frame = Some((self.frame_count, pixels.len()));
+        }
+        self.pixels.extend_from_slice(pixels);
+        self.frame_count += 1;
+        self
+    }
 
-        let start = idx + skip;
-        if bytes.len() < start + 3 {
-            return Err(InvalidFileFormat(
-                start,
-                "Incomplete pixels data".to_string(),
-            ));
+    /// Add a frame, `image` must be the same size as the animation
+    ///
+    /// Only the pixels are used, the image's palette is only used if no palette has been set
+    pub fn add_frame_image(&mut self, image: &IndexedImage) -> &mut Self {
+        if self.palette.is_none() {
+            self.palette = Some(image.get_palette().to_vec());
         }
-        let width = bytes[start];
-        let height = bytes[start + 1];
-        let play_type_byte = bytes[start + 2];
-        let play_type = PlayType::from_byte(play_type_byte);
-        if play_type.is_none() {
-            return Err(InvalidFileFormat(
-                start + 2,
-                format!("Unsupported play type: {play_type_byte}"),
-            ));
+        if image.size() != (self.width, self.height) && self.invalid_frame.is_none() {
+            self.invalid_frame = Some((self.frame_count, image.get_pixels().len()));
         }
-        let frame_count = bytes[start + 3];
-        if frame_count == 0 {
-            return Err(InvalidFileFormat(
-                start + 3,
-                "Image has no frames".to_string(),
+        self.pixels.extend_from_slice(image.get_pixels());
+        self.frame_count += 1;
+        self
+    }
+
+    #[inline]
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Returns an error if there are no frames or more than 65535, any frame was the wrong size,
+    /// no palette was set or it doesn't have enough colors for the frames
+    pub fn build(&self) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        if let Some((frame, len)) = self.invalid_frame {
+            return Err(InvalidFrameSize(
+                frame,
+                len,
+                self.width as usize * self.height as usize,
             ));
         }
-        let f64_bytes = &bytes[start + 4..=start + 11];
-        let per_frame = f64::from_be_bytes([
-            f64_bytes[0],
-            f64_bytes[1],
-            f64_bytes[2],
-            f64_bytes[3],
-            f64_bytes[4],
-            f64_bytes[5],
-            f64_bytes[6],
-            f64_bytes[7],
-        ]);
-        if per_frame <= 0.0 {
-            return Err(InvalidFileFormat(
-                start + 4,
-                format!("Per frame time is invalid: {per_frame}"),
-            ));
+        if self.frame_count == 0 {
+            return Err(NoFrames);
         }
-        let pixels_start = start + 12;
-        let frame_size = width as usize * height as usize;
-        let frame_pixel_count = frame_size * frame_count as usize;
-        if bytes.len() < pixels_start + frame_pixel_count {
-            return Err(InvalidFileFormat(
-                pixels_start,
-                "Image has incomplete frame data".to_string(),
-            ));
+        if self.frame_count > u16::MAX as usize {
+            return Err(TooManyFrames(self.frame_count));
+        }
+        let palette = self.palette.clone().unwrap_or_default();
+        let highest = self.pixels.iter().max().copied().unwrap_or_default();
+        if !palette.is_empty() && highest as usize >= palette.len() {
+            return Err(PaletteTooFewColors(highest));
         }
-        let pixels = &bytes[pixels_start..pixels_start + frame_pixel_count];
-
-        let highest = *pixels.iter().max().expect("Invalid pixels data") as usize;
-        let colors = match colors {
-            None => vec![TRANSPARENT; highest + 1],
-            Some(colors) => colors,
-        };
-
         AnimatedIndexedImage::new(
-            width,
-            height,
-            per_frame,
-            frame_count,
-            colors,
-            pixels.to_vec(),
-            play_type.unwrap(),
+            self.width,
+            self.height,
+            self.per_frame,
+            self.frame_count as u16,
+            palette,
+            self.pixels.clone(),
+            self.play_type,
         )
-        .map(|image| (image, pal_type))
     }
 }
 
@@ -552,6 +1771,124 @@ mod test {
     use super::*;
     use crate::palette::FilePalette::*;
 
+    #[test]
+    fn metadata_round_trip() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED], vec![0, 0], Loops).unwrap();
+        image.set_metadata(metadata::TOOL, "test").unwrap();
+        let bytes = image.to_file_contents(&Colors).unwrap();
+        let (output, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output.metadata().get(metadata::TOOL).unwrap(), "test");
+    }
+
+    #[test]
+    fn many_frames() {
+        let image = AnimatedIndexedImage::new(1, 1, 0.1, 300, vec![RED, BLUE], vec![1; 300], Loops)
+            .unwrap();
+        let bytes = image.to_file_contents(&Colors).unwrap();
+        assert_eq!(&bytes[..4], &HEADER_V2);
+        let (output, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output.frame_count(), 300);
+        assert_eq!(output, image);
+        assert_eq!(output.get_frame_pixels(299).unwrap(), &[1]);
+    }
+
+    #[test]
+    fn current_frame_rgba() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 1.0, 2, vec![RED, BLUE], vec![0, 1], Loops).unwrap();
+        assert_eq!(image.current_frame_rgba(), vec![255, 0, 0, 255]);
+        image.skip_to_next_frame();
+        image.update(0.0);
+        assert_eq!(
+            image.current_frame_rgba_premultiplied(),
+            vec![0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn strip_rgba() {
+        let image =
+            AnimatedIndexedImage::new(1, 2, 0.1, 2, vec![RED, BLUE], vec![0, 1, 1, 0], Loops)
+                .unwrap();
+        assert_eq!(
+            image.strip_rgba(),
+            vec![255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 255, 255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn remap_to_palette() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN, BLUE],
+            vec![0, 1, 2, 0],
+            LoopsBoth,
+        )
+        .unwrap();
+        image.set_base_palette();
+        let remapped = image
+            .remap_to_palette(&[WHITE, BLUE, RED], Dithering::None)
+            .unwrap();
+        assert_eq!(remapped.get_palette(), &[WHITE, BLUE, RED]);
+        assert_eq!(remapped.get_pixels(), &[2, 0, 1, 2]);
+        assert_eq!(remapped.play_type(), LoopsBoth);
+        assert!(remapped.base_palette().is_none());
+        assert_eq!(
+            remapped.frames_rgba(),
+            vec![
+                vec![255, 0, 0, 255, 255, 255, 255, 255],
+                vec![0, 0, 255, 255, 255, 0, 0, 255]
+            ]
+        );
+        assert!(image.remap_to_palette(&[], Dithering::None).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_files() {
+        let dir = std::env::temp_dir().join("ici_files_from_files");
+        std::fs::create_dir_all(&dir).unwrap();
+        let frames = [
+            IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap(),
+            IndexedImage::new(2, 1, vec![GREEN, RED, WHITE], vec![1, 0]).unwrap(),
+        ];
+        let paths: Vec<PathBuf> = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let path = dir.join(format!("{i}.ici"));
+                let bytes = frame.to_file_contents(&Colors).unwrap();
+                std::fs::write(&path, bytes).unwrap();
+                path
+            })
+            .collect();
+        let image = AnimatedIndexedImage::from_files(&paths, 0.1, Loops).unwrap();
+        assert_eq!(image.frame_count(), 2);
+        assert_eq!(image.get_palette(), &[RED, BLUE, GREEN]);
+        assert_eq!(image.get_pixels(), &[0, 1, 0, 2]);
+        assert!(matches!(
+            AnimatedIndexedImage::from_files(&[], 0.1, Loops),
+            Err(NoFrames)
+        ));
+        assert!(matches!(
+            AnimatedIndexedImage::from_files(&[dir.join("missing.ici")], 0.1, Loops),
+            Err(FileIo(_, _))
+        ));
+        let mut bytes = frames[0].to_file_contents(&Colors).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] = 5;
+        let path = dir.join("outside.ici");
+        std::fs::write(&path, bytes).unwrap();
+        assert!(matches!(
+            AnimatedIndexedImage::from_files(&[path], 0.1, Loops),
+            Err(InvalidValue { what, .. }) if what == "color index"
+        ));
+    }
+
     #[test]
     fn write_and_read_no_data() {
         let input = AnimatedIndexedImage::new(
@@ -824,7 +2161,10 @@ mod test {
         modified
             .set_palette(&[Color::new(255, 0, 0, 255), Color::new(0, 255, 0, 255)])
             .unwrap();
-        assert_eq!(image.highest_palette_idx, modified.highest_palette_idx);
+        assert_eq!(
+            image.min_palette_size_supported(),
+            modified.min_palette_size_supported()
+        );
         assert_eq!(image.height, modified.height);
         assert_eq!(image.width, modified.width);
         assert_eq!(image.pixels, image.pixels);
@@ -854,7 +2194,7 @@ mod test {
         modified
             .set_palette_replace_id(&[Color::new(5, 5, 5, 5)], 0)
             .unwrap();
-        assert_eq!(modified.highest_palette_idx, 0);
+        assert_eq!(modified.min_palette_size_supported(), 0);
         assert_eq!(image.height, modified.height);
         assert_eq!(image.width, modified.width);
         assert_eq!(image.pixels, image.pixels);
@@ -879,7 +2219,7 @@ mod test {
         .unwrap();
         let mut modified = image.clone();
         modified.set_palette_replace_color(&[Color::new(5, 5, 5, 5)], Color::new(5, 5, 5, 5));
-        assert_eq!(modified.highest_palette_idx, 2);
+        assert_eq!(modified.min_palette_size_supported(), 2);
         assert_eq!(image.height, modified.height);
         assert_eq!(image.width, modified.width);
         assert_eq!(image.pixels, image.pixels);
@@ -919,6 +2259,14 @@ mod test {
         assert_eq!(image.get_pixel(0, idx).unwrap(), 2);
     }
 
+    #[test]
+    fn hue_shift() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 1.0, 2, vec![RED, WHITE], vec![0, 1], Loops).unwrap();
+        image.tint_hue_shift(240.0);
+        assert_eq!(image.get_palette(), &[BLUE, WHITE]);
+    }
+
     #[test]
     fn updates() {
         let mut image = AnimatedIndexedImage::new(
@@ -944,6 +2292,260 @@ mod test {
         assert_eq!(image.get_current_frame_pixels(), &[1]);
     }
 
+    #[test]
+    fn motion_blur() {
+        let palette = vec![BLACK, WHITE, Color::gray(170)];
+        let image =
+            AnimatedIndexedImage::new(1, 1, 0.1, 3, palette.clone(), vec![1, 0, 0], Once).unwrap();
+        assert_eq!(image.bake_motion_blur(1.0).get_pixels(), &[2, 0, 0]);
+        assert_eq!(image.bake_motion_blur(0.0).get_pixels(), &[1, 0, 0]);
+        let image = AnimatedIndexedImage::new(1, 1, 0.1, 3, palette, vec![0, 0, 1], Loops).unwrap();
+        assert_eq!(image.bake_motion_blur(1.0).get_pixels(), &[0, 0, 2]);
+        let palette = vec![TRANSPARENT, RED, Color::new(255, 0, 0, 170)];
+        let image = AnimatedIndexedImage::new(1, 1, 0.1, 3, palette, vec![1, 0, 0], Once).unwrap();
+        assert_eq!(image.bake_motion_blur(1.0).get_pixels(), &[2, 0, 0]);
+    }
+
+    #[test]
+    fn no_panics() {
+        assert!(matches!(
+            AnimatedIndexedImage::new(1, 1, 0.1, 0, vec![RED], vec![], PlayType::Loops),
+            Err(NoFrames)
+        ));
+        let mut image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            2,
+            vec![RED; Palette::MAX_COLORS],
+            vec![0, 255],
+            PlayType::Loops,
+        )
+        .unwrap();
+        assert_eq!(image.get_color(255).unwrap(), RED);
+        image.set_color(255, BLUE).unwrap();
+        assert_eq!(image.get_frame(1).unwrap().get_color(255).unwrap(), BLUE);
+        assert!(image.get_frame(2).is_err());
+
+        for per_frame in [f64::NAN, f64::INFINITY, -1.0, f64::MIN_POSITIVE] {
+            image.set_per_frame(per_frame);
+            image.update(1.0);
+            image.update(1.0);
+            image.seek(1e300);
+            #[cfg(feature = "image-interop")]
+            assert_eq!(::image::Frames::from(&image).count(), 2);
+        }
+    }
+
+    /// Polls `future` until it's ready, returning the output and number of polls
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        struct NoopWaker;
+        impl std::task::Wake for NoopWaker {
+            fn wake(self: std::sync::Arc<Self>) {}
+        }
+        let waker = std::task::Waker::from(std::sync::Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        let mut polls = 1;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, polls),
+                Poll::Pending => polls += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn load_with_progress() {
+        let image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            250,
+            vec![RED, BLUE],
+            (0..250).map(|i| i as u8 % 2).collect(),
+            PlayType::Loops,
+        )
+        .unwrap();
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap();
+
+        let mut updates = vec![];
+        let (output, _) =
+            AnimatedIndexedImage::from_file_contents_with_progress(&bytes, |p| updates.push(p))
+                .unwrap();
+        assert_eq!(output, image);
+        assert_eq!(updates.len(), 251);
+        assert_eq!(updates.first(), Some(&0.0));
+        assert_eq!(updates.last(), Some(&1.0));
+        assert!(updates.windows(2).all(|w| w[0] < w[1]));
+
+        let mut updates = vec![];
+        let (result, polls) = block_on(AnimatedIndexedImage::from_file_contents_async(
+            &bytes,
+            |p| updates.push(p),
+        ));
+        assert_eq!(result.unwrap().0, image);
+        assert_eq!(polls, 126);
+        assert_eq!(updates.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn thumbnail() {
+        let frame: Vec<u8> = (0..16).map(|i| (i % 4 >= 2) as u8).collect();
+        let mut pixels = vec![];
+        for i in 0..6 {
+            pixels.extend(frame.iter().map(|p| p + (i % 3) * 2));
+        }
+        let image = AnimatedIndexedImage::new(
+            4,
+            4,
+            0.1,
+            6,
+            vec![RED, GREEN, BLUE, WHITE, BLACK, MAGENTA],
+            pixels,
+            PlayType::LoopsBoth,
+        )
+        .unwrap();
+
+        let thumb = image.thumbnail_animation(2, 3, 3).unwrap();
+        assert_eq!(thumb.size(), (2, 2));
+        assert_eq!(thumb.frame_count(), 3);
+        assert!((thumb.get_per_frame() - 0.2).abs() < 0.0001);
+        assert_eq!(thumb.get_palette(), image.get_palette());
+        assert_eq!(thumb.play_type(), PlayType::LoopsBoth);
+        assert_eq!(thumb.get_frame_pixels(0).unwrap(), &[0, 1, 0, 1]);
+        assert_eq!(thumb.get_frame_pixels(1).unwrap(), &[4, 5, 4, 5]);
+        assert_eq!(thumb.get_frame_pixels(2).unwrap(), &[2, 3, 2, 3]);
+
+        let same = image.thumbnail_animation(10, 10, 10).unwrap();
+        assert_eq!(same, image);
+        assert!(image.thumbnail_animation(0, 10, 10).is_err());
+        assert!(image.thumbnail_animation(10, 10, 0).is_err());
+    }
+
+    #[test]
+    fn deltas() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            2,
+            0.1,
+            3,
+            vec![RED, GREEN, BLUE],
+            vec![0, 0, 1, 1, 0, 2, 1, 0, 0, 0, 1, 1],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let delta = image.frame_delta(0, 1).unwrap();
+        assert_eq!(delta, vec![(1, 0, 2), (3, 1, 0)]);
+        assert!(image.frame_delta(0, 3).is_err());
+        assert!(image.frame_delta(0, 2).unwrap().is_empty());
+
+        assert!(matches!(
+            image.apply_delta(1, &delta),
+            Err(DeltaMismatch(1, 0, 2))
+        ));
+        assert!(image.apply_delta(2, &[(4, 0, 1)]).is_err());
+        assert!(image.apply_delta(2, &[(0, 0, 1), (1, 0, 3)]).is_err());
+        assert_eq!(image.get_frame_pixels(2).unwrap(), &[0, 0, 1, 1]);
+
+        image.apply_delta(2, &delta).unwrap();
+        assert_eq!(
+            image.get_frame_pixels(2).unwrap(),
+            image.get_frame_pixels(1).unwrap()
+        );
+    }
+
+    #[test]
+    fn whole_frames() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN, BLUE],
+            vec![0, 1, 2, 2],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let frame = image.frame(1).unwrap();
+        assert_eq!(frame.get_pixels(), &[2, 2]);
+        assert_eq!(frame.get_palette(), &[RED, GREEN, BLUE]);
+        assert!(image.frame(2).is_err());
+
+        let replacement = IndexedImage::new(2, 1, vec![WHITE, WHITE], vec![1, 0]).unwrap();
+        image.set_frame(1, &replacement).unwrap();
+        assert_eq!(image.get_frame_pixels(1).unwrap(), &[1, 0]);
+        assert_eq!(image.min_palette_size_supported(), 1);
+        assert_eq!(image.get_palette(), &[RED, GREEN, BLUE]);
+
+        assert!(matches!(
+            image.set_frame(0, &IndexedImage::blank(1, 1, vec![RED])),
+            Err(InvalidImageSize)
+        ));
+        let too_many = IndexedImage::new(2, 1, vec![RED; 4], vec![3, 0]).unwrap();
+        assert!(matches!(
+            image.set_frame(0, &too_many),
+            Err(PaletteTooFewColors(3))
+        ));
+        assert!(image.set_frame(2, &replacement).is_err());
+    }
+
+    #[test]
+    fn frame_editing() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 0.1, 3, vec![RED; 4], vec![0, 1, 2], Loops).unwrap();
+        image.skip_to_next_frame();
+        image.update(0.0);
+        assert_eq!(image.get_current_frame_pixels(), &[1]);
+
+        image.insert_frame(0, &[3]).unwrap();
+        assert_eq!(image.get_pixels(), &[3, 0, 1, 2]);
+        assert_eq!(image.get_current_frame_pixels(), &[1]);
+        assert!(image.insert_frame(0, &[4]).is_err());
+        assert!(image.insert_frame(9, &[0]).is_err());
+
+        image.swap_frames(0, 2).unwrap();
+        assert_eq!(image.get_pixels(), &[1, 0, 3, 2]);
+        assert_eq!(image.get_current_frame_pixels(), &[1]);
+
+        image.duplicate_frame(3).unwrap();
+        assert_eq!(image.get_pixels(), &[1, 0, 3, 2, 2]);
+        assert_eq!(image.frame_count(), 5);
+
+        assert_eq!(image.remove_frame(0).unwrap(), vec![1]);
+        assert_eq!(image.get_pixels(), &[0, 3, 2, 2]);
+        assert_eq!(image.get_current_frame_pixels(), &[0]);
+        assert_eq!(image.min_palette_size_supported(), 3);
+        for _ in 0..3 {
+            image.remove_frame(0).unwrap();
+        }
+        assert!(matches!(image.remove_frame(0), Err(NoFrames)));
+    }
+
+    #[test]
+    fn builder() {
+        let frame = IndexedImage::new(2, 1, vec![RED, BLUE], vec![1, 0]).unwrap();
+        let mut builder = AnimatedIndexedImageBuilder::new(2, 1);
+        assert!(matches!(builder.build(), Err(NoFrames)));
+        builder
+            .per_frame(0.5)
+            .play_type(Once)
+            .add_frame_image(&frame)
+            .add_frame(&[0, 0]);
+        let image = builder.build().unwrap();
+        assert_eq!(image.frame_count(), 2);
+        assert_eq!(image.get_pixels(), &[1, 0, 0, 0]);
+        assert_eq!(image.get_palette(), &[RED, BLUE]);
+        assert_eq!(image.get_per_frame(), 0.5);
+        assert_eq!(image.play_type(), Once);
+
+        builder.add_frame(&[0]);
+        assert!(matches!(builder.build(), Err(InvalidFrameSize(2, 1, 2))));
+        let mut builder = AnimatedIndexedImageBuilder::new(1, 1);
+        builder.palette(&[RED]).add_frame(&[1]);
+        assert!(matches!(builder.build(), Err(PaletteTooFewColors(1))));
+    }
+
     #[test]
     fn once() {
         let mut image = AnimatedIndexedImage::new(
@@ -989,4 +2591,268 @@ mod test {
         assert!(!image.animating());
         assert_eq!(image.get_current_frame_pixels(), &[0]);
     }
+
+    #[test]
+    fn transforms() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.5,
+            2,
+            vec![RED, BLUE, GREEN],
+            vec![0, 1, 2, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        image.set_metadata("author", "test").unwrap();
+        image.skip_to_next_frame();
+        image.update(0.0);
+
+        let scaled = image.scale(Scaling::nn_double()).unwrap();
+        assert_eq!(scaled.size(), (4, 2));
+        assert_eq!(
+            scaled.frame(1).unwrap(),
+            image.frame(1).unwrap().scale(Scaling::nn_double()).unwrap()
+        );
+        assert_eq!(
+            scaled.get_current_frame_pixels(),
+            scaled.get_frame_pixels(1).unwrap()
+        );
+        assert_eq!(scaled.metadata(), image.metadata());
+
+        let rotated = image.rotate_cw();
+        assert_eq!(rotated.size(), (1, 2));
+        assert_eq!(rotated.get_pixels(), &[0, 1, 2, 0]);
+        assert_eq!(rotated.rotate_ccw(), image);
+        assert_eq!(image.flip_horizontal().unwrap().get_pixels(), &[1, 0, 0, 2]);
+        assert_eq!(image.flip_vertical().unwrap(), image);
+        assert!(image
+            .rotate_cw()
+            .scale(Scaling::nearest_neighbour(1, 200).unwrap())
+            .is_err());
+
+        let wrapper = IndexedWrapper::from(image.clone());
+        assert_eq!(wrapper.rotate_cw(), IndexedWrapper::from(rotated));
+        assert_eq!(
+            wrapper.flip_horizontal().unwrap().get_pixels(),
+            &[1, 0, 0, 2]
+        );
+    }
+
+    #[test]
+    fn seek() {
+        for play_type in [
+            Once,
+            OnceReversed,
+            Loops,
+            LoopsReversed,
+            LoopsBoth,
+            OnceBoth,
+        ] {
+            let mut image = AnimatedIndexedImage::new(
+                1,
+                1,
+                0.25,
+                3,
+                vec![RED, GREEN, BLUE],
+                vec![0, 1, 2],
+                play_type,
+            )
+            .unwrap();
+            image.reset();
+            let mut expected = image.clone();
+            expected.set_animate(true);
+            // updating in small steps is close to continuous, away from the frame changes
+            for i in 1..=300 {
+                expected.update(0.01);
+                if i % 10 != 3 {
+                    continue;
+                }
+                image.set_animate(true);
+                image.seek(i as f64 * 0.01);
+                assert_eq!(
+                    image.get_current_frame_pixels(),
+                    expected.get_current_frame_pixels(),
+                    "{play_type:?} at {i}"
+                );
+                assert_eq!(image.animating(), expected.animating());
+                if !expected.animating() {
+                    break;
+                }
+            }
+        }
+
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 0.5, 3, vec![RED, GREEN, BLUE], vec![0, 1, 2], Loops)
+                .unwrap();
+        image.seek(1000.25);
+        assert_eq!(image.get_current_frame_pixels(), &[2]);
+        image.set_animate(false);
+        image.seek(0.6);
+        assert_eq!(image.get_current_frame_pixels(), &[1]);
+        assert!(!image.animating());
+    }
+
+    #[test]
+    fn validate() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        assert_eq!(image.debug_validate(), Ok(()));
+        image.pixels[1] = 3;
+        image.current_frame = 2;
+        image.frame_size = 3;
+        assert_eq!(
+            image.debug_validate(),
+            Err(vec![
+                InvariantViolation::FrameSize(3, 2),
+                InvariantViolation::CurrentFrame(2, 2),
+                InvariantViolation::PixelsOutsidePalette(1, 1, 2),
+                InvariantViolation::HighestPaletteIdx(1, 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn current_frame() {
+        let mut image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            3,
+            vec![RED, GREEN, BLUE],
+            vec![0, 1, 2],
+            PlayType::Loops,
+        )
+        .unwrap();
+        assert_eq!(image.get_current_frame(), 0);
+        image.set_current_frame(2).unwrap();
+        assert_eq!(image.get_current_frame(), 2);
+        assert_eq!(image.get_current_frame_pixels(), &[2]);
+        image.update(0.05);
+        assert_eq!(image.get_current_frame(), 2);
+        assert!(image.set_current_frame(3).is_err());
+        assert_eq!(image.get_current_frame(), 2);
+    }
+
+    #[test]
+    fn events() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED, GREEN], vec![0, 1], PlayType::Once)
+                .unwrap();
+        image.set_animate(true);
+        assert_eq!(image.update_with_events(0.15), None);
+        assert_eq!(
+            image.update_with_events(0.1),
+            Some(AnimationEvent::FrameChanged(1))
+        );
+        assert_eq!(image.update_with_events(0.0), None);
+        image.update(0.1);
+        assert_eq!(
+            image.update_with_events(0.1),
+            Some(AnimationEvent::Completed)
+        );
+        assert!(!image.animating());
+
+        image.set_play_type(PlayType::LoopsReversed);
+        image.skip_to_next_frame();
+        assert_eq!(
+            image.update_with_events(0.0),
+            Some(AnimationEvent::Looped(1))
+        );
+    }
+
+    #[test]
+    fn once_both() {
+        let mut image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            3,
+            vec![RED, GREEN, BLUE],
+            vec![0, 1, 2],
+            OnceBoth,
+        )
+        .unwrap();
+        assert!(!image.animating());
+        image.set_animate(true);
+        let mut frames = vec![image.get_current_frame()];
+        while image.animating() {
+            image.skip_to_next_frame();
+            image.update(0.0);
+            frames.push(image.get_current_frame());
+        }
+        assert_eq!(frames, vec![0, 1, 2, 2, 1, 0, 0]);
+        assert_eq!(PlayType::from_byte(OnceBoth.to_byte()), Some(OnceBoth));
+    }
+
+    #[test]
+    fn prune_unused_colors() {
+        let mut image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            2,
+            vec![RED, BLUE, GREEN],
+            vec![2, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        assert_eq!(image.prune_unused_colors(), 1);
+        assert_eq!(image.get_palette(), &[RED, GREEN]);
+        assert_eq!(image.get_pixels(), &[1, 0]);
+    }
+
+    #[test]
+    fn read_errors() {
+        let image = AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED], vec![0, 0], PlayType::Loops)
+            .unwrap();
+        let mut bytes = image.to_file_contents(&FilePalette::NoData).unwrap();
+        // header, palette and size
+        bytes[8] = 9;
+        assert_eq!(
+            AnimatedIndexedImage::from_file_contents(&bytes).unwrap_err(),
+            InvalidValue {
+                section: FileSection::Animation,
+                offset: 8,
+                what: "play type".to_string(),
+                expected: "0..=5".to_string(),
+                found: "9".to_string(),
+            }
+        );
+        bytes[8] = PlayType::Loops.to_byte();
+        let error = AnimatedIndexedImage::from_file_contents(&bytes[..19]).unwrap_err();
+        assert_eq!(error.section(), Some(FileSection::Pixels));
+        assert_eq!(error.offset(), Some(18));
+    }
+
+    #[test]
+    fn set_pixel() {
+        let mut image =
+            AnimatedIndexedImage::new(2, 1, 0.1, 2, vec![RED, GREEN], vec![0; 4], Loops).unwrap();
+        image.set_pixel(1, 1, 1).unwrap();
+        assert_eq!(
+            image.set_pixel(1, 0, 2),
+            Err(IndexOutOfRange(2, 2, "palette"))
+        );
+        assert_eq!(
+            image.set_pixel(2, 0, 0),
+            Err(IndexOutOfRange(2, 2, "frames"))
+        );
+        assert_eq!(image.get_pixels(), &[0, 0, 0, 1]);
+        assert_eq!(image.min_palette_size_supported(), 1);
+        unsafe { image.set_pixel_unchecked(0, 1, 1) };
+        assert_eq!(image.get_pixels(), &[0, 1, 0, 1]);
+        image.set_pixel(0, 1, 0).unwrap();
+        image.set_pixel(1, 1, 0).unwrap();
+        assert_eq!(image.min_palette_size_supported(), 0);
+        image.set_palette(&[BLUE]).unwrap();
+    }
 }
@@ -0,0 +1,235 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const NORTH: u8 = 1;
+const NORTH_EAST: u8 = 2;
+const EAST: u8 = 4;
+const SOUTH_EAST: u8 = 8;
+const SOUTH: u8 = 16;
+const SOUTH_WEST: u8 = 32;
+const WEST: u8 = 64;
+const NORTH_WEST: u8 = 128;
+
+/// Standard auto tiling layouts
+///
+/// Tiles are expected in [TileSet] order (left to right, top to bottom)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AutoTileLayout {
+    /// 16 tiles, the tile index is the bitmask of neighbouring terrain
+    /// north = 1, east = 2, south = 4, west = 8
+    Wang16,
+    /// 47 tiles, uses the bitmask of all 8 neighbours
+    /// north = 1, north east = 2, east = 4, south east = 8, south = 16, south west = 32, west = 64, north west = 128
+    /// corners are only counted if both adjacent sides are terrain, see [blob_masks] for the tile order
+    Blob47,
+}
+
+impl AutoTileLayout {
+    pub fn tile_count(&self) -> usize {
+        match self {
+            AutoTileLayout::Wang16 => 16,
+            AutoTileLayout::Blob47 => 47,
+        }
+    }
+}
+
+/// The 47 possible masks for [AutoTileLayout::Blob47] in ascending order, the position is the tile index
+pub fn blob_masks() -> Vec<u8> {
+    (0..=255_u8)
+        .filter(|mask| reduce_corners(*mask) == *mask)
+        .collect()
+}
+
+/// Remove corners that don't have both adjacent sides set
+fn reduce_corners(mut mask: u8) -> u8 {
+    let corners = [
+        (NORTH_EAST, NORTH, EAST),
+        (SOUTH_EAST, SOUTH, EAST),
+        (SOUTH_WEST, SOUTH, WEST),
+        (NORTH_WEST, NORTH, WEST),
+    ];
+    for (corner, a, b) in corners {
+        if mask & a == 0 || mask & b == 0 {
+            mask &= !corner;
+        }
+    }
+    mask
+}
+
+/// Calculate the tile index for every cell of `terrain` (`width` cells wide, left to right, top to bottom)
+///
+/// Cells that aren't terrain are `None`, cells outside the grid count as not terrain
+pub fn tile_indexes(
+    terrain: &[bool],
+    width: usize,
+    layout: AutoTileLayout,
+) -> Result<Vec<Option<usize>>, IndexedImageError> {
+    let height = terrain_height(terrain, width)?;
+    let is_terrain = |x: isize, y: isize| {
+        x >= 0
+            && y >= 0
+            && (x as usize) < width
+            && (y as usize) < height
+            && terrain[x as usize + y as usize * width]
+    };
+    let masks = blob_masks();
+    let mut output = Vec::with_capacity(terrain.len());
+    for (i, cell) in terrain.iter().enumerate() {
+        if !cell {
+            output.push(None);
+            continue;
+        }
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        let neighbours = [
+            (NORTH, 0, -1),
+            (NORTH_EAST, 1, -1),
+            (EAST, 1, 0),
+            (SOUTH_EAST, 1, 1),
+            (SOUTH, 0, 1),
+            (SOUTH_WEST, -1, 1),
+            (WEST, -1, 0),
+            (NORTH_WEST, -1, -1),
+        ];
+        let mask = neighbours
+            .iter()
+            .filter(|(_, dx, dy)| is_terrain(x + dx, y + dy))
+            .fold(0, |acc, (bit, _, _)| acc | bit);
+        let idx = match layout {
+            AutoTileLayout::Wang16 => {
+                let mut idx = 0;
+                for (i, side) in [NORTH, EAST, SOUTH, WEST].iter().enumerate() {
+                    if mask & side != 0 {
+                        idx |= 1 << i;
+                    }
+                }
+                idx
+            }
+            AutoTileLayout::Blob47 => {
+                let reduced = reduce_corners(mask);
+                masks.iter().position(|m| *m == reduced).unwrap_or(0)
+            }
+        };
+        output.push(Some(idx));
+    }
+    Ok(output)
+}
+
+/// Create a [TileMap] for `terrain`, cells that aren't terrain will use `empty_tile`
+///
+/// See [tile_indexes]
+pub fn build_map(
+    terrain: &[bool],
+    width: usize,
+    layout: AutoTileLayout,
+    empty_tile: usize,
+) -> Result<TileMap, IndexedImageError> {
+    let height = terrain_height(terrain, width)?;
+    let cells = tile_indexes(terrain, width, layout)?
+        .into_iter()
+        .map(|idx| TileRef::new(idx.unwrap_or(empty_tile)))
+        .collect();
+    TileMap::new(width, height, cells)
+}
+
+/// Draw `terrain` using `tileset`, which must contain at least [AutoTileLayout::tile_count] tiles
+///
+/// See [build_map]
+pub fn render(
+    tileset: &TileSet,
+    terrain: &[bool],
+    width: usize,
+    layout: AutoTileLayout,
+    empty_tile: usize,
+) -> Result<IndexedImage, IndexedImageError> {
+    if tileset.tile_count() < layout.tile_count() {
+        return Err(TooFewTiles(tileset.tile_count(), layout.tile_count()));
+    }
+    let map = build_map(terrain, width, layout, empty_tile)?;
+    tileset.render(&map)
+}
+
+fn terrain_height(terrain: &[bool], width: usize) -> Result<usize, IndexedImageError> {
+    if width == 0 {
+        return Err(WidthIsZero);
+    }
+    if terrain.is_empty() {
+        return Err(HeightIsZero);
+    }
+    if !terrain.len().is_multiple_of(width) {
+        return Err(MissingData(
+            terrain.len(),
+            terrain.len().div_ceil(width) * width,
+        ));
+    }
+    Ok(terrain.len() / width)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blob_mask_count() {
+        let masks = blob_masks();
+        assert_eq!(masks.len(), 47);
+        assert_eq!(masks[0], 0);
+        assert_eq!(masks[46], 255);
+    }
+
+    #[test]
+    fn wang() {
+        #[rustfmt::skip]
+        let terrain = [
+            false, true, false,
+            true, true, true,
+            false, false, false,
+        ];
+        let output = tile_indexes(&terrain, 3, AutoTileLayout::Wang16).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                None,
+                Some(4),
+                None,
+                Some(2),
+                Some(1 + 2 + 8),
+                Some(8),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn blob() {
+        let terrain = [true; 9];
+        let output = tile_indexes(&terrain, 3, AutoTileLayout::Blob47).unwrap();
+        let masks = blob_masks();
+        assert_eq!(output[4], Some(46));
+        assert_eq!(output[0].map(|i| masks[i]), Some(EAST | SOUTH_EAST | SOUTH));
+        let output = tile_indexes(&[true, true], 2, AutoTileLayout::Blob47).unwrap();
+        assert_eq!(output[0].map(|i| masks[i]), Some(EAST));
+    }
+
+    #[test]
+    fn render_terrain() {
+        let sheet = IndexedImage::new(16, 1, vec![RED; 16], (0..16).collect()).unwrap();
+        let tileset = TileSet::from_image(&sheet, 1, 1).unwrap();
+        let image = render(
+            &tileset,
+            &[true, true, false, true],
+            2,
+            AutoTileLayout::Wang16,
+            0,
+        )
+        .unwrap();
+        assert_eq!(image.get_pixels(), &[2, 12, 0, 1]);
+        assert!(render(&tileset, &[true], 1, AutoTileLayout::Blob47, 0).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Bevy asset loaders
+//!
+//! Add [IciPlugin] to the app, then `.ici` files can be loaded as [Image]s and `.ica` files as [IciAnimation]s
+//!
+//! ```ignore
+//! let image: Handle<Image> = asset_server.load("player.ici");
+//! let animation: Handle<IciAnimation> = asset_server.load("player.ica");
+//! // once loaded
+//! let animation = animations.get(&animation).unwrap();
+//! Sprite::from_atlas_image(animation.image.clone(), TextureAtlas::from(animation.layout.clone()));
+//! ```
+//!
+//! Images use nearest neighbour sampling
+
+use crate::image::indexes_to_rgba;
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+use bevy_app::{App, Plugin};
+use bevy_asset::io::Reader;
+use bevy_asset::{Asset, AssetApp, Handle, LoadContext, RenderAssetUsages};
+use bevy_image::{Image, ImageSampler, TextureAtlasLayout};
+use bevy_math::UVec2;
+use bevy_reflect::TypePath;
+use wgpu_types::{Extent3d, TextureDimension, TextureFormat};
+
+/// Registers [IciImageLoader], [IciAnimationLoader] and [IciAnimation]
+///
+/// Bevy's `ImagePlugin` (part of `DefaultPlugins`) must also be added
+#[derive(Debug, Default)]
+pub struct IciPlugin;
+
+impl Plugin for IciPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<IciAnimation>()
+            .init_asset_loader::<IciImageLoader>()
+            .init_asset_loader::<IciAnimationLoader>();
+    }
+}
+
+/// Animation loaded from an `.ica` file, the frames are in a grid in `image` and `layout` has one cell per frame
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct IciAnimation {
+    #[dependency]
+    pub image: Handle<Image>,
+    #[dependency]
+    pub layout: Handle<TextureAtlasLayout>,
+    pub frame_count: u16,
+    /// Seconds per frame
+    pub per_frame: f64,
+    pub play_type: PlayType,
+    /// Named frame ranges, see [AnimatedIndexedImage::tags]
+    pub tags: BTreeMap<String, FrameTag>,
+}
+
+/// Loads `.ici` files as [Image]s
+#[derive(Debug, Default, TypePath)]
+pub struct IciImageLoader;
+
+impl bevy_asset::AssetLoader for IciImageLoader {
+    type Asset = Image;
+    type Settings = ();
+    type Error = IndexedImageError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Image, IndexedImageError> {
+        let bytes = read(reader, load_context).await?;
+        let (image, _) = IndexedImage::from_file_contents(&bytes)?;
+        Ok(to_bevy_image(&image))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ici"]
+    }
+}
+
+/// Loads `.ica` files as [IciAnimation]s, the image is labeled `image` and the layout `layout`
+#[derive(Debug, Default, TypePath)]
+pub struct IciAnimationLoader;
+
+impl bevy_asset::AssetLoader for IciAnimationLoader {
+    type Asset = IciAnimation;
+    type Settings = ();
+    type Error = IndexedImageError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<IciAnimation, IndexedImageError> {
+        let bytes = read(reader, load_context).await?;
+        let (animation, _) = AnimatedIndexedImage::from_file_contents(&bytes)?;
+        let (image, layout) = to_bevy_atlas(&animation);
+        Ok(IciAnimation {
+            image: load_context.add_labeled_asset("image".to_string(), image),
+            layout: load_context.add_labeled_asset("layout".to_string(), layout),
+            frame_count: animation.frame_count(),
+            per_frame: animation.get_per_frame(),
+            play_type: animation.play_type(),
+            tags: animation.tags().clone(),
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ica"]
+    }
+}
+
+async fn read(
+    reader: &mut dyn Reader,
+    load_context: &LoadContext<'_>,
+) -> Result<Vec<u8>, IndexedImageError> {
+    let mut bytes = vec![];
+    reader
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|e| IndexedImageError::FileIo(load_context.path().to_string(), e.to_string()))?;
+    Ok(bytes)
+}
+
+fn rgba_image(width: u32, height: u32, rgba: Vec<u8>) -> Image {
+    let mut image = Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        rgba,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+/// Convert to a bevy image
+pub fn to_bevy_image(image: &IndexedImage) -> Image {
+    rgba_image(
+        image.width() as u32,
+        image.height() as u32,
+        image.to_rgba_bytes(),
+    )
+}
+
+/// Convert to a bevy image with the frames in a grid, and a layout with one cell per frame
+///
+/// The grid is as close to square as possible (so long animations stay within GPU texture size limits),
+/// frames are placed left to right then top to bottom and any unused cells are transparent
+pub fn to_bevy_atlas(animation: &AnimatedIndexedImage) -> (Image, TextureAtlasLayout) {
+    let (width, height) = (animation.width() as u32, animation.height() as u32);
+    let frames = animation.frame_count() as u32;
+    let (columns, rows) = grid_size(width, height, frames);
+    let atlas_width = (width * columns) as usize;
+    let row_len = width as usize * 4;
+    let mut rgba = vec![0; atlas_width * (height * rows) as usize * 4];
+    for frame in 0..frames {
+        let pixels = animation.get_frame_pixels(frame as u16).unwrap_or_default();
+        let frame_rgba = indexes_to_rgba(animation.get_palette(), pixels, false);
+        let (x, y) = ((frame % columns) * width, (frame / columns) * height);
+        for (row, src) in frame_rgba.chunks_exact(row_len).enumerate() {
+            let start = ((y as usize + row) * atlas_width + x as usize) * 4;
+            rgba[start..start + row_len].copy_from_slice(src);
+        }
+    }
+    let image = rgba_image(width * columns, height * rows, rgba);
+    let mut layout =
+        TextureAtlasLayout::from_grid(UVec2::new(width, height), columns, rows, None, None);
+    layout.textures.truncate(frames as usize);
+    (image, layout)
+}
+
+/// Columns and rows of a grid with at least `frames` cells of `width`x`height` that's close to square
+fn grid_size(width: u32, height: u32, frames: u32) -> (u32, u32) {
+    let columns = (frames as f64 * height as f64 / width as f64).sqrt().ceil() as u32;
+    let columns = columns.clamp(1, frames.max(1));
+    (columns, frames.div_ceil(columns))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn atlas() {
+        let animation = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            3,
+            vec![RED, BLUE],
+            vec![0, 1, 1, 0, 0, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let (image, layout) = to_bevy_atlas(&animation);
+        assert_eq!((image.width(), image.height()), (4, 2));
+        let (r, b, t) = ([255, 0, 0, 255], [0, 0, 255, 255], [0; 4]);
+        assert_eq!(
+            image.data.as_deref(),
+            Some([r, b, b, r, r, r, t, t].concat().as_slice())
+        );
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout.textures[1].min, UVec2::new(2, 0));
+        assert_eq!(layout.textures[2].min, UVec2::new(0, 1));
+
+        let image = to_bevy_image(&animation.get_frame(1).unwrap());
+        assert_eq!(image.width(), 2);
+        assert_eq!(
+            image.data.as_deref(),
+            Some([0, 0, 255, 255, 255, 0, 0, 255].as_slice())
+        );
+    }
+
+    #[test]
+    fn many_frame_atlas() {
+        let animation = AnimatedIndexedImage::new(
+            64,
+            64,
+            0.1,
+            300,
+            vec![RED, BLUE],
+            (0..300).flat_map(|i| [(i % 2) as u8; 64 * 64]).collect(),
+            PlayType::Loops,
+        )
+        .unwrap();
+        let (image, layout) = to_bevy_atlas(&animation);
+        assert!(image.width() <= 8192 && image.height() <= 8192);
+        assert_eq!((image.width(), image.height()), (64 * 18, 64 * 17));
+        assert_eq!(layout.len(), 300);
+        assert_eq!(layout.textures[299].min, UVec2::new(64 * 11, 64 * 16));
+        let data = image.data.unwrap();
+        let pixel = |x: usize, y: usize| &data[(y * 64 * 18 + x) * 4..(y * 64 * 18 + x) * 4 + 4];
+        assert_eq!(pixel(64 * 11, 64 * 16), &[0, 0, 255, 255]);
+        assert_eq!(pixel(64 * 12, 64 * 16), &[0, 0, 0, 0]);
+    }
+}
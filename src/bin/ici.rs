@@ -0,0 +1,441 @@
+//! Command line tool for inspecting and converting ICI files
+//!
+//! Build with `cargo install ici-files --features cli`
+
+use clap::{Parser, Subcommand, ValueEnum};
+use gif::{DisposalMethod, Repeat};
+use ici_files::export::{rgba_to_png, to_png_bytes};
+use ici_files::file::{describe, verify_format_version, FileType};
+use ici_files::import::{from_png_bytes, from_rgba_with_palette};
+use ici_files::palette::FilePalette;
+use ici_files::prelude::*;
+use ici_files::quantize::quantize_rgba;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+/// Used when importing GIFs without a frame delay
+const DEFAULT_PER_FRAME: f64 = 0.1;
+
+#[derive(Debug, Parser)]
+#[command(name = "ici", version, about = "Inspect and convert ICI files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the header, palette, size and frames of a file
+    Inspect {
+        file: PathBuf,
+        /// Print every field with its offset
+        #[arg(long)]
+        bytes: bool,
+    },
+    /// Convert between png, gif, ici and ica, the output format is picked by extension
+    ///
+    /// Animations are saved as PNGs as a horizontal strip of frames
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Max palette size when importing PNGs or GIFs
+        #[arg(long, default_value_t = 256)]
+        colors: usize,
+    },
+    /// Replace the palette with colors from a JASC palette file
+    Repalette {
+        input: PathBuf,
+        palette: PathBuf,
+        output: PathBuf,
+        /// Replace colors by index instead of using the closest color for each pixel
+        #[arg(long)]
+        exact: bool,
+    },
+    /// Scale an image or animation
+    Scale {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long, value_enum, default_value_t = Algo::Nearest)]
+        algo: Algo,
+        /// Multiplier for nearest neighbour
+        #[arg(long, default_value_t = 2)]
+        factor: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Algo {
+    Nearest,
+    Epx2x,
+    Epx4x,
+    Scale2x,
+    Scale3x,
+    Eagle,
+    #[cfg(feature = "hq2x")]
+    Hq2x,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Inspect { file, bytes } => inspect(&file, bytes),
+        Command::Convert {
+            input,
+            output,
+            colors,
+        } => load(&input, colors).and_then(|image| save(&output, &image)),
+        Command::Repalette {
+            input,
+            palette,
+            output,
+            exact,
+        } => load(&input, 256)
+            .and_then(|image| repalette(image, &palette, exact))
+            .and_then(|image| save(&output, &image)),
+        Command::Scale {
+            input,
+            output,
+            algo,
+            factor,
+        } => load(&input, 256)
+            .and_then(|image| scale(&image, algo, factor))
+            .and_then(|image| save(&output, &image)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn read(path: &Path) -> CliResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+fn inspect(path: &Path, bytes: bool) -> CliResult<()> {
+    let contents = read(path)?;
+    if bytes {
+        println!("{}", describe(&contents));
+        return Ok(());
+    }
+    let (version, file_type) = verify_format_version(&contents)?;
+    println!("Type: {} (.{})", file_type.name(), file_type.ext());
+    println!("Version: {version}");
+    if file_type == FileType::Bundle {
+        let (bundle, file_palette) = IciBundle::from_file_contents(&contents)?;
+        println!(
+            "Palette: {} ({} colors)",
+            palette_name(&file_palette),
+            bundle.get_palette().len()
+        );
+        println!("Entries: {}", bundle.len());
+        for (name, image) in bundle.iter() {
+            println!(
+                "  {name}: {}x{}, {} frames",
+                image.width(),
+                image.height(),
+                image.frame_count()
+            );
+        }
+        return Ok(());
+    }
+    let (image, file_palette) = IndexedWrapper::from_file_contents(&contents)?;
+    println!(
+        "Palette: {} ({} colors)",
+        palette_name(&file_palette),
+        image.get_palette().len()
+    );
+    println!("Size: {}x{}", image.width(), image.height());
+    if let IndexedWrapper::Animated(animation) = &image {
+        println!("Frames: {}", animation.frame_count());
+        println!("Frame time: {}s", animation.get_per_frame());
+        println!("Play type: {:?}", animation.play_type());
+    }
+    let metadata = match &image {
+        IndexedWrapper::Static(img) => img.metadata(),
+        IndexedWrapper::Animated(img) => img.metadata(),
+    };
+    for (key, value) in metadata {
+        println!("{key}: {value}");
+    }
+    Ok(())
+}
+
+fn palette_name(palette: &FilePalette) -> String {
+    match palette {
+        FilePalette::NoData => "none".to_string(),
+        FilePalette::ID(id) => format!("id {id}"),
+        FilePalette::Name(name) => format!("name '{name}'"),
+        FilePalette::Colors => "colors".to_string(),
+    }
+}
+
+/// Read an ICI, PNG or GIF file, the format is detected from the contents
+fn load(path: &Path, colors: usize) -> CliResult<IndexedWrapper> {
+    let bytes = read(path)?;
+    if bytes.starts_with(b"ICI") {
+        Ok(IndexedWrapper::from_file_contents(&bytes)?.0)
+    } else if bytes.starts_with(b"GIF8") {
+        decode_gif(&bytes, colors)
+    } else {
+        Ok(from_png_bytes(&bytes, colors, Dithering::None)?.into())
+    }
+}
+
+/// Write an image as ICI, ICA, PNG or GIF depending on the extension of `path`
+fn save(path: &Path, image: &IndexedWrapper) -> CliResult<()> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let bytes = match (ext.as_deref(), image) {
+        (Some("ici"), IndexedWrapper::Static(img)) => img.to_file_contents(&FilePalette::Colors)?,
+        (Some("ici"), IndexedWrapper::Animated(_)) => {
+            return Err("animations must be saved as .ica, .gif or .png".into())
+        }
+        (Some("ica"), IndexedWrapper::Static(img)) => {
+            single_frame(img)?.to_file_contents(&FilePalette::Colors)?
+        }
+        (Some("ica"), IndexedWrapper::Animated(img)) => {
+            img.to_file_contents(&FilePalette::Colors)?
+        }
+        (Some("png"), IndexedWrapper::Static(img)) => to_png_bytes(img)?,
+        (Some("png"), IndexedWrapper::Animated(img)) => {
+            let (width, height) = strip_size(img);
+            rgba_to_png(width, height, &img.strip_rgba())?
+        }
+        (Some("gif"), IndexedWrapper::Static(img)) => encode_gif(&single_frame(img)?)?,
+        (Some("gif"), IndexedWrapper::Animated(img)) => encode_gif(img)?,
+        _ => return Err(format!("unsupported output format: {}", path.display()).into()),
+    };
+    std::fs::write(path, bytes).map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+fn single_frame(image: &IndexedImage) -> CliResult<AnimatedIndexedImage> {
+    Ok(AnimatedIndexedImage::new(
+        image.width(),
+        image.height(),
+        DEFAULT_PER_FRAME,
+        1,
+        image.get_palette().to_vec(),
+        image.get_pixels().to_vec(),
+        PlayType::Once,
+    )?)
+}
+
+fn strip_size(image: &AnimatedIndexedImage) -> (u32, u32) {
+    (
+        image.width() as u32 * image.frame_count() as u32,
+        image.height() as u32,
+    )
+}
+
+/// Decode all frames, drawn on top of each other as a viewer would, and quantize them to one palette
+fn decode_gif(bytes: &[u8], colors: usize) -> CliResult<IndexedWrapper> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(bytes)?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+    if width > 255 || height > 255 {
+        return Err(IndexedImageError::ImageTooLarge(width, height).into());
+    }
+    let play_type = match decoder.repeat() {
+        Repeat::Infinite => PlayType::Loops,
+        Repeat::Finite(_) => PlayType::Once,
+    };
+    let mut canvas = vec![0; width * height * 4];
+    let mut frames = vec![];
+    let mut per_frame = None;
+    while let Some(frame) = decoder.read_next_frame()? {
+        if per_frame.is_none() && frame.delay > 0 {
+            per_frame = Some(frame.delay as f64 / 100.0);
+        }
+        let previous = canvas.clone();
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let (frame_width, frame_height) = (frame.width as usize, frame.height as usize);
+        let in_canvas = |x: usize, y: usize| left + x < width && top + y < height;
+        for y in 0..frame_height {
+            for x in 0..frame_width {
+                let src = (y * frame_width + x) * 4;
+                if in_canvas(x, y) && frame.buffer[src + 3] > 0 {
+                    let dst = ((top + y) * width + left + x) * 4;
+                    canvas[dst..dst + 4].copy_from_slice(&frame.buffer[src..src + 4]);
+                }
+            }
+        }
+        frames.push(canvas.clone());
+        match frame.dispose {
+            DisposalMethod::Background => {
+                for y in 0..frame_height {
+                    for x in 0..frame_width {
+                        if in_canvas(x, y) {
+                            let dst = ((top + y) * width + left + x) * 4;
+                            canvas[dst..dst + 4].fill(0);
+                        }
+                    }
+                }
+            }
+            DisposalMethod::Previous => canvas = previous,
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+    }
+    if frames.is_empty() {
+        return Err(IndexedImageError::NoFrames.into());
+    }
+    let palette = quantize_rgba(&frames.concat(), colors);
+    let frames = frames
+        .iter()
+        .map(|rgba| {
+            from_rgba_with_palette(width as u8, height as u8, rgba, &palette, Dithering::None)
+        })
+        .collect::<Result<Vec<IndexedImage>, IndexedImageError>>()?;
+    if frames.len() == 1 {
+        return Ok(frames[0].clone().into());
+    }
+    let pixels: Vec<u8> = frames
+        .iter()
+        .flat_map(|f| f.get_pixels().to_vec())
+        .collect();
+    Ok(AnimatedIndexedImage::new(
+        width as u8,
+        height as u8,
+        per_frame.unwrap_or(DEFAULT_PER_FRAME),
+        frames.len() as u16,
+        frames[0].get_palette().to_vec(),
+        pixels,
+        play_type,
+    )?
+    .into())
+}
+
+/// GIFs can only have one transparent color and can't play backwards, so fully transparent colors are merged
+/// and the frames are written in the order they'd be shown
+fn encode_gif(image: &AnimatedIndexedImage) -> CliResult<Vec<u8>> {
+    let palette = image.get_palette();
+    let transparent = palette.iter().position(|c| c.a == 0).map(|i| i as u8);
+    let rgb: Vec<u8> = palette.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    let count = image.frame_count() as usize;
+    let forward: Vec<usize> = (0..count).collect();
+    let backward: Vec<usize> = (0..count).rev().collect();
+    let (order, repeat) = match image.play_type() {
+        PlayType::Once => (forward, Repeat::Finite(0)),
+        PlayType::OnceReversed => (backward, Repeat::Finite(0)),
+        PlayType::Loops => (forward, Repeat::Infinite),
+        PlayType::LoopsReversed => (backward, Repeat::Infinite),
+        PlayType::LoopsBoth => (
+            forward
+                .iter()
+                .chain(backward.iter().skip(1).take(count.saturating_sub(2)))
+                .copied()
+                .collect(),
+            Repeat::Infinite,
+        ),
+        PlayType::OnceBoth => (
+            forward
+                .iter()
+                .chain(backward.iter().skip(1))
+                .copied()
+                .collect(),
+            Repeat::Finite(0),
+        ),
+    };
+    let delay = (image.get_per_frame() * 100.0).round() as u16;
+    let frames = image.as_images();
+    let mut bytes = vec![];
+    {
+        let mut encoder = gif::Encoder::new(
+            &mut bytes,
+            image.width() as u16,
+            image.height() as u16,
+            &rgb,
+        )?;
+        encoder.set_repeat(repeat)?;
+        for idx in order {
+            let pixels: Vec<u8> = frames[idx]
+                .get_pixels()
+                .iter()
+                .map(|i| match transparent {
+                    Some(t) if palette.get(*i).is_some_and(|c| c.a == 0) => t,
+                    _ => *i,
+                })
+                .collect();
+            let mut frame = gif::Frame::from_indexed_pixels(
+                image.width() as u16,
+                image.height() as u16,
+                pixels,
+                transparent,
+            );
+            frame.delay = delay;
+            frame.dispose = DisposalMethod::Background;
+            encoder.write_frame(&frame)?;
+        }
+    }
+    Ok(bytes)
+}
+
+fn repalette(image: IndexedWrapper, path: &Path, exact: bool) -> CliResult<IndexedWrapper> {
+    let text = String::from_utf8(read(path)?)?;
+    let colors = JascPalette::from_file_contents(&text)?.colors;
+    if exact {
+        let mut image = image;
+        image.set_palette(&colors)?;
+        return Ok(image);
+    }
+    match image {
+        IndexedWrapper::Static(img) => Ok(img.remap_to_palette(&colors, Dithering::None)?.into()),
+        IndexedWrapper::Animated(img) => Ok(img.remap_to_palette(&colors, Dithering::None)?.into()),
+    }
+}
+
+fn scale(image: &IndexedWrapper, algo: Algo, factor: usize) -> CliResult<IndexedWrapper> {
+    let scaling = match algo {
+        Algo::Nearest => Scaling::nearest_neighbour(factor, factor)?,
+        Algo::Epx2x => Scaling::Epx2x,
+        Algo::Epx4x => Scaling::Epx4x,
+        Algo::Scale2x => Scaling::Scale2x,
+        Algo::Scale3x => Scaling::Scale3x,
+        Algo::Eagle => Scaling::Eagle,
+        #[cfg(feature = "hq2x")]
+        Algo::Hq2x => Scaling::Hq2x,
+    };
+    Ok(image.scale(scaling)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gif_round_trip() {
+        let animation = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.2,
+            2,
+            vec![RED, TRANSPARENT, BLUE],
+            vec![0, 1, 2, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let bytes = encode_gif(&animation).unwrap();
+        let IndexedWrapper::Animated(decoded) = decode_gif(&bytes, 256).unwrap() else {
+            panic!("expected animation");
+        };
+        assert_eq!(decoded.frame_count(), 2);
+        assert_eq!(decoded.get_per_frame(), 0.2);
+        assert_eq!(decoded.play_type(), PlayType::Loops);
+        assert_eq!(
+            decoded.as_images()[0].to_rgba_bytes(),
+            vec![255, 0, 0, 255, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            decoded.as_images()[1].to_rgba_bytes(),
+            vec![0, 0, 255, 255, 255, 0, 0, 255]
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Brush stamps and strokes for drawing tools
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+use core::ops::RangeInclusive;
+
+/// Shape drawn by [IndexedImage::apply_brush]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Brush<'a> {
+    /// Circle with radius, 0 is a single pixel
+    Circle(u8),
+    /// Square with side length, centered on the position (rounded up and left for even sizes)
+    Square(u8),
+    /// Visible (not fully transparent) pixels of the image, centered on the position
+    Custom(&'a IndexedImage),
+}
+
+impl Brush<'_> {
+    /// Offsets from the brush position of every pixel drawn
+    fn offsets(&self) -> Vec<(isize, isize)> {
+        match self {
+            Brush::Circle(radius) => {
+                let radius = *radius as isize;
+                (-radius..=radius)
+                    .flat_map(|y| (-radius..=radius).map(move |x| (x, y)))
+                    .filter(|(x, y)| x * x + y * y <= radius * radius)
+                    .collect()
+            }
+            Brush::Square(size) => {
+                let size = *size as isize;
+                let start = -(size / 2);
+                (start..start + size)
+                    .flat_map(|y| (start..start + size).map(move |x| (x, y)))
+                    .collect()
+            }
+            Brush::Custom(image) => {
+                let (width, height) = (image.width() as isize, image.height() as isize);
+                image
+                    .get_pixels()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, idx)| {
+                        image
+                            .get_palette()
+                            .get(**idx)
+                            .map(|color| color.a > 0)
+                            .unwrap_or_default()
+                    })
+                    .map(|(i, _)| {
+                        let (x, y) = (i as isize % width, i as isize / width);
+                        (x - width / 2, y - height / 2)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl IndexedImage {
+    /// Draw `brush` centered at `x`,`y` with `color_idx`, any part outside the image is ignored
+    pub fn apply_brush(
+        &mut self,
+        x: isize,
+        y: isize,
+        brush: Brush,
+        color_idx: u8,
+    ) -> Result<(), IndexedImageError> {
+        self.apply_brush_line((x, y), (x, y), brush, color_idx)
+    }
+
+    /// Draw `brush` at every point on the line from `start` to `end` (inclusive) with `color_idx`,
+    /// for smooth strokes between the positions of successive stamps
+    ///
+    /// Any part outside the image is ignored
+    pub fn apply_brush_line(
+        &mut self,
+        start: (isize, isize),
+        end: (isize, isize),
+        brush: Brush,
+        color_idx: u8,
+    ) -> Result<(), IndexedImageError> {
+        let palette_len = self.get_palette().len();
+        if color_idx as usize >= palette_len {
+            return Err(IndexOutOfRange(color_idx as usize, palette_len, "palette"));
+        }
+        let offsets = brush.offsets();
+        let (Some(min_dx), Some(max_dx), Some(min_dy), Some(max_dy)) = (
+            offsets.iter().map(|(dx, _)| *dx).min(),
+            offsets.iter().map(|(dx, _)| *dx).max(),
+            offsets.iter().map(|(_, dy)| *dy).min(),
+            offsets.iter().map(|(_, dy)| *dy).max(),
+        ) else {
+            return Ok(());
+        };
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let pixels = self.raw_pixels_mut();
+        // only positions where some of the brush is inside the image
+        let x_range = -max_dx..=width - 1 - min_dx;
+        let y_range = -max_dy..=height - 1 - min_dy;
+        for (x, y) in line(start, end, x_range, y_range) {
+            for (dx, dy) in &offsets {
+                let (px, py) = (x + dx, y + dy);
+                if px >= 0 && py >= 0 && px < width && py < height {
+                    pixels[(px + py * width) as usize] = color_idx;
+                }
+            }
+        }
+        self.refresh_highest_palette_idx();
+        Ok(())
+    }
+}
+
+/// Points on the line from `start` to `end` (inclusive) that are within `x_range` and `y_range`
+///
+/// Steps along the longer axis rounding halves away from `start` (like Bresenham's algorithm),
+/// starting and stopping at the ranges so ends far outside them are cheap
+fn line(
+    start: (isize, isize),
+    end: (isize, isize),
+    x_range: RangeInclusive<isize>,
+    y_range: RangeInclusive<isize>,
+) -> impl Iterator<Item = (isize, isize)> {
+    let x_major = start.0.abs_diff(end.0) >= start.1.abs_diff(end.1);
+    let (major, minor, major_range, minor_range) = if x_major {
+        ((start.0, end.0), (start.1, end.1), x_range, y_range)
+    } else {
+        ((start.1, end.1), (start.0, end.0), y_range, x_range)
+    };
+    let major_len = major.0.abs_diff(major.1) as u128;
+    let minor_len = minor.0.abs_diff(minor.1) as u128;
+    let major_step: i128 = if major.0 <= major.1 { 1 } else { -1 };
+    let minor_step: i128 = if minor.0 <= minor.1 { 1 } else { -1 };
+    // steps from start where the major axis is within its range
+    let (low, high) = (
+        *major_range.start() as i128 - major.0 as i128,
+        *major_range.end() as i128 - major.0 as i128,
+    );
+    let (low, high) = if major_step > 0 {
+        (low, high)
+    } else {
+        (-high, -low)
+    };
+    let first = low.max(0) as u128;
+    let end = (high.min(major_len as i128) + 1).max(0) as u128;
+    (first..end).filter_map(move |step| {
+        let offset = (step * minor_len + major_len / 2)
+            .checked_div(major_len)
+            .unwrap_or_default();
+        let major = major.0 as i128 + major_step * step as i128;
+        let minor = minor.0 as i128 + minor_step * offset as i128;
+        let minor = isize::try_from(minor).ok()?;
+        if !minor_range.contains(&minor) {
+            return None;
+        }
+        let major = major as isize;
+        Some(if x_major {
+            (major, minor)
+        } else {
+            (minor, major)
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn brushes() {
+        let mut image = IndexedImage::blank(5, 5, vec![TRANSPARENT, RED, BLUE]);
+        image.apply_brush(2, 2, Brush::Circle(1), 1).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(image.get_pixels(), &[
+            0, 0, 0, 0, 0,
+            0, 0, 1, 0, 0,
+            0, 1, 1, 1, 0,
+            0, 0, 1, 0, 0,
+            0, 0, 0, 0, 0,
+        ]);
+        image.apply_brush(0, 4, Brush::Square(2), 2).unwrap();
+        image.apply_brush(-5, 2, Brush::Square(3), 2).unwrap();
+        let custom = IndexedImage::new(3, 1, vec![WHITE, TRANSPARENT], vec![0, 1, 0]).unwrap();
+        image.apply_brush(4, 0, Brush::Custom(&custom), 2).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(image.get_pixels(), &[
+            0, 0, 0, 2, 0,
+            0, 0, 1, 0, 0,
+            0, 1, 1, 1, 0,
+            2, 0, 1, 0, 0,
+            2, 0, 0, 0, 0,
+        ]);
+        assert_eq!(image.min_palette_size_supported(), 2);
+        assert!(image.apply_brush(0, 0, Brush::Circle(1), 3).is_err());
+
+        let mut image = IndexedImage::blank(5, 3, vec![TRANSPARENT, RED]);
+        image
+            .apply_brush_line((0, 0), (4, 2), Brush::Square(1), 1)
+            .unwrap();
+        #[rustfmt::skip]
+        assert_eq!(image.get_pixels(), &[
+            1, 0, 0, 0, 0,
+            0, 1, 1, 0, 0,
+            0, 0, 0, 1, 1,
+        ]);
+
+        let mut image = IndexedImage::blank(5, 3, vec![TRANSPARENT, RED]);
+        image
+            .apply_brush_line(
+                (isize::MIN, isize::MIN),
+                (isize::MAX, isize::MAX),
+                Brush::Square(1),
+                1,
+            )
+            .unwrap();
+        image
+            .apply_brush_line((-1_000_000_000, 2), (isize::MAX, 2), Brush::Circle(0), 1)
+            .unwrap();
+        image
+            .apply_brush_line((isize::MAX, 1), (isize::MIN, 1), Brush::Circle(0), 1)
+            .unwrap();
+        image
+            .apply_brush_line((4, isize::MIN), (4, -3), Brush::Circle(2), 1)
+            .unwrap();
+        #[rustfmt::skip]
+        assert_eq!(image.get_pixels(), &[
+            1, 0, 0, 0, 0,
+            1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1,
+        ]);
+        image
+            .apply_brush_line((isize::MIN, 0), (isize::MAX, 0), Brush::Square(0), 0)
+            .unwrap();
+        assert_eq!(image.get_pixels()[0], 1);
+    }
+}
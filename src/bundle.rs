@@ -0,0 +1,480 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::file::FileType;
+use crate::file::FileType::{Animated, Bundle, Image};
+use crate::file::{
+    append_checksum, read_header, verify_format_version, wrong_file_type, HEADER, HEADER_V2,
+};
+use crate::image_ref::IndexedImageRef;
+use crate::palette;
+use crate::palette::{FilePalette, Palette};
+use crate::prelude::*;
+use crate::reader::ByteReader;
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+/// Size of each index entry, excluding the name
+const INDEX_ENTRY_SIZE: usize = 1 + 1 + 4 + 4;
+
+/// Multiple named images and animations sharing one palette, stored in a single file
+///
+/// # File format
+/// After the header and palette is the entry count (u16) and an index of
+/// name length (u8), name (UTF-8), file type (u8), offset from start of file (u32) and length (u32) for each entry
+/// Each entry is stored as a complete ICI file without palette data
+#[derive(Debug, Clone, PartialEq)]
+pub struct IciBundle {
+    palette: Palette,
+    entries: BTreeMap<String, IndexedWrapper>,
+}
+
+impl IciBundle {
+    pub fn new(palette: Vec<Color>) -> Result<Self, IndexedImageError> {
+        Ok(IciBundle {
+            palette: Palette::new(palette)?,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Replace the palette for the bundle and every image in it
+    /// Will return an error if the new palette has fewer colors than an image needs, is empty or has more than 256 colors
+    pub fn set_palette(&mut self, palette: &[Color]) -> Result<(), IndexedImageError> {
+        let palette = Palette::try_from(palette)?;
+        if let Some(needed) = self
+            .entries
+            .values()
+            .map(|image| image.min_palette_size_supported())
+            .find(|idx| *idx as usize >= palette.len())
+        {
+            return Err(PaletteTooFewColors(needed));
+        }
+        for image in self.entries.values_mut() {
+            image.set_palette(&palette)?;
+        }
+        self.palette = palette;
+        Ok(())
+    }
+
+    /// Add an image or animation, replacing and returning any existing entry with the same name
+    ///
+    /// The image's palette is replaced with the bundle's, so it must only use indexes within the bundle palette
+    /// `name` must be 1..=255 bytes
+    pub fn insert<I: Into<IndexedWrapper>>(
+        &mut self,
+        name: &str,
+        image: I,
+    ) -> Result<Option<IndexedWrapper>, IndexedImageError> {
+        if name.is_empty() || name.len() > 255 {
+            return Err(InvalidBundleName(name.to_string()));
+        }
+        if !self.entries.contains_key(name) && self.entries.len() >= u16::MAX as usize {
+            return Err(BundleTooManyEntries);
+        }
+        let mut image = image.into();
+        if image.min_palette_size_supported() as usize >= self.palette.len() {
+            return Err(PaletteTooFewColors(image.min_palette_size_supported()));
+        }
+        image.set_palette(&self.palette)?;
+        Ok(self.entries.insert(name.to_string(), image))
+    }
+
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&IndexedWrapper> {
+        self.entries.get(name)
+    }
+
+    /// Changing the palette of the returned image will only affect that image and it will be replaced if
+    /// [IciBundle::set_palette] is called
+    #[inline]
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut IndexedWrapper> {
+        self.entries.get_mut(name)
+    }
+
+    #[inline]
+    pub fn remove(&mut self, name: &str) -> Option<IndexedWrapper> {
+        self.entries.remove(name)
+    }
+
+    /// Entry names in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    /// Entries in alphabetical order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &IndexedWrapper)> {
+        self.entries
+            .iter()
+            .map(|(name, image)| (name.as_str(), image))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl IciBundle {
+    /// Errors will only be returned if you [FilePalette::Name] and the len is invalid or the bundle is over 4GB
+    pub fn to_file_contents(&self, palette: &FilePalette) -> Result<Vec<u8>, IndexedImageError> {
+        let v2 = palette::needs_v2(palette, &self.palette);
+        let mut output = vec![];
+        output.extend_from_slice(if v2 { &HEADER_V2 } else { &HEADER });
+        output.push(Bundle.to_byte());
+
+        palette::write(palette, &self.palette, v2, &mut output)?;
+        output.extend_from_slice(&(self.entries.len() as u16).to_be_bytes());
+
+        let mut data = Vec::with_capacity(self.entries.len());
+        for image in self.entries.values() {
+            data.push(match image {
+                IndexedWrapper::Static(img) => (Image, img.to_file_contents(&FilePalette::NoData)?),
+                IndexedWrapper::Animated(img) => {
+                    (Animated, img.to_file_contents(&FilePalette::NoData)?)
+                }
+            });
+        }
+        let index_len: usize = self
+            .entries
+            .keys()
+            .map(|name| name.len() + INDEX_ENTRY_SIZE)
+            .sum();
+        let mut offset = output.len() + index_len;
+        for (name, (file_type, bytes)) in self.entries.keys().zip(&data) {
+            output.push(name.len() as u8);
+            output.extend_from_slice(name.as_bytes());
+            output.push(file_type.to_byte());
+            let start = u32::try_from(offset).map_err(|_| BundleTooLarge)?;
+            let len = u32::try_from(bytes.len()).map_err(|_| BundleTooLarge)?;
+            output.extend_from_slice(&start.to_be_bytes());
+            output.extend_from_slice(&len.to_be_bytes());
+            offset += bytes.len();
+        }
+        u32::try_from(offset).map_err(|_| BundleTooLarge)?;
+        for (_, bytes) in data {
+            output.extend_from_slice(&bytes);
+        }
+
+        Ok(output)
+    }
+
+    /// Same as `to_file_contents` but a CRC-32 checksum is appended, this is verified when reading
+    pub fn to_file_contents_with_checksum(
+        &self,
+        palette: &FilePalette,
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        let mut output = self.to_file_contents(palette)?;
+        append_checksum(&mut output);
+        Ok(output)
+    }
+
+    /// Create an [IciBundle], palette will be filled with transparency unless file contains colors
+    /// use `bundle.set_palette` to replace the palette
+    ///
+    /// Every entry is decoded, see [LazyBundle] to only decode entries as needed
+    pub fn from_file_contents(bytes: &[u8]) -> Result<(IciBundle, FilePalette), IndexedImageError> {
+        let lazy = LazyBundle::from_file_contents(bytes)?;
+        let mut entries = BTreeMap::new();
+        for (name, image) in lazy.iter() {
+            entries.insert(name.to_string(), image?);
+        }
+
+        let highest = entries
+            .values()
+            .map(|image| image.min_palette_size_supported())
+            .max()
+            .unwrap_or_default() as usize;
+        let colors = match lazy.palette {
+            None => vec![TRANSPARENT; highest + 1],
+            Some(colors) => colors.into_vec(),
+        };
+        let mut bundle = IciBundle::new(colors)?;
+        for (name, image) in entries {
+            bundle.insert(&name, image)?;
+        }
+        Ok((bundle, lazy.pal_type))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct LazyEntry {
+    file_type: FileType,
+    start: usize,
+    len: usize,
+}
+
+/// Reads a bundle file's index without decoding any entries, entries are decoded when requested
+///
+/// Useful for large bundles where only a few images are needed at once
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyBundle<'a> {
+    bytes: &'a [u8],
+    pal_type: FilePalette,
+    palette: Option<Palette>,
+    entries: BTreeMap<String, LazyEntry>,
+}
+
+impl<'a> LazyBundle<'a> {
+    /// Reads the header, palette and index
+    ///
+    /// Entry types and positions are validated but the entries themselves aren't decoded
+    pub fn from_file_contents(bytes: &'a [u8]) -> Result<LazyBundle<'a>, IndexedImageError> {
+        LazyBundle::read_from(bytes, &mut ByteReader::new(bytes))
+    }
+
+    /// Like [LazyBundle::from_file_contents] but reading with `reader`, which must be at the start of `bytes`
+    pub(crate) fn read_from(
+        bytes: &'a [u8],
+        reader: &mut ByteReader<'a>,
+    ) -> Result<LazyBundle<'a>, IndexedImageError> {
+        let (_, file_type) = verify_format_version(bytes)?;
+        if file_type != Bundle {
+            return Err(wrong_file_type(Bundle.name(), &file_type));
+        }
+        read_header(reader)?;
+        let (pal_type, colors) = palette::read(reader)?;
+        let palette = colors.map(Palette::new).transpose()?;
+
+        reader.set_section(FileSection::BundleIndex);
+        let count = reader.u16("entry count")?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let name_len = reader.u8("entry name length")?;
+            let name = reader.string(name_len as usize, "entry name")?;
+            let type_offset = reader.offset();
+            let entry_type = reader.u8("entry type")?;
+            let range_offset = reader.offset();
+            let start = reader.u32("entry offset")? as usize;
+            let len = reader.u32("entry length")? as usize;
+            if start
+                .checked_add(len)
+                .filter(|end| *end <= bytes.len())
+                .is_none()
+            {
+                return Err(reader.invalid(
+                    range_offset,
+                    &format!("entry {name} data"),
+                    format!("within 0..{}", bytes.len()),
+                    format!("{start}..{}", start.saturating_add(len)),
+                ));
+            }
+            let file_type = match FileType::from_byte(entry_type) {
+                Some(file_type @ (Image | Animated)) => file_type,
+                _ => {
+                    return Err(reader.invalid(
+                        type_offset,
+                        &format!("entry {name} type"),
+                        "1 or 2",
+                        entry_type,
+                    ))
+                }
+            };
+            entries.insert(
+                name,
+                LazyEntry {
+                    file_type,
+                    start,
+                    len,
+                },
+            );
+        }
+        Ok(LazyBundle {
+            bytes,
+            pal_type,
+            palette,
+            entries,
+        })
+    }
+
+    /// How the palette was stored in the file
+    #[inline]
+    pub fn file_palette(&self) -> &FilePalette {
+        &self.pal_type
+    }
+
+    /// Bundle palette, only available if the file contains colors
+    #[inline]
+    pub fn get_palette(&self) -> Option<&Palette> {
+        self.palette.as_ref()
+    }
+
+    /// Decode entry `name`, returns `Ok(None)` if there's no entry with that name
+    ///
+    /// If the file contains colors then the image will use the bundle palette,
+    /// otherwise the palette is filled with transparency
+    pub fn get(&self, name: &str) -> Result<Option<IndexedWrapper>, IndexedImageError> {
+        self.entries
+            .get(name)
+            .map(|entry| self.decode(entry))
+            .transpose()
+    }
+
+    /// Entry `name` with its pixels borrowed from the file, returns `Ok(None)` if there's no entry with that name
+    ///
+    /// Uses the same palette as [LazyBundle::get]
+    pub fn get_ref(&self, name: &str) -> Result<Option<IndexedImageRef<'a>>, IndexedImageError> {
+        self.entries
+            .get(name)
+            .map(|entry| {
+                let data = &self.bytes[entry.start..entry.start + entry.len];
+                IndexedImageRef::read(data, self.palette.as_ref())
+            })
+            .transpose()
+    }
+
+    /// Type of entry `name`
+    pub fn file_type(&self, name: &str) -> Option<FileType> {
+        self.entries.get(name).map(|entry| entry.file_type.clone())
+    }
+
+    #[inline]
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// Name and position in the file of each entry
+    pub(crate) fn entry_ranges(&self) -> impl Iterator<Item = (&str, Range<usize>)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry.start..entry.start + entry.len))
+    }
+
+    /// Entry names in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    /// Decodes each entry as it's iterated, in alphabetical order
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&str, Result<IndexedWrapper, IndexedImageError>)> + '_ {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), self.decode(entry)))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn decode(&self, entry: &LazyEntry) -> Result<IndexedWrapper, IndexedImageError> {
+        let data = &self.bytes[entry.start..entry.start + entry.len];
+        let mut image = match entry.file_type {
+            Animated => IndexedWrapper::Animated(AnimatedIndexedImage::from_file_contents(data)?.0),
+            _ => IndexedWrapper::Static(IndexedImage::from_file_contents(data)?.0),
+        };
+        if let Some(palette) = &self.palette {
+            image.set_palette(palette)?;
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::animated::PlayType;
+    use crate::palette::FilePalette::*;
+
+    fn bundle() -> IciBundle {
+        let mut bundle = IciBundle::new(vec![RED, GREEN, BLUE]).unwrap();
+        bundle
+            .insert(
+                "player",
+                IndexedImage::new(2, 1, vec![WHITE, WHITE], vec![0, 1]).unwrap(),
+            )
+            .unwrap();
+        bundle
+            .insert(
+                "coin",
+                AnimatedIndexedImage::new(
+                    1,
+                    1,
+                    0.2,
+                    3,
+                    vec![WHITE; 3],
+                    vec![0, 1, 2],
+                    PlayType::Loops,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        bundle
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut bundle = bundle();
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle.names().collect::<Vec<_>>(), vec!["coin", "player"]);
+        let player = bundle.get("player").unwrap();
+        assert_eq!(player.get_palette(), &[RED, GREEN, BLUE]);
+        assert!(bundle.get("missing").is_none());
+        let too_many_colors = IndexedImage::new(1, 1, vec![WHITE; 4], vec![3]).unwrap();
+        assert!(bundle.insert("big", too_many_colors).is_err());
+        assert!(bundle
+            .insert("", IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let bundle = bundle();
+        let bytes = bundle.to_file_contents(&Colors).unwrap();
+        let (output, pal_type) = IciBundle::from_file_contents(&bytes).unwrap();
+        assert_eq!(pal_type, Colors);
+        assert_eq!(output, bundle);
+
+        let bytes = bundle.to_file_contents(&ID(4)).unwrap();
+        let (output, pal_type) = IciBundle::from_file_contents(&bytes).unwrap();
+        assert_eq!(pal_type, ID(4));
+        assert_eq!(output.get_palette(), &[TRANSPARENT; 3]);
+        assert!(IndexedImage::from_file_contents(&bytes).is_err());
+    }
+
+    #[test]
+    fn palette() {
+        let mut bundle = bundle();
+        assert!(bundle.set_palette(&[RED, GREEN]).is_err());
+        bundle.set_palette(&[WHITE, BLACK, RED, BLUE]).unwrap();
+        assert_eq!(
+            bundle.get("coin").unwrap().get_palette(),
+            &[WHITE, BLACK, RED, BLUE]
+        );
+    }
+
+    #[test]
+    fn lazy() {
+        let bundle = bundle();
+        let bytes = bundle.to_file_contents(&Colors).unwrap();
+        let lazy = LazyBundle::from_file_contents(&bytes).unwrap();
+        assert_eq!(lazy.len(), 2);
+        assert_eq!(lazy.file_type("coin"), Some(Animated));
+        assert_eq!(lazy.get("missing").unwrap(), None);
+        assert_eq!(lazy.get("player").unwrap().as_ref(), bundle.get("player"));
+        for ((name, image), (expected_name, expected)) in lazy.iter().zip(bundle.iter()) {
+            assert_eq!(name, expected_name);
+            assert_eq!(&image.unwrap(), expected);
+        }
+
+        let mut truncated = bytes.clone();
+        truncated.truncate(bytes.len() - 1);
+        assert!(LazyBundle::from_file_contents(&truncated).is_err());
+    }
+}
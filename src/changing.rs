@@ -1,3 +1,4 @@
+use crate::compat::*;
 use crate::prelude::*;
 
 pub trait ChangeColors {
@@ -91,17 +92,17 @@ impl ChangeColors for Vec<Color> {
 impl ChangeColors for IndexedImage {
     fn with_saturate(&self, amount: f32) -> Self {
         let mut image = self.clone();
-        image
-            .set_palette(&image.get_palette().to_vec().with_saturate(amount))
-            .expect("Color disappeared when changing saturation, please raise an issue on GitHub emmabritton/ici-files");
+        for color in image.palette_mut().iter_mut() {
+            *color = color.with_saturate(amount);
+        }
         image
     }
 
     fn with_brightness(&self, amount: f32) -> Self {
         let mut image = self.clone();
-        image
-            .set_palette(&image.get_palette().to_vec().with_brightness(amount))
-            .expect("Color disappeared when changing saturation, please raise an issue on GitHub emmabritton/ici-files");
+        for color in image.palette_mut().iter_mut() {
+            *color = color.with_brightness(amount);
+        }
         image
     }
 }
@@ -109,17 +110,17 @@ impl ChangeColors for IndexedImage {
 impl ChangeColors for AnimatedIndexedImage {
     fn with_saturate(&self, amount: f32) -> Self {
         let mut image = self.clone();
-        image
-            .set_palette(&image.get_palette().to_vec().with_saturate(amount))
-            .expect("Color disappeared when changing saturation, please raise an issue on GitHub emmabritton/ici-files");
+        for color in image.palette_mut().iter_mut() {
+            *color = color.with_saturate(amount);
+        }
         image
     }
 
     fn with_brightness(&self, amount: f32) -> Self {
         let mut image = self.clone();
-        image
-            .set_palette(&image.get_palette().to_vec().with_brightness(amount))
-            .expect("Color disappeared when changing saturation, please raise an issue on GitHub emmabritton/ici-files");
+        for color in image.palette_mut().iter_mut() {
+            *color = color.with_brightness(amount);
+        }
         image
     }
 }
@@ -0,0 +1,247 @@
+use crate::compat::*;
+use crate::dither::Dithering;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::import::from_rgba;
+use crate::prelude::*;
+
+impl IndexedImage {
+    /// Two color mask, index 0 (transparent) where pixels are fully transparent and index 1 (white) everywhere else
+    pub fn alpha_mask(&self) -> IndexedImage {
+        let pixels = self
+            .get_pixels()
+            .iter()
+            .map(|idx| {
+                let visible = self
+                    .get_palette()
+                    .get(*idx)
+                    .map(|color| color.a > 0)
+                    .unwrap_or_default();
+                visible as u8
+            })
+            .collect();
+        IndexedImage::from_parts(
+            self.width(),
+            self.height(),
+            Palette::from_vec_lossy(vec![TRANSPARENT, WHITE]),
+            pixels,
+        )
+    }
+
+    /// Copy of the image where pixels are only kept where `mask` is visible (not fully transparent),
+    /// everywhere else is set to `transparent_idx`
+    ///
+    /// `mask` must be the same size, its palette is only used to check visibility
+    pub fn mask(
+        &self,
+        mask: &IndexedImage,
+        transparent_idx: u8,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        self.apply_mask(mask, transparent_idx, true)
+    }
+
+    /// Opposite of [IndexedImage::mask], pixels are set to `transparent_idx` where `mask` is visible
+    /// and kept everywhere else
+    pub fn stencil(
+        &self,
+        mask: &IndexedImage,
+        transparent_idx: u8,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        self.apply_mask(mask, transparent_idx, false)
+    }
+
+    fn apply_mask(
+        &self,
+        mask: &IndexedImage,
+        transparent_idx: u8,
+        keep_visible: bool,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        if self.size() != mask.size() {
+            return Err(InvalidImageSize);
+        }
+        let palette_len = self.get_palette().len();
+        if transparent_idx as usize >= palette_len {
+            return Err(IndexOutOfRange(
+                transparent_idx as usize,
+                palette_len,
+                "palette",
+            ));
+        }
+        let pixels = self
+            .get_pixels()
+            .iter()
+            .zip(mask.get_pixels())
+            .map(|(idx, mask_idx)| {
+                let visible = mask
+                    .get_palette()
+                    .get(*mask_idx)
+                    .map(|color| color.a > 0)
+                    .unwrap_or_default();
+                if visible == keep_visible {
+                    *idx
+                } else {
+                    transparent_idx
+                }
+            })
+            .collect();
+        Ok(IndexedImage::from_parts(
+            self.width(),
+            self.height(),
+            self.get_palette().clone(),
+            pixels,
+        ))
+    }
+
+    /// Copy of the image where each palette color is replaced with an opaque gray of the same brightness
+    ///
+    /// Pixels are unchanged
+    pub fn brightness_map(&self) -> IndexedImage {
+        let mut output = self.clone();
+        for color in output.palette_mut().iter_mut() {
+            let level = (color.brightness() * 255.0).round().clamp(0.0, 255.0) as u8;
+            *color = Color::gray(level);
+        }
+        output
+    }
+
+    /// Copy of the image where each palette color is replaced with a color from `gradient` based on its brightness,
+    /// the darkest colors use the start of the gradient and the brightest the end
+    ///
+    /// Colors between gradient stops are interpolated and the alpha of the original color is kept,
+    /// if `gradient` is empty the palette is unchanged
+    pub fn gradient_map(&self, gradient: &[Color]) -> IndexedImage {
+        let mut output = self.clone();
+        if gradient.is_empty() {
+            return output;
+        }
+        for color in output.palette_mut().iter_mut() {
+            let position = color.brightness().clamp(0.0, 1.0) * (gradient.len() - 1) as f32;
+            let start = position.floor() as usize;
+            let end = (start + 1).min(gradient.len() - 1);
+            let t = position - start as f32;
+            let lerp =
+                |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+            let (from, to) = (gradient[start], gradient[end]);
+            *color = Color::new(
+                lerp(from.r, to.r),
+                lerp(from.g, to.g),
+                lerp(from.b, to.b),
+                color.a,
+            );
+        }
+        output
+    }
+
+    /// Create an image using the RGB of `color_src` and the alpha of `alpha_src`, they must be the same size
+    ///
+    /// If there are more than 256 combinations the colors will be reduced with median cut
+    pub fn recombine(
+        color_src: &IndexedImage,
+        alpha_src: &IndexedImage,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        if color_src.size() != alpha_src.size() {
+            return Err(InvalidImageSize);
+        }
+        let mut palette: Vec<Color> = vec![];
+        let mut pixels = Vec::with_capacity(color_src.get_pixels().len());
+        let mut rgba = Vec::with_capacity(color_src.get_pixels().len() * 4);
+        for (color_idx, alpha_idx) in color_src.get_pixels().iter().zip(alpha_src.get_pixels()) {
+            let color = color_src
+                .get_palette()
+                .get(*color_idx)
+                .unwrap_or(TRANSPARENT);
+            let alpha = alpha_src
+                .get_palette()
+                .get(*alpha_idx)
+                .unwrap_or(TRANSPARENT)
+                .a;
+            let color = Color::new(color.r, color.g, color.b, alpha);
+            rgba.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+            let idx = match palette.iter().position(|c| *c == color) {
+                Some(idx) => idx,
+                None => {
+                    palette.push(color);
+                    palette.len() - 1
+                }
+            };
+            pixels.push(idx);
+        }
+        if palette.len() > Palette::MAX_COLORS {
+            return from_rgba(
+                color_src.width(),
+                color_src.height(),
+                &rgba,
+                Palette::MAX_COLORS,
+                Dithering::None,
+            );
+        }
+        IndexedImage::new(
+            color_src.width(),
+            color_src.height(),
+            palette,
+            pixels.into_iter().map(|i| i as u8).collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_and_recombine() {
+        let semi = Color::new(10, 20, 30, 100);
+        let image = IndexedImage::new(3, 1, vec![TRANSPARENT, RED, semi], vec![0, 1, 2]).unwrap();
+        let mask = image.alpha_mask();
+        assert_eq!(mask.get_pixels(), &[0, 1, 1]);
+        assert_eq!(mask.get_palette(), &[TRANSPARENT, WHITE]);
+
+        let brightness = image.brightness_map();
+        assert_eq!(brightness.get_pixels(), image.get_pixels());
+        assert_eq!(brightness.get_color(0).unwrap(), BLACK);
+        assert_eq!(brightness.get_color(1).unwrap(), Color::gray(54));
+
+        let output = IndexedImage::recombine(&image, &image).unwrap();
+        assert_eq!(output.to_rgba_bytes(), image.to_rgba_bytes());
+        let output = IndexedImage::recombine(&brightness, &mask).unwrap();
+        assert_eq!(output.get_color(output.get_pixels()[0]).unwrap().a, 0);
+        assert_eq!(
+            output.get_color(output.get_pixels()[1]).unwrap(),
+            Color::gray(54)
+        );
+        assert!(IndexedImage::recombine(&image, &IndexedImage::blank(1, 1, vec![RED])).is_err());
+    }
+
+    #[test]
+    fn gradient_map() {
+        let image = IndexedImage::new(
+            3,
+            1,
+            vec![BLACK, Color::new(255, 255, 255, 100), Color::gray(128)],
+            vec![0, 1, 2],
+        )
+        .unwrap();
+        let output = image.gradient_map(&[BLUE, RED, GREEN]);
+        assert_eq!(output.get_pixels(), image.get_pixels());
+        assert_eq!(output.get_color(0).unwrap(), BLUE);
+        assert_eq!(output.get_color(1).unwrap(), GREEN.with_alpha(100));
+        assert_eq!(output.get_color(2).unwrap(), Color::new(254, 1, 0, 255));
+        assert_eq!(image.gradient_map(&[]), image);
+    }
+
+    #[test]
+    fn mask_and_stencil() {
+        let image =
+            IndexedImage::new(2, 2, vec![TRANSPARENT, RED, BLUE], vec![1, 2, 2, 1]).unwrap();
+        let shape = IndexedImage::new(2, 2, vec![WHITE, TRANSPARENT], vec![0, 1, 0, 1]).unwrap();
+        assert_eq!(image.mask(&shape, 0).unwrap().get_pixels(), &[1, 0, 2, 0]);
+        assert_eq!(
+            image.stencil(&shape, 0).unwrap().get_pixels(),
+            &[0, 2, 0, 1]
+        );
+        assert!(image.mask(&shape, 3).is_err());
+        assert!(image
+            .stencil(&IndexedImage::blank(1, 2, vec![WHITE]), 0)
+            .is_err());
+    }
+}
@@ -1,14 +1,78 @@
-use crate::Tint;
+use crate::compat::*;
+use crate::{HueShift, Tint};
 
-use crate::conversion::ColorConversion;
+use crate::conversion::{ColorConversion, OpaqueColorConversion};
 use crate::errors::IndexedImageError;
 use crate::prelude::IndexedImageError::InvalidHexFormat;
 #[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
-use serde::{Deserializer, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Sets of colors that look good together, based on hue position on the color wheel
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HarmonyScheme {
+    /// Base and the opposite hue
+    Complementary,
+    /// Base and the hues 30° either side
+    Analogous,
+    /// Base and the hues 120° and 240° away
+    Triadic,
+    /// Base and the hues 30° either side of the complement
+    SplitComplementary,
+}
+
+impl HarmonyScheme {
+    /// Hue offsets in degrees, starting with the base (0)
+    pub fn offsets(&self) -> &'static [f32] {
+        match self {
+            HarmonyScheme::Complementary => &[0.0, 180.0],
+            HarmonyScheme::Analogous => &[0.0, -30.0, 30.0],
+            HarmonyScheme::Triadic => &[0.0, 120.0, 240.0],
+            HarmonyScheme::SplitComplementary => &[0.0, 150.0, 210.0],
+        }
+    }
+}
+
+/// Types of color blindness that can be simulated
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ColorBlindness {
+    /// No red cones
+    Protanopia,
+    /// No green cones
+    Deuteranopia,
+    /// No blue cones
+    Tritanopia,
+    /// No color vision
+    Achromatopsia,
+}
+
+impl ColorBlindness {
+    /// Machado et al. (2009) matrices for linear RGB, at full severity
+    fn matrix(&self) -> [[f32; 3]; 3] {
+        match self {
+            ColorBlindness::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            ColorBlindness::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            ColorBlindness::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+            ColorBlindness::Achromatopsia => [[0.2126, 0.7152, 0.0722]; 3],
+        }
+    }
+}
 
 ///This represents an RGBA color
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Color {
     //red channel
     pub r: u8,
@@ -20,6 +84,10 @@ pub struct Color {
     pub a: u8,
 }
 
+/// Old name for [Color], `IciColor` was replaced by `Color` in 0.2.0
+#[deprecated(since = "0.5.0", note = "use Color")]
+pub type IciColor = Color;
+
 #[cfg(feature = "serde")]
 impl Serialize for Color {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -154,6 +222,12 @@ impl Color {
         self.a < 255
     }
 
+    /// Copy color with RGB multiplied by alpha
+    pub fn premultiplied(&self) -> Color {
+        let mul = |channel: u8| ((channel as u16 * self.a as u16 + 127) / 255) as u8;
+        Color::new(mul(self.r), mul(self.g), mul(self.b), self.a)
+    }
+
     pub fn darken(&self) -> Color {
         self.with_brightness(0.9)
     }
@@ -222,6 +296,121 @@ impl Color {
         }
     }
 
+    /// Returns hue (0..360), saturation (0..=1) and lightness (0..=1)
+    ///
+    /// ignores alpha
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let [r, g, b]: [f32; 3] = self.to_rgb();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.0;
+        let delta = max - min;
+        if delta == 0.0 {
+            return (0.0, 0.0, lightness);
+        }
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        (hue, saturation.clamp(0.0, 1.0), lightness)
+    }
+
+    /// Create a color from hue (degrees, wraps), saturation (0..=1) and lightness (0..=1)
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: u8) -> Color {
+        let hue = hue.rem_euclid(360.0);
+        let saturation = saturation.clamp(0.0, 1.0);
+        let lightness = lightness.clamp(0.0, 1.0);
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+        let m = lightness - chroma / 2.0;
+        let (r, g, b) = match (hue / 60.0) as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        Color::from_rgb((r + m, g + m, b + m)).with_alpha(alpha)
+    }
+
+    /// Colors matching `scheme`, the first color is always this one
+    ///
+    /// Grays have no hue so all colors will be the same
+    pub fn harmonies(&self, scheme: HarmonyScheme) -> Vec<Color> {
+        scheme
+            .offsets()
+            .iter()
+            .map(|offset| {
+                let mut color = *self;
+                color.tint_hue_shift(*offset);
+                color
+            })
+            .collect()
+    }
+
+    /// How this color would look with `kind` color blindness, alpha is unchanged
+    pub fn simulate(&self, kind: ColorBlindness) -> Color {
+        let to_srgb = |value: f32| {
+            let value = value.clamp(0.0, 1.0);
+            let value = if value <= 0.0031308 {
+                value * 12.92
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            };
+            (value * 255.0).round() as u8
+        };
+        let linear = self.to_linear();
+        let [r, g, b] = kind
+            .matrix()
+            .map(|row| to_srgb(row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]));
+        Color::new(r, g, b, self.a)
+    }
+
+    /// RGB as linear light (0..=1), ignores alpha
+    fn to_linear(self) -> [f32; 3] {
+        [self.r, self.g, self.b].map(|value| {
+            let value = value as f32 / 255.0;
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    }
+
+    /// Returns CIE L\*a\*b\* lightness (0..=100), a and b (D65 white point)
+    ///
+    /// ignores alpha
+    pub fn to_lab(&self) -> (f32, f32, f32) {
+        let [r, g, b] = self.to_linear();
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+        let f = |t: f32| {
+            if t > 0.008856 {
+                t.cbrt()
+            } else {
+                7.787 * t + 16.0 / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+    }
+
+    /// Perceptual difference (CIE76 delta E) between two colors, around 2.3 is just noticeable
+    ///
+    /// ignores alpha
+    pub fn delta_e(&self, other: &Color) -> f32 {
+        let (l1, a1, b1) = self.to_lab();
+        let (l2, a2, b2) = other.to_lab();
+        ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+    }
+
     /// diff between two colors
     pub fn diff(&self, other: &Color) -> usize {
         (self.r as isize - other.r as isize).unsigned_abs()
@@ -257,6 +446,16 @@ impl Tint for Color {
     }
 }
 
+impl HueShift for Color {
+    fn tint_hue_shift(&mut self, degrees: f32) {
+        let (hue, saturation, lightness) = self.to_hsl();
+        if saturation == 0.0 {
+            return;
+        }
+        *self = Color::from_hsl(hue + degrees, saturation, lightness, self.a);
+    }
+}
+
 pub const WHITE: Color = Color::gray(255);
 pub const OFF_WHITE: Color = Color::gray(250);
 pub const BLACK: Color = Color::gray(0);
@@ -299,6 +498,26 @@ mod test {
         color
     }
 
+    #[test]
+    fn lab() {
+        let (l, a, b) = WHITE.to_lab();
+        assert!((l - 100.0).abs() < 0.1 && a.abs() < 0.1 && b.abs() < 0.1);
+        assert_eq!(BLACK.to_lab(), (0.0, 0.0, 0.0));
+        assert!((RED.delta_e(&GREEN) - 170.6).abs() < 1.0);
+        assert_eq!(RED.delta_e(&RED), 0.0);
+    }
+
+    #[test]
+    fn simulate() {
+        assert_eq!(WHITE.simulate(ColorBlindness::Protanopia), WHITE);
+        assert_eq!(BLACK.simulate(ColorBlindness::Tritanopia), BLACK);
+        let gray = RED.simulate(ColorBlindness::Achromatopsia);
+        assert_eq!(gray, Color::gray(gray.r));
+        let red = RED.simulate(ColorBlindness::Deuteranopia);
+        let green = GREEN.simulate(ColorBlindness::Deuteranopia);
+        assert!(red.diff(&green) < RED.diff(&GREEN) / 2);
+    }
+
     #[test]
     fn tint_add() {
         let initial = Color {
@@ -448,6 +667,66 @@ mod test {
         assert!(!LIGHT_GRAY.is_dark());
     }
 
+    #[test]
+    fn premultiplied() {
+        assert_eq!(
+            Color::new(200, 100, 50, 255).premultiplied(),
+            Color::new(200, 100, 50, 255)
+        );
+        assert_eq!(
+            Color::new(200, 100, 50, 128).premultiplied(),
+            Color::new(100, 50, 25, 128)
+        );
+        assert_eq!(Color::new(200, 100, 50, 0).premultiplied(), TRANSPARENT);
+    }
+
+    #[test]
+    fn harmonies() {
+        assert_eq!(RED.harmonies(HarmonyScheme::Complementary), vec![RED, CYAN]);
+        assert_eq!(
+            RED.harmonies(HarmonyScheme::Triadic),
+            vec![RED, GREEN, BLUE]
+        );
+        assert_eq!(
+            MID_GRAY.harmonies(HarmonyScheme::Analogous),
+            vec![MID_GRAY; 3]
+        );
+    }
+
+    #[test]
+    fn hsl() {
+        assert_eq!(RED.to_hsl(), (0.0, 1.0, 0.5));
+        assert_eq!(GREEN.to_hsl(), (120.0, 1.0, 0.5));
+        assert_eq!(BLUE.to_hsl(), (240.0, 1.0, 0.5));
+        assert_eq!(WHITE.to_hsl(), (0.0, 0.0, 1.0));
+        assert_eq!(MAGENTA.to_hsl(), (300.0, 1.0, 0.5));
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for color in [
+            RED, GREEN, BLUE, ORANGE, BROWN, PURPLE, CYAN, GB_1, MID_GRAY,
+        ] {
+            let (h, s, l) = color.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l, color.a), color);
+        }
+    }
+
+    #[test]
+    fn hue_shift() {
+        let mut color = RED;
+        color.tint_hue_shift(120.0);
+        assert_eq!(color, GREEN);
+        color.tint_hue_shift(-240.0);
+        assert_eq!(color, BLUE);
+        let mut color = RED.with_alpha(100);
+        color.tint_hue_shift(-60.0);
+        assert_eq!(color, MAGENTA.with_alpha(100));
+        let mut gray = MID_GRAY;
+        gray.tint_hue_shift(90.0);
+        assert_eq!(gray, MID_GRAY);
+    }
+
     #[test]
     fn _u32() {
         let num: u32 = RED.to_rgba();
@@ -0,0 +1,9 @@
+//! Items from the `std` prelude that aren't in the `core` prelude, so modules build with and without `std`
+
+pub(crate) use alloc::string::{String, ToString};
+pub(crate) use alloc::vec::Vec;
+pub(crate) use alloc::{format, vec};
+
+// tests always link `std`, where these are inherent methods
+#[cfg(all(not(feature = "std"), not(test)))]
+pub(crate) use crate::math::Float;
@@ -1,10 +1,35 @@
 use crate::color::Color;
+#[cfg(all(not(feature = "std"), not(test)))]
+use crate::math::Float;
 
 #[inline(always)]
 fn f32_to_u8(value: f32) -> u8 {
     (value * 255.).round().clamp(0., 255.) as u8
 }
 
+/// Channel order of `u32` pixels in a framebuffer
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum PixelFormat {
+    /// `0xRRGGBBAA`
+    #[default]
+    Rgba,
+    /// `0xAARRGGBB`, i.e. `minifb`
+    Argb,
+    /// Bytes R,G,B,A in memory whatever the endianness, i.e. `pixels`
+    RgbaBytes,
+}
+
+impl PixelFormat {
+    #[inline]
+    pub fn pack(self, color: Color) -> u32 {
+        match self {
+            PixelFormat::Rgba => color.to_rgba(),
+            PixelFormat::Argb => color.to_argb(),
+            PixelFormat::RgbaBytes => u32::from_ne_bytes([color.r, color.g, color.b, color.a]),
+        }
+    }
+}
+
 /// Converts to/from RGB
 pub trait OpaqueColorConversion<T> {
     fn to_rgb(self) -> T;
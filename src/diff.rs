@@ -0,0 +1,172 @@
+//! Changes between two versions of an image, for undo/redo and syncing edits without sending full copies
+
+use crate::compat::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+
+/// Palette entries and pixels that changed between two images of the same size
+///
+/// Create with [IndexedImage::diff] and apply with [IndexedImage::apply_diff]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct ImageDiff {
+    /// Palette length before and after
+    pub palette_len: (usize, usize),
+    /// Palette entries that changed as `(index, old color, new color)`, `None` if the entry doesn't exist
+    pub palette: Vec<(u8, Option<Color>, Option<Color>)>,
+    /// Pixels that changed as `(pixel index, old palette index, new palette index)`
+    pub pixels: Vec<(usize, u8, u8)>,
+}
+
+impl ImageDiff {
+    /// True if nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.palette_len.0 == self.palette_len.1
+            && self.palette.is_empty()
+            && self.pixels.is_empty()
+    }
+
+    /// Diff that undoes this one
+    pub fn reversed(&self) -> ImageDiff {
+        ImageDiff {
+            palette_len: (self.palette_len.1, self.palette_len.0),
+            palette: self
+                .palette
+                .iter()
+                .map(|(idx, old, new)| (*idx, *new, *old))
+                .collect(),
+            pixels: self
+                .pixels
+                .iter()
+                .map(|(idx, old, new)| (*idx, *new, *old))
+                .collect(),
+        }
+    }
+}
+
+impl IndexedImage {
+    /// Changes needed to turn this image into `other`
+    ///
+    /// Returns an error if the images are different sizes
+    pub fn diff(&self, other: &IndexedImage) -> Result<ImageDiff, IndexedImageError> {
+        if self.size() != other.size() {
+            return Err(InvalidImageSize);
+        }
+        let (old_palette, new_palette) = (self.get_palette(), other.get_palette());
+        let palette = (0..old_palette.len().max(new_palette.len()))
+            .map(|idx| idx as u8)
+            .map(|idx| (idx, old_palette.get(idx), new_palette.get(idx)))
+            .filter(|(_, old, new)| old != new)
+            .collect();
+        let pixels = self
+            .get_pixels()
+            .iter()
+            .zip(other.get_pixels())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(i, (old, new))| (i, *old, *new))
+            .collect();
+        Ok(ImageDiff {
+            palette_len: (old_palette.len(), new_palette.len()),
+            palette,
+            pixels,
+        })
+    }
+
+    /// Apply changes from [IndexedImage::diff]
+    ///
+    /// Every old color and pixel must match this image and every pixel must be in the new palette,
+    /// otherwise an error is returned and the image is unchanged
+    pub fn apply_diff(&mut self, diff: &ImageDiff) -> Result<(), IndexedImageError> {
+        let (old_len, new_len) = diff.palette_len;
+        if old_len != self.get_palette().len() {
+            return Err(InvalidPaletteSize);
+        }
+        let mut palette = self.get_palette().to_vec();
+        palette.resize(new_len, TRANSPARENT);
+        for (idx, old, new) in &diff.palette {
+            if self.get_palette().get(*idx) != *old || ((*idx as usize) < new_len) != new.is_some()
+            {
+                return Err(PaletteDeltaMismatch(*idx as usize));
+            }
+            if let Some(color) = new {
+                palette[*idx as usize] = *color;
+            }
+        }
+        let palette = Palette::new(palette)?;
+
+        let mut pixels = self.get_pixels().to_vec();
+        for (offset, old, new) in &diff.pixels {
+            let Some(current) = pixels.get_mut(*offset) else {
+                return Err(IndexOutOfRange(*offset, self.get_pixels().len(), "pixels"));
+            };
+            if current != old {
+                return Err(DeltaMismatch(*offset, *old, *current));
+            }
+            *current = *new;
+        }
+        let highest = pixels.iter().max().copied().unwrap_or_default();
+        if highest as usize >= palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
+        self.set_raw_pixels(pixels);
+        self.refresh_highest_palette_idx();
+        self.set_palette(&palette)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_and_apply() {
+        let original = IndexedImage::new(2, 2, vec![RED, GREEN], vec![0, 1, 1, 0]).unwrap();
+        let mut edited = original.clone();
+        edited.set_palette(&[RED, BLUE, WHITE]).unwrap();
+        edited.set_pixel(0, 2).unwrap();
+
+        let diff = original.diff(&edited).unwrap();
+        assert_eq!(diff.palette_len, (2, 3));
+        assert_eq!(
+            diff.palette,
+            vec![(1, Some(GREEN), Some(BLUE)), (2, None, Some(WHITE))]
+        );
+        assert_eq!(diff.pixels, vec![(0, 0, 2)]);
+        assert!(original.diff(&original).unwrap().is_empty());
+
+        let mut image = original.clone();
+        image.apply_diff(&diff).unwrap();
+        assert_eq!(image, edited);
+        assert!(matches!(image.apply_diff(&diff), Err(InvalidPaletteSize)));
+        image.apply_diff(&diff.reversed()).unwrap();
+        assert_eq!(image, original);
+
+        let bad_pixel = ImageDiff {
+            palette_len: (2, 2),
+            pixels: vec![(1, 0, 1)],
+            ..ImageDiff::default()
+        };
+        assert!(matches!(
+            image.apply_diff(&bad_pixel),
+            Err(DeltaMismatch(1, 0, 1))
+        ));
+        let shrink = ImageDiff {
+            palette_len: (2, 1),
+            palette: vec![(1, Some(GREEN), None)],
+            pixels: vec![],
+        };
+        assert!(matches!(
+            image.apply_diff(&shrink),
+            Err(PaletteTooFewColors(1))
+        ));
+        assert_eq!(image, original);
+
+        let small = IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap();
+        assert!(original.diff(&small).is_err());
+    }
+}
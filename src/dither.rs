@@ -0,0 +1,149 @@
+use crate::compat::*;
+use crate::palette::{nearest_index_by, ColorDistance};
+use crate::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// How to hide banding when reducing colors
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Dithering {
+    /// Use the closest color
+    #[default]
+    None,
+    /// 4x4 Bayer matrix, gives a regular cross hatch pattern
+    Ordered,
+    /// Floyd–Steinberg error diffusion, gives a noisy but more accurate result
+    FloydSteinberg,
+}
+
+/// Convert RGBA pixel data (4 bytes per pixel) to indexes of `palette`
+///
+/// `pixels` must contain `width * height` pixels
+pub fn dither_rgba(
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: &[Color],
+    dithering: Dithering,
+) -> Vec<u8> {
+    let metric = ColorDistance::EuclideanAlpha;
+    let colors = pixels
+        .chunks_exact(4)
+        .take(width * height)
+        .map(|px| Color::new(px[0], px[1], px[2], px[3]));
+    match dithering {
+        Dithering::None => colors
+            .map(|color| nearest_index_by(palette, color, metric))
+            .collect(),
+        Dithering::Ordered => {
+            let spread = 255.0 / (palette.len() as f32).cbrt().max(1.0);
+            colors
+                .enumerate()
+                .map(|(i, color)| {
+                    let threshold = BAYER_4X4[(i / width) % 4][(i % width) % 4] as f32;
+                    let offset = (threshold / 16.0 - 0.5) * spread;
+                    let adjust =
+                        |channel: u8| (channel as f32 + offset).round().clamp(0.0, 255.0) as u8;
+                    let adjusted =
+                        Color::new(adjust(color.r), adjust(color.g), adjust(color.b), color.a);
+                    nearest_index_by(palette, adjusted, metric)
+                })
+                .collect()
+        }
+        Dithering::FloydSteinberg => {
+            let colors: Vec<Color> = colors.collect();
+            let mut errors = vec![[0.0_f32; 3]; colors.len()];
+            let mut output = Vec::with_capacity(colors.len());
+            for (i, color) in colors.iter().enumerate() {
+                let (x, y) = (i % width, i / width);
+                let err = errors[i];
+                let value = [
+                    (color.r as f32 + err[0]).clamp(0.0, 255.0),
+                    (color.g as f32 + err[1]).clamp(0.0, 255.0),
+                    (color.b as f32 + err[2]).clamp(0.0, 255.0),
+                ];
+                let adjusted = Color::new(
+                    value[0].round() as u8,
+                    value[1].round() as u8,
+                    value[2].round() as u8,
+                    color.a,
+                );
+                let idx = nearest_index_by(palette, adjusted, metric);
+                output.push(idx);
+                let chosen = palette.get(idx as usize).copied().unwrap_or(adjusted);
+                let diff = [
+                    value[0] - chosen.r as f32,
+                    value[1] - chosen.g as f32,
+                    value[2] - chosen.b as f32,
+                ];
+                let mut spread = |dx: isize, dy: usize, amount: f32| {
+                    let nx = x as isize + dx;
+                    let ny = y + dy;
+                    if nx >= 0 && (nx as usize) < width && ny < height {
+                        let target = &mut errors[nx as usize + ny * width];
+                        for c in 0..3 {
+                            target[c] += diff[c] * amount;
+                        }
+                    }
+                };
+                spread(1, 0, 7.0 / 16.0);
+                spread(-1, 1, 3.0 / 16.0);
+                spread(0, 1, 5.0 / 16.0);
+                spread(1, 1, 1.0 / 16.0);
+            }
+            output
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gradient() -> Vec<u8> {
+        (0..16_u8)
+            .flat_map(|i| {
+                let v = i * 17;
+                [v, v, v, 255]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn none_uses_nearest() {
+        let output = dither_rgba(16, 1, &gradient(), &[BLACK, WHITE], Dithering::None);
+        assert_eq!(output, vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn ordered_mixes_colors() {
+        let pixels: Vec<u8> = [128, 128, 128, 255].repeat(16);
+        let output = dither_rgba(4, 4, &pixels, &[BLACK, WHITE], Dithering::Ordered);
+        let white = output.iter().filter(|i| **i == 1).count();
+        assert!(white > 4 && white < 12, "{white}");
+    }
+
+    #[test]
+    fn floyd_steinberg_preserves_average() {
+        let pixels: Vec<u8> = [64, 64, 64, 255].repeat(64);
+        let output = dither_rgba(8, 8, &pixels, &[BLACK, WHITE], Dithering::FloydSteinberg);
+        let white = output.iter().filter(|i| **i == 1).count();
+        assert!((14..=18).contains(&white), "{white}");
+    }
+
+    #[test]
+    fn keeps_transparency() {
+        let pixels = [0, 0, 0, 0, 0, 0, 0, 255];
+        for dithering in [
+            Dithering::None,
+            Dithering::Ordered,
+            Dithering::FloydSteinberg,
+        ] {
+            let output = dither_rgba(2, 1, &pixels, &[BLACK, TRANSPARENT], dithering);
+            assert_eq!(output, vec![1, 0]);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::prelude::*;
+
+/// Shift horizontal strips of `image` by `offset` multiplied by each strip's factor, wrapping within the strip
+///
+/// Useful for previewing parallax backgrounds, positive offsets move the strip right
+/// Strips are clipped to the image, later strips overwrite earlier ones and uncovered pixels are unchanged
+pub fn horizontal_parallax_strips(
+    image: &IndexedImage,
+    strips: &[(IciRect, f32)],
+    offset: f32,
+) -> IndexedImage {
+    let mut output = image.clone();
+    let width = image.width() as usize;
+    let source = image.get_pixels();
+    let pixels = output.raw_pixels_mut();
+    for (rect, factor) in strips {
+        let Some(rect) = rect.clip(image.width(), image.height()) else {
+            continue;
+        };
+        let strip_width = rect.width as isize;
+        let shift = (offset * factor).round() as isize;
+        for y in rect.y as usize..rect.bottom() {
+            let row = y * width + rect.x as usize;
+            for dx in 0..strip_width {
+                let src = (dx - shift).rem_euclid(strip_width) as usize;
+                pixels[row + dx as usize] = source[row + src];
+            }
+        }
+    }
+    output
+}
+
+impl AnimatedIndexedImage {
+    /// Looping animation of `image` scrolling by `dx`,`dy` pixels per frame, wrapping around the edges
+    ///
+    /// For seamless tiles such as water, conveyor belts or starfields, the palette is copied from `image`
+    pub fn from_scrolling(
+        image: &IndexedImage,
+        dx: i8,
+        dy: i8,
+        frames: u16,
+        per_frame: f64,
+    ) -> Result<AnimatedIndexedImage, IndexedImageError> {
+        let (width, height) = (image.width() as isize, image.height() as isize);
+        let source = image.get_pixels();
+        let mut pixels = Vec::with_capacity(source.len() * frames as usize);
+        for frame in 0..frames as isize {
+            let (offset_x, offset_y) = (dx as isize * frame, dy as isize * frame);
+            for y in 0..height {
+                let src_y = (y - offset_y).rem_euclid(height);
+                for x in 0..width {
+                    let src_x = (x - offset_x).rem_euclid(width);
+                    pixels.push(source[(src_x + src_y * width) as usize]);
+                }
+            }
+        }
+        AnimatedIndexedImage::new(
+            image.width(),
+            image.height(),
+            per_frame,
+            frames,
+            image.get_palette().to_vec(),
+            pixels,
+            PlayType::Loops,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parallax() {
+        #[rustfmt::skip]
+        let image = IndexedImage::new(4, 3, vec![RED, GREEN, BLUE, WHITE], vec![
+            0, 1, 2, 3,
+            0, 1, 2, 3,
+            0, 1, 2, 3,
+        ]).unwrap();
+        let strips = [
+            (IciRect::new(0, 0, 4, 1), 1.0),
+            (IciRect::new(0, 1, 4, 1), 0.5),
+            (IciRect::new(1, 2, 10, 10), -1.0),
+        ];
+        let output = horizontal_parallax_strips(&image, &strips, 2.0);
+        #[rustfmt::skip]
+        assert_eq!(output.get_pixels(), &[
+            2, 3, 0, 1,
+            3, 0, 1, 2,
+            0, 3, 1, 2,
+        ]);
+        assert_eq!(horizontal_parallax_strips(&image, &strips, 0.0), image);
+    }
+
+    #[test]
+    fn scrolling() {
+        let image = IndexedImage::new(3, 2, vec![RED, BLUE], vec![1, 0, 0, 0, 0, 0]).unwrap();
+        let animation = AnimatedIndexedImage::from_scrolling(&image, 1, -1, 3, 0.1).unwrap();
+        assert_eq!(animation.frame_count(), 3);
+        assert_eq!(animation.play_type(), PlayType::Loops);
+        assert_eq!(animation.get_frame_pixels(0).unwrap(), image.get_pixels());
+        assert_eq!(animation.get_frame_pixels(1).unwrap(), &[0, 0, 0, 0, 1, 0]);
+        assert_eq!(animation.get_frame_pixels(2).unwrap(), &[0, 0, 1, 0, 0, 0]);
+        assert!(AnimatedIndexedImage::from_scrolling(&image, 1, 0, 0, 0.1).is_err());
+        let long = AnimatedIndexedImage::from_scrolling(&image, 1, -1, 300, 0.1).unwrap();
+        assert_eq!(long.frame_count(), 300);
+        assert_eq!(long.get_frame_pixels(299), long.get_frame_pixels(5));
+        assert_eq!(long.get_frame_pixels(6).unwrap(), image.get_pixels());
+    }
+}
@@ -0,0 +1,340 @@
+//! Images and animations parsed at compile time, for shipping assets inside the binary without runtime parsing
+//!
+//! ```ignore
+//! static PLAYER: StaticImage = include_ici!("../assets/player.ica");
+//! ```
+//!
+//! Files must contain palette colors, checksums and chunks (metadata, tags, etc) are ignored
+//!
+//! ```
+//! # use ici_files::embedded::StaticImage;
+//! const BYTES: &[u8] = &[b'I', b'C', b'I', 1, 1, 3, 2, 255, 0, 0, 255, 0, 0, 255, 255, 2, 1, 0, 1];
+//! static IMAGE: StaticImage = StaticImage::parse(BYTES);
+//! assert_eq!(IMAGE.size(), (2, 1));
+//! ```
+
+use crate::errors::IndexedImageError;
+use crate::file::FileType;
+use crate::image::draw_indexes_to_buffer;
+use crate::palette::PAL_COLORS;
+use crate::prelude::*;
+
+/// Read only image or animation that borrows its palette and pixels from static file contents
+///
+/// Create with [include_ici!](crate::include_ici) or [StaticImage::parse] in a `const`/`static`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StaticImage {
+    width: u8,
+    height: u8,
+    frame_count: u16,
+    playback: Option<(f64, PlayType)>,
+    /// RGBA, 4 bytes per color
+    palette: &'static [u8],
+    pixels: &'static [u8],
+}
+
+/// `len` bytes from `offset`, if there are enough
+const fn take(bytes: &'static [u8], offset: usize, len: usize) -> Option<&'static [u8]> {
+    if offset > bytes.len() || len > bytes.len() - offset {
+        return None;
+    }
+    Some(bytes.split_at(offset).1.split_at(len).0)
+}
+
+/// Reads a u8 (version 1) or u16 (version 2)
+const fn size(bytes: &'static [u8], offset: usize, v2: bool) -> Option<(u16, usize)> {
+    if v2 {
+        match take(bytes, offset, 2) {
+            Some(value) => Some((u16::from_be_bytes([value[0], value[1]]), offset + 2)),
+            None => None,
+        }
+    } else {
+        match take(bytes, offset, 1) {
+            Some(value) => Some((value[0] as u16, offset + 1)),
+            None => None,
+        }
+    }
+}
+
+macro_rules! try_const {
+    ($value:expr, $err:expr) => {
+        match $value {
+            Some(value) => value,
+            None => return Err($err),
+        }
+    };
+}
+
+const TOO_SHORT: &str = "File is too short";
+
+impl StaticImage {
+    /// Read an image or animation file, this can be used in `const` contexts
+    ///
+    /// Returns a description of the problem if the file is invalid or doesn't contain palette colors
+    pub const fn from_file_contents(bytes: &'static [u8]) -> Result<StaticImage, &'static str> {
+        let header = try_const!(take(bytes, 0, 5), TOO_SHORT);
+        if header[0] != b'I' || header[1] != b'C' || header[2] != b'I' {
+            return Err("Not an ICI file");
+        }
+        let v2 = match header[3] {
+            1 => false,
+            2 => true,
+            _ => return Err("Unsupported file version"),
+        };
+        let animated = match header[4] {
+            1 => false,
+            2 => true,
+            _ => return Err("Unsupported file type, must be an image or animation"),
+        };
+        let pal_header = try_const!(take(bytes, 5, 2), TOO_SHORT);
+        if pal_header[0] != PAL_COLORS {
+            return Err("File must contain palette colors");
+        }
+        let color_count = match pal_header[1] {
+            0 => 256,
+            count => count as usize,
+        };
+        let palette = try_const!(take(bytes, 7, color_count * 4), TOO_SHORT);
+        let offset = 7 + color_count * 4;
+        let (width, offset) = try_const!(size(bytes, offset, v2), TOO_SHORT);
+        let (height, mut offset) = try_const!(size(bytes, offset, v2), TOO_SHORT);
+        if width == 0 || height == 0 {
+            return Err("Width and height must be at least 1");
+        }
+        if width > u8::MAX as u16 || height > u8::MAX as u16 {
+            return Err("Width and height must be at most 255");
+        }
+        let mut frame_count = 1;
+        let mut playback = None;
+        if animated {
+            let play_type = try_const!(take(bytes, offset, 1), TOO_SHORT);
+            let play_type = try_const!(PlayType::from_byte(play_type[0]), "Invalid play type");
+            let (count, next) = try_const!(size(bytes, offset + 1, v2), TOO_SHORT);
+            let per_frame = try_const!(take(bytes, next, 8), TOO_SHORT);
+            let per_frame = f64::from_be_bytes([
+                per_frame[0],
+                per_frame[1],
+                per_frame[2],
+                per_frame[3],
+                per_frame[4],
+                per_frame[5],
+                per_frame[6],
+                per_frame[7],
+            ]);
+            if count == 0 {
+                return Err("Animation has no frames");
+            }
+            frame_count = count;
+            playback = Some((per_frame, play_type));
+            offset = next + 8;
+        }
+        let pixel_count = width as usize * height as usize * frame_count as usize;
+        let pixels = try_const!(take(bytes, offset, pixel_count), TOO_SHORT);
+        let mut i = 0;
+        while i < pixels.len() {
+            if pixels[i] as usize >= color_count {
+                return Err("Pixel uses a color outside the palette");
+            }
+            i += 1;
+        }
+        Ok(StaticImage {
+            width: width as u8,
+            height: height as u8,
+            frame_count,
+            playback,
+            palette,
+            pixels,
+        })
+    }
+
+    /// Like [StaticImage::from_file_contents] but panics if the file is invalid, in a `const` context this is a
+    /// compile error
+    #[allow(clippy::panic)] // intended for const contexts, where this stops invalid files being compiled in
+    pub const fn parse(bytes: &'static [u8]) -> StaticImage {
+        match StaticImage::from_file_contents(bytes) {
+            Ok(image) => image,
+            Err(msg) => panic!("{}", msg),
+        }
+    }
+
+    #[inline]
+    pub const fn width(&self) -> u8 {
+        self.width
+    }
+
+    #[inline]
+    pub const fn height(&self) -> u8 {
+        self.height
+    }
+
+    #[inline]
+    pub const fn size(&self) -> (u8, u8) {
+        (self.width, self.height)
+    }
+
+    #[inline]
+    pub const fn file_type(&self) -> FileType {
+        if self.playback.is_some() {
+            FileType::Animated
+        } else {
+            FileType::Image
+        }
+    }
+
+    /// Static images have 1 frame
+    #[inline]
+    pub const fn frame_count(&self) -> u16 {
+        self.frame_count
+    }
+
+    /// Seconds per frame and play type, `None` for static images
+    #[inline]
+    pub const fn playback(&self) -> Option<(f64, PlayType)> {
+        self.playback
+    }
+
+    #[inline]
+    pub const fn palette_len(&self) -> usize {
+        self.palette.len() / 4
+    }
+
+    pub const fn get_color(&self, idx: u8) -> Option<Color> {
+        let i = idx as usize * 4;
+        if i >= self.palette.len() {
+            return None;
+        }
+        Some(Color::new(
+            self.palette[i],
+            self.palette[i + 1],
+            self.palette[i + 2],
+            self.palette[i + 3],
+        ))
+    }
+
+    /// Pixels for every frame
+    #[inline]
+    pub const fn get_pixels(&self) -> &'static [u8] {
+        self.pixels
+    }
+
+    pub const fn get_frame_pixels(&self, idx: u16) -> Option<&'static [u8]> {
+        if idx >= self.frame_count {
+            return None;
+        }
+        let frame_size = self.width as usize * self.height as usize;
+        take(self.pixels, frame_size * idx as usize, frame_size)
+    }
+
+    /// Palette colors, on the stack so drawing doesn't allocate
+    fn colors(&self) -> ([Color; 256], usize) {
+        let mut colors = [TRANSPARENT; 256];
+        for (color, rgba) in colors.iter_mut().zip(self.palette.chunks_exact(4)) {
+            *color = Color::new(rgba[0], rgba[1], rgba[2], rgba[3]);
+        }
+        (colors, self.palette_len())
+    }
+
+    /// Copy frame `idx` into a framebuffer, see [IndexedImage::draw_to_buffer]
+    ///
+    /// Does nothing if `idx` is out of range
+    pub fn draw_to_buffer(
+        &self,
+        idx: u16,
+        buffer: &mut [u32],
+        buffer_width: usize,
+        x: isize,
+        y: isize,
+        format: PixelFormat,
+    ) {
+        if let Some(pixels) = self.get_frame_pixels(idx) {
+            let (colors, len) = self.colors();
+            draw_indexes_to_buffer(
+                &colors[..len],
+                pixels,
+                (self.width as usize, self.height as usize),
+                buffer,
+                buffer_width,
+                (x, y),
+                format,
+            );
+        }
+    }
+
+    /// Copy into an [IndexedImage] or [AnimatedIndexedImage]
+    pub fn to_owned_image(&self) -> Result<IndexedWrapper, IndexedImageError> {
+        let (colors, len) = self.colors();
+        let colors = colors[..len].to_vec();
+        Ok(match self.playback {
+            Some((per_frame, play_type)) => AnimatedIndexedImage::new(
+                self.width,
+                self.height,
+                per_frame,
+                self.frame_count,
+                colors,
+                self.pixels.to_vec(),
+                play_type,
+            )?
+            .into(),
+            None => {
+                IndexedImage::new(self.width, self.height, colors, self.pixels.to_vec())?.into()
+            }
+        })
+    }
+}
+
+/// Include an image or animation file as a [StaticImage](crate::embedded::StaticImage), parsed at compile time
+///
+/// The path is relative to the current file, like [include_bytes]. Invalid files are compile errors
+#[macro_export]
+macro_rules! include_ici {
+    ($path:expr) => {{
+        const IMAGE: $crate::embedded::StaticImage =
+            $crate::embedded::StaticImage::parse(include_bytes!($path));
+        IMAGE
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette;
+
+    #[test]
+    fn parse() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.25,
+            2,
+            vec![RED, GREEN, BLUE],
+            vec![0, 1, 2, 0],
+            PlayType::OnceBoth,
+        )
+        .unwrap();
+        let expected = image.clone();
+        image.set_metadata("author", "test").unwrap();
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap().leak();
+        let embedded = StaticImage::from_file_contents(bytes).unwrap();
+        assert_eq!(embedded.file_type(), FileType::Animated);
+        assert_eq!(embedded.playback(), Some((0.25, PlayType::OnceBoth)));
+        assert_eq!(embedded.get_frame_pixels(1), Some([2, 0].as_slice()));
+        assert_eq!(embedded.get_frame_pixels(2), None);
+        assert_eq!(embedded.get_color(2), Some(BLUE));
+        assert_eq!(embedded.get_color(3), None);
+        let mut buffer = vec![0; 2];
+        embedded.draw_to_buffer(1, &mut buffer, 2, 0, 0, PixelFormat::Argb);
+        assert_eq!(buffer, vec![BLUE.to_argb(), RED.to_argb()]);
+        assert_eq!(embedded.to_owned_image().unwrap(), expected.into());
+
+        let image = IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap();
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap().leak();
+        assert_eq!(
+            StaticImage::parse(bytes).to_owned_image().unwrap(),
+            image.clone().into()
+        );
+        let bytes = image.to_file_contents(&FilePalette::ID(1)).unwrap().leak();
+        assert!(StaticImage::from_file_contents(bytes).is_err());
+        assert!(StaticImage::from_file_contents(&[b'I', b'C', b'I', 1, 1, 3, 1]).is_err());
+        assert!(StaticImage::from_file_contents(b"PNG").is_err());
+    }
+}
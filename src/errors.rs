@@ -1,7 +1,42 @@
-use std::string::FromUtf8Error;
+use crate::compat::*;
+use crate::image::HighestIdx;
+use alloc::string::FromUtf8Error;
+use core::fmt::{Display, Formatter};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+/// Part of a file, used by errors to describe where a problem was found
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileSection {
+    /// Magic, version and file type
+    Header,
+    Palette,
+    /// Width and height
+    Dimensions,
+    /// Play type, frame count and frame timing
+    Animation,
+    Pixels,
+    /// Metadata, tags, levels of detail, etc after the pixels
+    Chunks,
+    /// Bundle entry names, types and positions
+    BundleIndex,
+}
+
+impl Display for FileSection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            FileSection::Header => "header",
+            FileSection::Palette => "palette",
+            FileSection::Dimensions => "dimensions",
+            FileSection::Animation => "animation",
+            FileSection::Pixels => "pixels",
+            FileSection::Chunks => "chunks",
+            FileSection::BundleIndex => "bundle index",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum IndexedImageError {
     #[error("Invalid file header")]
     NotIciFile,
@@ -11,12 +46,28 @@ pub enum IndexedImageError {
     PaletteNameTooShort,
     #[error("Palette name has more than 255 character")]
     PaletteNameTooLong,
-    #[error("Palette has more than 255 colors")]
+    #[error("Palette has more than 256 colors")]
     PaletteTooManyColors,
     #[error("Image requires a palette with at least {0} colors")]
     PaletteTooFewColors(u8),
-    #[error("Invalid file format/contents at {0}: {1}")]
-    InvalidFileFormat(usize, String),
+    #[error(
+        "Incomplete {section} at {offset}: {what} needs {expected} bytes but only {found} remain"
+    )]
+    Truncated {
+        section: FileSection,
+        offset: usize,
+        what: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Invalid {section} at {offset}: {what} should be {expected} but was {found}")]
+    InvalidValue {
+        section: FileSection,
+        offset: usize,
+        what: String,
+        expected: String,
+        found: String,
+    },
     #[error("Palette name was not valid UTF-8")]
     PaletteNameNotUtf8(#[from] FromUtf8Error),
     #[error("ID was greater than palette size")]
@@ -43,4 +94,124 @@ pub enum IndexedImageError {
     InvalidPaletteSize,
     #[error("Hex string has invalid format: {0}")]
     InvalidHexFormat(String),
+    #[error("Target image must be {0}x{1} but was {2}x{3}")]
+    InvalidTargetSize(u8, u8, u8, u8),
+    #[error("Watermark is {0} bytes but image can only hold {1}")]
+    WatermarkTooLarge(usize, usize),
+    #[error("Tile {0} uses {1} colors, more than fit in one bank")]
+    TileNeedsMultipleBanks(usize, usize),
+    #[error("Image size must be a multiple of the tile size {0}x{1}")]
+    InvalidTileSize(u8, u8),
+    #[error("Tile set has {0} tiles but {1} are needed")]
+    TooFewTiles(usize, usize),
+    #[error("Unable to read {0}: {1}")]
+    FileIo(String, String),
+    #[error("Unable to decode image: {0}")]
+    ImageDecoding(String),
+    #[error("Unable to encode image: {0}")]
+    ImageEncoding(String),
+    #[error("Image is {0}x{1} but the max is 255x255")]
+    ImageTooLarge(usize, usize),
+    #[error("Animation must have at least one frame")]
+    NoFrames,
+    #[error("Animation has {0} frames but the max is 65535")]
+    TooManyFrames(usize),
+    #[error("Bundle entry name must be 1..=255 bytes: {0}")]
+    InvalidBundleName(String),
+    #[error("Bundle has more than 65535 entries")]
+    BundleTooManyEntries,
+    #[error("Bundle is too large, the max size is 4GB")]
+    BundleTooLarge,
+    #[error("Metadata key must be 1..=255 bytes, value must be at most 65535 bytes and there can be at most 65535 entries: {0}")]
+    InvalidMetadata(String),
+    #[error("File is corrupt, checksum was {1:08X} but expected {0:08X}")]
+    ChecksumMismatch(u32, u32),
+    #[error("Level of detail {0}x must be 2..=255, unique, the image size multiplied by the scale, have the same number of frames and fit the palette")]
+    InvalidLevelOfDetail(u8),
+    #[error("Frame {0} has {1} pixels but expected {2}")]
+    InvalidFrameSize(usize, usize, usize),
+    #[error("Rect {0},{1} {2}x{3} is empty or not inside the image")]
+    InvalidRect(u8, u8, u8, u8),
+    #[error("Delta expected index {1} at {0} but found {2}")]
+    DeltaMismatch(usize, u8, u8),
+    #[error("Delta expected a different color at palette index {0}")]
+    PaletteDeltaMismatch(usize),
+    #[error("Frame tag name must be 1..=255 bytes and its frames must be in the animation: {0}")]
+    InvalidFrameTag(String),
+    #[error("Invalid playback state: {0}")]
+    InvalidPlaybackState(String),
+}
+
+impl IndexedImageError {
+    /// Position in the file, for errors found while reading a file
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Truncated { offset, .. } | Self::InvalidValue { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// Part of the file, for errors found while reading a file
+    pub fn section(&self) -> Option<FileSection> {
+        match self {
+            Self::Truncated { section, .. } | Self::InvalidValue { section, .. } => Some(*section),
+            _ => None,
+        }
+    }
+}
+
+/// Broken internal state found by `debug_validate`, constructors and safe methods check their input so these
+/// can only happen after misusing `unsafe`/`_unchecked` methods or a bug in this crate
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InvariantViolation {
+    #[error("Size is {0}x{1}, neither can be 0")]
+    ZeroSize(u8, u8),
+    #[error("Palette has {0} colors but must have 1..=256")]
+    InvalidPaletteSize(usize),
+    #[error("There are {0} pixels but expected {1}")]
+    PixelCount(usize, usize),
+    #[error("{1} pixels use colors outside the palette (len {2}), the first is at {0}")]
+    PixelsOutsidePalette(usize, usize, usize),
+    #[error("Highest palette index is recorded as {0} but pixels use {1}")]
+    HighestPaletteIdx(u8, u8),
+    #[error("Frame size is {0} but expected {1}")]
+    FrameSize(usize, usize),
+    #[error("Frame count is {0} but must be 1..=65535")]
+    FrameCount(usize),
+    #[error("Current frame is {0} but there are only {1} frames")]
+    CurrentFrame(usize, usize),
+    #[error("Per frame timing is {0}, it can't be negative")]
+    PerFrame(f64),
+}
+
+/// Checks shared by the `debug_validate` methods
+pub(crate) fn pixel_violations(
+    pixels: &[u8],
+    expected_len: usize,
+    palette_len: usize,
+    highest: HighestIdx,
+) -> Vec<InvariantViolation> {
+    let mut violations = vec![];
+    if !(1..=256).contains(&palette_len) {
+        violations.push(InvariantViolation::InvalidPaletteSize(palette_len));
+    }
+    if pixels.len() != expected_len {
+        violations.push(InvariantViolation::PixelCount(pixels.len(), expected_len));
+    }
+    let mut outside = pixels
+        .iter()
+        .enumerate()
+        .filter(|(_, idx)| **idx as usize >= palette_len);
+    if let Some((first, _)) = outside.next() {
+        violations.push(InvariantViolation::PixelsOutsidePalette(
+            first,
+            outside.count() + 1,
+            palette_len,
+        ));
+    }
+    let actual = pixels.iter().max().copied().unwrap_or_default();
+    if let Err(recorded) = highest.check(actual) {
+        violations.push(InvariantViolation::HighestPaletteIdx(recorded, actual));
+    }
+    violations
 }
@@ -0,0 +1,161 @@
+//! Export images as PNGs at the sizes needed by stores and websites
+//!
+//! Images are scaled by the largest whole number that fits (or shrunk by the smallest whole number if
+//! they're too big) so pixels stay square, and then centered on a background color
+
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Preset {
+    /// itch.io cover image, 630x500
+    ItchCover,
+    /// Steam main capsule, 616x353
+    SteamCapsule,
+    /// Browser favicon, 16x16
+    Favicon16,
+    /// Any size, neither can be 0
+    Custom(u16, u16),
+}
+
+impl Preset {
+    pub fn size(&self) -> (u16, u16) {
+        match self {
+            Preset::ItchCover => (630, 500),
+            Preset::SteamCapsule => (616, 353),
+            Preset::Favicon16 => (16, 16),
+            Preset::Custom(width, height) => (*width, *height),
+        }
+    }
+}
+
+/// RGBA pixels of `image` scaled and centered on `background` at the size of `preset`
+///
+/// Partially transparent pixels are blended with the background
+pub fn preset_rgba(
+    image: &IndexedImage,
+    preset: Preset,
+    background: Color,
+) -> Result<Vec<u8>, IndexedImageError> {
+    let (width, height) = preset.size();
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return Err(InvalidScaleParams(width, height));
+    }
+    let (img_width, img_height) = (image.width() as usize, image.height() as usize);
+    let scale = (width / img_width).min(height / img_height);
+    // output pixels per image pixel as a fraction, shrinking skips image pixels
+    let (mul, div) = if scale > 0 {
+        (scale, 1)
+    } else {
+        (
+            1,
+            img_width.div_ceil(width).max(img_height.div_ceil(height)),
+        )
+    };
+    let (out_width, out_height) = (img_width * mul / div, img_height * mul / div);
+    let (left, top) = ((width - out_width) / 2, (height - out_height) / 2);
+
+    let palette = image.get_palette();
+    let mut output = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let color = if x < left || y < top || x >= left + out_width || y >= top + out_height {
+                background
+            } else {
+                let src_x = (x - left) * div / mul;
+                let src_y = (y - top) * div / mul;
+                let pixel = image.get_pixels()[src_x + src_y * img_width];
+                let color = palette.get(pixel).unwrap_or(TRANSPARENT);
+                match color.a {
+                    0 => background,
+                    255 => color,
+                    _ => background.blend(color),
+                }
+            };
+            output.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+    Ok(output)
+}
+
+/// PNG file contents of `image` scaled and centered on `background` at the size of `preset`
+///
+/// See [preset_rgba]
+pub fn preset(
+    image: &IndexedImage,
+    preset: Preset,
+    background: Color,
+) -> Result<Vec<u8>, IndexedImageError> {
+    let pixels = preset_rgba(image, preset, background)?;
+    let (width, height) = preset.size();
+    rgba_to_png(width as u32, height as u32, &pixels)
+}
+
+/// PNG file contents of `image` at its original size
+pub fn to_png_bytes(image: &IndexedImage) -> Result<Vec<u8>, IndexedImageError> {
+    rgba_to_png(
+        image.width() as u32,
+        image.height() as u32,
+        &image.to_rgba_bytes(),
+    )
+}
+
+/// PNG file contents of RGBA pixel data (4 bytes per pixel)
+pub fn rgba_to_png(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>, IndexedImageError> {
+    let expected = width as usize * height as usize * 4;
+    if pixels.len() != expected {
+        return Err(MissingData(pixels.len(), expected));
+    }
+    let mut bytes = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| ImageEncoding(e.to_string()))?;
+        writer
+            .write_image_data(pixels)
+            .map_err(|e| ImageEncoding(e.to_string()))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scales_and_pads() {
+        let image = IndexedImage::new(2, 1, vec![RED, TRANSPARENT], vec![0, 1]).unwrap();
+        let pixels = preset_rgba(&image, Preset::Custom(5, 3), BLUE).unwrap();
+        let colors: Vec<Color> = pixels
+            .chunks_exact(4)
+            .map(|px| Color::new(px[0], px[1], px[2], px[3]))
+            .collect();
+        #[rustfmt::skip]
+        assert_eq!(colors, vec![
+            RED, RED, BLUE, BLUE, BLUE,
+            RED, RED, BLUE, BLUE, BLUE,
+            BLUE, BLUE, BLUE, BLUE, BLUE,
+        ]);
+
+        let big = IndexedImage::new(32, 32, vec![RED, GREEN], vec![1; 32 * 32]).unwrap();
+        let pixels = preset_rgba(&big, Preset::Favicon16, BLUE).unwrap();
+        assert_eq!(pixels.len(), 16 * 16 * 4);
+        assert!(pixels.chunks_exact(4).all(|px| px == [0, 255, 0, 255]));
+        assert!(preset_rgba(&big, Preset::Custom(0, 5), BLUE).is_err());
+    }
+
+    #[test]
+    fn png() {
+        let image = IndexedImage::new(2, 2, vec![RED], vec![0; 4]).unwrap();
+        let bytes = preset(&image, Preset::ItchCover, BLACK).unwrap();
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, 630);
+        assert_eq!(reader.info().height, 500);
+    }
+}
@@ -0,0 +1,247 @@
+//! C API for using images and animations from other languages
+//!
+//! Build a shared library with `cargo rustc --release --features ffi --crate-type cdylib`
+//!
+//! Every function that takes an `IciHandle` pointer accepts null and does nothing (returning 0/false/null).
+//! Handles are created with [ici_load] and must be released with [ici_free], byte buffers from [ici_save]
+//! must be released with [ici_free_bytes].
+//!
+//! ```c
+//! IciHandle *image = ici_load(data, len);
+//! if (!image) { ici_last_error(msg, sizeof(msg)); }
+//! size_t size = ici_get_rgba(image, NULL, 0);
+//! uint8_t *rgba = malloc(size);
+//! ici_set_animate(image, true);
+//! // every frame
+//! if (ici_update(image, delta)) { ici_get_rgba(image, rgba, size); }
+//! ici_free(image);
+//! ```
+
+use crate::palette::FilePalette;
+use crate::prelude::*;
+use std::cell::RefCell;
+use std::ffi::c_char;
+use std::ptr::null_mut;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: IndexedImageError) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(err.to_string()));
+}
+
+/// Opaque image or animation
+#[derive(Debug, Clone, PartialEq)]
+pub struct IciHandle {
+    image: IndexedWrapper,
+}
+
+/// Read an image or animation file, returns null if the file is invalid (see [ici_last_error])
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn ici_load(data: *const u8, len: usize) -> *mut IciHandle {
+    if data.is_null() {
+        return null_mut();
+    }
+    let bytes = std::slice::from_raw_parts(data, len);
+    match IndexedWrapper::from_file_contents(bytes) {
+        Ok((image, _)) => Box::into_raw(Box::new(IciHandle { image })),
+        Err(err) => {
+            set_last_error(err);
+            null_mut()
+        }
+    }
+}
+
+/// Release a handle
+///
+/// # Safety
+///
+/// `handle` must be null or from [ici_load], and not used after this
+#[no_mangle]
+pub unsafe extern "C" fn ici_free(handle: *mut IciHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Write the image to file contents (including palette colors), the length is written to `out_len`
+///
+/// Returns null on failure (see [ici_last_error])
+///
+/// # Safety
+///
+/// `handle` must be null or from [ici_load], `out_len` must be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn ici_save(handle: *const IciHandle, out_len: *mut usize) -> *mut u8 {
+    let Some(handle) = handle.as_ref() else {
+        return null_mut();
+    };
+    if out_len.is_null() {
+        return null_mut();
+    }
+    match handle.image.to_file_contents(&FilePalette::Colors) {
+        Ok(bytes) => {
+            let bytes = bytes.into_boxed_slice();
+            *out_len = bytes.len();
+            Box::into_raw(bytes) as *mut u8
+        }
+        Err(err) => {
+            set_last_error(err);
+            null_mut()
+        }
+    }
+}
+
+/// Release bytes from [ici_save]
+///
+/// # Safety
+///
+/// `data` and `len` must be from [ici_save], and not used after this
+#[no_mangle]
+pub unsafe extern "C" fn ici_free_bytes(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// # Safety
+///
+/// `handle` must be null or from [ici_load]
+#[no_mangle]
+pub unsafe extern "C" fn ici_width(handle: *const IciHandle) -> u8 {
+    handle.as_ref().map(|h| h.image.width()).unwrap_or(0)
+}
+
+/// # Safety
+///
+/// `handle` must be null or from [ici_load]
+#[no_mangle]
+pub unsafe extern "C" fn ici_height(handle: *const IciHandle) -> u8 {
+    handle.as_ref().map(|h| h.image.height()).unwrap_or(0)
+}
+
+/// # Safety
+///
+/// `handle` must be null or from [ici_load]
+#[no_mangle]
+pub unsafe extern "C" fn ici_frame_count(handle: *const IciHandle) -> u16 {
+    handle.as_ref().map(|h| h.image.frame_count()).unwrap_or(0)
+}
+
+/// # Safety
+///
+/// `handle` must be null or from [ici_load]
+#[no_mangle]
+pub unsafe extern "C" fn ici_set_animate(handle: *mut IciHandle, animate: bool) {
+    if let Some(handle) = handle.as_mut() {
+        handle.image.set_animate(animate);
+    }
+}
+
+/// Advance the animation by `delta` seconds, returns true if the frame changed and the image should be redrawn
+///
+/// # Safety
+///
+/// `handle` must be null or from [ici_load]
+#[no_mangle]
+pub unsafe extern "C" fn ici_update(handle: *mut IciHandle, delta: f64) -> bool {
+    handle
+        .as_mut()
+        .map(|h| h.image.update_with_events(delta).is_some())
+        .unwrap_or(false)
+}
+
+/// Copy the RGBA pixels of the current frame into `out`
+///
+/// Returns the number of bytes needed (`width * height * 4`), nothing is written if `out` is null or `out_len` is too small
+///
+/// # Safety
+///
+/// `handle` must be null or from [ici_load], `out` must be null or valid for writes of `out_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn ici_get_rgba(
+    handle: *const IciHandle,
+    out: *mut u8,
+    out_len: usize,
+) -> usize {
+    let Some(handle) = handle.as_ref() else {
+        return 0;
+    };
+    let rgba = match &handle.image {
+        IndexedWrapper::Static(img) => img.to_rgba_bytes(),
+        IndexedWrapper::Animated(img) => img.current_frame_rgba(),
+    };
+    if !out.is_null() && out_len >= rgba.len() {
+        std::ptr::copy_nonoverlapping(rgba.as_ptr(), out, rgba.len());
+    }
+    rgba.len()
+}
+
+/// Copy the last error message on this thread into `out` as a nul terminated string
+///
+/// Returns the number of bytes needed including the terminator (0 if there's been no error), nothing is written if `out` is null or `out_len` is too small
+///
+/// # Safety
+///
+/// `out` must be null or valid for writes of `out_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn ici_last_error(out: *mut c_char, out_len: usize) -> usize {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        None => 0,
+        Some(msg) => {
+            let needed = msg.len() + 1;
+            if !out.is_null() && out_len >= needed {
+                std::ptr::copy_nonoverlapping(msg.as_ptr(), out as *mut u8, msg.len());
+                *out.add(msg.len()) = 0;
+            }
+            needed
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn round_trip() {
+        let animation =
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED, BLUE], vec![0, 1], PlayType::Loops)
+                .unwrap();
+        let bytes = animation.to_file_contents(&FilePalette::Colors).unwrap();
+        unsafe {
+            let handle = ici_load(bytes.as_ptr(), bytes.len());
+            assert!(!handle.is_null());
+            assert_eq!(ici_width(handle), 1);
+            assert_eq!(ici_frame_count(handle), 2);
+            let mut rgba = [0; 4];
+            assert_eq!(ici_get_rgba(handle, null_mut(), 0), 4);
+            assert_eq!(ici_get_rgba(handle, rgba.as_mut_ptr(), rgba.len()), 4);
+            assert_eq!(rgba, [255, 0, 0, 255]);
+            ici_set_animate(handle, true);
+            assert!(!ici_update(handle, 0.11));
+            assert!(ici_update(handle, 0.01));
+            ici_get_rgba(handle, rgba.as_mut_ptr(), rgba.len());
+            assert_eq!(rgba, [0, 0, 255, 255]);
+
+            let mut len = 0;
+            let saved = ici_save(handle, &mut len);
+            assert_eq!(std::slice::from_raw_parts(saved, len), bytes.as_slice());
+            ici_free_bytes(saved, len);
+            ici_free(handle);
+
+            assert!(ici_load(bytes.as_ptr(), 3).is_null());
+            let mut msg = [0; 128];
+            let needed = ici_last_error(msg.as_mut_ptr(), msg.len());
+            assert!(needed > 1);
+            assert!(!CStr::from_ptr(msg.as_ptr()).to_bytes().is_empty());
+            assert_eq!(ici_width(null_mut()), 0);
+        }
+    }
+}
@@ -1,6 +1,17 @@
-use crate::errors::IndexedImageError;
+use crate::animated::{AnimationParts, PlayType};
+use crate::bundle::LazyBundle;
+use crate::color::Color;
+use crate::compat::*;
 use crate::errors::IndexedImageError::*;
+use crate::errors::{FileSection, IndexedImageError};
 use crate::file::FileType::*;
+use crate::metadata;
+use crate::palette;
+use crate::palette::FilePalette;
+use crate::playback::PlaybackState;
+use crate::reader::ByteReader;
+use crate::tags::FrameTag;
+use alloc::collections::BTreeMap;
 
 //last is file version
 pub(crate) const HEADER: [u8; 4] = [b'I', b'C', b'I', 1];
@@ -9,6 +20,7 @@ pub(crate) const HEADER: [u8; 4] = [b'I', b'C', b'I', 1];
 pub enum FileType {
     Image,
     Animated,
+    Bundle,
 }
 
 impl FileType {
@@ -16,6 +28,7 @@ impl FileType {
         match self {
             Image => 1,
             Animated => 2,
+            Bundle => 3,
         }
     }
 
@@ -23,6 +36,7 @@ impl FileType {
         match byte {
             1 => Some(Image),
             2 => Some(Animated),
+            3 => Some(Bundle),
             _ => None,
         }
     }
@@ -31,6 +45,7 @@ impl FileType {
         match self {
             Image => "Image",
             Animated => "Animated Image",
+            Bundle => "Bundle",
         }
     }
 
@@ -38,20 +53,440 @@ impl FileType {
         match self {
             Image => "ici",
             Animated => "ica",
+            Bundle => "icb",
         }
     }
 }
 
-pub(super) fn verify_format(bytes: &[u8]) -> Result<FileType, IndexedImageError> {
+/// Version 2 uses u16 for sizes and counts
+pub(crate) const HEADER_V2: [u8; 4] = [b'I', b'C', b'I', 2];
+
+/// Returns the file version and type
+pub fn verify_format_version(bytes: &[u8]) -> Result<(u8, FileType), IndexedImageError> {
     if bytes.len() < 10 {
         return Err(NotIciFile);
     }
-    if bytes[0..HEADER.len()] != HEADER {
+    if bytes[0..3] != HEADER[0..3] {
         return Err(NotIciFile);
     }
+    verify_checksum(bytes)?;
+    let version = bytes[3];
+    if version != HEADER[3] && version != HEADER_V2[3] {
+        return Err(UnknownIciVersion(version));
+    }
     let format = bytes[HEADER.len()];
     match FileType::from_byte(format) {
         None => Err(UnknownIciVersion(format)),
-        Some(file_type) => Ok(file_type),
+        Some(file_type) => Ok((version, file_type)),
+    }
+}
+
+/// Reads the magic, version and file type, which must already have been checked by [verify_format_version]
+pub(crate) fn read_header(reader: &mut ByteReader) -> Result<(), IndexedImageError> {
+    reader.string(HEADER.len() - 1, "magic")?;
+    reader.u8("version")?;
+    reader.u8("file type")?;
+    Ok(())
+}
+
+/// Error for a file that isn't `expected`, such as an animation when an image is needed
+pub(crate) fn wrong_file_type(expected: &str, found: &FileType) -> IndexedImageError {
+    InvalidValue {
+        section: FileSection::Header,
+        offset: HEADER.len(),
+        what: "file type".to_string(),
+        expected: expected.to_string(),
+        found: found.name().to_string(),
+    }
+}
+
+pub(crate) const CHECKSUM_TAG: [u8; 4] = *b"CRC ";
+pub(crate) const CHECKSUM_CHUNK_LEN: usize = 12;
+
+/// CRC-32 (as used by PNG and zip)
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Appends a checksum chunk containing the CRC-32 of everything already in `output`
+///
+/// This must be the last chunk in the file
+pub(crate) fn append_checksum(output: &mut Vec<u8>) {
+    let crc = crc32(output);
+    metadata::write_chunk(CHECKSUM_TAG, &crc.to_be_bytes(), output);
+}
+
+/// If the file ends with a checksum chunk then verify the rest of the file matches it
+///
+/// Files without a checksum are always valid
+pub(crate) fn verify_checksum(bytes: &[u8]) -> Result<(), IndexedImageError> {
+    if bytes.len() < CHECKSUM_CHUNK_LEN {
+        return Ok(());
+    }
+    let (content, chunk) = bytes.split_at(bytes.len() - CHECKSUM_CHUNK_LEN);
+    if chunk[0..4] != CHECKSUM_TAG || chunk[4..8] != 4_u32.to_be_bytes() {
+        return Ok(());
+    }
+    let expected = u32::from_be_bytes([chunk[8], chunk[9], chunk[10], chunk[11]]);
+    let actual = crc32(content);
+    if expected != actual {
+        return Err(ChecksumMismatch(expected, actual));
+    }
+    Ok(())
+}
+
+/// Differences between two image files, from [compare_files]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiffSummary {
+    pub file_types: (FileType, FileType),
+    pub versions: (u8, u8),
+    pub palette_types: (FilePalette, FilePalette),
+    /// Palette colors that are different or only in one file, 0 if neither file includes colors
+    pub changed_colors: usize,
+    pub sizes: ((u8, u8), (u8, u8)),
+    /// Static images have 1 frame
+    pub frame_counts: (u16, u16),
+    /// Pixels with different color indexes, `None` if the sizes or frame counts are different
+    pub changed_pixels: Option<usize>,
+    pub metadata_changed: bool,
+}
+
+impl FileDiffSummary {
+    /// True if the files have the same palette, pixels and metadata
+    ///
+    /// Whether a checksum was included isn't compared
+    pub fn is_identical(&self) -> bool {
+        self.file_types.0 == self.file_types.1
+            && self.versions.0 == self.versions.1
+            && self.palette_types.0 == self.palette_types.1
+            && self.changed_colors == 0
+            && self.changed_pixels == Some(0)
+            && !self.metadata_changed
+    }
+}
+
+/// Image or animation file contents, without building the image
+pub(crate) struct FileParts<'a> {
+    pub(crate) version: u8,
+    pub(crate) file_type: FileType,
+    pub(crate) pal_type: FilePalette,
+    pub(crate) colors: Option<Vec<Color>>,
+    pub(crate) size: (u8, u8),
+    pub(crate) frame_count: u16,
+    /// per frame time and play type, for animations
+    pub(crate) playback: Option<(f64, PlayType)>,
+    pub(crate) pixels: &'a [u8],
+    pub(crate) metadata: BTreeMap<String, String>,
+    /// for animations
+    pub(crate) tags: BTreeMap<String, FrameTag>,
+    /// for animations
+    pub(crate) state: Option<PlaybackState>,
+}
+
+impl<'a> FileParts<'a> {
+    pub(crate) fn read(bytes: &'a [u8]) -> Result<Self, IndexedImageError> {
+        FileParts::read_from(bytes, &mut ByteReader::new(bytes))
+    }
+
+    /// Like [FileParts::read] but reading with `reader`, which must be at the start of `bytes`
+    fn read_from(bytes: &'a [u8], reader: &mut ByteReader<'a>) -> Result<Self, IndexedImageError> {
+        let (version, file_type) = verify_format_version(bytes)?;
+        match file_type {
+            Image => {
+                let v2 = version == HEADER_V2[3];
+                read_header(reader)?;
+                let (pal_type, colors) = palette::read(reader)?;
+                reader.set_section(FileSection::Dimensions);
+                let width = reader.size(v2, "width")?;
+                let height = reader.size(v2, "height")?;
+                if width > 255 || height > 255 {
+                    return Err(ImageTooLarge(width, height));
+                }
+                if width == 0 {
+                    return Err(WidthIsZero);
+                }
+                if height == 0 {
+                    return Err(HeightIsZero);
+                }
+                reader.set_section(FileSection::Pixels);
+                let palette_len = colors.as_ref().map(Vec::len);
+                let pixels = reader.pixels(width * height, palette_len, "pixels data")?;
+                let metadata = metadata::read(reader.offset(), reader.remaining())?;
+                Ok(Self {
+                    version,
+                    file_type,
+                    pal_type,
+                    colors,
+                    size: (width as u8, height as u8),
+                    frame_count: 1,
+                    playback: None,
+                    pixels,
+                    metadata,
+                    tags: BTreeMap::new(),
+                    state: None,
+                })
+            }
+            Animated => {
+                let parts = AnimationParts::read_from(bytes, reader)?;
+                Ok(Self {
+                    version,
+                    file_type,
+                    pal_type: parts.pal_type,
+                    colors: parts.colors,
+                    size: (parts.width, parts.height),
+                    frame_count: parts.frame_count,
+                    playback: Some((parts.per_frame, parts.play_type)),
+                    pixels: parts.pixels,
+                    metadata: parts.metadata,
+                    tags: parts.tags,
+                    state: parts.state,
+                })
+            }
+            Bundle => Err(wrong_file_type("an image", &file_type)),
+        }
+    }
+}
+
+/// Compare two image or animation files without building the images, i.e. to summarise asset changes
+///
+/// Returns an error if either file is invalid or a bundle
+pub fn compare_files(a: &[u8], b: &[u8]) -> Result<FileDiffSummary, IndexedImageError> {
+    let a = FileParts::read(a)?;
+    let b = FileParts::read(b)?;
+    let changed_colors = match (&a.colors, &b.colors) {
+        (Some(a), Some(b)) => {
+            let changed = a.iter().zip(b).filter(|(a, b)| a != b).count();
+            changed + a.len().abs_diff(b.len())
+        }
+        (Some(colors), None) | (None, Some(colors)) => colors.len(),
+        (None, None) => 0,
+    };
+    let changed_pixels = (a.size == b.size && a.frame_count == b.frame_count).then(|| {
+        a.pixels
+            .iter()
+            .zip(b.pixels)
+            .filter(|(a, b)| a != b)
+            .count()
+    });
+    Ok(FileDiffSummary {
+        metadata_changed: a.metadata != b.metadata,
+        file_types: (a.file_type, b.file_type),
+        versions: (a.version, b.version),
+        palette_types: (a.pal_type, b.pal_type),
+        changed_colors,
+        sizes: (a.size, b.size),
+        frame_counts: (a.frame_count, b.frame_count),
+        changed_pixels,
+    })
+}
+
+/// Bytes shown per field by [describe], longer fields are truncated
+const DESCRIBE_BYTES: usize = 8;
+
+/// Annotated breakdown of an image, animation or bundle file, i.e. to debug files from other tools
+///
+/// Each line has the offset, bytes, field name and decoded value of a field read by the parser.
+/// Invalid files are described up to the problem, followed by the error
+pub fn describe(bytes: &[u8]) -> String {
+    let mut reader = ByteReader::traced(bytes);
+    let file_type = verify_format_version(bytes).map(|(_, file_type)| file_type);
+    let is_bundle = matches!(file_type, Ok(Bundle));
+    let result = match file_type {
+        Ok(Bundle) => LazyBundle::read_from(bytes, &mut reader).map(|_| ()),
+        Ok(_) => FileParts::read_from(bytes, &mut reader).map(|_| ()),
+        Err(e) => Err(e),
+    };
+    let mut output = String::new();
+    for field in reader.fields() {
+        describe_field(
+            &mut output,
+            bytes,
+            field.offset,
+            field.len,
+            &field.what,
+            field.value.as_deref(),
+        );
+    }
+    if let Err(e) = result {
+        output.push_str(&format!("error: {e}\n"));
+        return output;
+    }
+    let mut start = reader.offset();
+    if is_bundle {
+        let has_checksum = bytes.len() >= start + CHECKSUM_CHUNK_LEN
+            && bytes[bytes.len() - CHECKSUM_CHUNK_LEN..].starts_with(&CHECKSUM_TAG);
+        let end = if has_checksum {
+            bytes.len() - CHECKSUM_CHUNK_LEN
+        } else {
+            bytes.len()
+        };
+        describe_field(&mut output, bytes, start, end - start, "entry data", None);
+        start = end;
+    }
+    let chunks = metadata::read_chunks(&bytes[start..]);
+    let mut end = start;
+    for (chunk_offset, tag, payload) in chunks {
+        let offset = start + chunk_offset;
+        let name = String::from_utf8_lossy(&tag);
+        describe_field(&mut output, bytes, offset, 4, "chunk tag", Some(&name));
+        let len = payload.len().to_string();
+        describe_field(
+            &mut output,
+            bytes,
+            offset + 4,
+            4,
+            "chunk length",
+            Some(&len),
+        );
+        let value = match payload {
+            [a, b, c, d] if tag == CHECKSUM_TAG => {
+                Some(format!("{:08X}", u32::from_be_bytes([*a, *b, *c, *d])))
+            }
+            _ => None,
+        };
+        describe_field(
+            &mut output,
+            bytes,
+            offset + 8,
+            payload.len(),
+            &format!("{} data", name.trim_end()),
+            value.as_deref(),
+        );
+        end = offset + 8 + payload.len();
+    }
+    if end < bytes.len() {
+        describe_field(
+            &mut output,
+            bytes,
+            end,
+            bytes.len() - end,
+            "unknown data",
+            None,
+        );
+    }
+    output
+}
+
+fn describe_field(
+    output: &mut String,
+    bytes: &[u8],
+    offset: usize,
+    len: usize,
+    what: &str,
+    value: Option<&str>,
+) {
+    let shown = &bytes[offset..offset + len.min(DESCRIBE_BYTES)];
+    let mut hex: Vec<String> = shown.iter().map(|byte| format!("{byte:02X}")).collect();
+    if len > DESCRIBE_BYTES {
+        hex.push("..".to_string());
+    }
+    let hex = hex.join(" ");
+    let line = match value {
+        Some(value) => format!("{offset:08X}  {hex:<26}  {what}: {value}"),
+        None if len > DESCRIBE_BYTES => format!("{offset:08X}  {hex:<26}  {what} ({len} bytes)"),
+        None => format!("{offset:08X}  {hex:<26}  {what}"),
+    };
+    output.push_str(line.trim_end());
+    output.push('\n');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut bytes = vec![b'I', b'C', b'I', 1, 1, 0, 1, 1, 1, 0];
+        append_checksum(&mut bytes);
+        assert!(verify_format_version(&bytes).is_ok());
+        bytes[9] = 1;
+        assert!(matches!(
+            verify_format_version(&bytes),
+            Err(ChecksumMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn compare() {
+        use crate::prelude::*;
+        let image = IndexedImage::new(2, 1, vec![RED, GREEN], vec![0, 1]).unwrap();
+        let a = image.to_file_contents(&FilePalette::Colors).unwrap();
+        let summary = compare_files(&a, &a).unwrap();
+        assert!(summary.is_identical());
+
+        let mut edited = image.clone();
+        edited.set_pixel(0, 1).unwrap();
+        edited.set_color(0, BLUE).unwrap();
+        let b = edited
+            .to_file_contents_with_checksum(&FilePalette::Colors)
+            .unwrap();
+        let summary = compare_files(&a, &b).unwrap();
+        assert!(!summary.is_identical());
+        assert_eq!(summary.changed_colors, 1);
+        assert_eq!(summary.changed_pixels, Some(1));
+        assert!(!summary.metadata_changed);
+
+        let animated = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let c = animated.to_file_contents(&FilePalette::NoData).unwrap();
+        let summary = compare_files(&a, &c).unwrap();
+        assert_eq!(summary.file_types, (Image, Animated));
+        assert_eq!(summary.frame_counts, (1, 2));
+        assert_eq!(summary.changed_colors, 2);
+        assert_eq!(summary.changed_pixels, None);
+        assert!(compare_files(&a, &[0; 12]).is_err());
+    }
+
+    #[test]
+    fn describe_file() {
+        let mut image = IndexedImage::new(2, 1, vec![RED, GREEN], vec![0, 1]).unwrap();
+        image.set_metadata("author", "me").unwrap();
+        let bytes = image
+            .to_file_contents_with_checksum(&FilePalette::Colors)
+            .unwrap();
+        let text = describe(&bytes);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "00000000  49 43 49                    magic: ICI");
+        assert_eq!(
+            lines[2],
+            "00000004  01                          file type: 1"
+        );
+        assert!(text.contains("  width: 2\n"));
+        assert!(text.contains("  chunk tag: META\n"));
+        assert!(lines[lines.len() - 1].ends_with(&format!(
+            "CRC data: {:08X}",
+            crc32(&bytes[..bytes.len() - 12])
+        )));
+
+        let text = describe(&bytes[..8]);
+        assert!(text.starts_with("error: "));
+        let mut truncated = image.to_file_contents(&FilePalette::Colors).unwrap();
+        truncated.truncate(16);
+        let text = describe(&truncated);
+        assert!(text.contains("palette color count: 2\n"));
+        assert!(text.lines().last().unwrap().starts_with("error: "));
     }
 }
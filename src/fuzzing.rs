@@ -0,0 +1,191 @@
+//! [Arbitrary](::arbitrary::Arbitrary) implementations for fuzzing
+//!
+//! Generated images are always valid and, when saved with [FilePalette::Colors], will be read back unchanged
+
+use crate::metadata;
+use crate::prelude::*;
+use ::arbitrary::{Arbitrary, Result, Unstructured};
+use alloc::collections::BTreeMap;
+
+impl<'a> Arbitrary<'a> for Color {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let [r, g, b, a] = u.arbitrary::<[u8; 4]>()?;
+        Ok(Color::new(r, g, b, a))
+    }
+
+    fn size_hint(_: usize) -> (usize, Option<usize>) {
+        (4, Some(4))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Palette {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let len = u.int_in_range(1..=Palette::MAX_COLORS)?;
+        let colors = (0..len)
+            .map(|_| Color::arbitrary(u))
+            .collect::<Result<Vec<Color>>>()?;
+        Palette::new(colors).map_err(|_| ::arbitrary::Error::IncorrectFormat)
+    }
+}
+
+impl<'a> Arbitrary<'a> for PlayType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[
+            PlayType::Once,
+            PlayType::OnceReversed,
+            PlayType::Loops,
+            PlayType::LoopsReversed,
+            PlayType::LoopsBoth,
+            PlayType::OnceBoth,
+        ])?)
+    }
+
+    fn size_hint(_: usize) -> (usize, Option<usize>) {
+        (1, Some(1))
+    }
+}
+
+impl<'a> Arbitrary<'a> for FilePalette {
+    /// Names are always 1..=255 bytes so they can be written
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=3)? {
+            0 => FilePalette::NoData,
+            1 => FilePalette::ID(u.arbitrary()?),
+            2 => FilePalette::Name(name(u)?),
+            _ => FilePalette::Colors,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for IndexedImage {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let width = u.int_in_range(1..=u8::MAX)?;
+        let height = u.int_in_range(1..=u8::MAX)?;
+        let palette = Palette::arbitrary(u)?;
+        let pixels = pixels(u, width as usize * height as usize, palette.len())?;
+        let mut image = IndexedImage::new(width, height, palette.into_vec(), pixels)
+            .map_err(|_| ::arbitrary::Error::IncorrectFormat)?;
+        for (key, value) in arbitrary_metadata(u)? {
+            image
+                .set_metadata(&key, &value)
+                .map_err(|_| ::arbitrary::Error::IncorrectFormat)?;
+        }
+        Ok(image)
+    }
+}
+
+impl<'a> Arbitrary<'a> for AnimatedIndexedImage {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let width = u.int_in_range(1..=u8::MAX)?;
+        let height = u.int_in_range(1..=u8::MAX)?;
+        // limit the frame count by the input size, so small inputs can't create huge animations
+        let frame_size = width as usize * height as usize;
+        let max_frames = (u.len() / frame_size).clamp(1, u16::MAX as usize) as u16;
+        let frame_count = u.int_in_range(1..=max_frames)?;
+        // per frame time must be positive to be read from a file
+        let per_frame = u.int_in_range(1..=u32::MAX)? as f64 / 1000.0;
+        let play_type = PlayType::arbitrary(u)?;
+        let palette = Palette::arbitrary(u)?;
+        let pixel_count = frame_size * frame_count as usize;
+        let pixels = pixels(u, pixel_count, palette.len())?;
+        let mut image = AnimatedIndexedImage::new(
+            width,
+            height,
+            per_frame,
+            frame_count,
+            palette.into_vec(),
+            pixels,
+            play_type,
+        )
+        .map_err(|_| ::arbitrary::Error::IncorrectFormat)?;
+        for (key, value) in arbitrary_metadata(u)? {
+            image
+                .set_metadata(&key, &value)
+                .map_err(|_| ::arbitrary::Error::IncorrectFormat)?;
+        }
+        Ok(image)
+    }
+}
+
+/// Pixels are read directly from the input (wrapped to the palette) to keep large images fast to generate
+fn pixels(u: &mut Unstructured, count: usize, palette_len: usize) -> Result<Vec<u8>> {
+    let available = u.len().min(count);
+    let mut pixels: Vec<u8> = u
+        .bytes(available)?
+        .iter()
+        .map(|i| (*i as usize % palette_len) as u8)
+        .collect();
+    pixels.resize(count, 0);
+    Ok(pixels)
+}
+
+/// String of 1..=255 bytes
+fn name(u: &mut Unstructured) -> Result<String> {
+    let mut text = String::arbitrary(u)?;
+    while text.len() > 255 {
+        text.pop();
+    }
+    if text.is_empty() {
+        text.push('_');
+    }
+    Ok(text)
+}
+
+fn arbitrary_metadata(u: &mut Unstructured) -> Result<BTreeMap<String, String>> {
+    let mut output = BTreeMap::new();
+    let count = u.int_in_range(0..=4)?;
+    for _ in 0..count {
+        let key = name(u)?;
+        let mut value = String::arbitrary(u)?;
+        while value.len() > u16::MAX as usize {
+            value.pop();
+        }
+        output.insert(key, value);
+    }
+    if u.arbitrary()? {
+        output.insert(metadata::TOOL.to_string(), "fuzz".to_string());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette::*;
+
+    /// Simple xorshift so the test doesn't need a random number crate
+    fn input(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for seed in 0..32 {
+            let data = input(seed, 4096);
+            let mut u = Unstructured::new(&data);
+            let image = IndexedImage::arbitrary(&mut u).unwrap();
+            let bytes = image.to_file_contents(&Colors).unwrap();
+            assert_eq!(
+                IndexedImage::from_file_contents(&bytes).unwrap(),
+                (image, Colors)
+            );
+            let animated = AnimatedIndexedImage::arbitrary(&mut u).unwrap();
+            let bytes = animated.to_file_contents(&Colors).unwrap();
+            assert_eq!(
+                AnimatedIndexedImage::from_file_contents(&bytes).unwrap(),
+                (animated, Colors)
+            );
+            let palette = FilePalette::arbitrary(&mut u).unwrap();
+            let mut output = vec![];
+            crate::palette::write(&palette, &[RED], false, &mut output).unwrap();
+        }
+    }
+}
@@ -1,23 +1,46 @@
+use crate::compat::*;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+use crate::dither::Dithering;
 use crate::errors::IndexedImageError;
 use crate::errors::IndexedImageError::*;
+use crate::errors::{pixel_violations, InvariantViolation};
 use crate::file::FileType::Image;
-use crate::file::{verify_format, HEADER};
+use crate::file::{
+    append_checksum, read_header, verify_format_version, wrong_file_type, HEADER, HEADER_V2,
+};
+use crate::import::from_rgba_with_palette;
+use crate::metadata;
 use crate::palette;
-use crate::palette::FilePalette;
+use crate::palette::{hue_order, BankAssignment, FilePalette, Palette};
 use crate::prelude::*;
+use crate::reader::ByteReader;
 use crate::scaling::*;
+use alloc::collections::BTreeMap;
+use core::cell::Cell;
+use core::mem;
+use core::ops::{Deref, DerefMut};
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct IndexedImage {
     width: u8,
     height: u8,
-    palette: Vec<Color>,
+    palette: Palette,
     pixels: Vec<u8>,
-    highest_palette_idx: u8,
+    highest_palette_idx: HighestIdx,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    metadata: BTreeMap<String, String>,
+    /// Original colors for [IndexedImage::reset_palette_to_base], not saved in files
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    base_palette: Option<Palette>,
 }
 
 impl IndexedImage {
@@ -33,86 +56,108 @@ impl IndexedImage {
         if height == 0 {
             return Err(HeightIsZero);
         }
-        if palette.is_empty() {
-            return Err(PaletteIsEmpty);
-        }
+        let palette = Palette::new(palette)?;
         if pixels.len() != (width as usize * height as usize) {
             return Err(MissingData(pixels.len(), width as usize * height as usize));
         }
-        let highest_palette_idx = *pixels
-            .iter()
-            .max()
-            .expect("Unable to get highest color index");
+        let highest_palette_idx = HighestIdx::new(&pixels);
+        let highest = highest_palette_idx.get(&pixels);
+        if highest as usize >= palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
         Ok(Self {
             width,
             height,
             palette,
             pixels,
             highest_palette_idx,
+            metadata: BTreeMap::new(),
+            base_palette: None,
         })
     }
 
+    /// Creates an image with all pixels set to 0
+    ///
+    /// If `palette` has more than 256 colors the extra are dropped, if it's empty a single transparent color is used
     pub fn blank(width: u8, height: u8, palette: Vec<Color>) -> Self {
+        IndexedImage::blank_with(width, height, Palette::from_vec_lossy(palette))
+    }
+
+    /// Callers must make sure `pixels` is `width * height` long
+    pub(crate) fn from_parts(width: u8, height: u8, palette: Palette, pixels: Vec<u8>) -> Self {
+        let highest_palette_idx = HighestIdx::new(&pixels);
+        Self {
+            width,
+            height,
+            palette,
+            pixels,
+            highest_palette_idx,
+            metadata: BTreeMap::new(),
+            base_palette: None,
+        }
+    }
+
+    pub(crate) fn blank_with(width: u8, height: u8, palette: Palette) -> Self {
         Self {
             width,
             height,
             palette,
             pixels: vec![0; width as usize * height as usize],
-            highest_palette_idx: 0,
+            highest_palette_idx: HighestIdx::default(),
+            metadata: BTreeMap::new(),
+            base_palette: None,
         }
     }
 }
 
 impl IndexedImage {
     /// Replace palette for image
-    /// Will only return an error if the new palette has less colors than the image needs
+    /// Will return an error if the new palette has less colors than the image needs, is empty or has more than 256 colors
     pub fn set_palette(&mut self, palette: &[Color]) -> Result<(), IndexedImageError> {
-        assert!(!palette.is_empty());
-        if palette.len() < self.highest_palette_idx as usize {
-            return Err(PaletteTooFewColors(self.highest_palette_idx));
+        let palette = Palette::try_from(palette)?;
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
+        if palette.len() <= highest as usize {
+            return Err(PaletteTooFewColors(highest));
         }
-        self.palette = palette.to_vec();
+        self.palette = palette;
         Ok(())
     }
 
     /// Replace palette for image, any pixels outside the new palette will be replaced with `id`
-    /// Will only return an error if id is outside the new palette
+    /// Will return an error if id is outside the new palette, or the palette is empty or has more than 256 colors
     pub fn set_palette_replace_id(
         &mut self,
         palette: &[Color],
         id: u8,
     ) -> Result<(), IndexedImageError> {
-        let new_palette_len = palette.len() as u8;
-        assert!(!palette.is_empty());
-        if new_palette_len <= id {
+        let palette = Palette::try_from(palette)?;
+        let new_palette_len = palette.len();
+        if new_palette_len <= id as usize {
             return Err(IdOutsideOfNewPalette);
         }
-        self.palette = palette.to_vec();
+        self.palette = palette;
         for i in self.pixels.iter_mut() {
-            if *i >= new_palette_len {
+            if *i as usize >= new_palette_len {
                 *i = id;
             }
         }
-        self.highest_palette_idx = *self
-            .pixels
-            .iter()
-            .max()
-            .expect("Unable to get highest color index");
+        self.highest_palette_idx = HighestIdx::new(&self.pixels);
         Ok(())
     }
 
     /// Replace palette for image, any color indexes outside the palette will be expanded with `color`
+    /// This never fails, colors after the first 256 are dropped
     pub fn set_palette_replace_color<C: Into<Color> + Copy>(
         &mut self,
         palette: &[Color],
         color: C,
     ) {
-        assert!(!palette.is_empty());
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
         let mut tmp_pal = palette.to_vec();
-        while tmp_pal.len() <= self.highest_palette_idx as usize {
+        while tmp_pal.len() <= highest as usize {
             tmp_pal.push(color.into());
         }
-        self.palette = tmp_pal;
+        self.palette = Palette::from_vec_lossy(tmp_pal);
     }
 
     #[inline]
@@ -125,15 +170,15 @@ impl IndexedImage {
         if pixel_idx >= self.pixels.len() {
             return Err(IndexOutOfRange(pixel_idx, self.pixels.len(), "pixels"));
         }
-        if color_idx >= self.palette.len() as u8 {
+        if color_idx as usize >= self.palette.len() {
             return Err(IndexOutOfRange(
                 color_idx as usize,
                 self.palette.len(),
                 "palette",
             ));
         }
-        self.pixels[pixel_idx] = color_idx;
-        self.highest_palette_idx = self.highest_palette_idx.max(color_idx);
+        let old = mem::replace(&mut self.pixels[pixel_idx], color_idx);
+        self.highest_palette_idx.write(old, color_idx);
         Ok(())
     }
 
@@ -142,8 +187,8 @@ impl IndexedImage {
     /// Out of bounds may occur
     #[inline]
     pub unsafe fn set_pixel_unchecked(&mut self, pixel_idx: usize, color_idx: u8) {
-        self.highest_palette_idx = self.highest_palette_idx.max(color_idx);
-        self.pixels[pixel_idx] = color_idx;
+        let old = mem::replace(&mut self.pixels[pixel_idx], color_idx);
+        self.highest_palette_idx.write(old, color_idx);
     }
 
     #[inline]
@@ -151,6 +196,96 @@ impl IndexedImage {
         &self.pixels
     }
 
+    /// Iterate over pixels as `(x, y, color index)`, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u8, u8, u8)> + '_ {
+        let width = self.width as usize;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(i, idx)| ((i % width) as u8, (i / width) as u8, *idx))
+    }
+
+    /// Iterate over pixels as `(x, y, color)`, row by row
+    ///
+    /// [TRANSPARENT] is used for any index outside the palette
+    pub fn colors(&self) -> impl Iterator<Item = (u8, u8, Color)> + '_ {
+        self.pixels()
+            .map(|(x, y, idx)| (x, y, self.palette.get(idx).unwrap_or(TRANSPARENT)))
+    }
+
+    /// True if both images are the same size and every pixel is the same color, regardless of how the
+    /// palettes are ordered
+    ///
+    /// Fully transparent colors are all treated as the same, as they render identically
+    pub fn visually_equal(&self, other: &IndexedImage) -> bool {
+        let visible = |color: Color| if color.a == 0 { TRANSPARENT } else { color };
+        self.size() == other.size()
+            && self
+                .colors()
+                .zip(other.colors())
+                .all(|((_, _, a), (_, _, b))| visible(a) == visible(b))
+    }
+
+    /// Color indexes of row `y`
+    pub fn row(&self, y: u8) -> Result<&[u8], IndexedImageError> {
+        if y >= self.height {
+            return Err(IndexOutOfRange(y as usize, self.height as usize, "height"));
+        }
+        let start = y as usize * self.width as usize;
+        Ok(&self.pixels[start..start + self.width as usize])
+    }
+
+    /// Mutable color indexes of row `y`
+    ///
+    /// When the returned row is dropped any indexes outside the palette are replaced with 0
+    pub fn row_mut(&mut self, y: u8) -> Result<RowMut<'_>, IndexedImageError> {
+        if y >= self.height {
+            return Err(IndexOutOfRange(y as usize, self.height as usize, "height"));
+        }
+        let start = y as usize * self.width as usize;
+        let end = start + self.width as usize;
+        Ok(RowMut {
+            old_highest: max_index(&self.pixels[start..end]),
+            pixels: &mut self.pixels,
+            start,
+            end,
+            palette_len: self.palette.len(),
+            highest: &mut self.highest_palette_idx,
+        })
+    }
+
+    /// Color indexes of column `x`, from top to bottom
+    pub fn column(&self, x: u8) -> Result<impl Iterator<Item = u8> + '_, IndexedImageError> {
+        if x >= self.width {
+            return Err(IndexOutOfRange(x as usize, self.width as usize, "width"));
+        }
+        Ok(self
+            .pixels
+            .iter()
+            .skip(x as usize)
+            .step_by(self.width as usize)
+            .copied())
+    }
+
+    /// Iterate over pixels as `(x, y, pixel)`, row by row, where the pixel's color index can be changed
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = (u8, u8, PixelMut<'_>)> + '_ {
+        let width = self.width as usize;
+        let palette_len = self.palette.len();
+        let highest = Cell::from_mut(&mut self.highest_palette_idx);
+        let pixels = Cell::from_mut(self.pixels.as_mut_slice()).as_slice_of_cells();
+        pixels.iter().enumerate().map(move |(i, value)| {
+            (
+                (i % width) as u8,
+                (i / width) as u8,
+                PixelMut {
+                    value,
+                    palette_len,
+                    highest,
+                },
+            )
+        })
+    }
+
     #[inline]
     pub fn get_pixel(&self, pixel_idx: usize) -> Result<u8, IndexedImageError> {
         if pixel_idx >= self.pixels.len() {
@@ -187,7 +322,7 @@ impl IndexedImage {
 
     #[inline]
     pub fn get_color(&self, idx: u8) -> Result<Color, IndexedImageError> {
-        if idx >= self.palette.len() as u8 {
+        if idx as usize >= self.palette.len() {
             return Err(IndexOutOfRange(idx as usize, self.palette.len(), "palette"));
         }
         Ok(self.palette[idx as usize])
@@ -203,13 +338,19 @@ impl IndexedImage {
 
     #[inline]
     pub fn set_color(&mut self, idx: u8, color: Color) -> Result<(), IndexedImageError> {
-        if idx >= self.palette.len() as u8 {
+        if idx as usize >= self.palette.len() {
             return Err(IndexOutOfRange(idx as usize, self.palette.len(), "palette"));
         }
         self.palette[idx as usize] = color;
         Ok(())
     }
 
+    /// Set several palette colors at once, if any index is outside the palette then no colors are changed
+    #[inline]
+    pub fn set_colors(&mut self, colors: &[(u8, Color)]) -> Result<(), IndexedImageError> {
+        self.palette.set_colors(colors)
+    }
+
     /// # Safety
     ///
     /// Out of bounds may occur
@@ -219,13 +360,188 @@ impl IndexedImage {
     }
 
     #[inline]
-    pub fn get_palette(&self) -> &[Color] {
+    pub fn get_palette(&self) -> &Palette {
         &self.palette
     }
 
+    /// Reorder the palette and update the pixels to match, so the image looks the same
+    ///
+    /// Returns a table mapping each old index to its new index
+    pub fn normalize_palette(&mut self, order: PaletteSort) -> Vec<u8> {
+        let remap = match order {
+            PaletteSort::Luminance => self
+                .palette
+                .sort_by_key(|color| (color.brightness() * 1_000_000.0) as u32),
+            PaletteSort::Hue => self.palette.sort_by_hue(),
+            PaletteSort::Usage => {
+                let mut counts = vec![0_usize; self.palette.len()];
+                for idx in &self.pixels {
+                    if let Some(count) = counts.get_mut(*idx as usize) {
+                        *count += 1;
+                    }
+                }
+                let mut order: Vec<usize> = (0..counts.len()).collect();
+                order.sort_by_key(|i| core::cmp::Reverse(counts[*i]));
+                self.palette.reorder(order)
+            }
+        };
+        self.remap_indexes(&remap);
+        remap
+    }
+
+    /// Merge identical palette colors and update the pixels to match, so the image looks the same
+    ///
+    /// Returns a table mapping each old index to its new index
+    pub fn dedup_palette(&mut self) -> Vec<u8> {
+        let remap = self.palette.dedup();
+        self.remap_indexes(&remap);
+        remap
+    }
+
+    /// Remove palette colors that no pixel uses, shifting the indexes of later colors down
+    ///
+    /// Returns the number of colors removed
+    pub fn prune_unused_colors(&mut self) -> usize {
+        let remap = palette::prune_unused(&mut self.palette, &self.pixels);
+        for idx in self.pixels.iter_mut() {
+            *idx = remap.get(*idx as usize).copied().flatten().unwrap_or(*idx);
+        }
+        self.refresh_highest_palette_idx();
+        if let Some(base) = &self.base_palette {
+            self.base_palette = Palette::new(palette::retain_remapped(base, &remap)).ok();
+        }
+        remap.iter().filter(|new| new.is_none()).count()
+    }
+
+    /// Update pixels and the base palette after the palette has been reordered
+    fn remap_indexes(&mut self, remap: &[u8]) {
+        for idx in self.pixels.iter_mut() {
+            *idx = remap.get(*idx as usize).copied().unwrap_or(*idx);
+        }
+        self.refresh_highest_palette_idx();
+        if let Some(base) = &self.base_palette {
+            self.base_palette = Palette::new(palette::remap_colors(base, remap)).ok();
+        }
+    }
+
+    /// Store the current palette so it can be restored with [IndexedImage::reset_palette_to_base]
+    ///
+    /// Useful for effects such as lighting that tint every frame, resetting before tinting stops rounding errors building up
+    pub fn set_base_palette(&mut self) {
+        self.base_palette = Some(self.palette.clone());
+    }
+
+    /// Palette stored by [IndexedImage::set_base_palette]
+    #[inline]
+    pub fn base_palette(&self) -> Option<&Palette> {
+        self.base_palette.as_ref()
+    }
+
+    pub fn clear_base_palette(&mut self) {
+        self.base_palette = None;
+    }
+
+    /// Replace the palette with the one stored by [IndexedImage::set_base_palette], does nothing if there isn't one
+    ///
+    /// Will return an error if the base palette has fewer colors than the image needs
+    pub fn reset_palette_to_base(&mut self) -> Result<(), IndexedImageError> {
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
+        if let Some(base) = &self.base_palette {
+            if base.len() <= highest as usize {
+                return Err(PaletteTooFewColors(highest));
+            }
+            self.palette.clone_from(base);
+        }
+        Ok(())
+    }
+
+    /// Key/value data saved with the file, see [metadata](crate::metadata) for common keys
+    #[inline]
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Set a metadata value, returning the previous value
+    ///
+    /// `key` must be 1..=255 bytes and `value` at most 65535 bytes
+    pub fn set_metadata(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<Option<String>, IndexedImageError> {
+        metadata::insert(&mut self.metadata, key, value)
+    }
+
+    #[inline]
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.metadata.remove(key)
+    }
+
+    /// Callers must only insert entries that are valid for [IndexedImage::set_metadata]
+    #[inline]
+    pub(crate) fn metadata_mut(&mut self) -> &mut BTreeMap<String, String> {
+        &mut self.metadata
+    }
+
+    /// RGBA8 pixel data (`width * height * 4` bytes), for uploading to textures, etc
+    pub fn to_rgba_bytes(&self) -> Vec<u8> {
+        indexes_to_rgba(&self.palette, &self.pixels, false)
+    }
+
+    /// Like [IndexedImage::to_rgba_bytes] but with RGB multiplied by alpha
+    pub fn to_rgba_bytes_premultiplied(&self) -> Vec<u8> {
+        indexes_to_rgba(&self.palette, &self.pixels, true)
+    }
+
+    /// Copy this image into a framebuffer (`buffer_width` pixels wide) with the top left at `x`,`y`
+    ///
+    /// Parts outside the buffer are clipped and fully transparent pixels are skipped, other colors are copied as is
+    pub fn draw_to_buffer(
+        &self,
+        buffer: &mut [u32],
+        buffer_width: usize,
+        x: isize,
+        y: isize,
+        format: PixelFormat,
+    ) {
+        draw_indexes_to_buffer(
+            &self.palette,
+            &self.pixels,
+            (self.width as usize, self.height as usize),
+            buffer,
+            buffer_width,
+            (x, y),
+            format,
+        );
+    }
+
+    /// Highest color index used by the pixels, the palette must have more colors than this
+    ///
+    /// Tracked as pixels are changed, the pixels are only rescanned if the last use of the highest index was overwritten
     #[inline]
     pub fn min_palette_size_supported(&self) -> u8 {
-        self.highest_palette_idx
+        self.highest_palette_idx.get(&self.pixels)
+    }
+
+    /// Check the internal state is valid, for use in tests and debug builds after using `_unchecked` methods
+    ///
+    /// All problems found are returned
+    pub fn debug_validate(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations = vec![];
+        if self.width == 0 || self.height == 0 {
+            violations.push(InvariantViolation::ZeroSize(self.width, self.height));
+        }
+        violations.extend(pixel_violations(
+            &self.pixels,
+            self.width as usize * self.height as usize,
+            self.palette.len(),
+            self.highest_palette_idx,
+        ));
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
     }
 
     #[inline]
@@ -239,16 +555,16 @@ impl IndexedImage {
     }
 
     pub fn rotate_cw(&self) -> IndexedImage {
-        let mut output = IndexedImage::blank(self.height, self.width, self.palette.clone());
+        let mut output = IndexedImage::blank_with(self.height, self.width, self.palette.clone());
         for y in 0..self.height {
             for x in 0..self.width {
-                let new_y = x;
-                let new_x = output.width - y - 1;
-                let new_i = output.get_pixel_index(new_x, new_y).unwrap();
-                let i = output.get_pixel_index(x, y).unwrap();
-                output.set_pixel(new_i, self.get_pixel(i).unwrap()).unwrap();
+                let new_y = x as usize;
+                let new_x = (output.width - y - 1) as usize;
+                output.pixels[new_x + new_y * output.width as usize] =
+                    self.pixels[x as usize + y as usize * self.width as usize];
             }
         }
+        output.highest_palette_idx = self.highest_palette_idx;
         output
     }
 
@@ -256,13 +572,13 @@ impl IndexedImage {
     ///
     /// Out of bounds may occur
     pub unsafe fn rotate_cw_unchecked(&self) -> IndexedImage {
-        let mut output = IndexedImage::blank(self.height, self.width, self.palette.clone());
+        let mut output = IndexedImage::blank_with(self.height, self.width, self.palette.clone());
         for y in 0..self.height {
             for x in 0..self.width {
                 let new_y = x;
                 let new_x = output.width - y - 1;
                 let new_i = output.get_pixel_index_unchecked(new_x, new_y);
-                let i = output.get_pixel_index_unchecked(x, y);
+                let i = self.get_pixel_index_unchecked(x, y);
                 output.set_pixel_unchecked(new_i, self.get_pixel_unchecked(i));
             }
         }
@@ -270,16 +586,16 @@ impl IndexedImage {
     }
 
     pub fn rotate_ccw(&self) -> IndexedImage {
-        let mut output = IndexedImage::blank(self.height, self.width, self.palette.clone());
+        let mut output = IndexedImage::blank_with(self.height, self.width, self.palette.clone());
         for y in 0..self.height {
             for x in 0..self.width {
-                let new_y = output.height - x - 1;
-                let new_x = y;
-                let new_i = output.get_pixel_index(new_x, new_y).unwrap();
-                let i = output.get_pixel_index(x, y).unwrap();
-                output.set_pixel(new_i, self.get_pixel(i).unwrap()).unwrap();
+                let new_y = (output.height - x - 1) as usize;
+                let new_x = y as usize;
+                output.pixels[new_x + new_y * output.width as usize] =
+                    self.pixels[x as usize + y as usize * self.width as usize];
             }
         }
+        output.highest_palette_idx = self.highest_palette_idx;
         output
     }
 
@@ -287,13 +603,13 @@ impl IndexedImage {
     ///
     /// Out of bounds may occur
     pub unsafe fn rotate_ccw_unchecked(&self) -> IndexedImage {
-        let mut output = IndexedImage::blank(self.height, self.width, self.palette.clone());
+        let mut output = IndexedImage::blank_with(self.height, self.width, self.palette.clone());
         for y in 0..self.height {
             for x in 0..self.width {
                 let new_y = output.height - x - 1;
                 let new_x = y;
                 let new_i = output.get_pixel_index_unchecked(new_x, new_y);
-                let i = output.get_pixel_index_unchecked(x, y);
+                let i = self.get_pixel_index_unchecked(x, y);
                 output.set_pixel_unchecked(new_i, self.get_pixel_unchecked(i));
             }
         }
@@ -301,7 +617,7 @@ impl IndexedImage {
     }
 
     pub fn flip_vertical(&self) -> Result<IndexedImage, IndexedImageError> {
-        let mut output = IndexedImage::blank(self.width, self.height, self.palette.clone());
+        let mut output = IndexedImage::blank_with(self.width, self.height, self.palette.clone());
         for y in 0..self.height {
             let target_y = self.height - 1 - y;
             for x in 0..self.width {
@@ -320,7 +636,7 @@ impl IndexedImage {
         let mut output = self.clone();
         let half_height = (output.height as f32 / 2.).floor() as u8;
         for y in 0..half_height {
-            std::ptr::swap_nonoverlapping(
+            core::ptr::swap_nonoverlapping(
                 &mut output.pixels[y as usize * output.width as usize],
                 &mut output.pixels[(output.height - 1 - y) as usize * output.width as usize],
                 output.width as usize,
@@ -330,8 +646,8 @@ impl IndexedImage {
     }
 
     pub fn flip_horizontal(&self) -> Result<IndexedImage, IndexedImageError> {
-        let mut output = IndexedImage::blank(self.width, self.height, self.palette.clone());
-        let half_width = (self.width as f32 / 2.).floor() as u8;
+        let mut output = IndexedImage::blank_with(self.width, self.height, self.palette.clone());
+        let half_width = (self.width as f32 / 2.).ceil() as u8;
         for y in 0..self.height {
             for x in 0..half_width {
                 let target_right_i = output.get_pixel_index(self.width - x - 1, y)?;
@@ -352,8 +668,8 @@ impl IndexedImage {
     ///
     /// Out of bounds may occur
     pub unsafe fn flip_horizontal_unchecked(&self) -> IndexedImage {
-        let mut output = IndexedImage::blank(self.width, self.height, self.palette.clone());
-        let half_width = (self.width as f32 / 2.).floor() as u8;
+        let mut output = IndexedImage::blank_with(self.width, self.height, self.palette.clone());
+        let half_width = (self.width as f32 / 2.).ceil() as u8;
         for y in 0..self.height {
             for x in 0..half_width {
                 let target_right_i = output.get_pixel_index_unchecked(self.width - 1 - x, y);
@@ -370,6 +686,60 @@ impl IndexedImage {
         output
     }
 
+    /// Swap the x and y axis, i.e. mirror along the top left to bottom right diagonal
+    pub fn transpose(&self) -> IndexedImage {
+        let mut output = IndexedImage::blank_with(self.height, self.width, self.palette.clone());
+        for y in 0..self.height as usize {
+            for x in 0..self.width as usize {
+                output.pixels[y + x * self.height as usize] =
+                    self.pixels[x + y * self.width as usize];
+            }
+        }
+        output.highest_palette_idx = self.highest_palette_idx;
+        output
+    }
+
+    /// Shift each row right by `amount` pixels per row down (negative shifts left)
+    ///
+    /// The image is widened to fit and empty space is filled with `fill_idx`
+    pub fn shear_x(&self, amount: f32, fill_idx: u8) -> Result<IndexedImage, IndexedImageError> {
+        if fill_idx as usize >= self.palette.len() {
+            return Err(IndexOutOfRange(
+                fill_idx as usize,
+                self.palette.len(),
+                "palette",
+            ));
+        }
+        let offsets: Vec<isize> = (0..self.height)
+            .map(|y| (amount * y as f32).round() as isize)
+            .collect();
+        let min = offsets.iter().min().copied().unwrap_or_default();
+        let max = offsets.iter().max().copied().unwrap_or_default();
+        let width = self.width as usize;
+        let new_width = width + (max - min) as usize;
+        if new_width > 255 {
+            return Err(TooBigPostScale(new_width, self.height as usize));
+        }
+        let mut pixels = vec![fill_idx; new_width * self.height as usize];
+        for (y, offset) in offsets.into_iter().enumerate() {
+            let start = (offset - min) as usize + y * new_width;
+            pixels[start..start + width].copy_from_slice(&self.pixels[y * width..(y + 1) * width]);
+        }
+        Ok(IndexedImage::from_parts(
+            new_width as u8,
+            self.height,
+            self.palette.clone(),
+            pixels,
+        ))
+    }
+
+    /// Shift each column down by `amount` pixels per column right (negative shifts up)
+    ///
+    /// The image is made taller to fit and empty space is filled with `fill_idx`
+    pub fn shear_y(&self, amount: f32, fill_idx: u8) -> Result<IndexedImage, IndexedImageError> {
+        Ok(self.transpose().shear_x(amount, fill_idx)?.transpose())
+    }
+
     pub fn scale(&self, algo: Scaling) -> Result<IndexedImage, IndexedImageError> {
         match algo {
             Scaling::NearestNeighbour { x_scale, y_scale } => {
@@ -377,12 +747,51 @@ impl IndexedImage {
             }
             Scaling::Epx2x => scale_epx(self),
             Scaling::Epx4x => scale_epx(&scale_epx(self)?),
+            #[cfg(target_has_atomic = "ptr")]
+            Scaling::Custom(scaler) => scaler.scale(self),
+            _ => {
+                let factor = algo.fixed_factor().unwrap_or(1);
+                let (width, height) = scaled_size(self, factor, factor)?;
+                let pixels = filter_pixels(self, algo).unwrap_or_default();
+                Ok(IndexedImage::from_parts(
+                    width,
+                    height,
+                    self.palette.clone(),
+                    pixels,
+                ))
+            }
+        }
+    }
+
+    /// Resize to exactly `width`x`height`, unlike [IndexedImage::scale] the sizes don't have to be multiples
+    ///
+    /// The palette is kept, metadata is not
+    pub fn resize(
+        &self,
+        width: u8,
+        height: u8,
+        mode: ResizeMode,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        if width == 0 {
+            return Err(WidthIsZero);
         }
+        if height == 0 {
+            return Err(HeightIsZero);
+        }
+        let pixels = resize_pixels(self, width, height, mode);
+        Ok(IndexedImage::from_parts(
+            width,
+            height,
+            self.palette.clone(),
+            pixels,
+        ))
     }
 
     /// # Safety
     ///
     /// Out of bounds may occur
+    ///
+    /// If a [Scaling::Custom] scaler fails a copy of this image is returned
     pub unsafe fn scale_unchecked(&self, algo: Scaling) -> IndexedImage {
         match algo {
             Scaling::NearestNeighbour { x_scale, y_scale } => {
@@ -390,9 +799,129 @@ impl IndexedImage {
             }
             Scaling::Epx2x => scale_epx_unchecked(self),
             Scaling::Epx4x => scale_epx_unchecked(&scale_epx_unchecked(self)),
+            #[cfg(target_has_atomic = "ptr")]
+            Scaling::Custom(scaler) => scaler.scale(self).unwrap_or_else(|_| self.clone()),
+            _ => {
+                let factor = algo.fixed_factor().unwrap_or(1);
+                IndexedImage::from_parts(
+                    (self.width as usize * factor) as u8,
+                    (self.height as usize * factor) as u8,
+                    self.palette.clone(),
+                    filter_pixels(self, algo).unwrap_or_default(),
+                )
+            }
         }
     }
 
+    /// Partition the palette into banks of at most `bank_size` colors so that every
+    /// `tile_width`x`tile_height` tile only uses colors from a single bank
+    ///
+    /// Tiles on the right and bottom edges may be smaller if the image size isn't a multiple of the tile size
+    /// Will return an error if a tile uses more than `bank_size` colors
+    pub fn assign_banks(
+        &self,
+        tile_width: u8,
+        tile_height: u8,
+        bank_size: u8,
+    ) -> Result<BankAssignment, IndexedImageError> {
+        if tile_width == 0 {
+            return Err(WidthIsZero);
+        }
+        if tile_height == 0 {
+            return Err(HeightIsZero);
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (tile_width, tile_height) = (tile_width as usize, tile_height as usize);
+        let mut tile_colors: Vec<Vec<Color>> = vec![];
+        for tile_y in (0..height).step_by(tile_height) {
+            for tile_x in (0..width).step_by(tile_width) {
+                let mut colors = vec![];
+                for y in tile_y..(tile_y + tile_height).min(height) {
+                    for x in tile_x..(tile_x + tile_width).min(width) {
+                        let idx = self.pixels[x + y * width];
+                        let color = self.palette.get(idx).unwrap_or(TRANSPARENT);
+                        if !colors.contains(&color) {
+                            colors.push(color);
+                        }
+                    }
+                }
+                tile_colors.push(colors);
+            }
+        }
+
+        let bank_size = bank_size as usize;
+        let mut order: Vec<usize> = (0..tile_colors.len()).collect();
+        order.sort_by_key(|i| core::cmp::Reverse(tile_colors[*i].len()));
+        let mut banks: Vec<Vec<Color>> = vec![];
+        let mut tiles = vec![0; tile_colors.len()];
+        for tile in order {
+            let colors = &tile_colors[tile];
+            if colors.len() > bank_size {
+                return Err(TileNeedsMultipleBanks(tile, colors.len()));
+            }
+            let best = banks
+                .iter()
+                .enumerate()
+                .map(|(i, bank)| {
+                    (
+                        i,
+                        colors.iter().filter(|c| !bank.contains(c)).count(),
+                        bank.len(),
+                    )
+                })
+                .filter(|(_, missing, len)| len + missing <= bank_size)
+                .min_by_key(|(_, missing, _)| *missing);
+            match best {
+                Some((i, _, _)) => {
+                    for color in colors {
+                        if !banks[i].contains(color) {
+                            banks[i].push(*color);
+                        }
+                    }
+                    tiles[tile] = i;
+                }
+                None => {
+                    banks.push(colors.clone());
+                    tiles[tile] = banks.len() - 1;
+                }
+            }
+        }
+
+        for color in &self.palette {
+            if banks.iter().any(|bank| bank.contains(color)) {
+                continue;
+            }
+            match banks.iter_mut().find(|bank| bank.len() < bank_size) {
+                Some(bank) => bank.push(*color),
+                None => banks.push(vec![*color]),
+            }
+        }
+        for bank in banks.iter_mut() {
+            bank.sort_by_key(hue_order);
+        }
+
+        Ok(BankAssignment { banks, tiles })
+    }
+
+    /// Create a copy of this image using `palette`, each pixel will be replaced with the closest color
+    ///
+    /// Will only return an error if `palette` is empty or has more than 256 colors
+    pub fn remap_to_palette(
+        &self,
+        palette: &[Color],
+        dithering: Dithering,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        let rgba: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|i| {
+                let color = self.palette.get(*i).unwrap_or(TRANSPARENT);
+                [color.r, color.g, color.b, color.a]
+            })
+            .collect();
+        from_rgba_with_palette(self.width, self.height, &rgba, palette, dithering)
+    }
+
     pub fn tint_palette_add(&self, color_diff: &[(isize, isize, isize, isize)]) -> IndexedImage {
         let mut output = self.clone();
 
@@ -436,65 +965,521 @@ impl IndexedImage {
     }
 }
 
+/// Convert palette indexes to RGBA8, indexes outside the palette become transparent
+pub(crate) fn indexes_to_rgba(palette: &[Color], pixels: &[u8], premultiplied: bool) -> Vec<u8> {
+    let palette: Vec<Color> = if premultiplied {
+        palette.iter().map(|c| c.premultiplied()).collect()
+    } else {
+        palette.to_vec()
+    };
+    pixels
+        .iter()
+        .flat_map(|idx| {
+            let color = palette.get(*idx as usize).copied().unwrap_or(TRANSPARENT);
+            [color.r, color.g, color.b, color.a]
+        })
+        .collect()
+}
+
+/// Copy `pixels` (`width` x `height`) into `buffer` at `x`,`y`, see [IndexedImage::draw_to_buffer]
+pub(crate) fn draw_indexes_to_buffer(
+    palette: &[Color],
+    pixels: &[u8],
+    (width, height): (usize, usize),
+    buffer: &mut [u32],
+    buffer_width: usize,
+    (x, y): (isize, isize),
+    format: PixelFormat,
+) {
+    if buffer_width == 0 {
+        return;
+    }
+    let buffer_height = buffer.len() / buffer_width;
+    let mut colors = [None; 256];
+    for (packed, color) in colors.iter_mut().zip(palette) {
+        if color.a > 0 {
+            *packed = Some(format.pack(*color));
+        }
+    }
+    // the part of the image that's inside the buffer
+    let left = x.saturating_neg().clamp(0, width as isize) as usize;
+    let top = y.saturating_neg().clamp(0, height as isize) as usize;
+    let right = (buffer_width as isize)
+        .saturating_sub(x)
+        .clamp(0, width as isize) as usize;
+    let bottom = (buffer_height as isize)
+        .saturating_sub(y)
+        .clamp(0, height as isize) as usize;
+    if left >= right || top >= bottom {
+        return;
+    }
+    for row in top..bottom {
+        let src = &pixels[row * width + left..row * width + right];
+        let dst_start = (y + row as isize) as usize * buffer_width + (x + left as isize) as usize;
+        let dst = &mut buffer[dst_start..dst_start + src.len()];
+        for (out, idx) in dst.iter_mut().zip(src) {
+            if let Some(color) = colors[*idx as usize] {
+                *out = color;
+            }
+        }
+    }
+}
+
 impl IndexedImage {
+    /// Checks `target` is `width`x`height` and copies this image's palette into it
+    fn prepare_target(
+        &self,
+        target: &mut IndexedImage,
+        width: u8,
+        height: u8,
+    ) -> Result<(), IndexedImageError> {
+        if target.width != width || target.height != height {
+            return Err(InvalidTargetSize(
+                width,
+                height,
+                target.width,
+                target.height,
+            ));
+        }
+        target.palette.clone_from(&self.palette);
+        Ok(())
+    }
+
+    /// Direct pixel access for rearranging existing pixels
+    ///
+    /// Callers must only write indexes that are valid for the palette and call [IndexedImage::refresh_highest_palette_idx] if needed
+    #[inline]
+    pub(crate) fn raw_pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// Colors can be changed but not added or removed
+    #[inline]
+    pub(crate) fn palette_mut(&mut self) -> &mut Palette {
+        &mut self.palette
+    }
+
+    pub(crate) fn into_pixels(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    /// Copy of everything but the pixels, which must be set with [IndexedImage::set_raw_pixels] before use
+    pub(crate) fn clone_without_pixels(&self) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            palette: self.palette.clone(),
+            pixels: vec![],
+            highest_palette_idx: self.highest_palette_idx,
+            metadata: self.metadata.clone(),
+            base_palette: self.base_palette.clone(),
+        }
+    }
+
+    /// Callers must make sure `pixels` is `width * height` long and valid for the palette
+    pub(crate) fn set_raw_pixels(&mut self, pixels: Vec<u8>) {
+        self.pixels = pixels;
+    }
+
+    pub(crate) fn refresh_highest_palette_idx(&mut self) {
+        self.highest_palette_idx = HighestIdx::new(&self.pixels);
+    }
+
+    /// Like [rotate_cw] but writes into `target`, reusing its allocations
+    ///
+    /// `target` must be `height`x`width` of this image, its palette will be replaced
+    pub fn rotate_cw_into(&self, target: &mut IndexedImage) -> Result<(), IndexedImageError> {
+        self.prepare_target(target, self.height, self.width)?;
+        let width = self.width as usize;
+        let height = self.height as usize;
+        for (y, row) in self.pixels.chunks_exact(width).enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                target.pixels[(height - y - 1) + x * height] = *pixel;
+            }
+        }
+        target.highest_palette_idx = self.highest_palette_idx;
+        Ok(())
+    }
+
+    /// Like [rotate_ccw] but writes into `target`, reusing its allocations
+    ///
+    /// `target` must be `height`x`width` of this image, its palette will be replaced
+    pub fn rotate_ccw_into(&self, target: &mut IndexedImage) -> Result<(), IndexedImageError> {
+        self.prepare_target(target, self.height, self.width)?;
+        let width = self.width as usize;
+        let height = self.height as usize;
+        for (y, row) in self.pixels.chunks_exact(width).enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                target.pixels[y + (width - x - 1) * height] = *pixel;
+            }
+        }
+        target.highest_palette_idx = self.highest_palette_idx;
+        Ok(())
+    }
+
+    /// Like [flip_vertical] but writes into `target`, reusing its allocations
+    ///
+    /// `target` must be the same size as this image, its palette will be replaced
+    pub fn flip_vertical_into(&self, target: &mut IndexedImage) -> Result<(), IndexedImageError> {
+        self.prepare_target(target, self.width, self.height)?;
+        let width = self.width as usize;
+        for (source, dest) in self
+            .pixels
+            .chunks_exact(width)
+            .zip(target.pixels.chunks_exact_mut(width).rev())
+        {
+            dest.copy_from_slice(source);
+        }
+        target.highest_palette_idx = self.highest_palette_idx;
+        Ok(())
+    }
+
+    /// Like [flip_horizontal] but writes into `target`, reusing its allocations
+    ///
+    /// `target` must be the same size as this image, its palette will be replaced
+    pub fn flip_horizontal_into(&self, target: &mut IndexedImage) -> Result<(), IndexedImageError> {
+        self.prepare_target(target, self.width, self.height)?;
+        let width = self.width as usize;
+        for (source, dest) in self
+            .pixels
+            .chunks_exact(width)
+            .zip(target.pixels.chunks_exact_mut(width))
+        {
+            for (pixel, value) in dest.iter_mut().zip(source.iter().rev()) {
+                *pixel = *value;
+            }
+        }
+        target.highest_palette_idx = self.highest_palette_idx;
+        Ok(())
+    }
+
+    /// Like [scale] but writes into `target`, reusing its allocations
+    ///
+    /// `target` must be the size of this image after scaling, its palette will be replaced
+    ///
+    /// [Scaling::Epx4x] still allocates an intermediate 2x image, and [Scaling::Scale2x], [Scaling::Scale3x],
+    /// [Scaling::Eagle], `Hq2x` and [Scaling::Custom] allocate the output before copying it into `target`
+    ///
+    /// For [Scaling::Custom] the palette is replaced with the one from the scaler
+    pub fn scale_into(
+        &self,
+        algo: Scaling,
+        target: &mut IndexedImage,
+    ) -> Result<(), IndexedImageError> {
+        match algo {
+            Scaling::NearestNeighbour { x_scale, y_scale } => {
+                let (x_scale, y_scale) = (usize::from(x_scale), usize::from(y_scale));
+                let (width, height) = scaled_size(self, x_scale, y_scale)?;
+                self.prepare_target(target, width, height)?;
+                scale_nearest_neighbor_into(self, x_scale, y_scale, &mut target.pixels);
+            }
+            Scaling::Epx2x => {
+                let (width, height) = scaled_size(self, 2, 2)?;
+                self.prepare_target(target, width, height)?;
+                scale_epx_into(self, &mut target.pixels);
+            }
+            Scaling::Epx4x => {
+                let (width, height) = scaled_size(self, 4, 4)?;
+                self.prepare_target(target, width, height)?;
+                let doubled = scale_epx(self)?;
+                scale_epx_into(&doubled, &mut target.pixels);
+            }
+            #[cfg(target_has_atomic = "ptr")]
+            Scaling::Custom(scaler) => {
+                let scaled = scaler.scale(self)?;
+                self.prepare_target(target, scaled.width, scaled.height)?;
+                target.palette = scaled.palette;
+                target.pixels.copy_from_slice(&scaled.pixels);
+            }
+            _ => {
+                let factor = algo.fixed_factor().unwrap_or(1);
+                let (width, height) = scaled_size(self, factor, factor)?;
+                self.prepare_target(target, width, height)?;
+                if let Some(pixels) = filter_pixels(self, algo) {
+                    target.pixels.copy_from_slice(&pixels);
+                }
+            }
+        }
+        target.refresh_highest_palette_idx();
+        Ok(())
+    }
+}
+
+impl IndexedImage {
+    /// Writes a version 1 file, unless the palette colors are saved and there are 256 then version 2 is used
+    ///
     /// Errors will only be returned if you [FilePalette::Name] and the len is invalid
     pub fn to_file_contents(&self, palette: &FilePalette) -> Result<Vec<u8>, IndexedImageError> {
+        let v2 = palette::needs_v2(palette, self.get_palette());
         let mut output = vec![];
-        output.extend_from_slice(&HEADER);
+        output.extend_from_slice(if v2 { &HEADER_V2 } else { &HEADER });
         output.push(Image.to_byte());
 
-        palette::write(palette, self.get_palette(), &mut output)?;
-        output.push(self.width);
-        output.push(self.height);
+        palette::write(palette, self.get_palette(), v2, &mut output)?;
+        if v2 {
+            output.extend_from_slice(&(self.width as u16).to_be_bytes());
+            output.extend_from_slice(&(self.height as u16).to_be_bytes());
+        } else {
+            output.push(self.width);
+            output.push(self.height);
+        }
         output.extend_from_slice(&self.pixels);
+        metadata::write(&self.metadata, &mut output);
 
         Ok(output)
     }
 
+    /// Same as `to_file_contents` but a CRC-32 checksum is appended, this is verified when reading
+    pub fn to_file_contents_with_checksum(
+        &self,
+        palette: &FilePalette,
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        let mut output = self.to_file_contents(palette)?;
+        append_checksum(&mut output);
+        Ok(output)
+    }
+
     /// Create an [IndexedImage], image palette will be filled with transparency unless file contains colors
     /// use `image.set_palette*` to replace the palette
     pub fn from_file_contents(
         bytes: &[u8],
     ) -> Result<(IndexedImage, FilePalette), IndexedImageError> {
-        let file_type = verify_format(bytes)?;
-        if file_type != Image {
-            return Err(InvalidFileFormat(
-                0,
-                format!("Expected Image file but found {}", file_type.name()),
-            ));
-        }
-        let idx = HEADER.len() + 1;
-        let (skip, pal_type, colors) = palette::read(idx, bytes)?;
+        IndexedImage::read_file(bytes, Vec::with_capacity)
+    }
 
-        let start = idx + skip;
-        if bytes.len() < start + 3 {
-            return Err(InvalidFileFormat(
-                start,
-                "Incomplete pixels data".to_string(),
-            ));
+    /// Pixels are copied into the buffer returned by `take`, which is only called (with the
+    /// number of pixels) once the file has been read successfully
+    pub(crate) fn read_file(
+        bytes: &[u8],
+        take: impl FnOnce(usize) -> Vec<u8>,
+    ) -> Result<(IndexedImage, FilePalette), IndexedImageError> {
+        let (version, file_type) = verify_format_version(bytes)?;
+        if file_type != Image {
+            return Err(wrong_file_type(Image.name(), &file_type));
         }
-        let width = bytes[start];
-        let height = bytes[start + 1];
-        let pixels_len = width as usize * height as usize;
-        if bytes.len() < start + 2 + pixels_len {
-            return Err(InvalidFileFormat(
-                start + 2,
-                format!(
-                    "Incomplete pixels data, found {} but expected {}",
-                    pixels_len,
-                    width * height
-                ),
-            ));
+        let v2 = version == HEADER_V2[3];
+        let mut reader = ByteReader::new(bytes);
+        read_header(&mut reader)?;
+        let (pal_type, colors) = palette::read(&mut reader)?;
+
+        reader.set_section(FileSection::Dimensions);
+        let width = reader.size(v2, "width")?;
+        let height = reader.size(v2, "height")?;
+        if width > 255 || height > 255 {
+            return Err(ImageTooLarge(width, height));
         }
-        let pixels = &bytes[start + 2..start + 2 + pixels_len];
+        reader.set_section(FileSection::Pixels);
+        let palette_len = colors.as_ref().map(Vec::len);
+        let pixels = reader.pixels(width * height, palette_len, "pixels data")?;
+        let metadata = metadata::read(reader.offset(), reader.remaining())?;
 
-        let highest = *pixels.iter().max().expect("Invalid pixels data") as usize;
+        let highest = pixels.iter().max().copied().unwrap_or_default() as usize;
         let colors = match colors {
             None => vec![TRANSPARENT; highest + 1],
             Some(colors) => colors,
         };
 
-        IndexedImage::new(width, height, colors, pixels.to_vec()).map(|image| (image, pal_type))
+        let mut buffer = take(pixels.len());
+        buffer.clear();
+        buffer.extend_from_slice(pixels);
+        let mut image = IndexedImage::new(width as u8, height as u8, colors, buffer)?;
+        image.metadata = metadata;
+        Ok((image, pal_type))
+    }
+}
+
+/// Highest color index in `pixels`
+#[inline]
+pub(crate) fn max_index(pixels: &[u8]) -> u8 {
+    pixels.iter().max().copied().unwrap_or_default()
+}
+
+/// Highest color index used by some pixels
+///
+/// Overwriting the last use of the highest index only marks it as stale (an upper bound) instead of
+/// rescanning every pixel, the exact value is found when needed by [HighestIdx::get] or [HighestIdx::refresh]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "u8", into = "u8")
+)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct HighestIdx {
+    value: u8,
+    stale: bool,
+}
+
+impl HighestIdx {
+    #[inline]
+    pub(crate) fn new(pixels: &[u8]) -> Self {
+        HighestIdx {
+            value: max_index(pixels),
+            stale: false,
+        }
+    }
+
+    /// Exact highest index, `pixels` are only scanned if stale
+    #[inline]
+    pub(crate) fn get(&self, pixels: &[u8]) -> u8 {
+        if self.stale {
+            max_index(pixels)
+        } else {
+            self.value
+        }
+    }
+
+    /// Like [HighestIdx::get] but keeps the result
+    #[inline]
+    pub(crate) fn refresh(&mut self, pixels: &[u8]) -> u8 {
+        if self.stale {
+            *self = HighestIdx::new(pixels);
+        }
+        self.value
+    }
+
+    /// Update after a pixel changed from `old` to `new`
+    #[inline]
+    pub(crate) fn write(&mut self, old: u8, new: u8) {
+        if new < self.value && old == self.value {
+            self.stale = true;
+        } else {
+            self.include(new);
+        }
+    }
+
+    /// Update after pixels using up to `new` were added
+    #[inline]
+    pub(crate) fn include(&mut self, new: u8) {
+        if new >= self.value {
+            self.value = new;
+            self.stale = false;
+        }
+    }
+
+    /// If this is correct for pixels whose highest index is `actual`, returns the stored value if not
+    pub(crate) fn check(&self, actual: u8) -> Result<(), u8> {
+        if self.value < actual || (!self.stale && self.value != actual) {
+            Err(self.value)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Only the stored value is kept, so it's read back as an upper bound
+impl From<u8> for HighestIdx {
+    fn from(value: u8) -> Self {
+        HighestIdx { value, stale: true }
+    }
+}
+
+impl From<HighestIdx> for u8 {
+    fn from(value: HighestIdx) -> Self {
+        value.value
+    }
+}
+
+/// Always equal, as it only depends on the pixels (which are compared separately)
+impl PartialEq for HighestIdx {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for HighestIdx {}
+
+/// Pixel from [IndexedImage::pixels_mut]
+#[derive(Debug)]
+pub struct PixelMut<'a> {
+    value: &'a Cell<u8>,
+    palette_len: usize,
+    highest: &'a Cell<HighestIdx>,
+}
+
+impl PixelMut<'_> {
+    /// Color index of the pixel
+    #[inline]
+    pub fn get(&self) -> u8 {
+        self.value.get()
+    }
+
+    /// Set the color index of the pixel, returns an error if `idx` is outside the palette
+    #[inline]
+    pub fn set(&mut self, idx: u8) -> Result<(), IndexedImageError> {
+        if idx as usize >= self.palette_len {
+            return Err(IndexOutOfRange(idx as usize, self.palette_len, "palette"));
+        }
+        let old = self.value.replace(idx);
+        let mut highest = self.highest.get();
+        highest.write(old, idx);
+        self.highest.set(highest);
+        Ok(())
+    }
+}
+
+/// Row from [IndexedImage::row_mut]
+#[derive(Debug)]
+pub struct RowMut<'a> {
+    /// every pixel in the image, the row is `start..end`
+    pixels: &'a mut [u8],
+    start: usize,
+    end: usize,
+    palette_len: usize,
+    highest: &'a mut HighestIdx,
+    /// highest index in the row before it was changed
+    old_highest: u8,
+}
+
+impl Deref for RowMut<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.pixels[self.start..self.end]
+    }
+}
+
+impl DerefMut for RowMut<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels[self.start..self.end]
+    }
+}
+
+impl Drop for RowMut<'_> {
+    fn drop(&mut self) {
+        let row = &mut self.pixels[self.start..self.end];
+        for idx in row.iter_mut() {
+            if *idx as usize >= self.palette_len {
+                *idx = 0;
+            }
+        }
+        self.highest.write(self.old_highest, max_index(row));
+    }
+}
+
+/// Note: [IndexedImage] has inherent `tint_add` and `tint_mul` methods that return a new image,
+/// use `Tint::tint_add(&mut image, ..)` to tint in place
+impl Tint for IndexedImage {
+    /// Tint every palette color
+    fn tint_add(&mut self, r_diff: isize, g_diff: isize, b_diff: isize, a_diff: isize) {
+        for color in self.palette.iter_mut() {
+            color.tint_add(r_diff, g_diff, b_diff, a_diff);
+        }
+    }
+
+    /// Tint every palette color
+    fn tint_mul(&mut self, r_diff: f32, g_diff: f32, b_diff: f32, a_diff: f32) {
+        for color in self.palette.iter_mut() {
+            color.tint_mul(r_diff, g_diff, b_diff, a_diff);
+        }
+    }
+}
+
+impl HueShift for IndexedImage {
+    /// Shift the hue of every palette color
+    fn tint_hue_shift(&mut self, degrees: f32) {
+        for color in self.palette.iter_mut() {
+            color.tint_hue_shift(degrees);
+        }
     }
 }
 
@@ -504,6 +1489,42 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn pixel_iterators() {
+        let mut image = IndexedImage::new(2, 2, vec![RED, GREEN, BLUE], vec![0, 1, 2, 0]).unwrap();
+        assert_eq!(
+            image.pixels().collect::<Vec<_>>(),
+            vec![(0, 0, 0), (1, 0, 1), (0, 1, 2), (1, 1, 0)]
+        );
+        assert_eq!(image.colors().nth(2), Some((0, 1, BLUE)));
+
+        for (x, y, mut pixel) in image.pixels_mut() {
+            if x == y {
+                pixel.set(2).unwrap();
+            }
+            assert!(pixel.set(3).is_err());
+        }
+        assert_eq!(image.get_pixels(), &[2, 1, 2, 2]);
+        assert_eq!(image.min_palette_size_supported(), 2);
+    }
+
+    #[test]
+    fn rows_and_columns() {
+        let mut image =
+            IndexedImage::new(3, 2, vec![RED, GREEN, BLUE], vec![0, 1, 0, 0, 0, 1]).unwrap();
+        assert_eq!(image.row(1).unwrap(), &[0, 0, 1]);
+        assert!(image.row(2).is_err());
+        assert_eq!(image.column(1).unwrap().collect::<Vec<_>>(), vec![1, 0]);
+        assert!(image.column(3).is_err());
+
+        let mut row = image.row_mut(0).unwrap();
+        row.copy_from_slice(&[2, 5, 1]);
+        drop(row);
+        assert_eq!(image.row(0).unwrap(), &[2, 0, 1]);
+        assert_eq!(image.min_palette_size_supported(), 2);
+        assert!(image.row_mut(2).is_err());
+    }
+
     #[test]
     fn write_and_read_no_data() {
         let width = 2;
@@ -701,7 +1722,10 @@ mod test {
         modified
             .set_palette(&[Color::new(255, 0, 0, 255), Color::new(0, 255, 0, 255)])
             .unwrap();
-        assert_eq!(image.highest_palette_idx, modified.highest_palette_idx);
+        assert_eq!(
+            image.min_palette_size_supported(),
+            modified.min_palette_size_supported()
+        );
         assert_eq!(image.height, modified.height);
         assert_eq!(image.width, modified.width);
         assert_eq!(image.pixels, image.pixels);
@@ -728,7 +1752,7 @@ mod test {
         modified
             .set_palette_replace_id(&[Color::new(5, 5, 5, 5)], 0)
             .unwrap();
-        assert_eq!(modified.highest_palette_idx, 0);
+        assert_eq!(modified.min_palette_size_supported(), 0);
         assert_eq!(image.height, modified.height);
         assert_eq!(image.width, modified.width);
         assert_eq!(image.pixels, image.pixels);
@@ -750,7 +1774,7 @@ mod test {
         .unwrap();
         let mut modified = image.clone();
         modified.set_palette_replace_color(&[Color::new(5, 5, 5, 5)], Color::new(5, 5, 5, 5));
-        assert_eq!(modified.highest_palette_idx, 2);
+        assert_eq!(modified.min_palette_size_supported(), 2);
         assert_eq!(image.height, modified.height);
         assert_eq!(image.width, modified.width);
         assert_eq!(image.pixels, image.pixels);
@@ -830,4 +1854,446 @@ mod test {
         assert!(image.set_pixel(idx, 2).is_ok());
         assert_eq!(image.get_pixel(idx).unwrap(), 2);
     }
+
+    #[test]
+    fn banks() {
+        let image = IndexedImage::new(
+            4,
+            2,
+            vec![RED, GREEN, BLUE, WHITE, BLACK],
+            vec![0, 1, 2, 3, 1, 0, 3, 2],
+        )
+        .unwrap();
+        let banks = image.assign_banks(2, 2, 3).unwrap();
+        assert_eq!(banks.tiles, vec![0, 1]);
+        assert_eq!(
+            banks.banks,
+            vec![vec![BLACK, RED, GREEN], vec![WHITE, BLUE]]
+        );
+        assert!(matches!(
+            image.assign_banks(2, 2, 1),
+            Err(TileNeedsMultipleBanks(0, 2))
+        ));
+        assert!(image.assign_banks(4, 2, 3).is_err());
+    }
+
+    #[test]
+    fn hue_shift() {
+        let mut image = IndexedImage::new(2, 1, vec![GREEN, BLACK], vec![0, 1]).unwrap();
+        image.tint_hue_shift(-120.0);
+        assert_eq!(image.get_palette(), &[RED, BLACK]);
+    }
+
+    #[test]
+    fn tint_trait() {
+        let mut image = IndexedImage::new(2, 1, vec![Color::gray(100), BLACK], vec![0, 1]).unwrap();
+        Tint::tint_add(&mut image, 10, 0, 0, 0);
+        assert_eq!(
+            image.get_palette(),
+            &[Color::new(110, 100, 100, 255), Color::new(10, 0, 0, 255)]
+        );
+        let mut wrapper = IndexedWrapper::from(image);
+        wrapper.tint_mul(0.5, 1.0, 1.0, 1.0);
+        assert_eq!(wrapper.get_palette()[0], Color::new(55, 100, 100, 255));
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let mut image = IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap();
+        image.set_metadata(metadata::AUTHOR, "Emma").unwrap();
+        assert!(image.set_metadata("", "x").is_err());
+        let bytes = image.to_file_contents(&Colors).unwrap();
+        let (output, _) = IndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output.metadata().get(metadata::AUTHOR).unwrap(), "Emma");
+        assert_eq!(output, image);
+    }
+
+    #[test]
+    fn checksum_round_trip() {
+        let mut image = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        image.set_metadata(metadata::TOOL, "test").unwrap();
+        let mut bytes = image.to_file_contents_with_checksum(&Colors).unwrap();
+        let (output, _) = IndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output, image);
+        bytes[8] ^= 0xFF;
+        assert!(matches!(
+            IndexedImage::from_file_contents(&bytes),
+            Err(ChecksumMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn rgba_bytes() {
+        let image =
+            IndexedImage::new(2, 1, vec![RED, Color::new(200, 0, 0, 128)], vec![1, 0]).unwrap();
+        assert_eq!(image.to_rgba_bytes(), vec![200, 0, 0, 128, 255, 0, 0, 255]);
+        assert_eq!(
+            image.to_rgba_bytes_premultiplied(),
+            vec![100, 0, 0, 128, 255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn remap() {
+        let image = IndexedImage::new(
+            2,
+            2,
+            vec![Color::gray(20), Color::gray(200), TRANSPARENT],
+            vec![0, 1, 2, 1],
+        )
+        .unwrap();
+        let output = image
+            .remap_to_palette(&[TRANSPARENT, WHITE, BLACK], Dithering::None)
+            .unwrap();
+        assert_eq!(output.get_pixels(), &[2, 1, 0, 1]);
+        assert!(image.remap_to_palette(&[], Dithering::None).is_err());
+    }
+
+    #[test]
+    fn rotate_non_square() {
+        let image = IndexedImage::new(
+            3,
+            2,
+            vec![TRANSPARENT, RED, GREEN, BLUE],
+            vec![0, 1, 2, 3, 0, 1],
+        )
+        .unwrap();
+        let cw = image.rotate_cw();
+        assert_eq!(cw.size(), (2, 3));
+        assert_eq!(cw.get_pixels(), &[3, 0, 0, 1, 1, 2]);
+        let ccw = image.rotate_ccw();
+        assert_eq!(ccw.size(), (2, 3));
+        assert_eq!(ccw.get_pixels(), &[2, 1, 1, 0, 0, 3]);
+        assert_eq!(unsafe { image.rotate_cw_unchecked() }, cw);
+        assert_eq!(unsafe { image.rotate_ccw_unchecked() }, ccw);
+        let flipped = image.flip_horizontal().unwrap();
+        assert_eq!(flipped.get_pixels(), &[2, 1, 0, 1, 0, 3]);
+        assert_eq!(unsafe { image.flip_horizontal_unchecked() }, flipped);
+    }
+
+    #[test]
+    fn into_matches_allocating() {
+        let image = IndexedImage::new(
+            3,
+            2,
+            vec![TRANSPARENT, RED, GREEN, BLUE],
+            vec![0, 1, 2, 3, 0, 1],
+        )
+        .unwrap();
+        let mut rotated = IndexedImage::blank(2, 3, vec![TRANSPARENT]);
+        image.rotate_cw_into(&mut rotated).unwrap();
+        assert_eq!(rotated, image.rotate_cw());
+        image.rotate_ccw_into(&mut rotated).unwrap();
+        assert_eq!(rotated, image.rotate_ccw());
+
+        let mut flipped = IndexedImage::blank(3, 2, vec![TRANSPARENT]);
+        image.flip_vertical_into(&mut flipped).unwrap();
+        assert_eq!(flipped, image.flip_vertical().unwrap());
+        image.flip_horizontal_into(&mut flipped).unwrap();
+        assert_eq!(flipped, image.flip_horizontal().unwrap());
+
+        let mut scaled = IndexedImage::blank(6, 4, vec![TRANSPARENT]);
+        image.scale_into(Scaling::nn_double(), &mut scaled).unwrap();
+        assert_eq!(scaled, image.scale(Scaling::nn_double()).unwrap());
+        image.scale_into(Scaling::Epx2x, &mut scaled).unwrap();
+        assert_eq!(scaled, image.scale(Scaling::Epx2x).unwrap());
+    }
+
+    #[test]
+    fn into_wrong_size() {
+        let image =
+            IndexedImage::new(3, 2, vec![TRANSPARENT, RED], vec![0, 1, 0, 1, 0, 1]).unwrap();
+        let mut target = IndexedImage::blank(3, 2, vec![TRANSPARENT]);
+        assert!(matches!(
+            image.rotate_cw_into(&mut target),
+            Err(InvalidTargetSize(2, 3, 3, 2))
+        ));
+        assert!(image.scale_into(Scaling::Epx2x, &mut target).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let mut image = IndexedImage::new(2, 2, vec![RED, GREEN], vec![0, 1, 1, 0]).unwrap();
+        assert_eq!(image.debug_validate(), Ok(()));
+        unsafe {
+            image.set_pixel_unchecked(0, 5);
+        }
+        image.pixels.push(0);
+        assert_eq!(
+            image.debug_validate(),
+            Err(vec![
+                InvariantViolation::PixelCount(5, 4),
+                InvariantViolation::PixelsOutsidePalette(0, 1, 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn shear_and_transpose() {
+        #[rustfmt::skip]
+        let image = IndexedImage::new(3, 2, vec![RED, GREEN, BLUE], vec![
+            0, 1, 1,
+            1, 1, 0,
+        ]).unwrap();
+        let transposed = image.transpose();
+        assert_eq!(transposed.size(), (2, 3));
+        assert_eq!(transposed.get_pixels(), &[0, 1, 1, 1, 1, 0]);
+        assert_eq!(transposed.transpose(), image);
+
+        let sheared = image.shear_x(1.0, 2).unwrap();
+        assert_eq!(sheared.size(), (4, 2));
+        assert_eq!(sheared.get_pixels(), &[0, 1, 1, 2, 2, 1, 1, 0]);
+        let sheared = image.shear_x(-1.0, 2).unwrap();
+        assert_eq!(sheared.get_pixels(), &[2, 0, 1, 1, 1, 1, 0, 2]);
+        assert_eq!(image.shear_x(0.0, 0).unwrap(), image);
+
+        let sheared = image.shear_y(0.5, 2).unwrap();
+        assert_eq!(sheared.size(), (3, 3));
+        assert_eq!(sheared.get_pixels(), &[0, 2, 2, 1, 1, 1, 2, 1, 0]);
+
+        assert!(image.shear_x(1.0, 3).is_err());
+        assert!(image.shear_y(200.0, 0).is_err());
+    }
+
+    #[test]
+    fn base_palette() {
+        let mut image = IndexedImage::new(2, 1, vec![RED, Color::gray(101)], vec![0, 1]).unwrap();
+        image.reset_palette_to_base().unwrap();
+        image.set_base_palette();
+        for _ in 0..10 {
+            image.reset_palette_to_base().unwrap();
+            Tint::tint_mul(&mut image, 0.33, 0.33, 0.33, 1.0);
+        }
+        assert_eq!(image.get_palette()[1], Color::gray(33));
+        image.reset_palette_to_base().unwrap();
+        assert_eq!(image.get_palette(), &[RED, Color::gray(101)]);
+        assert_eq!(image.base_palette().unwrap(), &[RED, Color::gray(101)]);
+
+        image.clear_base_palette();
+        assert!(image.base_palette().is_none());
+
+        let mut image = IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap();
+        image.set_base_palette();
+        image.set_palette(&[RED, GREEN]).unwrap();
+        image.set_pixel(0, 1).unwrap();
+        assert!(image.reset_palette_to_base().is_err());
+    }
+
+    #[test]
+    fn visually_equal() {
+        let image = IndexedImage::new(2, 1, vec![RED, BLUE, TRANSPARENT], vec![0, 2]).unwrap();
+        let reordered =
+            IndexedImage::new(2, 1, vec![Color::new(1, 2, 3, 0), RED, GREEN], vec![1, 0]).unwrap();
+        assert!(image.visually_equal(&reordered));
+        assert_ne!(image, reordered);
+        let changed = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        assert!(!image.visually_equal(&changed));
+        let resized = IndexedImage::new(1, 2, vec![RED, TRANSPARENT], vec![0, 1]).unwrap();
+        assert!(!image.visually_equal(&resized));
+    }
+
+    #[test]
+    fn normalize_palette() {
+        let colors = vec![WHITE, RED, BLACK];
+        let image = IndexedImage::new(4, 1, colors, vec![0, 1, 1, 2]).unwrap();
+
+        let mut sorted = image.clone();
+        assert_eq!(
+            sorted.normalize_palette(PaletteSort::Luminance),
+            vec![2, 1, 0]
+        );
+        assert_eq!(sorted.get_palette(), &[BLACK, RED, WHITE]);
+        assert_eq!(sorted.get_pixels(), &[2, 1, 1, 0]);
+        assert!(sorted.visually_equal(&image));
+
+        let mut sorted = image.clone();
+        sorted.set_base_palette();
+        sorted.normalize_palette(PaletteSort::Usage);
+        assert_eq!(sorted.get_palette(), &[RED, WHITE, BLACK]);
+        assert_eq!(sorted.base_palette().unwrap(), &[RED, WHITE, BLACK]);
+        assert!(sorted.visually_equal(&image));
+
+        let mut sorted = image.clone();
+        sorted.normalize_palette(PaletteSort::Hue);
+        assert_eq!(sorted.get_palette(), &[BLACK, WHITE, RED]);
+        assert!(sorted.visually_equal(&image));
+    }
+
+    #[test]
+    fn dedup_palette() {
+        let colors = vec![RED, BLUE, RED, GREEN, BLUE];
+        let mut image = IndexedImage::new(5, 1, colors, vec![4, 3, 2, 1, 0]).unwrap();
+        let original = image.clone();
+        assert_eq!(image.dedup_palette(), vec![0, 1, 0, 2, 1]);
+        assert_eq!(image.get_palette(), &[RED, BLUE, GREEN]);
+        assert_eq!(image.get_pixels(), &[1, 2, 0, 1, 0]);
+        assert_eq!(image.min_palette_size_supported(), 2);
+        assert!(image.visually_equal(&original));
+    }
+
+    #[test]
+    fn prune_unused_colors() {
+        let colors = vec![RED, BLUE, GREEN, WHITE];
+        let mut image = IndexedImage::new(3, 1, colors, vec![3, 1, 3]).unwrap();
+        image.set_base_palette();
+        let original = image.clone();
+        assert_eq!(image.prune_unused_colors(), 2);
+        assert_eq!(image.get_palette(), &[BLUE, WHITE]);
+        assert_eq!(image.base_palette().unwrap(), &[BLUE, WHITE]);
+        assert_eq!(image.get_pixels(), &[1, 0, 1]);
+        assert!(image.visually_equal(&original));
+        assert_eq!(image.prune_unused_colors(), 0);
+    }
+
+    #[test]
+    fn draw_to_buffer() {
+        let image =
+            IndexedImage::new(2, 2, vec![RED, TRANSPARENT, BLUE], vec![0, 1, 2, 0]).unwrap();
+        let (r, b, w) = (RED.to_argb(), BLUE.to_argb(), WHITE.to_argb());
+        let mut buffer = vec![w; 9];
+        image.draw_to_buffer(&mut buffer, 3, 0, 0, PixelFormat::Argb);
+        assert_eq!(buffer, vec![r, w, w, b, r, w, w, w, w]);
+
+        let mut buffer = vec![w; 9];
+        image.draw_to_buffer(&mut buffer, 3, 2, -1, PixelFormat::Argb);
+        assert_eq!(buffer, vec![w, w, b, w, w, w, w, w, w]);
+
+        let mut buffer = vec![w; 9];
+        image.draw_to_buffer(&mut buffer, 3, -1, 2, PixelFormat::Rgba);
+        assert_eq!(buffer, vec![w, w, w, w, w, w, w, w, w]);
+        image.draw_to_buffer(&mut buffer, 3, 3, 0, PixelFormat::Rgba);
+        image.draw_to_buffer(&mut buffer, 0, 0, 0, PixelFormat::Rgba);
+        assert!(buffer.iter().all(|c| *c == w));
+        image.draw_to_buffer(&mut buffer, 3, 1, 1, PixelFormat::Rgba);
+        assert_eq!(buffer[4], RED.to_rgba());
+
+        for (x, y) in [
+            (isize::MIN, 0),
+            (0, isize::MIN),
+            (isize::MAX, isize::MAX),
+            (isize::MIN, isize::MAX),
+            (isize::MAX, isize::MIN),
+        ] {
+            let mut buffer = vec![w; 9];
+            image.draw_to_buffer(&mut buffer, 3, x, y, PixelFormat::Argb);
+            assert!(buffer.iter().all(|c| *c == w));
+        }
+
+        let mut buffer = vec![0; 1];
+        IndexedImage::new(1, 1, vec![Color::new(1, 2, 3, 4)], vec![0])
+            .unwrap()
+            .draw_to_buffer(&mut buffer, 1, 0, 0, PixelFormat::RgbaBytes);
+        assert_eq!(buffer[0].to_ne_bytes(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn highest_palette_idx_after_edits() {
+        let mut image = IndexedImage::new(2, 2, vec![RED, GREEN, BLUE], vec![0, 2, 1, 2]).unwrap();
+        image.set_pixel(1, 0).unwrap();
+        assert_eq!(image.min_palette_size_supported(), 2);
+        image.set_pixel(3, 1).unwrap();
+        assert_eq!(image.min_palette_size_supported(), 1);
+        image.set_palette(&[RED, GREEN]).unwrap();
+
+        for (_, _, mut pixel) in image.pixels_mut() {
+            pixel.set(0).unwrap();
+        }
+        assert_eq!(image.min_palette_size_supported(), 0);
+        image.row_mut(1).unwrap()[0] = 1;
+        assert_eq!(image.min_palette_size_supported(), 1);
+        image.row_mut(1).unwrap().fill(0);
+        assert_eq!(image.min_palette_size_supported(), 0);
+        image.set_palette(&[RED]).unwrap();
+        assert_eq!(image.debug_validate(), Ok(()));
+    }
+
+    #[test]
+    fn clear_full_image() {
+        let pixels: Vec<u8> = (0..255 * 255).map(|i| (i % 3) as u8).collect();
+        let mut image =
+            IndexedImage::new(255, 255, vec![RED, GREEN, BLUE], pixels.clone()).unwrap();
+        for i in 0..pixels.len() {
+            image.set_pixel(i, 0).unwrap();
+        }
+        assert_eq!(image.min_palette_size_supported(), 0);
+        assert_eq!(image.debug_validate(), Ok(()));
+        image.set_palette(&[RED]).unwrap();
+
+        let mut large = crate::large::LargeIndexedImage::new(
+            1024,
+            1024,
+            vec![RED, GREEN],
+            vec![1; 1024 * 1024],
+        )
+        .unwrap();
+        for i in 0..1024 * 1024 {
+            large.set_pixel(i, 0).unwrap();
+        }
+        assert_eq!(large.min_palette_size_supported(), 0);
+        large.set_palette(&[RED]).unwrap();
+
+        let mut animated = AnimatedIndexedImage::new(
+            255,
+            255,
+            0.1,
+            4,
+            vec![RED, GREEN, BLUE],
+            pixels.repeat(4),
+            PlayType::Loops,
+        )
+        .unwrap();
+        for frame in 0..4 {
+            for i in 0..pixels.len() {
+                animated.set_pixel(frame, i, 0).unwrap();
+            }
+        }
+        assert_eq!(animated.min_palette_size_supported(), 0);
+        assert_eq!(animated.debug_validate(), Ok(()));
+        animated.set_palette(&[RED]).unwrap();
+    }
+
+    #[test]
+    fn new_checks_palette() {
+        assert_eq!(
+            IndexedImage::new(2, 1, vec![RED], vec![0, 5]),
+            Err(PaletteTooFewColors(5))
+        );
+        assert_eq!(
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED], vec![0, 1], PlayType::Loops),
+            Err(PaletteTooFewColors(1))
+        );
+        let image = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        assert_eq!(image.debug_validate(), Ok(()));
+    }
+
+    #[test]
+    fn full_palette_files() {
+        let colors: Vec<Color> = (0..=255).map(Color::gray).collect();
+        let image = IndexedImage::new(2, 1, colors.clone(), vec![0, 255]).unwrap();
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap();
+        assert_eq!(&bytes[..4], &HEADER_V2);
+        assert!(crate::validate(&bytes).is_valid());
+        assert_eq!(
+            IndexedImage::from_file_contents(&bytes).unwrap(),
+            (image.clone(), FilePalette::Colors)
+        );
+        let bytes = image.to_file_contents(&FilePalette::ID(1)).unwrap();
+        assert_eq!(&bytes[..4], &HEADER);
+
+        let animated =
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, colors.clone(), vec![0, 255], PlayType::Loops)
+                .unwrap();
+        let bytes = animated.to_file_contents(&FilePalette::Colors).unwrap();
+        assert_eq!(&bytes[..4], &HEADER_V2);
+        let (output, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output.get_palette(), &colors);
+
+        let mut bundle = crate::bundle::IciBundle::new(colors.clone()).unwrap();
+        bundle.insert("image", image).unwrap();
+        let bytes = bundle.to_file_contents(&FilePalette::Colors).unwrap();
+        assert_eq!(&bytes[..4], &HEADER_V2);
+        assert!(crate::validate(&bytes).is_valid());
+        let (output, _) = crate::bundle::IciBundle::from_file_contents(&bytes).unwrap();
+        assert_eq!(output, bundle);
+    }
 }
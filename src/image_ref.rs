@@ -0,0 +1,342 @@
+//! Images and animations that borrow their pixels from file contents
+//!
+//! Useful for large files, such as memory mapped asset packs, where copying every image into the heap
+//! would be wasteful. Use [IndexedImageRef::to_owned_image] to get an editable copy
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::file::{FileParts, FileType};
+use crate::image::{draw_indexes_to_buffer, indexes_to_rgba};
+use crate::palette::{FilePalette, Palette};
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// Read only image or animation with pixels borrowed from file contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedImageRef<'a> {
+    file_type: FileType,
+    pal_type: FilePalette,
+    palette: Palette,
+    width: u8,
+    height: u8,
+    frame_count: u16,
+    playback: Option<(f64, PlayType)>,
+    pixels: &'a [u8],
+    metadata: BTreeMap<String, String>,
+    tags: BTreeMap<String, FrameTag>,
+    state: Option<PlaybackState>,
+}
+
+impl<'a> IndexedImageRef<'a> {
+    /// Read an image or animation file without copying the pixels
+    ///
+    /// The palette will be filled with transparency unless the file contains colors
+    pub fn from_file_contents(bytes: &'a [u8]) -> Result<IndexedImageRef<'a>, IndexedImageError> {
+        IndexedImageRef::read(bytes, None)
+    }
+
+    /// Like [IndexedImageRef::from_file_contents] but `palette` is used if the file doesn't contain colors
+    pub(crate) fn read(
+        bytes: &'a [u8],
+        palette: Option<&Palette>,
+    ) -> Result<IndexedImageRef<'a>, IndexedImageError> {
+        let parts = FileParts::read(bytes)?;
+        let highest = parts.pixels.iter().max().copied().unwrap_or_default();
+        let palette = match (parts.colors, palette) {
+            (Some(colors), _) => Palette::new(colors)?,
+            (None, Some(palette)) => palette.clone(),
+            (None, None) => Palette::transparent(highest as usize + 1),
+        };
+        if highest as usize >= palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
+        Ok(IndexedImageRef {
+            file_type: parts.file_type,
+            pal_type: parts.pal_type,
+            palette,
+            width: parts.size.0,
+            height: parts.size.1,
+            frame_count: parts.frame_count,
+            playback: parts.playback,
+            pixels: parts.pixels,
+            metadata: parts.metadata,
+            tags: parts.tags,
+            state: parts.state,
+        })
+    }
+
+    #[inline]
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
+    }
+
+    /// How the palette was stored in the file
+    #[inline]
+    pub fn file_palette(&self) -> &FilePalette {
+        &self.pal_type
+    }
+
+    #[inline]
+    pub fn is_animation(&self) -> bool {
+        self.file_type == FileType::Animated
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    #[inline]
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u8, u8) {
+        (self.width, self.height)
+    }
+
+    /// Static images have 1 frame
+    #[inline]
+    pub fn frame_count(&self) -> u16 {
+        self.frame_count
+    }
+
+    /// Pixels for every frame
+    #[inline]
+    pub fn get_pixels(&self) -> &'a [u8] {
+        self.pixels
+    }
+
+    pub fn get_frame_pixels(&self, idx: u16) -> Result<&'a [u8], IndexedImageError> {
+        if idx >= self.frame_count {
+            return Err(IndexOutOfRange(
+                idx as usize,
+                self.frame_count as usize,
+                "frames",
+            ));
+        }
+        let frame_size = self.width as usize * self.height as usize;
+        let start = frame_size * idx as usize;
+        Ok(&self.pixels[start..start + frame_size])
+    }
+
+    #[inline]
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Seconds per frame and play type, `None` for static images
+    #[inline]
+    pub fn playback(&self) -> Option<(f64, PlayType)> {
+        self.playback
+    }
+
+    /// Named frame ranges, always empty for static images
+    #[inline]
+    pub fn tags(&self) -> &BTreeMap<String, FrameTag> {
+        &self.tags
+    }
+
+    /// RGBA8 pixel data of frame `idx`, see [IndexedImage::to_rgba_bytes]
+    pub fn frame_rgba(&self, idx: u16) -> Result<Vec<u8>, IndexedImageError> {
+        Ok(indexes_to_rgba(
+            &self.palette,
+            self.get_frame_pixels(idx)?,
+            false,
+        ))
+    }
+
+    /// Copy frame `idx` into a framebuffer without copying the image first, see [IndexedImage::draw_to_buffer]
+    pub fn draw_to_buffer(
+        &self,
+        idx: u16,
+        buffer: &mut [u32],
+        buffer_width: usize,
+        x: isize,
+        y: isize,
+        format: PixelFormat,
+    ) -> Result<(), IndexedImageError> {
+        draw_indexes_to_buffer(
+            &self.palette,
+            self.get_frame_pixels(idx)?,
+            (self.width as usize, self.height as usize),
+            buffer,
+            buffer_width,
+            (x, y),
+            format,
+        );
+        Ok(())
+    }
+
+    /// Copy into an [IndexedImage] or [AnimatedIndexedImage], including metadata, tags and playback state
+    pub fn to_owned_image(&self) -> Result<IndexedWrapper, IndexedImageError> {
+        let mut image: IndexedWrapper = match self.playback {
+            Some((per_frame, play_type)) => AnimatedIndexedImage::new(
+                self.width,
+                self.height,
+                per_frame,
+                self.frame_count,
+                self.palette.to_vec(),
+                self.pixels.to_vec(),
+                play_type,
+            )?
+            .into(),
+            None => IndexedImage::new(
+                self.width,
+                self.height,
+                self.palette.to_vec(),
+                self.pixels.to_vec(),
+            )?
+            .into(),
+        };
+        for (key, value) in &self.metadata {
+            match &mut image {
+                IndexedWrapper::Static(img) => img.set_metadata(key, value)?,
+                IndexedWrapper::Animated(img) => img.set_metadata(key, value)?,
+            };
+        }
+        if let IndexedWrapper::Animated(img) = &mut image {
+            for (name, tag) in &self.tags {
+                img.set_tag(name, tag.start, tag.end)?;
+            }
+            if let Some(state) = &self.state {
+                img.set_playback_state(*state)?;
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// File opened with a memory map, so the contents are only read from disk when used
+///
+/// The file must not be changed while it's mapped
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedFile {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<MappedFile, IndexedImageError> {
+        let path = path.as_ref();
+        let io_err = |e: std::io::Error| FileIo(path.display().to_string(), e.to_string());
+        let file = std::fs::File::open(path).map_err(io_err)?;
+        // SAFETY: the map is read only, callers are told not to modify the file while it's open
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(io_err)?;
+        Ok(MappedFile { map })
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Read the file as an image or animation
+    pub fn image(&self) -> Result<IndexedImageRef<'_>, IndexedImageError> {
+        IndexedImageRef::from_file_contents(&self.map)
+    }
+
+    /// Read the file as a bundle, entries can be borrowed with [LazyBundle::get_ref]
+    pub fn bundle(&self) -> Result<LazyBundle<'_>, IndexedImageError> {
+        LazyBundle::from_file_contents(&self.map)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<'a> IndexedImageRef<'a> {
+    /// Image or animation borrowing its pixels from `file`
+    pub fn from_mmap(file: &'a MappedFile) -> Result<IndexedImageRef<'a>, IndexedImageError> {
+        file.image()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn borrowed() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.2,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 0],
+            PlayType::LoopsBoth,
+        )
+        .unwrap();
+        image.set_metadata("author", "test").unwrap();
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap();
+        let image_ref = IndexedImageRef::from_file_contents(&bytes).unwrap();
+        assert!(image_ref.is_animation());
+        assert_eq!(image_ref.frame_count(), 2);
+        assert_eq!(image_ref.get_frame_pixels(1).unwrap(), &[1, 0]);
+        assert!(image_ref.get_frame_pixels(2).is_err());
+        assert_eq!(image_ref.get_palette(), &[RED, GREEN]);
+        assert_eq!(image_ref.playback(), Some((0.2, PlayType::LoopsBoth)));
+        assert_eq!(
+            image_ref.frame_rgba(1).unwrap(),
+            image.as_images()[1].to_rgba_bytes()
+        );
+        let mut buffer = vec![0; 2];
+        image_ref
+            .draw_to_buffer(1, &mut buffer, 2, 0, 0, PixelFormat::Rgba)
+            .unwrap();
+        assert_eq!(buffer, vec![GREEN.to_rgba(), RED.to_rgba()]);
+        assert!(image_ref
+            .draw_to_buffer(2, &mut buffer, 2, 0, 0, PixelFormat::Rgba)
+            .is_err());
+        assert_eq!(image_ref.to_owned_image().unwrap(), image.clone().into());
+
+        image.set_tag("walk", 0, 1).unwrap();
+        image.set_animate(true);
+        image.skip_to_next_frame();
+        image.update(0.0);
+        let bytes = image
+            .to_file_contents_with_state(&FilePalette::Colors)
+            .unwrap();
+        let image_ref = IndexedImageRef::from_file_contents(&bytes).unwrap();
+        assert_eq!(image_ref.tags().get("walk"), Some(&FrameTag::new(0, 1)));
+        assert_eq!(image_ref.to_owned_image().unwrap(), image.into());
+
+        let image = IndexedImage::new(1, 2, vec![RED, GREEN, BLUE], vec![2, 0]).unwrap();
+        let bytes = image.to_file_contents(&FilePalette::NoData).unwrap();
+        let image_ref = IndexedImageRef::from_file_contents(&bytes).unwrap();
+        assert!(!image_ref.is_animation());
+        assert_eq!(image_ref.get_pixels(), &[2, 0]);
+        assert_eq!(image_ref.get_palette().len(), 3);
+
+        let mut bundle = IciBundle::new(vec![BLUE, WHITE, BLACK]).unwrap();
+        bundle.insert("tile", image).unwrap();
+        let bytes = bundle.to_file_contents(&FilePalette::Colors).unwrap();
+        let lazy = LazyBundle::from_file_contents(&bytes).unwrap();
+        let tile = lazy.get_ref("tile").unwrap().unwrap();
+        assert_eq!(tile.get_palette(), &[BLUE, WHITE, BLACK]);
+        assert_eq!(tile.get_pixels(), &[2, 0]);
+        assert!(lazy.get_ref("missing").unwrap().is_none());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() {
+        let image = IndexedImage::new(2, 1, vec![RED, GREEN], vec![1, 0]).unwrap();
+        let path = std::env::temp_dir().join("ici_files_mmap_test.ici");
+        std::fs::write(&path, image.to_file_contents(&FilePalette::Colors).unwrap()).unwrap();
+        let file = MappedFile::open(&path).unwrap();
+        let image_ref = IndexedImageRef::from_mmap(&file).unwrap();
+        assert_eq!(image_ref.get_pixels(), &[1, 0]);
+        assert!(file.bundle().is_err());
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+        assert!(MappedFile::open("missing.ici").is_err());
+    }
+}
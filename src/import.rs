@@ -0,0 +1,334 @@
+use crate::analysis::detect_dither_regions;
+use crate::compat::*;
+use crate::dither::{dither_rgba, Dithering};
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+use crate::quantize::quantize_rgba;
+use alloc::collections::BTreeSet;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How closely an imported image matches its true color source, see [conversion_report]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ConversionReport {
+    /// Number of unique colors in the source
+    pub source_colors: usize,
+    /// Number of unique colors used by the image
+    pub output_colors: usize,
+    /// Largest difference ([Color::delta_e]) between a source pixel and the image pixel, fully transparent pixels are ignored
+    pub max_delta_e: f32,
+    /// Average difference ([Color::delta_e]) between source pixels and image pixels, fully transparent pixels are ignored
+    pub mean_delta_e: f32,
+    /// Percentage (0..=100) of the image that looks dithered, see [detect_dither_regions]
+    pub dithered_percent: f32,
+}
+
+/// Compare `image` to the RGBA pixel data (4 bytes per pixel) it was created from
+///
+/// `pixels` must be the same size as `image`
+pub fn conversion_report(
+    pixels: &[u8],
+    image: &IndexedImage,
+) -> Result<ConversionReport, IndexedImageError> {
+    validate_rgba(image.width(), image.height(), pixels)?;
+    let source: Vec<Color> = pixels
+        .chunks_exact(4)
+        .map(|px| Color::new(px[0], px[1], px[2], px[3]))
+        .collect();
+    let output: Vec<Color> = image.colors().map(|(_, _, color)| color).collect();
+    let mut max_delta_e = 0.0_f32;
+    let mut total_delta_e = 0.0;
+    let mut counted = 0;
+    for (source, output) in source.iter().zip(&output) {
+        if source.a == 0 && output.a == 0 {
+            continue;
+        }
+        let delta_e = source.delta_e(output);
+        max_delta_e = max_delta_e.max(delta_e);
+        total_delta_e += delta_e;
+        counted += 1;
+    }
+    let dithered_area: usize = detect_dither_regions(image)
+        .iter()
+        .map(|rect| rect.width as usize * rect.height as usize)
+        .sum();
+    Ok(ConversionReport {
+        source_colors: source.iter().collect::<BTreeSet<_>>().len(),
+        output_colors: output.iter().collect::<BTreeSet<_>>().len(),
+        max_delta_e,
+        mean_delta_e: if counted == 0 {
+            0.0
+        } else {
+            total_delta_e / counted as f32
+        },
+        dithered_percent: dithered_area as f32 / output.len() as f32 * 100.0,
+    })
+}
+
+/// Like [from_rgba] but also returns a [ConversionReport]
+pub fn from_rgba_with_report(
+    width: u8,
+    height: u8,
+    pixels: &[u8],
+    max_colors: usize,
+    dithering: Dithering,
+) -> Result<(IndexedImage, ConversionReport), IndexedImageError> {
+    let image = from_rgba(width, height, pixels, max_colors, dithering)?;
+    let report = conversion_report(pixels, &image)?;
+    Ok((image, report))
+}
+
+/// Create an [IndexedImage] from RGBA pixel data (4 bytes per pixel)
+///
+/// The palette is generated with [quantize](crate::quantize::quantize) and will have at most `max_colors` (1..=256) colors
+pub fn from_rgba(
+    width: u8,
+    height: u8,
+    pixels: &[u8],
+    max_colors: usize,
+    dithering: Dithering,
+) -> Result<IndexedImage, IndexedImageError> {
+    validate_rgba(width, height, pixels)?;
+    let palette = quantize_rgba(pixels, max_colors.clamp(1, 256));
+    from_rgba_with_palette(width, height, pixels, &palette, dithering)
+}
+
+/// Create an [IndexedImage] from RGBA pixel data (4 bytes per pixel) using `palette`
+///
+/// Each pixel will be replaced with the closest color from `palette` (which must have 1..=256 colors)
+pub fn from_rgba_with_palette(
+    width: u8,
+    height: u8,
+    pixels: &[u8],
+    palette: &[Color],
+    dithering: Dithering,
+) -> Result<IndexedImage, IndexedImageError> {
+    validate_rgba(width, height, pixels)?;
+    if palette.is_empty() {
+        return Err(PaletteIsEmpty);
+    }
+    if palette.len() > 256 {
+        return Err(PaletteTooManyColors);
+    }
+    let indexes = dither_rgba(width as usize, height as usize, pixels, palette, dithering);
+    IndexedImage::new(width, height, palette.to_vec(), indexes)
+}
+
+/// Create a preview [IndexedImage] from RGBA pixel data (4 bytes per pixel) of any size
+///
+/// Images larger than 255x255 are shrunk to fit (keeping the aspect ratio) by averaging the pixels, smaller images
+/// are unchanged, then the colors are reduced to 256 without dithering so the same input always gives the same preview
+pub fn preview_fit(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<IndexedImage, IndexedImageError> {
+    if width == 0 {
+        return Err(WidthIsZero);
+    }
+    if height == 0 {
+        return Err(HeightIsZero);
+    }
+    let expected = width * height * 4;
+    if pixels.len() != expected {
+        return Err(MissingData(pixels.len(), expected));
+    }
+    let scale = (255.0 / width as f64).min(255.0 / height as f64).min(1.0);
+    let new_width = ((width as f64 * scale).round() as usize).clamp(1, 255);
+    let new_height = ((height as f64 * scale).round() as usize).clamp(1, 255);
+    // source pixels covered by new pixel `i` of `new_len`, always at least one
+    let span = |i: usize, len: usize, new_len: usize| {
+        let start = i * len / new_len;
+        let end = ((i + 1) * len / new_len).max(start + 1);
+        start..end
+    };
+    let mut output = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        let rows = span(y, height, new_height);
+        for x in 0..new_width {
+            let columns = span(x, width, new_width);
+            let mut total = [0_usize; 4];
+            for sy in rows.clone() {
+                for sx in columns.clone() {
+                    let i = (sx + sy * width) * 4;
+                    for (channel, value) in total.iter_mut().zip(&pixels[i..i + 4]) {
+                        *channel += *value as usize;
+                    }
+                }
+            }
+            let count = rows.len() * columns.len();
+            output.extend(total.map(|channel| ((channel + count / 2) / count) as u8));
+        }
+    }
+    from_rgba(
+        new_width as u8,
+        new_height as u8,
+        &output,
+        Palette::MAX_COLORS,
+        Dithering::None,
+    )
+}
+
+/// Create an [IndexedImage] from the contents of a PNG file
+///
+/// See [from_rgba]
+#[cfg(feature = "png")]
+pub fn from_png_bytes(
+    bytes: &[u8],
+    max_colors: usize,
+    dithering: Dithering,
+) -> Result<IndexedImage, IndexedImageError> {
+    let (width, height, pixels) = decode_png(bytes)?;
+    from_rgba(width, height, &pixels, max_colors, dithering)
+}
+
+/// Decode PNG file contents to RGBA pixel data
+#[cfg(feature = "png")]
+fn decode_png(bytes: &[u8]) -> Result<(u8, u8, Vec<u8>), IndexedImageError> {
+    use png::{ColorType, Decoder, Transformations};
+
+    let mut decoder = Decoder::new(bytes);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| ImageDecoding(e.to_string()))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| ImageDecoding(e.to_string()))?;
+    buffer.truncate(info.buffer_size());
+    if info.width > 255 || info.height > 255 {
+        return Err(ImageTooLarge(info.width as usize, info.height as usize));
+    }
+    let pixels = match info.color_type {
+        ColorType::Rgba => buffer,
+        ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .collect(),
+        ColorType::Grayscale => buffer.iter().flat_map(|v| [*v, *v, *v, 255]).collect(),
+        ColorType::Indexed => {
+            return Err(ImageDecoding("Indexed PNG was not expanded".to_string()));
+        }
+    };
+    Ok((info.width as u8, info.height as u8, pixels))
+}
+
+fn validate_rgba(width: u8, height: u8, pixels: &[u8]) -> Result<(), IndexedImageError> {
+    if width == 0 {
+        return Err(WidthIsZero);
+    }
+    if height == 0 {
+        return Err(HeightIsZero);
+    }
+    let expected = width as usize * height as usize * 4;
+    if pixels.len() != expected {
+        return Err(MissingData(pixels.len(), expected));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn basic() {
+        let pixels = [255, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0, 255, 0, 0, 0, 0];
+        let image = from_rgba(2, 2, &pixels, 256, Dithering::None).unwrap();
+        assert_eq!(image.get_palette(), &[RED, BLUE, TRANSPARENT]);
+        assert_eq!(image.get_pixels(), &[0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn reduces_colors() {
+        let pixels: Vec<u8> = (0..64_u8).flat_map(|i| [i * 4, 0, 0, 255]).collect();
+        let image = from_rgba(8, 8, &pixels, 4, Dithering::FloydSteinberg).unwrap();
+        assert_eq!(image.get_palette().len(), 4);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png() {
+        let mut bytes = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+        }
+        let image = from_png_bytes(&bytes, 256, Dithering::None).unwrap();
+        assert_eq!(image.get_palette(), &[RED, BLUE]);
+        assert_eq!(image.get_pixels(), &[0, 1]);
+        assert!(matches!(
+            from_png_bytes(&[1, 2, 3], 256, Dithering::None),
+            Err(ImageDecoding(_))
+        ));
+    }
+
+    #[test]
+    fn preview() {
+        let pixels: Vec<u8> = (0..1000 * 100)
+            .flat_map(|i| {
+                if (i % 1000) < 500 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                }
+            })
+            .collect();
+        let image = preview_fit(&pixels, 1000, 100).unwrap();
+        assert_eq!(image.size(), (255, 26));
+        assert_eq!(image.get_color(image.get_pixels()[0]).unwrap(), RED);
+        assert_eq!(image.get_color(image.get_pixels()[254]).unwrap(), BLUE);
+        assert_eq!(preview_fit(&pixels, 1000, 100).unwrap(), image);
+
+        let small = [255, 0, 0, 255, 0, 0, 255, 255];
+        let image = preview_fit(&small, 2, 1).unwrap();
+        assert_eq!(
+            image,
+            from_rgba(2, 1, &small, 256, Dithering::None).unwrap()
+        );
+        assert!(preview_fit(&small, 3, 1).is_err());
+    }
+
+    #[test]
+    fn report() {
+        let pixels = [255, 0, 0, 255, 250, 0, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0];
+        let (image, report) = from_rgba_with_report(2, 2, &pixels, 2, Dithering::None).unwrap();
+        assert_eq!(image.get_palette().len(), 2);
+        assert_eq!(report.source_colors, 4);
+        assert_eq!(report.output_colors, 2);
+        assert!(report.max_delta_e > 0.0);
+        assert!(report.mean_delta_e <= report.max_delta_e);
+        assert_eq!(report.dithered_percent, 0.0);
+
+        let (image, report) = from_rgba_with_report(2, 2, &pixels, 256, Dithering::None).unwrap();
+        assert_eq!(report.output_colors, 4);
+        assert_eq!(report.max_delta_e, 0.0);
+        assert!(conversion_report(&pixels[..8], &image).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(
+            from_rgba(2, 2, &[0; 12], 4, Dithering::None),
+            Err(MissingData(12, 16))
+        ));
+        assert!(matches!(
+            from_rgba(0, 2, &[], 4, Dithering::None),
+            Err(WidthIsZero)
+        ));
+        assert!(matches!(
+            from_rgba_with_palette(1, 1, &[0; 4], &[], Dithering::None),
+            Err(PaletteIsEmpty)
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+//! Conversions to and from the [image](::image) crate
+//!
+//! Converting from RGBA will reduce the image to 256 colors if necessary, see [quantize](crate::quantize::quantize)
+
+use crate::dither::{dither_rgba, Dithering};
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::image::indexes_to_rgba;
+use crate::prelude::*;
+use crate::quantize::quantize_rgba;
+use ::image::{Delay, Frame, Frames, RgbaImage};
+use std::time::Duration;
+
+impl From<&IndexedImage> for RgbaImage {
+    fn from(value: &IndexedImage) -> Self {
+        let (width, height) = (value.width() as u32, value.height() as u32);
+        RgbaImage::from_raw(width, height, value.to_rgba_bytes())
+            .unwrap_or_else(|| RgbaImage::new(width, height))
+    }
+}
+
+impl From<IndexedImage> for RgbaImage {
+    fn from(value: IndexedImage) -> Self {
+        RgbaImage::from(&value)
+    }
+}
+
+impl TryFrom<&RgbaImage> for IndexedImage {
+    type Error = IndexedImageError;
+
+    fn try_from(value: &RgbaImage) -> Result<Self, Self::Error> {
+        let (width, height) = check_size(value.width(), value.height())?;
+        crate::import::from_rgba(width, height, value.as_raw(), 256, Dithering::None)
+    }
+}
+
+impl TryFrom<RgbaImage> for IndexedImage {
+    type Error = IndexedImageError;
+
+    fn try_from(value: RgbaImage) -> Result<Self, Self::Error> {
+        IndexedImage::try_from(&value)
+    }
+}
+
+impl From<&AnimatedIndexedImage> for Frames<'static> {
+    /// Each frame uses the animation's per frame time, the play type is not kept
+    ///
+    /// Negative or NaN times become 0 and times longer than [u32::MAX] milliseconds are clamped
+    fn from(value: &AnimatedIndexedImage) -> Self {
+        let per_frame = value.get_per_frame();
+        let max = Duration::from_millis(u32::MAX as u64);
+        let duration = Duration::try_from_secs_f64(per_frame)
+            .unwrap_or(if per_frame > 0.0 { max } else { Duration::ZERO })
+            .min(max);
+        let delay = Delay::from_saturating_duration(duration);
+        let (width, height) = (value.width() as u32, value.height() as u32);
+        let frames: Vec<Frame> = (0..value.frame_count())
+            .map(|idx| {
+                let pixels = value.get_frame_pixels(idx).unwrap_or_default();
+                let buffer = RgbaImage::from_raw(
+                    width,
+                    height,
+                    indexes_to_rgba(value.get_palette(), pixels, false),
+                )
+                .unwrap_or_else(|| RgbaImage::new(width, height));
+                Frame::from_parts(buffer, 0, 0, delay)
+            })
+            .collect();
+        Frames::new(Box::new(frames.into_iter().map(Ok)))
+    }
+}
+
+impl TryFrom<Frames<'_>> for AnimatedIndexedImage {
+    type Error = IndexedImageError;
+
+    /// All frames must be the same size and are combined into one palette
+    /// The per frame time is taken from the first frame and the play type is [PlayType::Loops]
+    fn try_from(value: Frames<'_>) -> Result<Self, Self::Error> {
+        let frames = value
+            .collect_frames()
+            .map_err(|e| ImageDecoding(e.to_string()))?;
+        let first = frames.first().ok_or(NoFrames)?;
+        if frames.len() > u16::MAX as usize {
+            return Err(TooManyFrames(frames.len()));
+        }
+        let buffer = first.buffer();
+        let (width, height) = check_size(buffer.width(), buffer.height())?;
+        let (numer, denom) = first.delay().numer_denom_ms();
+        let per_frame = numer as f64 / denom as f64 / 1000.0;
+        let mut rgba = Vec::with_capacity(buffer.as_raw().len() * frames.len());
+        for frame in &frames {
+            if frame.buffer().dimensions() != buffer.dimensions() {
+                return Err(InvalidImageSize);
+            }
+            rgba.extend_from_slice(frame.buffer().as_raw());
+        }
+        let palette = quantize_rgba(&rgba, 256);
+        let pixels = dither_rgba(
+            width as usize,
+            height as usize * frames.len(),
+            &rgba,
+            &palette,
+            Dithering::None,
+        );
+        AnimatedIndexedImage::new(
+            width,
+            height,
+            per_frame,
+            frames.len() as u16,
+            palette,
+            pixels,
+            PlayType::Loops,
+        )
+    }
+}
+
+fn check_size(width: u32, height: u32) -> Result<(u8, u8), IndexedImageError> {
+    if width > 255 || height > 255 {
+        return Err(ImageTooLarge(width as usize, height as usize));
+    }
+    Ok((width as u8, height as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn image_round_trip() {
+        let image = IndexedImage::new(2, 1, vec![RED, BLUE], vec![1, 0]).unwrap();
+        let rgba = RgbaImage::from(&image);
+        assert_eq!(rgba.get_pixel(0, 0).0, [0, 0, 255, 255]);
+        assert_eq!(IndexedImage::try_from(rgba).unwrap().get_pixels(), &[0, 1]);
+        assert!(IndexedImage::try_from(RgbaImage::new(256, 1)).is_err());
+    }
+
+    #[test]
+    fn animation_round_trip() {
+        let image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.5,
+            3,
+            vec![RED, BLUE],
+            vec![0, 1, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let frames = Frames::from(&image);
+        let output = AnimatedIndexedImage::try_from(frames).unwrap();
+        assert_eq!(output.frame_count(), 3);
+        assert_eq!(output.get_per_frame(), 0.5);
+        assert_eq!(output.get_palette(), &[RED, BLUE]);
+        assert_eq!(output.get_pixels(), &[0, 1, 0]);
+    }
+
+    #[test]
+    fn invalid_per_frame() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 0.5, 1, vec![RED], vec![0], PlayType::Loops).unwrap();
+        for (per_frame, expected) in [
+            (f64::NAN, Duration::ZERO),
+            (-1.0, Duration::ZERO),
+            (f64::INFINITY, Duration::from_millis(u32::MAX as u64)),
+            (1e10, Duration::from_millis(u32::MAX as u64)),
+        ] {
+            image.set_per_frame(per_frame);
+            let frames = Frames::from(&image).collect_frames().unwrap();
+            assert_eq!(frames[0].delay(), Delay::from_saturating_duration(expected));
+        }
+    }
+}
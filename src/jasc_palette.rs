@@ -1,9 +1,10 @@
+use crate::compat::*;
 use crate::jasc_palette::PaletteError::*;
 use crate::jasc_palette::ParseIssue::*;
 use crate::prelude::*;
-use std::error::Error;
-use std::fmt::{Display, Formatter};
-use std::str::FromStr;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum PaletteError {
@@ -23,7 +24,7 @@ pub enum ParseIssue {
 }
 
 impl Display for PaletteError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             InvalidFileType => write!(f, "Invalid file type"),
             UnsupportedVersion => write!(f, "Palette file version is not supported"),
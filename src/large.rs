@@ -0,0 +1,338 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::file::FileType::Image;
+use crate::file::{append_checksum, verify_format_version, wrong_file_type, HEADER, HEADER_V2};
+use crate::image::{indexes_to_rgba, HighestIdx};
+use crate::metadata;
+use crate::palette;
+use crate::palette::{FilePalette, Palette};
+use crate::prelude::*;
+use crate::reader::ByteReader;
+use alloc::collections::BTreeMap;
+use core::mem;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Image that can be up to 65535x65535, for tilemaps, backgrounds, etc
+///
+/// Saved as version 2 files, can read version 1 and 2 image files
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LargeIndexedImage {
+    width: u16,
+    height: u16,
+    palette: Palette,
+    pixels: Vec<u8>,
+    highest_palette_idx: HighestIdx,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    metadata: BTreeMap<String, String>,
+}
+
+impl LargeIndexedImage {
+    pub fn new(
+        width: u16,
+        height: u16,
+        palette: Vec<Color>,
+        pixels: Vec<u8>,
+    ) -> Result<Self, IndexedImageError> {
+        if width == 0 {
+            return Err(WidthIsZero);
+        }
+        if height == 0 {
+            return Err(HeightIsZero);
+        }
+        let palette = Palette::new(palette)?;
+        if pixels.len() != (width as usize * height as usize) {
+            return Err(MissingData(pixels.len(), width as usize * height as usize));
+        }
+        let highest_palette_idx = HighestIdx::new(&pixels);
+        let highest = highest_palette_idx.get(&pixels);
+        if highest as usize >= palette.len() {
+            return Err(PaletteTooFewColors(highest));
+        }
+        Ok(Self {
+            width,
+            height,
+            palette,
+            pixels,
+            highest_palette_idx,
+            metadata: BTreeMap::new(),
+        })
+    }
+
+    /// Creates an image with all pixels set to 0
+    ///
+    /// If `palette` has more than 256 colors the extra are dropped, if it's empty a single transparent color is used
+    pub fn blank(width: u16, height: u16, palette: Vec<Color>) -> Self {
+        Self {
+            width,
+            height,
+            palette: Palette::from_vec_lossy(palette),
+            pixels: vec![0; width as usize * height as usize],
+            highest_palette_idx: HighestIdx::default(),
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+impl LargeIndexedImage {
+    /// Replace palette for image
+    /// Will return an error if the new palette has less colors than the image needs, is empty or has more than 256 colors
+    pub fn set_palette(&mut self, palette: &[Color]) -> Result<(), IndexedImageError> {
+        let palette = Palette::try_from(palette)?;
+        let highest = self.highest_palette_idx.refresh(&self.pixels);
+        if palette.len() <= highest as usize {
+            return Err(PaletteTooFewColors(highest));
+        }
+        self.palette = palette;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    #[inline]
+    pub fn get_pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub fn get_pixel(&self, pixel_idx: usize) -> Result<u8, IndexedImageError> {
+        if pixel_idx >= self.pixels.len() {
+            return Err(IndexOutOfRange(pixel_idx, self.pixels.len(), "pixels"));
+        }
+        Ok(self.pixels[pixel_idx])
+    }
+
+    #[inline]
+    pub fn set_pixel(&mut self, pixel_idx: usize, color_idx: u8) -> Result<(), IndexedImageError> {
+        if pixel_idx >= self.pixels.len() {
+            return Err(IndexOutOfRange(pixel_idx, self.pixels.len(), "pixels"));
+        }
+        if color_idx as usize >= self.palette.len() {
+            return Err(IndexOutOfRange(
+                color_idx as usize,
+                self.palette.len(),
+                "palette",
+            ));
+        }
+        let old = mem::replace(&mut self.pixels[pixel_idx], color_idx);
+        self.highest_palette_idx.write(old, color_idx);
+        Ok(())
+    }
+
+    pub fn get_pixel_index(&self, x: u16, y: u16) -> Result<usize, IndexedImageError> {
+        if x >= self.width {
+            return Err(IndexOutOfRange(x as usize, self.width as usize, "width"));
+        }
+        if y >= self.height {
+            return Err(IndexOutOfRange(y as usize, self.height as usize, "height"));
+        }
+        Ok(x as usize + y as usize * self.width as usize)
+    }
+
+    #[inline]
+    pub fn get_color(&self, idx: u8) -> Result<Color, IndexedImageError> {
+        self.palette
+            .get(idx)
+            .ok_or(IndexOutOfRange(idx as usize, self.palette.len(), "palette"))
+    }
+
+    #[inline]
+    pub fn set_color(&mut self, idx: u8, color: Color) -> Result<(), IndexedImageError> {
+        self.palette.set(idx, color)
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Key/value data saved with the file, see [metadata](crate::metadata) for common keys
+    #[inline]
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Set a metadata value, returning the previous value
+    ///
+    /// `key` must be 1..=255 bytes and `value` at most 65535 bytes
+    pub fn set_metadata(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<Option<String>, IndexedImageError> {
+        metadata::insert(&mut self.metadata, key, value)
+    }
+
+    #[inline]
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.metadata.remove(key)
+    }
+
+    /// Highest color index used by the pixels, the palette must have more colors than this
+    ///
+    /// Tracked as pixels are changed, the pixels are only rescanned if the last use of the highest index was overwritten
+    #[inline]
+    pub fn min_palette_size_supported(&self) -> u8 {
+        self.highest_palette_idx.get(&self.pixels)
+    }
+
+    /// RGBA8 pixel data (`width * height * 4` bytes), for uploading to textures, etc
+    pub fn to_rgba_bytes(&self) -> Vec<u8> {
+        indexes_to_rgba(&self.palette, &self.pixels, false)
+    }
+
+    /// Like [LargeIndexedImage::to_rgba_bytes] but with RGB multiplied by alpha
+    pub fn to_rgba_bytes_premultiplied(&self) -> Vec<u8> {
+        indexes_to_rgba(&self.palette, &self.pixels, true)
+    }
+}
+
+impl LargeIndexedImage {
+    /// Always writes a version 2 file
+    ///
+    /// Errors will only be returned if you [FilePalette::Name] and the len is invalid
+    pub fn to_file_contents(&self, palette: &FilePalette) -> Result<Vec<u8>, IndexedImageError> {
+        let mut output = vec![];
+        output.extend_from_slice(&HEADER_V2);
+        output.push(Image.to_byte());
+
+        palette::write(palette, self.get_palette(), true, &mut output)?;
+        output.extend_from_slice(&self.width.to_be_bytes());
+        output.extend_from_slice(&self.height.to_be_bytes());
+        output.extend_from_slice(&self.pixels);
+        metadata::write(&self.metadata, &mut output);
+
+        Ok(output)
+    }
+
+    /// Same as `to_file_contents` but a CRC-32 checksum is appended, this is verified when reading
+    pub fn to_file_contents_with_checksum(
+        &self,
+        palette: &FilePalette,
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        let mut output = self.to_file_contents(palette)?;
+        append_checksum(&mut output);
+        Ok(output)
+    }
+
+    /// Create a [LargeIndexedImage] from a version 1 or 2 image file, image palette will be filled with transparency unless file contains colors
+    /// use `image.set_palette*` to replace the palette
+    pub fn from_file_contents(
+        bytes: &[u8],
+    ) -> Result<(LargeIndexedImage, FilePalette), IndexedImageError> {
+        let (version, file_type) = verify_format_version(bytes)?;
+        if file_type != Image {
+            return Err(wrong_file_type(Image.name(), &file_type));
+        }
+        if version == HEADER[3] {
+            return IndexedImage::from_file_contents(bytes)
+                .map(|(image, pal_type)| (LargeIndexedImage::from(image), pal_type));
+        }
+        let mut reader = ByteReader::new(bytes);
+        reader.skip(HEADER_V2.len() + 1, "header")?;
+        let (pal_type, colors) = palette::read(&mut reader)?;
+
+        reader.set_section(FileSection::Dimensions);
+        let width = reader.u16("width")?;
+        let height = reader.u16("height")?;
+        reader.set_section(FileSection::Pixels);
+        let palette_len = colors.as_ref().map(Vec::len);
+        let pixels = reader.pixels(width as usize * height as usize, palette_len, "pixels data")?;
+        let metadata = metadata::read(reader.offset(), reader.remaining())?;
+
+        let highest = pixels.iter().max().copied().unwrap_or_default() as usize;
+        let colors = match colors {
+            None => vec![TRANSPARENT; highest + 1],
+            Some(colors) => colors,
+        };
+
+        let mut image = LargeIndexedImage::new(width, height, colors, pixels.to_vec())?;
+        image.metadata = metadata;
+        Ok((image, pal_type))
+    }
+}
+
+impl From<IndexedImage> for LargeIndexedImage {
+    fn from(value: IndexedImage) -> Self {
+        let (width, height) = value.size();
+        LargeIndexedImage {
+            width: width as u16,
+            height: height as u16,
+            highest_palette_idx: HighestIdx::from(value.min_palette_size_supported()),
+            palette: value.get_palette().clone(),
+            pixels: value.get_pixels().to_vec(),
+            metadata: value.metadata().clone(),
+        }
+    }
+}
+
+impl TryFrom<LargeIndexedImage> for IndexedImage {
+    type Error = IndexedImageError;
+
+    /// Fails if the image is bigger than 255x255
+    fn try_from(value: LargeIndexedImage) -> Result<Self, Self::Error> {
+        if value.width > 255 || value.height > 255 {
+            return Err(ImageTooLarge(value.width as usize, value.height as usize));
+        }
+        let mut image = IndexedImage::new(
+            value.width as u8,
+            value.height as u8,
+            value.palette.into_vec(),
+            value.pixels,
+        )?;
+        for (key, value) in value.metadata {
+            image.set_metadata(&key, &value)?;
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette::*;
+
+    #[test]
+    fn large_round_trip() {
+        let pixels: Vec<u8> = (0..300 * 2).map(|i| (i % 3) as u8).collect();
+        let image = LargeIndexedImage::new(300, 2, vec![RED, GREEN, BLUE], pixels).unwrap();
+        let bytes = image.to_file_contents(&Colors).unwrap();
+        assert_eq!(&bytes[..4], &HEADER_V2);
+        let (output, pal) = LargeIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output, image);
+        assert_eq!(pal, Colors);
+        assert!(matches!(
+            IndexedImage::from_file_contents(&bytes),
+            Err(ImageTooLarge(300, 2))
+        ));
+        assert!(IndexedImage::try_from(image).is_err());
+    }
+
+    #[test]
+    fn reads_v1() {
+        let image = IndexedImage::new(2, 2, vec![RED, BLUE], vec![0, 1, 1, 0]).unwrap();
+        let bytes = image.to_file_contents(&Colors).unwrap();
+        let (large, _) = LargeIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(large.size(), (2, 2));
+        assert_eq!(large.get_pixels(), image.get_pixels());
+        assert_eq!(IndexedImage::try_from(large).unwrap(), image);
+    }
+}
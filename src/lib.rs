@@ -1,25 +1,100 @@
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![cfg_attr(
+    not(test),
+    deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)
+)]
+
+extern crate alloc;
+
+pub mod analysis;
 pub mod animated;
+pub mod autotile;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+pub mod brush;
+pub mod bundle;
 pub mod changing;
+pub mod channels;
 pub mod color;
+mod compat;
 pub mod conversion;
+pub mod diff;
+pub mod dither;
+pub mod effects;
+pub mod embedded;
 pub mod errors;
+#[cfg(feature = "png")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod file;
+#[cfg(feature = "arbitrary")]
+pub mod fuzzing;
 pub mod image;
+pub mod image_ref;
+pub mod import;
+#[cfg(feature = "image-interop")]
+pub mod interop;
 pub mod jasc_palette;
+pub mod large;
+pub mod lod;
+#[cfg(not(feature = "std"))]
+mod math;
+pub mod metadata;
+pub mod ops;
+pub mod orientation;
 pub mod palette;
+pub mod playback;
+#[cfg(feature = "std")]
+pub mod pool;
+pub mod quantize;
+mod reader;
+pub mod rect;
+pub mod replace;
+pub mod rotsprite;
 pub mod scaling;
+pub mod seam;
+pub mod snapshot;
+pub mod tags;
+#[cfg(feature = "testdata")]
+#[allow(clippy::expect_used)] // test data is hardcoded and always valid
+pub mod testdata;
+pub mod tileset;
+pub mod validation;
+pub mod view;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watermark;
 pub mod wrapper;
 
+pub use validation::validate;
+
 pub mod prelude {
     pub use crate::animated::*;
+    pub use crate::brush::Brush;
+    pub use crate::bundle::*;
     pub use crate::changing::*;
     pub use crate::color::*;
     pub use crate::conversion::*;
+    pub use crate::diff::ImageDiff;
+    pub use crate::dither::Dithering;
     pub use crate::errors::*;
     pub use crate::image::*;
+    pub use crate::image_ref::*;
     pub use crate::jasc_palette::*;
-    pub use crate::palette::FilePalette;
+    pub use crate::large::*;
+    pub use crate::ops::ImageOps;
+    pub use crate::orientation::{DirectionSet, Orientation};
+    pub use crate::palette::{BankAssignment, ColorDistance, FilePalette, Palette, PaletteSort};
+    pub use crate::playback::PlaybackState;
+    #[cfg(feature = "std")]
+    pub use crate::pool::BufferPool;
+    pub use crate::rect::*;
     pub use crate::scaling::*;
+    pub use crate::snapshot::SnapshotImage;
+    pub use crate::tags::FrameTag;
+    pub use crate::tileset::*;
+    pub use crate::view::{ImageView, ImageViewMut};
     pub use crate::wrapper::*;
     pub use crate::*;
 }
@@ -34,3 +109,10 @@ pub trait Tint {
     /// Channels are clamped to 0..=255
     fn tint_mul(&mut self, r_diff: f32, g_diff: f32, b_diff: f32, a_diff: f32);
 }
+
+pub trait HueShift {
+    /// Rotate the hue by `degrees`, saturation, lightness and alpha are unchanged
+    ///
+    /// Negative values rotate backwards
+    fn tint_hue_shift(&mut self, degrees: f32);
+}
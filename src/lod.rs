@@ -0,0 +1,161 @@
+//! Levels of detail, pre-scaled copies of an animation stored in the same file
+//!
+//! Each level is stored as a chunk after the pixel data (so older readers will ignore them)
+//! containing the scale (u8) and then a complete animation file without palette data
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::metadata::{read_chunks, write_chunk};
+use crate::palette::FilePalette;
+use crate::prelude::*;
+
+pub(crate) const LOD_TAG: [u8; 4] = *b"LOD ";
+
+impl AnimatedIndexedImage {
+    /// Writes this animation (as scale 1) and `levels`, pre-scaled copies of it
+    ///
+    /// Each level must be this animation's size multiplied by the scale (2..=255), have the same number of frames
+    /// and only use colors from this animation's palette. Levels will use this animation's palette and metadata when loaded
+    pub fn to_file_contents_lod(
+        &self,
+        palette: &FilePalette,
+        levels: &[(u8, &AnimatedIndexedImage)],
+    ) -> Result<Vec<u8>, IndexedImageError> {
+        let mut output = self.to_file_contents(palette)?;
+        let mut scales = vec![];
+        for (scale, level) in levels {
+            let scale = *scale;
+            if scale < 2
+                || scales.contains(&scale)
+                || level.width() as usize != self.width() as usize * scale as usize
+                || level.height() as usize != self.height() as usize * scale as usize
+                || level.frame_count() != self.frame_count()
+                || level.min_palette_size_supported() as usize >= self.get_palette().len()
+            {
+                return Err(InvalidLevelOfDetail(scale));
+            }
+            scales.push(scale);
+            let mut level = (*level).clone();
+            let keys: Vec<String> = level.metadata().keys().cloned().collect();
+            for key in keys {
+                level.remove_metadata(&key);
+            }
+            let mut payload = vec![scale];
+            payload.extend_from_slice(&level.to_file_contents(&FilePalette::NoData)?);
+            write_chunk(LOD_TAG, &payload, &mut output);
+        }
+        Ok(output)
+    }
+
+    /// Scales available in the file, always includes 1
+    pub fn lod_scales(bytes: &[u8]) -> Result<Vec<u8>, IndexedImageError> {
+        let (_, _, pixels_end) = AnimatedIndexedImage::read_file(bytes)?;
+        let mut scales = vec![1];
+        scales.extend(
+            read_chunks(&bytes[pixels_end..])
+                .into_iter()
+                .filter(|(_, tag, payload)| *tag == LOD_TAG && !payload.is_empty())
+                .map(|(_, _, payload)| payload[0]),
+        );
+        scales.sort_unstable();
+        scales.dedup();
+        Ok(scales)
+    }
+
+    /// Create an [AnimatedIndexedImage] using the largest level of detail that is at most `scale`
+    ///
+    /// Files without levels of detail, or a `scale` of 0 or 1, will load the normal animation
+    pub fn from_file_contents_lod(
+        bytes: &[u8],
+        scale: u8,
+    ) -> Result<(AnimatedIndexedImage, FilePalette), IndexedImageError> {
+        let (image, pal_type, pixels_end) = AnimatedIndexedImage::read_file(bytes)?;
+        let best = read_chunks(&bytes[pixels_end..])
+            .into_iter()
+            .filter(|(_, tag, payload)| *tag == LOD_TAG && !payload.is_empty())
+            .filter(|(_, _, payload)| payload[0] > 1 && payload[0] <= scale)
+            .max_by_key(|(_, _, payload)| payload[0]);
+        let Some((offset, _, payload)) = best else {
+            return Ok((image, pal_type));
+        };
+        let (mut level, _) =
+            AnimatedIndexedImage::from_file_contents(&payload[1..]).map_err(|err| {
+                InvalidValue {
+                    section: FileSection::Chunks,
+                    offset: pixels_end + offset,
+                    what: format!("{}x level of detail", payload[0]),
+                    expected: "an animation".to_string(),
+                    found: err.to_string(),
+                }
+            })?;
+        if level.frame_count() != image.frame_count() {
+            return Err(InvalidLevelOfDetail(payload[0]));
+        }
+        level.set_palette(image.get_palette())?;
+        for (key, value) in image.metadata() {
+            level.set_metadata(key, value)?;
+        }
+        Ok((level, pal_type))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette::*;
+
+    fn scaled(image: &AnimatedIndexedImage, scale: usize) -> AnimatedIndexedImage {
+        let pixels = image
+            .as_images()
+            .iter()
+            .flat_map(|frame| {
+                let algo = Scaling::nearest_neighbour(scale, scale).unwrap();
+                frame.scale(algo).unwrap().get_pixels().to_vec()
+            })
+            .collect();
+        AnimatedIndexedImage::new(
+            image.width() * scale as u8,
+            image.height() * scale as u8,
+            image.get_per_frame(),
+            image.frame_count(),
+            image.get_palette().to_vec(),
+            pixels,
+            image.play_type(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn select_level() {
+        let mut image =
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED, BLUE], vec![0, 1], PlayType::Loops)
+                .unwrap();
+        image.set_metadata("author", "test").unwrap();
+        let double = scaled(&image, 2);
+        let triple = scaled(&image, 3);
+        let bytes = image
+            .to_file_contents_lod(&Colors, &[(2, &double), (3, &triple)])
+            .unwrap();
+
+        assert_eq!(
+            AnimatedIndexedImage::lod_scales(&bytes).unwrap(),
+            vec![1, 2, 3]
+        );
+        let (output, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(output, image);
+        let (output, _) = AnimatedIndexedImage::from_file_contents_lod(&bytes, 1).unwrap();
+        assert_eq!(output, image);
+        let (output, _) = AnimatedIndexedImage::from_file_contents_lod(&bytes, 2).unwrap();
+        assert_eq!(output.size(), (2, 2));
+        assert_eq!(output.metadata(), image.metadata());
+        let (output, _) = AnimatedIndexedImage::from_file_contents_lod(&bytes, 8).unwrap();
+        assert_eq!(output.size(), (3, 3));
+        assert_eq!(output.get_palette(), &[RED, BLUE]);
+
+        assert!(image
+            .to_file_contents_lod(&Colors, &[(3, &double)])
+            .is_err());
+        assert!(image.to_file_contents_lod(&Colors, &[(1, &image)]).is_err());
+    }
+}
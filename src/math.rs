@@ -0,0 +1,84 @@
+//! Float methods that are only inherent with `std`, using libm instead
+
+pub(crate) trait Float: Sized {
+    fn round(self) -> Self;
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn cbrt(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn rem_euclid(self, rhs: Self) -> Self;
+    fn sin_cos(self) -> (Self, Self);
+}
+
+macro_rules! impl_float {
+    ($ty:ty, $round:ident, $floor:ident, $ceil:ident, $sqrt:ident, $cbrt:ident, $pow:ident, $fmod:ident, $sin:ident, $cos:ident) => {
+        impl Float for $ty {
+            fn round(self) -> Self {
+                libm::$round(self)
+            }
+
+            fn floor(self) -> Self {
+                libm::$floor(self)
+            }
+
+            fn ceil(self) -> Self {
+                libm::$ceil(self)
+            }
+
+            fn sqrt(self) -> Self {
+                libm::$sqrt(self)
+            }
+
+            fn cbrt(self) -> Self {
+                libm::$cbrt(self)
+            }
+
+            fn powf(self, n: Self) -> Self {
+                libm::$pow(self, n)
+            }
+
+            fn powi(self, n: i32) -> Self {
+                libm::$pow(self, n as $ty)
+            }
+
+            fn rem_euclid(self, rhs: Self) -> Self {
+                let rem = libm::$fmod(self, rhs);
+                if rem < 0.0 {
+                    rem + rhs.abs()
+                } else {
+                    rem
+                }
+            }
+
+            fn sin_cos(self) -> (Self, Self) {
+                (libm::$sin(self), libm::$cos(self))
+            }
+        }
+    };
+}
+
+impl_float!(f32, roundf, floorf, ceilf, sqrtf, cbrtf, powf, fmodf, sinf, cosf);
+impl_float!(f64, round, floor, ceil, sqrt, cbrt, pow, fmod, sin, cos);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_std() {
+        for value in [-2.5_f32, -0.4, 0.0, 0.5, 1.7, 10.2] {
+            assert_eq!(Float::round(value), value.round());
+            assert_eq!(Float::floor(value), value.floor());
+            assert_eq!(Float::ceil(value), value.ceil());
+            assert_eq!(Float::rem_euclid(value, 3.0), value.rem_euclid(3.0));
+            assert!((Float::cbrt(value) - value.cbrt()).abs() < 1e-6);
+            assert!((Float::powi(value, 3) - value.powi(3)).abs() < 1e-3);
+            assert_eq!(Float::round(value as f64), (value as f64).round());
+        }
+        assert_eq!(Float::sqrt(16.0_f32), 4.0);
+        assert_eq!(Float::powf(2.0_f32, 3.0), 8.0);
+        assert_eq!(Float::sin_cos(0.0_f64), (0.0, 1.0));
+    }
+}
@@ -0,0 +1,150 @@
+//! Key/value metadata stored in ICI files
+//!
+//! Metadata is written as a chunk after the pixel data so older readers will ignore it
+//! Chunks are a 4 byte tag, payload length (u32) and payload
+
+use crate::compat::*;
+use crate::errors::IndexedImageError::*;
+use crate::errors::{FileSection, IndexedImageError};
+use crate::reader::ByteReader;
+use alloc::collections::BTreeMap;
+
+pub const AUTHOR: &str = "author";
+pub const TOOL: &str = "tool";
+/// Recommended format is ISO 8601
+pub const CREATED: &str = "created";
+pub const DESCRIPTION: &str = "description";
+pub const COPYRIGHT: &str = "copyright";
+/// EXIF style orientation (1..=8), see [Orientation](crate::orientation::Orientation)
+pub const ORIENTATION: &str = "orientation";
+/// Hex encoded payload, see [IndexedImage::set_watermark_metadata](crate::image::IndexedImage::set_watermark_metadata)
+pub const WATERMARK: &str = "watermark";
+
+pub(crate) const METADATA_TAG: [u8; 4] = *b"META";
+/// Length of a chunk's tag and payload length
+pub(crate) const CHUNK_HEADER_LEN: usize = 8;
+
+/// Inserts `key` and `value` if `key` is 1..=255 bytes, `value` is at most 65535 bytes
+/// and there's space for another entry
+pub(crate) fn insert(
+    metadata: &mut BTreeMap<String, String>,
+    key: &str,
+    value: &str,
+) -> Result<Option<String>, IndexedImageError> {
+    if key.is_empty() || key.len() > 255 || value.len() > u16::MAX as usize {
+        return Err(InvalidMetadata(key.to_string()));
+    }
+    if !metadata.contains_key(key) && metadata.len() >= u16::MAX as usize {
+        return Err(InvalidMetadata(key.to_string()));
+    }
+    Ok(metadata.insert(key.to_string(), value.to_string()))
+}
+
+pub(crate) fn write_chunk(tag: [u8; 4], payload: &[u8], output: &mut Vec<u8>) {
+    output.extend_from_slice(&tag);
+    output.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    output.extend_from_slice(payload);
+}
+
+/// Reads chunks from data after the pixels, stops at the first incomplete chunk
+///
+/// Returns the offset (from the start of `bytes`), tag and payload of each chunk
+pub(crate) fn read_chunks(bytes: &[u8]) -> Vec<(usize, [u8; 4], &[u8])> {
+    let mut chunks = vec![];
+    let mut pos = 0;
+    while bytes.len() >= pos + CHUNK_HEADER_LEN {
+        let tag = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        let len = u32::from_be_bytes([
+            bytes[pos + 4],
+            bytes[pos + 5],
+            bytes[pos + 6],
+            bytes[pos + 7],
+        ]) as usize;
+        let start = pos + CHUNK_HEADER_LEN;
+        let Some(payload) = start.checked_add(len).and_then(|end| bytes.get(start..end)) else {
+            break;
+        };
+        chunks.push((pos, tag, payload));
+        pos = start + len;
+    }
+    chunks
+}
+
+/// Reader for the payload of the first chunk with `tag` in the data after the pixels (`trailing`,
+/// which starts at `offset` in the file), `None` if there isn't one
+pub(crate) fn chunk_reader(offset: usize, trailing: &[u8], tag: [u8; 4]) -> Option<ByteReader<'_>> {
+    read_chunks(trailing)
+        .into_iter()
+        .find(|(_, chunk_tag, _)| *chunk_tag == tag)
+        .map(|(chunk_offset, _, payload)| {
+            ByteReader::with_offset(
+                payload,
+                offset + chunk_offset + CHUNK_HEADER_LEN,
+                FileSection::Chunks,
+            )
+        })
+}
+
+/// Writes the metadata chunk, nothing is written if `metadata` is empty
+pub(crate) fn write(metadata: &BTreeMap<String, String>, output: &mut Vec<u8>) {
+    if metadata.is_empty() {
+        return;
+    }
+    let mut payload = vec![];
+    payload.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+    for (key, value) in metadata {
+        payload.push(key.len() as u8);
+        payload.extend_from_slice(key.as_bytes());
+        payload.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        payload.extend_from_slice(value.as_bytes());
+    }
+    write_chunk(METADATA_TAG, &payload, output);
+}
+
+/// Reads metadata from data after the pixels (`trailing`, which starts at `offset` in the file)
+///
+/// Unrecognised trailing data is ignored
+pub(crate) fn read(
+    offset: usize,
+    trailing: &[u8],
+) -> Result<BTreeMap<String, String>, IndexedImageError> {
+    let mut metadata = BTreeMap::new();
+    let Some(mut reader) = chunk_reader(offset, trailing, METADATA_TAG) else {
+        return Ok(metadata);
+    };
+    let count = reader.u16("metadata count")?;
+    for _ in 0..count {
+        let key_len = reader.u8("metadata key length")?;
+        let key = reader.string(key_len as usize, "metadata key")?;
+        let value_len = reader.u16("metadata value length")?;
+        let value = reader.string(value_len as usize, "metadata value")?;
+        metadata.insert(key, value);
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut metadata = BTreeMap::new();
+        metadata.insert(AUTHOR.to_string(), "Emma".to_string());
+        metadata.insert("level".to_string(), String::new());
+        let mut output = vec![1, 2];
+        write(&metadata, &mut output);
+        assert_eq!(read(2, &output[2..]).unwrap(), metadata);
+    }
+
+    #[test]
+    fn ignores_other_data() {
+        assert!(read(0, &[9, 9, 9]).unwrap().is_empty());
+        let mut output = vec![];
+        write_chunk(*b"TEST", &[1, 2, 3], &mut output);
+        assert!(read(0, &output).unwrap().is_empty());
+        let mut output = vec![];
+        write_chunk(METADATA_TAG, &[0, 1, 5], &mut output);
+        assert!(read(0, &output).is_err());
+    }
+}
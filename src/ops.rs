@@ -0,0 +1,310 @@
+//! Chainable image operations
+//!
+//! Operations are deferred until [ImageOps::finish] so they can be combined: color changes are
+//! applied to the palette in one pass and any number of rotations and flips are done as a single pixel copy
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorOp {
+    Add(isize, isize, isize, isize),
+    Mul(f32, f32, f32, f32),
+    HueShift(f32),
+    Saturate(f32),
+    Brightness(f32),
+}
+
+impl ColorOp {
+    fn apply(&self, color: &mut Color) {
+        match *self {
+            ColorOp::Add(r, g, b, a) => Tint::tint_add(color, r, g, b, a),
+            ColorOp::Mul(r, g, b, a) => Tint::tint_mul(color, r, g, b, a),
+            ColorOp::HueShift(degrees) => color.tint_hue_shift(degrees),
+            ColorOp::Saturate(amount) => *color = color.with_saturate(amount),
+            ColorOp::Brightness(amount) => *color = color.with_brightness(amount),
+        }
+    }
+}
+
+/// Chain of operations on an [IndexedImage]
+///
+/// ```
+///# use ici_files::prelude::*;
+///# fn main() -> Result<(), IndexedImageError> {
+/// let image = IndexedImage::new(2, 1, vec![TRANSPARENT, RED], vec![0, 1])?;
+/// let output = ImageOps::new(image)
+///     .scale(Scaling::nearest_neighbour(2, 2)?)
+///     .rotate_cw()
+///     .tint_mul(0.5, 0.5, 0.5, 1.0)
+///     .finish()?;
+/// assert_eq!(output.size(), (2, 4));
+///# Ok(())
+///# }
+/// ```
+///
+/// The first error is returned by [ImageOps::finish], any operations after it are skipped
+#[derive(Debug)]
+pub struct ImageOps {
+    image: IndexedImage,
+    colors: Vec<ColorOp>,
+    /// clockwise rotations, applied after `flipped`
+    rotations: u8,
+    /// horizontal flip, applied before rotating
+    flipped: bool,
+    error: Option<IndexedImageError>,
+}
+
+impl ImageOps {
+    pub fn new(image: IndexedImage) -> Self {
+        Self {
+            image,
+            colors: vec![],
+            rotations: 0,
+            flipped: false,
+            error: None,
+        }
+    }
+
+    /// Apply the pending operations and return the image
+    pub fn finish(mut self) -> Result<IndexedImage, IndexedImageError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.apply_orientation();
+        self.apply_colors();
+        Ok(self.image)
+    }
+
+    pub fn scale(mut self, algo: Scaling) -> Self {
+        if self.error.is_none() {
+            self.apply_orientation();
+            match self.image.scale(algo) {
+                Ok(image) => self.image = image,
+                Err(err) => self.error = Some(err),
+            }
+        }
+        self
+    }
+
+    pub fn rotate_cw(mut self) -> Self {
+        self.rotations = (self.rotations + 1) % 4;
+        self
+    }
+
+    pub fn rotate_ccw(mut self) -> Self {
+        self.rotations = (self.rotations + 3) % 4;
+        self
+    }
+
+    pub fn flip_horizontal(mut self) -> Self {
+        self.rotations = (4 - self.rotations) % 4;
+        self.flipped = !self.flipped;
+        self
+    }
+
+    pub fn flip_vertical(mut self) -> Self {
+        // flipping vertically is the same as flipping horizontally and then rotating 180°
+        self.rotations = (6 - self.rotations) % 4;
+        self.flipped = !self.flipped;
+        self
+    }
+
+    /// See [Tint::tint_add]
+    pub fn tint_add(mut self, r_diff: isize, g_diff: isize, b_diff: isize, a_diff: isize) -> Self {
+        self.colors
+            .push(ColorOp::Add(r_diff, g_diff, b_diff, a_diff));
+        self
+    }
+
+    /// See [Tint::tint_mul]
+    pub fn tint_mul(mut self, r_diff: f32, g_diff: f32, b_diff: f32, a_diff: f32) -> Self {
+        self.colors
+            .push(ColorOp::Mul(r_diff, g_diff, b_diff, a_diff));
+        self
+    }
+
+    /// See [HueShift::tint_hue_shift]
+    pub fn hue_shift(mut self, degrees: f32) -> Self {
+        self.colors.push(ColorOp::HueShift(degrees));
+        self
+    }
+
+    /// See [ChangeColors::with_saturate]
+    pub fn saturate(mut self, amount: f32) -> Self {
+        self.colors.push(ColorOp::Saturate(amount));
+        self
+    }
+
+    /// See [ChangeColors::with_brightness]
+    pub fn brightness(mut self, amount: f32) -> Self {
+        self.colors.push(ColorOp::Brightness(amount));
+        self
+    }
+
+    /// Set fully transparent pixels next to (not diagonally) a visible pixel to `color_idx`
+    ///
+    /// Transparency is checked after any previous color changes
+    pub fn outline(mut self, color_idx: u8) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let palette_len = self.image.get_palette().len();
+        if color_idx as usize >= palette_len {
+            self.error = Some(IndexOutOfRange(color_idx as usize, palette_len, "palette"));
+            return self;
+        }
+        // outlines are the same in any orientation, so rotations and flips can stay pending
+        self.apply_colors();
+        let width = self.image.width() as usize;
+        let height = self.image.height() as usize;
+        let palette = self.image.get_palette();
+        let visible: Vec<bool> = self
+            .image
+            .get_pixels()
+            .iter()
+            .map(|idx| palette.get(*idx).map(|c| c.a > 0).unwrap_or_default())
+            .collect();
+        let is_visible = |x: usize, y: usize| visible[x + y * width];
+        let pixels = self.image.raw_pixels_mut();
+        for y in 0..height {
+            for x in 0..width {
+                if is_visible(x, y) {
+                    continue;
+                }
+                if (x > 0 && is_visible(x - 1, y))
+                    || (x + 1 < width && is_visible(x + 1, y))
+                    || (y > 0 && is_visible(x, y - 1))
+                    || (y + 1 < height && is_visible(x, y + 1))
+                {
+                    pixels[x + y * width] = color_idx;
+                }
+            }
+        }
+        self.image.refresh_highest_palette_idx();
+        self
+    }
+
+    fn apply_colors(&mut self) {
+        if self.colors.is_empty() {
+            return;
+        }
+        for color in self.image.palette_mut().iter_mut() {
+            for op in &self.colors {
+                op.apply(color);
+            }
+        }
+        self.colors.clear();
+    }
+
+    fn apply_orientation(&mut self) {
+        if self.rotations != 0 || self.flipped {
+            self.image = reorient(&self.image, self.flipped, self.rotations);
+        }
+        self.rotations = 0;
+        self.flipped = false;
+    }
+}
+
+/// Copy of `image` flipped horizontally (if `flipped`) and then rotated clockwise `rotations` times
+///
+/// Metadata is not copied
+pub(crate) fn reorient(image: &IndexedImage, flipped: bool, rotations: u8) -> IndexedImage {
+    let rotations = rotations % 4;
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let (output_width, output_height) = if rotations.is_multiple_of(2) {
+        (image.width(), image.height())
+    } else {
+        (image.height(), image.width())
+    };
+    let mut pixels = vec![0; width * height];
+    for (i, pixel) in image.get_pixels().iter().enumerate() {
+        let (mut x, mut y) = (i % width, i / width);
+        if flipped {
+            x = width - x - 1;
+        }
+        let (mut w, mut h) = (width, height);
+        for _ in 0..rotations {
+            (x, y) = (h - y - 1, x);
+            (w, h) = (h, w);
+        }
+        pixels[x + y * w] = *pixel;
+    }
+    IndexedImage::from_parts(
+        output_width,
+        output_height,
+        image.get_palette().clone(),
+        pixels,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image() -> IndexedImage {
+        IndexedImage::new(
+            3,
+            2,
+            vec![TRANSPARENT, RED, GREEN, BLUE],
+            vec![0, 1, 2, 3, 0, 0],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_individual_ops() {
+        let image = image();
+        let output = ImageOps::new(image.clone())
+            .rotate_cw()
+            .flip_horizontal()
+            .tint_add(10, 0, 0, 0)
+            .rotate_cw()
+            .hue_shift(90.0)
+            .flip_vertical()
+            .scale(Scaling::nearest_neighbour(2, 1).unwrap())
+            .rotate_ccw()
+            .finish()
+            .unwrap();
+
+        let mut expected = image
+            .rotate_cw()
+            .flip_horizontal()
+            .unwrap()
+            .rotate_cw()
+            .flip_vertical()
+            .unwrap()
+            .scale(Scaling::nearest_neighbour(2, 1).unwrap())
+            .unwrap()
+            .rotate_ccw();
+        Tint::tint_add(&mut expected, 10, 0, 0, 0);
+        expected.tint_hue_shift(90.0);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn outline() {
+        let output = ImageOps::new(image())
+            .tint_mul(1.0, 1.0, 1.0, 0.0)
+            .tint_add(0, 0, 0, 255)
+            .outline(3)
+            .finish()
+            .unwrap();
+        assert_eq!(output.get_pixels(), &[0, 1, 2, 3, 0, 0]);
+
+        let output = ImageOps::new(image()).outline(1).finish().unwrap();
+        assert_eq!(output.get_pixels(), &[1, 1, 2, 3, 1, 1]);
+    }
+
+    #[test]
+    fn first_error_returned() {
+        let result = ImageOps::new(image())
+            .outline(4)
+            .scale(Scaling::nearest_neighbour(200, 1).unwrap())
+            .finish();
+        assert!(matches!(result, Err(IndexOutOfRange(4, 4, "palette"))));
+    }
+}
@@ -0,0 +1,234 @@
+//! EXIF style orientation, stored in metadata so images can be reoriented without changing the pixels
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::metadata::ORIENTATION;
+use crate::ops::reorient;
+use crate::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How an image should be transformed before being shown, values match EXIF orientation
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum Orientation {
+    #[default]
+    Normal = 1,
+    FlipHorizontal = 2,
+    Rotate180 = 3,
+    FlipVertical = 4,
+    /// Flip horizontally and rotate 90° counter clockwise (swaps x and y)
+    Transpose = 5,
+    /// Rotate 90° clockwise
+    Rotate90 = 6,
+    /// Flip horizontally and rotate 90° clockwise
+    Transverse = 7,
+    /// Rotate 90° counter clockwise
+    Rotate270 = 8,
+}
+
+impl Orientation {
+    pub fn from_exif(value: u8) -> Option<Orientation> {
+        Some(match value {
+            1 => Orientation::Normal,
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Transpose,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Transverse,
+            8 => Orientation::Rotate270,
+            _ => return None,
+        })
+    }
+
+    #[inline]
+    pub fn exif(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Whether to flip horizontally and then the number of clockwise rotations
+    fn transform(&self) -> (bool, u8) {
+        match self {
+            Orientation::Normal => (false, 0),
+            Orientation::FlipHorizontal => (true, 0),
+            Orientation::Rotate180 => (false, 2),
+            Orientation::FlipVertical => (true, 2),
+            Orientation::Transpose => (true, 3),
+            Orientation::Rotate90 => (false, 1),
+            Orientation::Transverse => (true, 1),
+            Orientation::Rotate270 => (false, 3),
+        }
+    }
+
+    /// Size of an image after this orientation is applied
+    pub fn apply_to_size(&self, (width, height): (u8, u8)) -> (u8, u8) {
+        if self.transform().1.is_multiple_of(2) {
+            (width, height)
+        } else {
+            (height, width)
+        }
+    }
+}
+
+/// Facing directions made by [IndexedImage::generate_direction_set], the image must be drawn facing east (right)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DirectionSet {
+    /// East, south, west and north
+    FourWay,
+    /// East, south east, south, south west, west, north west, north and north east
+    ///
+    /// Diagonals are rotated with RotSprite so they will be larger, with the new space filled with `fill_idx`
+    EightWay { fill_idx: u8 },
+}
+
+impl IndexedImage {
+    /// Facing variants of this image, clockwise starting with this image (east)
+    ///
+    /// West is mirrored so the sprite stays upright, north and south are rotated
+    pub fn generate_direction_set(
+        &self,
+        set: DirectionSet,
+    ) -> Result<Vec<IndexedImage>, IndexedImageError> {
+        let east = reorient(self, false, 0);
+        let south = reorient(self, false, 1);
+        let west = reorient(self, true, 0);
+        let north = reorient(self, false, 3);
+        Ok(match set {
+            DirectionSet::FourWay => vec![east, south, west, north],
+            DirectionSet::EightWay { fill_idx } => {
+                let south_east = self.rotate_rotsprite(45.0, fill_idx)?;
+                let north_east = self.rotate_rotsprite(-45.0, fill_idx)?;
+                let south_west = reorient(&south_east, true, 0);
+                let north_west = reorient(&north_east, true, 0);
+                vec![
+                    east, south_east, south, south_west, west, north_west, north, north_east,
+                ]
+            }
+        })
+    }
+
+    /// Orientation from the metadata, [Orientation::Normal] if missing or invalid
+    pub fn orientation(&self) -> Orientation {
+        self.metadata()
+            .get(ORIENTATION)
+            .and_then(|value| value.parse::<u8>().ok())
+            .and_then(Orientation::from_exif)
+            .unwrap_or_default()
+    }
+
+    /// Store `orientation` in the metadata, the pixels are not changed
+    ///
+    /// [Orientation::Normal] removes the metadata entry
+    pub fn set_orientation(&mut self, orientation: Orientation) -> Result<(), IndexedImageError> {
+        if orientation == Orientation::Normal {
+            self.remove_metadata(ORIENTATION);
+        } else {
+            self.set_metadata(ORIENTATION, &orientation.exif().to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Copy of the image with the orientation applied to the pixels
+    ///
+    /// Metadata is kept, except for the orientation
+    pub fn apply_orientation(&self) -> IndexedImage {
+        let (flipped, rotations) = self.orientation().transform();
+        let mut output = reorient(self, flipped, rotations);
+        let metadata = output.metadata_mut();
+        metadata.clone_from(self.metadata());
+        metadata.remove(ORIENTATION);
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut image = IndexedImage::new(3, 2, vec![RED, BLUE], vec![0, 1, 0, 1, 1, 1]).unwrap();
+        image.set_metadata("author", "test").unwrap();
+        assert_eq!(image.orientation(), Orientation::Normal);
+        assert_eq!(image.apply_orientation(), image);
+
+        let expected = [
+            (
+                Orientation::FlipHorizontal,
+                image.flip_horizontal().unwrap(),
+            ),
+            (Orientation::Rotate180, image.rotate_cw().rotate_cw()),
+            (Orientation::FlipVertical, image.flip_vertical().unwrap()),
+            (
+                Orientation::Transpose,
+                image.flip_horizontal().unwrap().rotate_ccw(),
+            ),
+            (Orientation::Rotate90, image.rotate_cw()),
+            (
+                Orientation::Transverse,
+                image.flip_horizontal().unwrap().rotate_cw(),
+            ),
+            (Orientation::Rotate270, image.rotate_ccw()),
+        ];
+        for (orientation, expected) in expected {
+            assert_eq!(
+                Orientation::from_exif(orientation.exif()),
+                Some(orientation)
+            );
+            let mut image = image.clone();
+            image.set_orientation(orientation).unwrap();
+            assert_eq!(image.orientation(), orientation);
+            let output = image.apply_orientation();
+            assert_eq!(output.get_pixels(), expected.get_pixels());
+            assert_eq!(output.size(), orientation.apply_to_size(image.size()));
+            assert_eq!(output.orientation(), Orientation::Normal);
+            assert_eq!(output.metadata().get("author").unwrap(), "test");
+        }
+
+        let output = image.apply_orientation();
+        let mut image = image.clone();
+        image.set_orientation(Orientation::Transpose).unwrap();
+        image.set_orientation(Orientation::Normal).unwrap();
+        assert_eq!(image.apply_orientation(), output);
+    }
+
+    #[test]
+    fn direction_set() {
+        let image = IndexedImage::new(3, 2, vec![RED, BLUE], vec![0, 1, 0, 1, 1, 1]).unwrap();
+        let set = image.generate_direction_set(DirectionSet::FourWay).unwrap();
+        assert_eq!(
+            set,
+            vec![
+                image.clone(),
+                image.rotate_cw(),
+                image.flip_horizontal().unwrap(),
+                image.rotate_ccw()
+            ]
+        );
+
+        #[rustfmt::skip]
+        let pixels = vec![
+            0, 1, 1, 0,
+            0, 0, 1, 0,
+            1, 0, 0, 0,
+            0, 0, 0, 0,
+        ];
+        let image = IndexedImage::new(4, 4, vec![RED, BLUE, TRANSPARENT], pixels).unwrap();
+        let set = image
+            .generate_direction_set(DirectionSet::EightWay { fill_idx: 2 })
+            .unwrap();
+        assert_eq!(set.len(), 8);
+        assert_eq!(set[1].size(), (6, 6));
+        assert_eq!(set[0], image);
+        assert_eq!(set[2], image.rotate_cw());
+        assert_eq!(set[3], set[1].flip_horizontal().unwrap());
+        assert_eq!(set[4], image.flip_horizontal().unwrap());
+        assert_ne!(set[4], image);
+        assert_eq!(set[5], set[7].flip_horizontal().unwrap());
+        assert_eq!(set[6], image.rotate_ccw());
+        assert!(image
+            .generate_direction_set(DirectionSet::EightWay { fill_idx: 3 })
+            .is_err());
+    }
+}
@@ -1,7 +1,13 @@
+use crate::compat::*;
 use crate::errors::IndexedImageError::*;
 use crate::palette::FilePalette::*;
 use crate::prelude::*;
-use std::collections::HashSet;
+use crate::quantize::{quantize, quantize_in_place};
+use crate::reader::ByteReader;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 pub(crate) const PAL_NO_DATA: u8 = 0;
 pub(crate) const PAL_ID: u8 = 1;
@@ -17,24 +23,539 @@ pub enum FilePalette {
     ID(u16),
     /// Include palette name (reader will need to know what the name refers to) 1..=255 chars
     Name(String),
-    /// Include palette colors
+    /// Include palette colors (1..=256)
     Colors,
 }
 
-fn distinct_count(colors: &[Color]) -> usize {
-    colors.iter().collect::<HashSet<_>>().len()
+/// Colors for an indexed image, always contains 1..=256 colors so every `u8` index can refer to a color
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Vec<Color>", into = "Vec<Color>")
+)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Palette(Vec<Color>);
+
+impl Palette {
+    pub const MAX_COLORS: usize = 256;
+
+    /// Returns an error if `colors` is empty or has more than 256 colors
+    pub fn new(colors: Vec<Color>) -> Result<Palette, IndexedImageError> {
+        if colors.is_empty() {
+            return Err(PaletteIsEmpty);
+        }
+        if colors.len() > Palette::MAX_COLORS {
+            return Err(PaletteTooManyColors);
+        }
+        Ok(Palette(colors))
+    }
+
+    /// Palette of `len` transparent colors, `len` is clamped to 1..=256
+    pub fn transparent(len: usize) -> Palette {
+        Palette(vec![TRANSPARENT; len.clamp(1, Palette::MAX_COLORS)])
+    }
+
+    /// Creates a palette from `colors`, extra colors are dropped and if empty a single transparent color is used
+    pub(crate) fn from_vec_lossy(mut colors: Vec<Color>) -> Palette {
+        colors.truncate(Palette::MAX_COLORS);
+        if colors.is_empty() {
+            colors.push(TRANSPARENT);
+        }
+        Palette(colors)
+    }
+
+    #[inline]
+    pub fn get(&self, idx: u8) -> Option<Color> {
+        self.0.get(idx as usize).copied()
+    }
+
+    pub fn set(&mut self, idx: u8, color: Color) -> Result<(), IndexedImageError> {
+        let len = self.0.len();
+        let slot =
+            self.0
+                .get_mut(idx as usize)
+                .ok_or(IndexOutOfRange(idx as usize, len, "palette"))?;
+        *slot = color;
+        Ok(())
+    }
+
+    /// Set several colors at once, if any index is outside the palette then no colors are changed
+    pub fn set_colors(&mut self, colors: &[(u8, Color)]) -> Result<(), IndexedImageError> {
+        let len = self.0.len();
+        if let Some((idx, _)) = colors.iter().find(|(idx, _)| *idx as usize >= len) {
+            return Err(IndexOutOfRange(*idx as usize, len, "palette"));
+        }
+        for (idx, color) in colors {
+            self.0[*idx as usize] = *color;
+        }
+        Ok(())
+    }
+
+    /// Adds `color` to the end of the palette and returns its index
+    ///
+    /// Returns an error if the palette already has 256 colors
+    pub fn add(&mut self, color: Color) -> Result<u8, IndexedImageError> {
+        if self.0.len() >= Palette::MAX_COLORS {
+            return Err(PaletteTooManyColors);
+        }
+        self.0.push(color);
+        Ok((self.0.len() - 1) as u8)
+    }
+
+    /// Index of the first exact match for `color`
+    pub fn index_of(&self, color: Color) -> Option<u8> {
+        self.0.iter().position(|c| *c == color).map(|i| i as u8)
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[Color] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<Color> {
+        self.0
+    }
+
+    /// Removes duplicate colors, keeping the first of each
+    ///
+    /// Returns a table mapping each old index to its new index
+    pub fn dedup(&mut self) -> Vec<u8> {
+        let mut colors: Vec<Color> = vec![];
+        let mut remap = Vec::with_capacity(self.0.len());
+        for color in &self.0 {
+            match colors.iter().position(|c| c == color) {
+                Some(i) => remap.push(i as u8),
+                None => {
+                    remap.push(colors.len() as u8);
+                    colors.push(*color);
+                }
+            }
+        }
+        self.0 = colors;
+        remap
+    }
+
+    /// Sorts colors with grays first (by lightness) then by hue and lightness
+    ///
+    /// Returns a table mapping each old index to its new index
+    pub fn sort_by_hue(&mut self) -> Vec<u8> {
+        self.sort_by_key(hue_order)
+    }
+
+    /// Sorts colors by `key` (stable)
+    ///
+    /// Returns a table mapping each old index to its new index
+    pub fn sort_by_key<K: Ord, F: FnMut(&Color) -> K>(&mut self, mut key: F) -> Vec<u8> {
+        let mut order: Vec<usize> = (0..self.0.len()).collect();
+        order.sort_by_key(|i| key(&self.0[*i]));
+        self.reorder(order)
+    }
+
+    /// Moves color `order[i]` to `i`, `order` must contain every index once
+    ///
+    /// Returns a table mapping each old index to its new index
+    pub(crate) fn reorder(&mut self, order: Vec<usize>) -> Vec<u8> {
+        let mut remap = vec![0; self.0.len()];
+        for (new, old) in order.iter().enumerate() {
+            remap[*old] = new as u8;
+        }
+        self.0 = order.into_iter().map(|i| self.0[i]).collect();
+        remap
+    }
+}
+
+/// Colors can be read and changed but the palette length can't be changed this way
+impl Deref for Palette {
+    type Target = [Color];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Palette {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl AsRef<[Color]> for Palette {
+    fn as_ref(&self) -> &[Color] {
+        &self.0
+    }
+}
+
+impl TryFrom<Vec<Color>> for Palette {
+    type Error = IndexedImageError;
+
+    fn try_from(value: Vec<Color>) -> Result<Self, Self::Error> {
+        Palette::new(value)
+    }
+}
+
+impl TryFrom<&[Color]> for Palette {
+    type Error = IndexedImageError;
+
+    fn try_from(value: &[Color]) -> Result<Self, Self::Error> {
+        Palette::new(value.to_vec())
+    }
+}
+
+impl From<Palette> for Vec<Color> {
+    fn from(value: Palette) -> Self {
+        value.0
+    }
+}
+
+impl PartialEq<[Color]> for Palette {
+    fn eq(&self, other: &[Color]) -> bool {
+        self.0 == other
+    }
+}
+
+impl<const N: usize> PartialEq<[Color; N]> for Palette {
+    fn eq(&self, other: &[Color; N]) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<Vec<Color>> for Palette {
+    fn eq(&self, other: &Vec<Color>) -> bool {
+        &self.0 == other
+    }
+}
+
+impl<'a> IntoIterator for &'a Palette {
+    type Item = &'a Color;
+    type IntoIter = core::slice::Iter<'a, Color>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Order for [IndexedImage::normalize_palette]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PaletteSort {
+    /// Darkest first, ignoring alpha
+    Luminance,
+    /// Grays first (by lightness) then by hue and lightness, see [Palette::sort_by_hue]
+    Hue,
+    /// Most used first
+    Usage,
+}
+
+/// Copy of `colors` after their indexes have changed, `remap` maps each old index to its new index
+///
+/// If several colors are moved to the same index the first is kept
+pub(crate) fn remap_colors(colors: &[Color], remap: &[u8]) -> Vec<Color> {
+    let len = remap
+        .iter()
+        .max()
+        .map(|max| *max as usize + 1)
+        .unwrap_or_default();
+    let mut output: Vec<Option<Color>> = vec![None; len];
+    for (color, new) in colors.iter().zip(remap) {
+        output[*new as usize].get_or_insert(*color);
+    }
+    output
+        .into_iter()
+        .map(|color| color.unwrap_or(TRANSPARENT))
+        .collect()
+}
+
+/// Removes colors that aren't used by `pixels`, the palette is unchanged if none (or all) are unused
+///
+/// Returns a table mapping each old index to its new index, `None` for removed colors
+pub(crate) fn prune_unused(palette: &mut Palette, pixels: &[u8]) -> Vec<Option<u8>> {
+    let mut used = vec![false; palette.len()];
+    for idx in pixels {
+        if let Some(used) = used.get_mut(*idx as usize) {
+            *used = true;
+        }
+    }
+    if used.iter().all(|used| *used) || !used.iter().any(|used| *used) {
+        return (0..palette.len()).map(|i| Some(i as u8)).collect();
+    }
+    let mut next = 0;
+    let remap: Vec<Option<u8>> = used
+        .iter()
+        .map(|used| {
+            used.then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect();
+    palette.0 = retain_remapped(&palette.0, &remap);
+    remap
+}
+
+/// Colors from `colors` that weren't removed by [prune_unused]
+pub(crate) fn retain_remapped(colors: &[Color], remap: &[Option<u8>]) -> Vec<Color> {
+    colors
+        .iter()
+        .zip(remap)
+        .filter(|(_, new)| new.is_some())
+        .map(|(color, _)| *color)
+        .collect()
+}
+
+/// How the distance between two colors is measured
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ColorDistance {
+    /// Straight line distance between the RGB channels
+    #[default]
+    Euclidean,
+    /// Straight line distance between the RGBA channels
+    EuclideanAlpha,
+    /// RGB distance weighted to better match human perception (redmean)
+    Weighted,
+    /// Like [ColorDistance::Weighted] but also includes alpha
+    WeightedAlpha,
+}
+
+impl ColorDistance {
+    /// Distance between `lhs` and `rhs` squared, cheaper than [ColorDistance::distance] when only comparing
+    pub fn distance_squared(&self, lhs: Color, rhs: Color) -> f32 {
+        let r = lhs.r as f32 - rhs.r as f32;
+        let g = lhs.g as f32 - rhs.g as f32;
+        let b = lhs.b as f32 - rhs.b as f32;
+        let a = lhs.a as f32 - rhs.a as f32;
+        let weighted = || {
+            let red_mean = (lhs.r as f32 + rhs.r as f32) / 2.0;
+            (2.0 + red_mean / 256.0) * r * r
+                + 4.0 * g * g
+                + (2.0 + (255.0 - red_mean) / 256.0) * b * b
+        };
+        match self {
+            ColorDistance::Euclidean => r * r + g * g + b * b,
+            ColorDistance::EuclideanAlpha => r * r + g * g + b * b + a * a,
+            ColorDistance::Weighted => weighted(),
+            ColorDistance::WeightedAlpha => weighted() + a * a,
+        }
+    }
+
+    /// Distance between `lhs` and `rhs`
+    #[inline]
+    pub fn distance(&self, lhs: Color, rhs: Color) -> f32 {
+        self.distance_squared(lhs, rhs).sqrt()
+    }
+}
+
+/// Index of the color in `colors` closest to `target` using [ColorDistance::Euclidean]
+///
+/// Returns 0 if `colors` is empty, ties are resolved to the lowest index
+#[inline]
+pub fn nearest_index(colors: &[Color], target: Color) -> u8 {
+    nearest_index_by(colors, target, ColorDistance::default())
+}
+
+/// Index of the color in `colors` closest to `target` using `metric`
+///
+/// Returns 0 if `colors` is empty, ties are resolved to the lowest index
+/// Only the first 256 colors are checked
+pub fn nearest_index_by(colors: &[Color], target: Color, metric: ColorDistance) -> u8 {
+    let mut best = 0;
+    let mut best_distance = f32::MAX;
+    for (i, color) in colors.iter().take(256).enumerate() {
+        if *color == target {
+            return i as u8;
+        }
+        let distance = metric.distance_squared(*color, target);
+        if distance < best_distance {
+            best = i;
+            best_distance = distance;
+        }
+    }
+    best as u8
+}
+
+/// Index of the palette in `candidates` that best matches `target` and its score using [ColorDistance::Euclidean]
+///
+/// See [find_closest_palette_by]
+#[inline]
+pub fn find_closest_palette(target: &[Color], candidates: &[Vec<Color>]) -> Option<(usize, f32)> {
+    find_closest_palette_by(target, candidates, ColorDistance::default())
+}
+
+/// Index of the palette in `candidates` that best matches `target` and its score using `metric`
+///
+/// The score is the average distance from each color in `target` to the closest color in the candidate,
+/// so 0.0 means every color is in the candidate. Empty candidates are never chosen
+///
+/// Returns None if `candidates` is empty or only contains empty palettes, ties are resolved to the lowest index
+pub fn find_closest_palette_by(
+    target: &[Color],
+    candidates: &[Vec<Color>],
+    metric: ColorDistance,
+) -> Option<(usize, f32)> {
+    let mut best = None;
+    for (i, candidate) in candidates.iter().enumerate() {
+        if candidate.is_empty() {
+            continue;
+        }
+        let total: f32 = target
+            .iter()
+            .map(|color| {
+                candidate
+                    .iter()
+                    .map(|c| metric.distance_squared(*c, *color))
+                    .fold(f32::MAX, f32::min)
+                    .sqrt()
+            })
+            .sum();
+        let score = total / target.len().max(1) as f32;
+        if best.is_none_or(|(_, best_score)| score < best_score) {
+            best = Some((i, score));
+        }
+    }
+    best
+}
+
+/// Colors closer than this (Euclidean RGB) are treated as indistinguishable by [suggest_distinguishable]
+pub const DISTINGUISHABLE_DISTANCE: f32 = 40.0;
+
+/// Suggested replacements for colors that look the same as an earlier color with `kind` color blindness
+/// but are different with normal vision, as `(index, new color)`
+///
+/// Replacements only change the lightness, as little as possible, and will be distinguishable from every
+/// other color that's distinguishable with normal vision. Fully transparent colors are ignored,
+/// colors are skipped if no lightness change works
+pub fn suggest_distinguishable(colors: &[Color], kind: ColorBlindness) -> Vec<(u8, Color)> {
+    let distance = |lhs: Color, rhs: Color| ColorDistance::Euclidean.distance(lhs, rhs);
+    let mut working: Vec<Color> = colors.iter().take(Palette::MAX_COLORS).copied().collect();
+    let mut suggestions = vec![];
+    for j in 0..working.len() {
+        let original = colors[j];
+        if original.a == 0 {
+            continue;
+        }
+        // colors that should look different to this one
+        let others: Vec<usize> = (0..working.len())
+            .filter(|i| {
+                *i != j
+                    && colors[*i].a > 0
+                    && distance(colors[*i], original) >= DISTINGUISHABLE_DISTANCE
+            })
+            .collect();
+        let clashes = |candidate: Color, others: &[usize], working: &[Color]| {
+            let simulated = candidate.simulate(kind);
+            others
+                .iter()
+                .any(|i| distance(working[*i].simulate(kind), simulated) < DISTINGUISHABLE_DISTANCE)
+        };
+        let earlier: Vec<usize> = others.iter().copied().filter(|i| *i < j).collect();
+        if !clashes(original, &earlier, &working) {
+            continue;
+        }
+        let (hue, saturation, lightness) = original.to_hsl();
+        let replacement = (1..=20)
+            .flat_map(|step| {
+                let offset = step as f32 * 0.025;
+                [lightness + offset, lightness - offset]
+            })
+            .filter(|l| (0.0..=1.0).contains(l))
+            .map(|l| Color::from_hsl(hue, saturation, l, original.a))
+            .find(|candidate| !clashes(*candidate, &others, &working));
+        if let Some(replacement) = replacement {
+            working[j] = replacement;
+            suggestions.push((j as u8, replacement));
+        }
+    }
+    suggestions
 }
 
 /// merges similar colors until there are < `max` unique colors
 /// the result will contain duplicates so the index is preserved
+///
+/// see [quantize](crate::quantize::quantize)
 pub fn simplify_palette_to_fit(colors: &[Color], max: usize) -> Vec<Color> {
-    let mut output = colors.to_vec();
-    let mut threshold = 2;
-    while distinct_count(&output) >= max {
-        output = simplify_palette(&output, threshold);
-        threshold += 10;
+    quantize_in_place(colors, max.saturating_sub(1).max(1))
+}
+
+/// Single palette of at most `max` colors for every image in `images`, i.e. a master palette for all of a game's assets
+///
+/// Colors used by more pixels have more influence, see [quantize](crate::quantize::quantize)
+pub fn merge_palettes(images: &[&IndexedImage], max: usize) -> Vec<Color> {
+    let colors: Vec<Color> = images
+        .iter()
+        .flat_map(|image| image.colors().map(|(_, _, color)| color))
+        .collect();
+    quantize(&colors, max.min(Palette::MAX_COLORS))
+}
+
+/// Merged palette from [merge_palettes] and copies of `images` using it, each pixel is replaced with the closest color
+///
+/// Will only return an error if `max` is 0 and `images` isn't empty
+pub fn remap_to_merged_palette(
+    images: &[&IndexedImage],
+    max: usize,
+) -> Result<(Vec<Color>, Vec<IndexedImage>), IndexedImageError> {
+    let palette = merge_palettes(images, max);
+    let images = images
+        .iter()
+        .map(|image| image.remap_to_palette(&palette, Dithering::None))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((palette, images))
+}
+
+/// Colors split into hardware style sub palettes, see [IndexedImage::assign_banks]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BankAssignment {
+    /// Colors in each bank
+    pub banks: Vec<Vec<Color>>,
+    /// Bank used by each tile, left to right, top to bottom
+    pub tiles: Vec<usize>,
+}
+
+/// Sort key that puts grays first (by lightness) and then orders by hue and lightness
+pub(crate) fn hue_order(color: &Color) -> (bool, u32, u32) {
+    let (hue, saturation, lightness) = color.to_hsl();
+    if saturation < 0.1 {
+        (false, 0, (lightness * 1000.0) as u32)
+    } else {
+        (true, (hue * 100.0) as u32, (lightness * 1000.0) as u32)
     }
-    output
+}
+
+/// Creates a palette with a `ramp_len` shade ramp, dark to light, for each color in `base`'s harmony
+///
+/// Ramps keep the hue and saturation of each harmony color with evenly spaced lightness
+pub fn from_harmony(base: Color, scheme: HarmonyScheme, ramp_len: usize) -> Vec<Color> {
+    base.harmonies(scheme)
+        .iter()
+        .flat_map(|color| {
+            let (hue, saturation, _) = color.to_hsl();
+            (0..ramp_len).map(move |i| {
+                let lightness = (i + 1) as f32 / (ramp_len + 1) as f32;
+                Color::from_hsl(hue, saturation, lightness, color.a)
+            })
+        })
+        .collect()
+}
+
+/// Splits `colors` into banks of at most `bank_size` colors, like the 16 color sub palettes used by the SNES and GBA
+///
+/// Duplicates are removed and colors are grouped by hue so each bank contains similar colors
+/// Returns no banks if `bank_size` is 0
+pub fn split_into_banks(colors: &[Color], bank_size: u8) -> Vec<Vec<Color>> {
+    if bank_size == 0 {
+        return vec![];
+    }
+    let mut distinct: Vec<Color> = vec![];
+    for color in colors {
+        if !distinct.contains(color) {
+            distinct.push(*color);
+        }
+    }
+    distinct.sort_by_key(hue_order);
+    distinct
+        .chunks(bank_size as usize)
+        .map(|bank| bank.to_vec())
+        .collect()
 }
 
 /// merges similar colors that fall with threshold of each other
@@ -82,9 +603,15 @@ impl FilePalette {
     }
 }
 
+/// If saving `colors` as `palette` needs a version 2 file, version 1 files can only have 255 colors
+pub(crate) fn needs_v2(palette: &FilePalette, colors: &[Color]) -> bool {
+    matches!(palette, Colors) && colors.len() > u8::MAX as usize
+}
+
 pub(crate) fn write(
     palette: &FilePalette,
     colors: &[Color],
+    v2: bool,
     output: &mut Vec<u8>,
 ) -> Result<(), IndexedImageError> {
     output.push(palette.to_byte());
@@ -103,6 +630,10 @@ pub(crate) fn write(
             output.extend_from_slice(name.as_bytes())
         }
         Colors => {
+            if colors.len() > Palette::MAX_COLORS || (!v2 && colors.len() > u8::MAX as usize) {
+                return Err(PaletteTooManyColors);
+            }
+            // 256 is stored as 0 in version 2 files, as a palette can't be empty
             output.push(colors.len() as u8);
             for color in colors {
                 output.push(color.r);
@@ -117,116 +648,276 @@ pub(crate) fn write(
 }
 
 pub(crate) fn read(
-    mut start_idx: usize,
-    bytes: &[u8],
-) -> Result<(usize, FilePalette, Option<Vec<Color>>), IndexedImageError> {
-    if bytes.len() <= start_idx {
-        return Err(InvalidFileFormat(
-            start_idx,
-            "No data after header, expected palette format".to_string(),
-        ));
-    }
-    let pal_type = bytes[start_idx];
-    start_idx += 1;
+    reader: &mut ByteReader,
+) -> Result<(FilePalette, Option<Vec<Color>>), IndexedImageError> {
+    reader.set_section(FileSection::Palette);
+    let pal_type = reader.u8("palette format")?;
     match pal_type {
-        PAL_NO_DATA => Ok((1, NoData, None)),
-        PAL_ID => {
-            if bytes.len() < start_idx + 1 {
-                return Err(InvalidFileFormat(
-                    start_idx,
-                    "No data after palette format, expected ID".to_string(),
-                ));
-            }
-            let bytes = &bytes[start_idx..=start_idx + 1];
-            let id = u16::from_be_bytes([bytes[0], bytes[1]]);
-            Ok((3, ID(id), None))
-        }
+        PAL_NO_DATA => Ok((NoData, None)),
+        PAL_ID => Ok((ID(reader.u16("palette ID")?), None)),
         PAL_NAME => {
-            if bytes.len() < start_idx {
-                return Err(InvalidFileFormat(
-                    start_idx,
-                    "No data after palette format, expected palette name length".to_string(),
-                ));
-            }
-            let len = bytes[start_idx];
-            start_idx += 1;
-            let end = len as usize;
-            if bytes.len() < start_idx + end {
-                return Err(InvalidFileFormat(
-                    start_idx,
-                    "Incomplete data after palette name length, expected palette name".to_string(),
-                ));
-            }
-            let name = String::from_utf8(bytes[start_idx..start_idx + end].to_vec())
-                .map_err(PaletteNameNotUtf8)?;
-            Ok((end + 2, Name(name), None))
+            let len = reader.u8("palette name length")?;
+            let name = reader.bytes(len as usize, "palette name")?;
+            let name = String::from_utf8(name.to_vec()).map_err(PaletteNameNotUtf8)?;
+            Ok((Name(name), None))
         }
         PAL_COLORS => {
-            if bytes.len() < start_idx {
-                return Err(InvalidFileFormat(
-                    start_idx,
-                    "No data after palette format, expected color count".to_string(),
-                ));
-            }
-            let count = bytes[start_idx];
-            start_idx += 1;
-            let end = count as usize * 4;
-            if bytes.len() < start_idx + end {
-                return Err(InvalidFileFormat(
-                    start_idx,
-                    format!("Incomplete data after palette color count, expected {count} colors"),
-                ));
-            }
-            let mut colors = vec![];
-            let color_bytes: Vec<&u8> = bytes.iter().skip(start_idx).take(end).collect();
-            for color in color_bytes.chunks_exact(4) {
-                colors.push(Color::new(*color[0], *color[1], *color[2], *color[3]));
-            }
-            Ok((end + 2, Colors, Some(colors)))
+            let count = match reader.u8("palette color count")? {
+                0 => Palette::MAX_COLORS,
+                count => count as usize,
+            };
+            let color_bytes = reader.bytes(count * 4, "palette colors")?;
+            let colors = color_bytes
+                .chunks_exact(4)
+                .map(|color| Color::new(color[0], color[1], color[2], color[3]))
+                .collect();
+            Ok((Colors, Some(colors)))
+        }
+        _ => Err(reader.invalid(reader.offset() - 1, "palette format", "0..=3", pal_type)),
+    }
+}
+
+/// How [read_section] handles data that can be read but breaks the format rules
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ReadMode {
+    /// Accept anything the file readers accept, rule breaks are returned as warnings
+    #[default]
+    Lenient,
+    /// Rule breaks are errors
+    Strict,
+}
+
+/// Format rule broken by a palette section read with [ReadMode::Lenient]
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum PaletteWarning {
+    #[error("Palette name is empty")]
+    EmptyName,
+    #[error("{0} bytes after the palette section")]
+    TrailingData(usize),
+}
+
+/// Palette section read by [read_section]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteSection {
+    pub file_palette: FilePalette,
+    /// Only set for [FilePalette::Colors]
+    pub colors: Option<Palette>,
+    /// Always empty for [ReadMode::Strict]
+    pub warnings: Vec<PaletteWarning>,
+}
+
+/// Read a palette section (the data after the header in a file) that fills all of `bytes`
+///
+/// Lengths are checked before anything is read, so any input returns an error rather than panicking
+pub fn read_section(bytes: &[u8], mode: ReadMode) -> Result<PaletteSection, IndexedImageError> {
+    let mut reader = ByteReader::new(bytes);
+    let (file_palette, colors) = read(&mut reader)?;
+    let mut warnings = vec![];
+    if matches!(&file_palette, Name(name) if name.is_empty()) {
+        if mode == ReadMode::Strict {
+            return Err(PaletteNameTooShort);
+        }
+        warnings.push(PaletteWarning::EmptyName);
+    }
+    let trailing = reader.remaining().len();
+    if trailing > 0 {
+        if mode == ReadMode::Strict {
+            return Err(reader.invalid(
+                reader.offset(),
+                "data after palette",
+                "nothing",
+                format!("{trailing} bytes"),
+            ));
         }
-        _ => Err(InvalidFileFormat(
-            start_idx,
-            format!("Unsupport palette type {pal_type}"),
-        )),
+        warnings.push(PaletteWarning::TrailingData(trailing));
     }
+    Ok(PaletteSection {
+        file_palette,
+        colors: colors.map(Palette::new).transpose()?,
+        warnings,
+    })
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn distinguishable() {
+        let orange = Color::new(200, 80, 0, 255);
+        let olive = Color::new(110, 130, 0, 255);
+        let colors = [TRANSPARENT, orange, olive, BLUE, Color::new(0, 0, 5, 0)];
+        let suggestions = suggest_distinguishable(&colors, ColorBlindness::Deuteranopia);
+        assert_eq!(suggestions.len(), 1);
+        let (idx, replacement) = suggestions[0];
+        assert_eq!(idx, 2);
+        let simulated = |c: Color| c.simulate(ColorBlindness::Deuteranopia);
+        assert!(
+            ColorDistance::Euclidean.distance(simulated(replacement), simulated(orange))
+                >= DISTINGUISHABLE_DISTANCE
+        );
+        assert!((replacement.to_hsl().0 - olive.to_hsl().0).abs() < 2.0);
+
+        assert!(
+            suggest_distinguishable(&[RED, BLUE, WHITE], ColorBlindness::Protanopia).is_empty()
+        );
+        assert!(suggest_distinguishable(&[RED, RED], ColorBlindness::Protanopia).is_empty());
+    }
+
+    #[test]
+    fn closest_palette() {
+        let target = [RED, BLUE];
+        let candidates = vec![
+            vec![],
+            vec![GREEN, WHITE],
+            vec![Color::new(250, 0, 0, 255), Color::new(0, 0, 250, 255)],
+            vec![RED, BLUE, GREEN],
+        ];
+        assert_eq!(find_closest_palette(&target, &candidates), Some((3, 0.0)));
+        let (idx, score) = find_closest_palette(&target, &candidates[..3]).unwrap();
+        assert_eq!(idx, 2);
+        assert!((score - 5.0).abs() < 0.001);
+        assert_eq!(find_closest_palette(&target, &candidates[..1]), None);
+        assert_eq!(find_closest_palette(&[], &candidates), Some((1, 0.0)));
+    }
+
+    #[test]
+    fn palette_invariants() {
+        assert!(Palette::new(vec![]).is_err());
+        assert!(Palette::new(vec![RED; 257]).is_err());
+        let mut palette = Palette::new(vec![RED; 255]).unwrap();
+        assert_eq!(palette.add(BLUE).unwrap(), 255);
+        assert!(palette.add(BLUE).is_err());
+        assert!(palette.set(255, GREEN).is_ok());
+        assert_eq!(palette.get(255), Some(GREEN));
+        let mut palette = Palette::new(vec![RED]).unwrap();
+        assert!(palette.set(1, GREEN).is_err());
+        assert_eq!(palette.index_of(RED), Some(0));
+        assert_eq!(Palette::transparent(0).len(), 1);
+    }
+
+    #[test]
+    fn palette_dedup_sort() {
+        let mut palette = Palette::new(vec![RED, WHITE, RED, BLACK]).unwrap();
+        assert_eq!(palette.dedup(), vec![0, 1, 0, 2]);
+        assert_eq!(palette, [RED, WHITE, BLACK]);
+        assert_eq!(palette.sort_by_hue(), vec![2, 1, 0]);
+        assert_eq!(palette, [BLACK, WHITE, RED]);
+    }
+
+    #[test]
+    fn full_palette_round_trip() {
+        let colors: Vec<Color> = (0..=255).map(Color::gray).collect();
+        let mut output = vec![];
+        assert_eq!(
+            write(&Colors, &colors, false, &mut output),
+            Err(PaletteTooManyColors)
+        );
+        let mut output = vec![];
+        write(&Colors, &colors, true, &mut output).unwrap();
+        assert_eq!(output[1], 0);
+        let (_, read_colors) = read(&mut ByteReader::new(&output)).unwrap();
+        assert_eq!(read_colors, Some(colors));
+    }
+
+    #[test]
+    fn nearest() {
+        let colors = [BLACK, WHITE, RED, Color::new(255, 0, 0, 0)];
+        assert_eq!(nearest_index(&colors, Color::gray(10)), 0);
+        assert_eq!(nearest_index(&colors, Color::gray(240)), 1);
+        assert_eq!(nearest_index(&colors, Color::new(200, 10, 10, 255)), 2);
+        assert_eq!(nearest_index(&colors, Color::new(255, 0, 0, 10)), 2);
+        assert_eq!(
+            nearest_index_by(
+                &colors,
+                Color::new(255, 0, 0, 10),
+                ColorDistance::EuclideanAlpha
+            ),
+            3
+        );
+        assert_eq!(nearest_index(&[], RED), 0);
+    }
+
+    #[test]
+    fn weighted_prefers_green_accuracy() {
+        let colors = [
+            Color::new(100, 130, 100, 255),
+            Color::new(130, 100, 100, 255),
+        ];
+        let target = Color::new(100, 100, 100, 255);
+        assert_eq!(
+            ColorDistance::Euclidean.distance(colors[0], target),
+            ColorDistance::Euclidean.distance(colors[1], target)
+        );
+        assert_eq!(
+            nearest_index_by(&colors, target, ColorDistance::Weighted),
+            1
+        );
+    }
+
+    #[test]
+    fn banks() {
+        let colors = [
+            RED,
+            WHITE,
+            BLUE,
+            RED,
+            BLACK,
+            Color::new(200, 0, 0, 255),
+            GREEN,
+        ];
+        assert_eq!(
+            split_into_banks(&colors, 3),
+            vec![
+                vec![BLACK, WHITE, Color::new(200, 0, 0, 255)],
+                vec![RED, GREEN, BLUE]
+            ]
+        );
+        assert!(split_into_banks(&colors, 0).is_empty());
+    }
+
+    #[test]
+    fn simplify_to_fit() {
+        let colors: Vec<Color> = (0..100).map(|i| Color::gray(i * 2)).collect();
+        let output = simplify_palette_to_fit(&colors, 10);
+        assert_eq!(output.len(), colors.len());
+        let distinct: std::collections::HashSet<&Color> = output.iter().collect();
+        assert!(distinct.len() < 10);
+    }
+
     #[test]
     fn write_no_data() {
         let mut output = vec![];
-        write(&NoData, &[], &mut output).unwrap();
+        write(&NoData, &[], false, &mut output).unwrap();
         assert_eq!(output, vec![PAL_NO_DATA]);
 
         let mut output = vec![];
-        write(&NoData, &[Color::new(255, 45, 231, 2)], &mut output).unwrap();
+        write(&NoData, &[Color::new(255, 45, 231, 2)], false, &mut output).unwrap();
         assert_eq!(output, vec![PAL_NO_DATA]);
     }
 
     #[test]
     fn write_id() {
         let mut output = vec![];
-        write(&ID(5), &[], &mut output).unwrap();
+        write(&ID(5), &[], false, &mut output).unwrap();
         assert_eq!(output, vec![PAL_ID, 0, 5]);
 
         let mut output = vec![];
-        write(&ID(256), &[Color::new(255, 45, 231, 2)], &mut output).unwrap();
+        write(&ID(256), &[Color::new(255, 45, 231, 2)], false, &mut output).unwrap();
         assert_eq!(output, vec![PAL_ID, 1, 0]);
     }
 
     #[test]
     fn write_name() {
         let mut output = vec![];
-        write(&Name("test".to_string()), &[], &mut output).unwrap();
+        write(&Name("test".to_string()), &[], false, &mut output).unwrap();
         assert_eq!(output, vec![PAL_NAME, 4, b't', b'e', b's', b't']);
 
         let mut output = vec![];
         write(
             &Name("😺".to_string()),
             &[Color::new(255, 45, 231, 2)],
+            false,
             &mut output,
         )
         .unwrap();
@@ -236,13 +927,20 @@ mod test {
     #[test]
     fn write_colors() {
         let mut output = vec![];
-        write(&Colors, &[Color::new(100, 101, 102, 103)], &mut output).unwrap();
+        write(
+            &Colors,
+            &[Color::new(100, 101, 102, 103)],
+            false,
+            &mut output,
+        )
+        .unwrap();
         assert_eq!(output, vec![PAL_COLORS, 1, 100, 101, 102, 103]);
 
         let mut output = vec![];
         write(
             &Colors,
             &[Color::new(100, 101, 102, 103), Color::new(0, 0, 0, 255)],
+            false,
             &mut output,
         )
         .unwrap();
@@ -252,35 +950,85 @@ mod test {
         );
     }
 
+    #[test]
+    fn read_section_bounds() {
+        let mut sections = vec![];
+        for (palette, colors) in [
+            (NoData, vec![]),
+            (ID(500), vec![]),
+            (Name("pal".to_string()), vec![]),
+            (Colors, vec![RED]),
+            (Colors, vec![BLUE; 256]),
+        ] {
+            let mut bytes = vec![];
+            write(&palette, &colors, needs_v2(&palette, &colors), &mut bytes).unwrap();
+            for mode in [ReadMode::Lenient, ReadMode::Strict] {
+                let section = read_section(&bytes, mode).unwrap();
+                assert_eq!(section.file_palette, palette);
+                assert!(section.warnings.is_empty());
+                for len in 0..bytes.len() {
+                    let error = read_section(&bytes[..len], mode).unwrap_err();
+                    assert!(matches!(error, Truncated { offset, .. } if offset <= len));
+                }
+            }
+            sections.push(bytes);
+        }
+        for pal_type in 4..=u8::MAX {
+            assert!(read_section(&[pal_type, 0, 0], ReadMode::Lenient).is_err());
+        }
+
+        let mut bytes = sections[1].clone();
+        bytes.push(0);
+        let section = read_section(&bytes, ReadMode::Lenient).unwrap();
+        assert_eq!(section.warnings, vec![PaletteWarning::TrailingData(1)]);
+        assert_eq!(
+            read_section(&bytes, ReadMode::Strict).unwrap_err().offset(),
+            Some(3)
+        );
+        let section = read_section(&[PAL_NAME, 0], ReadMode::Lenient).unwrap();
+        assert_eq!(section.warnings, vec![PaletteWarning::EmptyName]);
+        assert_eq!(
+            read_section(&[PAL_NAME, 0], ReadMode::Strict),
+            Err(PaletteNameTooShort)
+        );
+        assert_eq!(
+            read_section(&sections[4], ReadMode::Strict).unwrap().colors,
+            Some(Palette::new(vec![BLUE; 256]).unwrap())
+        );
+    }
+
     #[test]
     fn read_no_data() {
-        let (skip, pal_type, colors) = read(0, &[PAL_NO_DATA]).unwrap();
-        assert_eq!(skip, 1);
+        let mut reader = ByteReader::new(&[PAL_NO_DATA]);
+        let (pal_type, colors) = read(&mut reader).unwrap();
+        assert_eq!(reader.offset(), 1);
         assert_eq!(pal_type, NoData);
         assert_eq!(colors, None);
     }
 
     #[test]
     fn read_id() {
-        let (skip, pal_type, colors) = read(0, &[PAL_ID, 0, 5]).unwrap();
-        assert_eq!(skip, 3);
+        let mut reader = ByteReader::new(&[PAL_ID, 0, 5]);
+        let (pal_type, colors) = read(&mut reader).unwrap();
+        assert_eq!(reader.offset(), 3);
         assert_eq!(pal_type, ID(5));
         assert_eq!(colors, None);
     }
 
     #[test]
     fn read_name() {
-        let (skip, pal_type, colors) = read(0, &[PAL_NAME, 4, 240, 159, 152, 186]).unwrap();
-        assert_eq!(skip, 6);
+        let mut reader = ByteReader::new(&[PAL_NAME, 4, 240, 159, 152, 186]);
+        let (pal_type, colors) = read(&mut reader).unwrap();
+        assert_eq!(reader.offset(), 6);
         assert_eq!(pal_type, Name("😺".to_string()));
         assert_eq!(colors, None);
     }
 
     #[test]
     fn read_colors() {
-        let (skip, pal_type, colors) =
-            read(0, &[PAL_COLORS, 2, 100, 101, 102, 103, 0, 0, 0, 255]).unwrap();
-        assert_eq!(skip, 10);
+        let mut reader = ByteReader::new(&[PAL_COLORS, 2, 100, 101, 102, 103, 0, 0, 0, 255]);
+        let (pal_type, colors) = read(&mut reader).unwrap();
+        assert_eq!(reader.offset(), 10);
         assert_eq!(pal_type, Colors);
         assert_eq!(
             colors,
@@ -294,7 +1042,7 @@ mod test {
     #[test]
     fn write_data_before() {
         let mut output = vec![1, 1, 1, 1];
-        write(&ID(5), &[], &mut output).unwrap();
+        write(&ID(5), &[], false, &mut output).unwrap();
         assert_eq!(output, vec![1, 1, 1, 1, PAL_ID, 0, 5]);
     }
 
@@ -303,9 +1051,10 @@ mod test {
         let bytes = [
             1, 1, 1, 1, PAL_COLORS, 2, 100, 101, 102, 103, 0, 0, 0, 255, 2, 2, 2, 2,
         ];
-        let start = 4;
-        let (skip, pal_type, colors) = read(start, &bytes).unwrap();
-        assert_eq!(skip, 10);
+        let mut reader = ByteReader::new(&bytes);
+        reader.skip(4, "header").unwrap();
+        let (pal_type, colors) = read(&mut reader).unwrap();
+        assert_eq!(reader.offset(), 14);
         assert_eq!(pal_type, Colors);
         assert_eq!(
             colors,
@@ -314,6 +1063,43 @@ mod test {
                 Color::new(0, 0, 0, 255)
             ])
         );
-        assert_eq!(bytes[start + skip..], [2, 2, 2, 2]);
+        assert_eq!(reader.remaining(), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn harmony_palette() {
+        let palette = from_harmony(RED, HarmonyScheme::Complementary, 3);
+        assert_eq!(palette.len(), 6);
+        assert_eq!(palette[1], RED);
+        assert_eq!(palette[4], CYAN);
+        assert!(palette[0].brightness() < palette[1].brightness());
+        assert!(from_harmony(RED, HarmonyScheme::Triadic, 0).is_empty());
+    }
+
+    #[test]
+    fn set_colors() {
+        let mut palette = Palette::new(vec![RED, GREEN, BLUE]).unwrap();
+        palette.set_colors(&[(0, BLUE), (2, RED)]).unwrap();
+        assert_eq!(palette, [BLUE, GREEN, RED]);
+        assert!(palette.set_colors(&[(1, WHITE), (3, WHITE)]).is_err());
+        assert_eq!(palette, [BLUE, GREEN, RED]);
+    }
+
+    #[test]
+    fn merge() {
+        let first = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        let second = IndexedImage::new(2, 1, vec![BLUE, GREEN, WHITE], vec![1, 0]).unwrap();
+        assert_eq!(
+            merge_palettes(&[&first, &second], 8),
+            vec![RED, BLUE, GREEN]
+        );
+        assert_eq!(merge_palettes(&[&first, &second], 2).len(), 2);
+
+        let (palette, images) = remap_to_merged_palette(&[&first, &second], 8).unwrap();
+        assert_eq!(palette, vec![RED, BLUE, GREEN]);
+        assert_eq!(images[1].get_palette(), &[RED, BLUE, GREEN]);
+        assert!(images[0].visually_equal(&first));
+        assert!(images[1].visually_equal(&second));
+        assert!(remap_to_merged_palette(&[&first], 0).is_err());
     }
 }
@@ -0,0 +1,177 @@
+//! Playback state of animations, for save games where an animation should resume where it was
+//!
+//! Only written by [AnimatedIndexedImage::to_file_contents_with_state], as a chunk after the pixel data
+//! containing the current frame (u16), time until the next frame (f64), flags (u8, bit 0 animate,
+//! bit 1 increasing, bit 2 segment) and, if the segment flag is set, the segment start (u16) and end (u16)
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::metadata::{chunk_reader, write_chunk};
+use crate::prelude::*;
+
+pub(crate) const STATE_TAG: [u8; 4] = *b"STAT";
+const ANIMATE: u8 = 1;
+const INCREASING: u8 = 2;
+const SEGMENT: u8 = 4;
+
+/// Where an animation is in its playback, see [AnimatedIndexedImage::playback_state]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackState {
+    pub current_frame: u16,
+    /// Seconds until the next frame
+    pub next_frame_time: f64,
+    pub animate: bool,
+    /// Direction for [PlayType::LoopsBoth] and [PlayType::OnceBoth]
+    pub increasing: bool,
+    /// Frames being played, `None` if playing all frames
+    pub segment: Option<FrameTag>,
+}
+
+impl PlaybackState {
+    /// Returns an error if the state can't be used with an animation of `frame_count` frames
+    pub(crate) fn validate(&self, frame_count: u16) -> Result<(), IndexedImageError> {
+        if self.current_frame >= frame_count {
+            return Err(IndexOutOfRange(
+                self.current_frame as usize,
+                frame_count as usize,
+                "frames",
+            ));
+        }
+        if let Some(segment) = self.segment {
+            if segment.start > segment.end
+                || segment.end >= frame_count
+                || !segment.contains(self.current_frame)
+            {
+                return Err(InvalidPlaybackState(format!(
+                    "segment {}..={} must contain frame {}",
+                    segment.start, segment.end, self.current_frame
+                )));
+            }
+        }
+        if !self.next_frame_time.is_finite() {
+            return Err(InvalidPlaybackState(format!(
+                "next frame time is {}",
+                self.next_frame_time
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn write(state: &PlaybackState, output: &mut Vec<u8>) {
+    let mut payload = vec![];
+    payload.extend_from_slice(&state.current_frame.to_be_bytes());
+    payload.extend_from_slice(&state.next_frame_time.to_be_bytes());
+    let mut flags = 0;
+    if state.animate {
+        flags |= ANIMATE;
+    }
+    if state.increasing {
+        flags |= INCREASING;
+    }
+    if state.segment.is_some() {
+        flags |= SEGMENT;
+    }
+    payload.push(flags);
+    if let Some(segment) = state.segment {
+        payload.extend_from_slice(&segment.start.to_be_bytes());
+        payload.extend_from_slice(&segment.end.to_be_bytes());
+    }
+    write_chunk(STATE_TAG, &payload, output);
+}
+
+/// Reads the state from data after the pixels (`trailing`, which starts at `offset` in the file)
+pub(crate) fn read(
+    offset: usize,
+    trailing: &[u8],
+    frame_count: u16,
+) -> Result<Option<PlaybackState>, IndexedImageError> {
+    let Some(mut reader) = chunk_reader(offset, trailing, STATE_TAG) else {
+        return Ok(None);
+    };
+    let start = reader.offset();
+    let current_frame = reader.u16("current frame")?;
+    let next_frame_time = reader.f64("next frame time")?;
+    let flags = reader.u8("playback flags")?;
+    let segment = if flags & SEGMENT == SEGMENT {
+        let start = reader.u16("segment start")?;
+        let end = reader.u16("segment end")?;
+        Some(FrameTag::new(start, end))
+    } else {
+        None
+    };
+    let state = PlaybackState {
+        current_frame,
+        next_frame_time,
+        animate: flags & ANIMATE == ANIMATE,
+        increasing: flags & INCREASING == INCREASING,
+        segment,
+    };
+    state.validate(frame_count).map_err(|e| {
+        reader.invalid(
+            start,
+            "playback state",
+            format!("valid for {frame_count} frames"),
+            e,
+        )
+    })?;
+    Ok(Some(state))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette;
+
+    #[test]
+    fn round_trip() {
+        let mut image = AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            4,
+            vec![RED, GREEN],
+            vec![0, 1, 0, 1],
+            PlayType::LoopsBoth,
+        )
+        .unwrap();
+        image.set_tag("end", 1, 3).unwrap();
+        image.play_segment("end").unwrap();
+        for _ in 0..4 {
+            image.skip_to_next_frame();
+            image.update(0.0);
+        }
+        image.update(0.03);
+        let state = image.playback_state();
+        assert_eq!(state.current_frame, 2);
+        assert!(!state.increasing);
+
+        let bytes = image
+            .to_file_contents_with_state(&FilePalette::Colors)
+            .unwrap();
+        let (read, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(read.playback_state(), state);
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap();
+        let (read, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(read.playback_state().current_frame, 0);
+
+        let mut other = read.clone();
+        assert!(other
+            .set_playback_state(PlaybackState {
+                current_frame: 0,
+                ..state
+            })
+            .is_err());
+        assert!(other
+            .set_playback_state(PlaybackState {
+                next_frame_time: f64::NAN,
+                ..state
+            })
+            .is_err());
+        other.set_playback_state(state).unwrap();
+        other.skip_to_next_frame();
+        other.update(0.0);
+        assert_eq!(other.get_current_frame(), 1);
+    }
+}
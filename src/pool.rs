@@ -0,0 +1,196 @@
+//! Reusable pixel buffers, to reduce allocations when loading or transforming many images
+
+use crate::errors::IndexedImageError;
+use crate::palette::{FilePalette, Palette};
+use crate::prelude::*;
+use std::sync::{Mutex, MutexGuard};
+
+/// Pool of pixel buffers that can be shared between threads
+///
+/// Buffers are returned to the pool with [BufferPool::recycle], [BufferPool::recycle_animated] or [BufferPool::give]
+/// and reused by the `_pooled` methods and [BufferPool::blank]
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new(16)
+    }
+}
+
+impl BufferPool {
+    /// Pool holding at most `max_buffers`, any extra buffers given to it are dropped
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(vec![]),
+            max_buffers,
+        }
+    }
+
+    fn buffers(&self) -> MutexGuard<'_, Vec<Vec<u8>>> {
+        // buffers are always valid, even if another thread panicked while holding the lock
+        self.buffers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Number of buffers available
+    pub fn len(&self) -> usize {
+        self.buffers().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Empty buffer with a capacity of at least `len`
+    ///
+    /// The smallest pooled buffer that is large enough is used, otherwise the largest is grown,
+    /// a new buffer is only allocated if the pool is empty
+    pub fn take(&self, len: usize) -> Vec<u8> {
+        let mut buffers = self.buffers();
+        let best = buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.capacity() >= len)
+            .min_by_key(|(_, buffer)| buffer.capacity())
+            .or_else(|| {
+                buffers
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, buffer)| buffer.capacity())
+            })
+            .map(|(i, _)| i);
+        match best {
+            Some(i) => {
+                let mut buffer = buffers.swap_remove(i);
+                buffer.clear();
+                buffer.reserve(len);
+                buffer
+            }
+            None => Vec::with_capacity(len),
+        }
+    }
+
+    /// Return a buffer to the pool
+    pub fn give(&self, buffer: Vec<u8>) {
+        let mut buffers = self.buffers();
+        if buffers.len() < self.max_buffers && buffer.capacity() > 0 {
+            buffers.push(buffer);
+        }
+    }
+
+    /// Return the pixel buffer of `image` to the pool
+    pub fn recycle(&self, image: IndexedImage) {
+        self.give(image.into_pixels());
+    }
+
+    /// Return the pixel buffer of `image` to the pool
+    pub fn recycle_animated(&self, image: AnimatedIndexedImage) {
+        self.give(image.into_pixels());
+    }
+
+    /// Blank image using a pooled buffer, for use as the target of methods such as [IndexedImage::scale_into]
+    ///
+    /// If `palette` has more than 256 colors the extra are dropped, if it's empty a single transparent color is used
+    pub fn blank(&self, width: u8, height: u8, palette: Vec<Color>) -> IndexedImage {
+        let len = width as usize * height as usize;
+        let mut pixels = self.take(len);
+        pixels.resize(len, 0);
+        IndexedImage::from_parts(width, height, Palette::from_vec_lossy(palette), pixels)
+    }
+}
+
+impl IndexedImage {
+    /// Like [IndexedImage::from_file_contents] but the pixels are stored in a buffer from `pool`
+    ///
+    /// The buffer is only taken once the file has been read, so the pool is unchanged if it's invalid
+    pub fn from_file_contents_pooled(
+        bytes: &[u8],
+        pool: &BufferPool,
+    ) -> Result<(IndexedImage, FilePalette), IndexedImageError> {
+        IndexedImage::read_file(bytes, |len| pool.take(len))
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Like [AnimatedIndexedImage::from_file_contents] but the pixels are stored in a buffer from `pool`
+    ///
+    /// The buffer is only taken once the file has been read, so the pool is unchanged if it's invalid
+    pub fn from_file_contents_pooled(
+        bytes: &[u8],
+        pool: &BufferPool,
+    ) -> Result<(AnimatedIndexedImage, FilePalette), IndexedImageError> {
+        AnimatedIndexedImage::read_file_into(bytes, |len| pool.take(len))
+            .map(|(image, pal_type, _)| (image, pal_type))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette::*;
+
+    #[test]
+    fn reuses_buffers() {
+        let pool = BufferPool::new(2);
+        let image = IndexedImage::new(2, 2, vec![RED, BLUE], vec![0, 1, 1, 0]).unwrap();
+        let bytes = image.to_file_contents(&Colors).unwrap();
+
+        let (first, _) = IndexedImage::from_file_contents_pooled(&bytes, &pool).unwrap();
+        assert_eq!(first, image);
+        let ptr = first.get_pixels().as_ptr();
+        pool.recycle(first);
+        assert_eq!(pool.len(), 1);
+        let (second, _) = IndexedImage::from_file_contents_pooled(&bytes, &pool).unwrap();
+        assert_eq!(second.get_pixels().as_ptr(), ptr);
+        assert!(pool.is_empty());
+
+        let mut target = pool.blank(4, 4, vec![]);
+        second
+            .scale_into(Scaling::nearest_neighbour(2, 2).unwrap(), &mut target)
+            .unwrap();
+        assert_eq!(target.get_palette(), &[RED, BLUE]);
+
+        pool.give(vec![0; 10]);
+        pool.give(vec![0; 100]);
+        pool.give(vec![0; 1000]);
+        assert_eq!(pool.len(), 2);
+        assert!(pool.take(50).capacity() >= 100);
+        assert!(pool.take(5).capacity() >= 10);
+    }
+
+    #[test]
+    fn takes_buffer_for_pixel_count() {
+        let pool = BufferPool::new(4);
+        let image = IndexedImage::blank(30, 30, vec![RED]);
+        let bytes = image.to_file_contents(&Colors).unwrap();
+        let animation =
+            AnimatedIndexedImage::new(30, 20, 0.1, 2, vec![RED], vec![0; 1200], PlayType::Loops)
+                .unwrap();
+        let animation_bytes = animation.to_file_contents(&Colors).unwrap();
+        let small = vec![0; 10];
+        let large = Vec::with_capacity(1000);
+        let larger = Vec::with_capacity(2000);
+        let (large_ptr, larger_ptr) = (large.as_ptr(), larger.as_ptr());
+        pool.give(small);
+        pool.give(large);
+        pool.give(larger);
+
+        assert!(IndexedImage::from_file_contents_pooled(&bytes[..bytes.len() - 1], &pool).is_err());
+        assert!(AnimatedIndexedImage::from_file_contents_pooled(&bytes, &pool).is_err());
+        assert_eq!(pool.len(), 3);
+
+        let (read, _) = IndexedImage::from_file_contents_pooled(&bytes, &pool).unwrap();
+        assert_eq!(read, image);
+        assert_eq!(read.get_pixels().as_ptr(), large_ptr);
+        let (read, _) =
+            AnimatedIndexedImage::from_file_contents_pooled(&animation_bytes, &pool).unwrap();
+        assert_eq!(read, animation);
+        assert_eq!(read.get_pixels().as_ptr(), larger_ptr);
+        assert_eq!(pool.len(), 1);
+    }
+}
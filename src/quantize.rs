@@ -0,0 +1,200 @@
+use crate::compat::*;
+use crate::palette::{nearest_index_by, ColorDistance};
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// Reduce `colors` to at most `max_colors` using median cut
+///
+/// If there are already `max_colors` or fewer distinct colors they are returned in order of first appearance
+/// Colors that appear more often have more influence over the result
+pub fn quantize(colors: &[Color], max_colors: usize) -> Vec<Color> {
+    let mut order = vec![];
+    let mut counts: BTreeMap<Color, usize> = BTreeMap::new();
+    for color in colors {
+        let count = counts.entry(*color).or_insert_with(|| {
+            order.push(*color);
+            0
+        });
+        *count += 1;
+    }
+    if order.len() <= max_colors {
+        return order;
+    }
+    if max_colors == 0 {
+        return vec![];
+    }
+
+    let entries = order.iter().map(|color| (*color, counts[color])).collect();
+    let mut boxes: Vec<ColorBox> = vec![ColorBox::new(entries)];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.entries.len() > 1)
+            .max_by_key(|(_, b)| b.widest_channel().1)
+            .map(|(i, _)| i);
+        match widest {
+            None => break,
+            Some(i) => {
+                let (first, second) = boxes.swap_remove(i).split();
+                boxes.push(first);
+                boxes.push(second);
+            }
+        }
+    }
+
+    boxes.iter().map(|b| b.average()).collect()
+}
+
+/// Reduce RGBA pixel data (4 bytes per pixel) to at most `max_colors` using median cut
+///
+/// See [quantize]
+pub fn quantize_rgba(pixels: &[u8], max_colors: usize) -> Vec<Color> {
+    let colors: Vec<Color> = pixels
+        .chunks_exact(4)
+        .map(|px| Color::new(px[0], px[1], px[2], px[3]))
+        .collect();
+    quantize(&colors, max_colors)
+}
+
+/// Replaces each color in `colors` with the closest color from [quantize]
+///
+/// The result will contain duplicates so the index is preserved, if `max_colors` is 0 it's empty
+pub fn quantize_in_place(colors: &[Color], max_colors: usize) -> Vec<Color> {
+    let reduced = quantize(colors, max_colors);
+    colors
+        .iter()
+        .filter_map(|color| {
+            let idx = nearest_index_by(&reduced, *color, ColorDistance::EuclideanAlpha);
+            reduced.get(idx as usize).copied()
+        })
+        .collect()
+}
+
+struct ColorBox {
+    entries: Vec<(Color, usize)>,
+}
+
+impl ColorBox {
+    fn new(entries: Vec<(Color, usize)>) -> Self {
+        Self { entries }
+    }
+
+    /// Returns the channel (0..4 for RGBA) with the largest range and that range
+    fn widest_channel(&self) -> (usize, u8) {
+        let mut min = [255_u8; 4];
+        let mut max = [0_u8; 4];
+        for (color, _) in &self.entries {
+            let channels = [color.r, color.g, color.b, color.a];
+            for i in 0..4 {
+                min[i] = min[i].min(channels[i]);
+                max[i] = max[i].max(channels[i]);
+            }
+        }
+        (0..4)
+            .map(|i| (i, max[i] - min[i]))
+            .max_by_key(|(i, range)| (*range, 3 - *i))
+            .unwrap_or((0, 0))
+    }
+
+    /// Splits at the weighted median of the widest channel
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        let value = |color: &Color| match channel {
+            0 => color.r,
+            1 => color.g,
+            2 => color.b,
+            _ => color.a,
+        };
+        self.entries.sort_by_key(|(color, _)| value(color));
+        let total: usize = self.entries.iter().map(|(_, count)| count).sum();
+        let mut running = 0;
+        let mut split_at = 1;
+        for (i, (_, count)) in self.entries.iter().enumerate() {
+            running += count;
+            if running * 2 >= total {
+                split_at = i + 1;
+                break;
+            }
+        }
+        let split_at = split_at.clamp(1, self.entries.len() - 1);
+        let second = self.entries.split_off(split_at);
+        (self, ColorBox::new(second))
+    }
+
+    fn average(&self) -> Color {
+        let mut sums = [0_usize; 4];
+        let mut total = 0;
+        for (color, count) in &self.entries {
+            sums[0] += color.r as usize * count;
+            sums[1] += color.g as usize * count;
+            sums[2] += color.b as usize * count;
+            sums[3] += color.a as usize * count;
+            total += count;
+        }
+        let avg = |sum: usize| ((sum + total / 2) / total.max(1)) as u8;
+        Color::new(avg(sums[0]), avg(sums[1]), avg(sums[2]), avg(sums[3]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fewer_colors_than_max() {
+        let colors = [RED, BLUE, RED, GREEN];
+        assert_eq!(quantize(&colors, 4), vec![RED, BLUE, GREEN]);
+        assert_eq!(quantize(&colors, 3), vec![RED, BLUE, GREEN]);
+    }
+
+    #[test]
+    fn splits_clusters() {
+        let colors = [
+            Color::new(250, 0, 0, 255),
+            Color::new(255, 5, 0, 255),
+            Color::new(0, 0, 250, 255),
+            Color::new(0, 5, 255, 255),
+        ];
+        let mut output = quantize(&colors, 2);
+        output.sort_by_key(|c| c.r);
+        assert_eq!(
+            output,
+            vec![Color::new(0, 3, 253, 255), Color::new(253, 3, 0, 255)]
+        );
+    }
+
+    #[test]
+    fn gradient() {
+        let colors: Vec<Color> = (0..=255).map(Color::gray).collect();
+        let output = quantize(&colors, 16);
+        assert_eq!(output.len(), 16);
+        let in_place = quantize_in_place(&colors, 16);
+        assert_eq!(in_place.len(), 256);
+        for (original, reduced) in colors.iter().zip(in_place.iter()) {
+            assert!(original.diff(reduced) <= 3 * 9);
+        }
+    }
+
+    #[test]
+    fn rgba() {
+        let pixels = [255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 0, 0];
+        assert_eq!(quantize_rgba(&pixels, 4), vec![RED, TRANSPARENT]);
+        assert_eq!(quantize_rgba(&pixels, 0), vec![]);
+    }
+
+    #[test]
+    fn in_place_alpha() {
+        let colors = [
+            BLACK,
+            BLACK,
+            Color::new(200, 200, 200, 0),
+            Color::new(10, 10, 10, 0),
+        ];
+        let in_place = quantize_in_place(&colors, 2);
+        assert_eq!(in_place[0], BLACK);
+        assert_eq!(in_place[3], Color::new(105, 105, 105, 0));
+        assert_eq!(quantize_in_place(&colors, 0), vec![]);
+        assert_eq!(quantize_in_place(&[], 0), vec![]);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError::*;
+use crate::errors::{FileSection, IndexedImageError};
+use core::fmt::Display;
+
+/// Bounds checked cursor over file bytes
+///
+/// Every read checks there is enough data remaining first, so lengths from untrusted
+/// files are validated before anything is allocated, and errors report the offset in the file
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// offset of `bytes` in the file, used for errors
+    base: usize,
+    /// part of the file being read, used for errors
+    section: FileSection,
+    /// every read, only recorded by [ByteReader::traced]
+    fields: Option<Vec<Field>>,
+}
+
+/// Bytes read by a [ByteReader]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Field {
+    pub offset: usize,
+    pub len: usize,
+    pub what: String,
+    /// decoded value, for numbers and strings
+    pub value: Option<String>,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            base: 0,
+            section: FileSection::Header,
+            fields: None,
+        }
+    }
+
+    /// Reader for `bytes`, which start at `offset` in the file and are part of `section`
+    pub fn with_offset(bytes: &'a [u8], offset: usize, section: FileSection) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            base: offset,
+            section,
+            fields: None,
+        }
+    }
+
+    /// Set the part of the file being read, for errors
+    pub fn set_section(&mut self, section: FileSection) {
+        self.section = section;
+    }
+
+    /// Reader that records every field read, see [ByteReader::fields]
+    pub fn traced(bytes: &'a [u8]) -> Self {
+        Self {
+            fields: Some(vec![]),
+            ..ByteReader::new(bytes)
+        }
+    }
+
+    /// Fields read so far, empty unless created with [ByteReader::traced]
+    pub fn fields(&self) -> &[Field] {
+        self.fields.as_deref().unwrap_or_default()
+    }
+
+    /// Set the decoded value of the last field read
+    fn annotate<T: ToString>(&mut self, value: T) -> T {
+        if let Some(field) = self.fields.as_mut().and_then(|fields| fields.last_mut()) {
+            field.value = Some(value.to_string());
+        }
+        value
+    }
+
+    /// Current position in the file
+    pub fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    pub fn skip(&mut self, len: usize, what: &str) -> Result<(), IndexedImageError> {
+        self.bytes(len, what).map(|_| ())
+    }
+
+    pub fn bytes(&mut self, len: usize, what: &str) -> Result<&'a [u8], IndexedImageError> {
+        let available = self.bytes.len() - self.pos;
+        if len > available {
+            return Err(Truncated {
+                section: self.section,
+                offset: self.offset(),
+                what: what.to_string(),
+                expected: len,
+                found: available,
+            });
+        }
+        if let Some(fields) = &mut self.fields {
+            fields.push(Field {
+                offset: self.base + self.pos,
+                len,
+                what: what.to_string(),
+                value: None,
+            });
+        }
+        let data = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(data)
+    }
+
+    pub fn array<const N: usize>(&mut self, what: &str) -> Result<[u8; N], IndexedImageError> {
+        let mut output = [0; N];
+        output.copy_from_slice(self.bytes(N, what)?);
+        Ok(output)
+    }
+
+    pub fn u8(&mut self, what: &str) -> Result<u8, IndexedImageError> {
+        let value = self.array::<1>(what)?[0];
+        Ok(self.annotate(value))
+    }
+
+    pub fn u16(&mut self, what: &str) -> Result<u16, IndexedImageError> {
+        let value = u16::from_be_bytes(self.array(what)?);
+        Ok(self.annotate(value))
+    }
+
+    pub fn u32(&mut self, what: &str) -> Result<u32, IndexedImageError> {
+        let value = u32::from_be_bytes(self.array(what)?);
+        Ok(self.annotate(value))
+    }
+
+    pub fn f64(&mut self, what: &str) -> Result<f64, IndexedImageError> {
+        let value = f64::from_be_bytes(self.array(what)?);
+        Ok(self.annotate(value))
+    }
+
+    /// Reads a u16 if `wide`, otherwise a u8
+    pub fn size(&mut self, wide: bool, what: &str) -> Result<usize, IndexedImageError> {
+        if wide {
+            self.u16(what).map(|v| v as usize)
+        } else {
+            self.u8(what).map(|v| v as usize)
+        }
+    }
+
+    /// Reads `len` color indexes, which must be inside the palette if its length is known
+    pub fn pixels(
+        &mut self,
+        len: usize,
+        palette_len: Option<usize>,
+        what: &str,
+    ) -> Result<&'a [u8], IndexedImageError> {
+        let offset = self.offset();
+        let pixels = self.bytes(len, what)?;
+        if let Some(palette_len) = palette_len {
+            if let Some(i) = pixels.iter().position(|idx| *idx as usize >= palette_len) {
+                let expected = format!("less than {palette_len}");
+                return Err(self.invalid(offset + i, "color index", expected, pixels[i]));
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Reads a string of `len` bytes
+    pub fn string(&mut self, len: usize, what: &str) -> Result<String, IndexedImageError> {
+        let offset = self.offset();
+        let bytes = self.bytes(len, what)?;
+        let value = String::from_utf8(bytes.to_vec())
+            .map_err(|_| self.invalid(offset, what, "UTF-8", String::from_utf8_lossy(bytes)))?;
+        Ok(self.annotate(value))
+    }
+
+    /// Error for an invalid `what` at `offset` in the current section
+    pub fn invalid<E: Display, F: Display>(
+        &self,
+        offset: usize,
+        what: &str,
+        expected: E,
+        found: F,
+    ) -> IndexedImageError {
+        InvalidValue {
+            section: self.section,
+            offset,
+            what: what.to_string(),
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bounds() {
+        let mut reader = ByteReader::with_offset(&[1, 0, 2, 3], 10, FileSection::Chunks);
+        assert_eq!(reader.u8("a").unwrap(), 1);
+        assert_eq!(reader.u16("b").unwrap(), 2);
+        assert_eq!(reader.offset(), 13);
+        assert_eq!(
+            reader.u16("c"),
+            Err(Truncated {
+                section: FileSection::Chunks,
+                offset: 13,
+                what: "c".to_string(),
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            reader.bytes(usize::MAX, "d").unwrap_err().offset(),
+            Some(13)
+        );
+        assert_eq!(reader.remaining(), &[3]);
+    }
+
+    #[test]
+    fn traced() {
+        let mut reader = ByteReader::traced(&[1, 0, 2, b'a']);
+        reader.u8("a").unwrap();
+        reader.skip(2, "b").unwrap();
+        reader.string(1, "c").unwrap();
+        let fields: Vec<(usize, usize, Option<&str>)> = reader
+            .fields()
+            .iter()
+            .map(|field| (field.offset, field.len, field.value.as_deref()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![(0, 1, Some("1")), (1, 2, None), (3, 1, Some("a"))]
+        );
+        assert!(ByteReader::new(&[1]).fields().is_empty());
+    }
+}
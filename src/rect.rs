@@ -0,0 +1,75 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Rectangle in pixel coordinates
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct IciRect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+impl IciRect {
+    pub const fn new(x: u8, y: u8, width: u8, height: u8) -> Self {
+        IciRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Exclusive right edge
+    #[inline]
+    pub const fn right(&self) -> usize {
+        self.x as usize + self.width as usize
+    }
+
+    /// Exclusive bottom edge
+    #[inline]
+    pub const fn bottom(&self) -> usize {
+        self.y as usize + self.height as usize
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    #[inline]
+    pub const fn contains(&self, x: u8, y: u8) -> bool {
+        x >= self.x && y >= self.y && (x as usize) < self.right() && (y as usize) < self.bottom()
+    }
+
+    /// The part of this rect inside a `width`x`height` image, `None` if there's no overlap
+    pub fn clip(&self, width: u8, height: u8) -> Option<IciRect> {
+        let right = self.right().min(width as usize);
+        let bottom = self.bottom().min(height as usize);
+        if self.x as usize >= right || self.y as usize >= bottom {
+            return None;
+        }
+        Some(IciRect::new(
+            self.x,
+            self.y,
+            (right - self.x as usize) as u8,
+            (bottom - self.y as usize) as u8,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clip() {
+        let rect = IciRect::new(2, 2, 10, 10);
+        assert_eq!(rect.clip(8, 20), Some(IciRect::new(2, 2, 6, 10)));
+        assert_eq!(rect.clip(2, 20), None);
+        assert_eq!(IciRect::new(0, 0, 0, 4).clip(8, 8), None);
+        assert!(rect.contains(11, 2));
+        assert!(!rect.contains(12, 2));
+    }
+}
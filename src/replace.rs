@@ -0,0 +1,153 @@
+//! Bulk recoloring of palettes and pixels
+
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::palette::{ColorDistance, Palette};
+use crate::prelude::*;
+
+impl IndexedImage {
+    /// Set every palette color within `tolerance` of `old` to `new`, returns the number of palette colors changed
+    ///
+    /// Distance is measured with [ColorDistance::EuclideanAlpha], so a `tolerance` of 0.0 only replaces exact matches
+    pub fn replace_color(&mut self, old: Color, new: Color, tolerance: f32) -> usize {
+        let mut count = 0;
+        for color in self.palette_mut().iter_mut() {
+            if ColorDistance::EuclideanAlpha.distance(*color, old) <= tolerance {
+                *color = new;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Set pixels in `rect` using `old_idx` to `new_idx`, returns the number of pixels changed
+    ///
+    /// Any part of `rect` outside the image is ignored, `new_idx` must be in the palette
+    pub fn replace_index_in_rect(
+        &mut self,
+        rect: IciRect,
+        old_idx: u8,
+        new_idx: u8,
+    ) -> Result<usize, IndexedImageError> {
+        check_index(self.get_palette(), new_idx)?;
+        let Some(rect) = rect.clip(self.width(), self.height()) else {
+            return Ok(0);
+        };
+        let width = self.width() as usize;
+        let pixels = self.raw_pixels_mut();
+        let mut count = 0;
+        for y in rect.y as usize..rect.bottom() {
+            let row = y * width;
+            count += replace_in(
+                &mut pixels[row + rect.x as usize..row + rect.right()],
+                old_idx,
+                new_idx,
+            );
+        }
+        self.refresh_highest_palette_idx();
+        Ok(count)
+    }
+
+    /// Set every pixel using `from` to `to`, returns the number of pixels changed
+    ///
+    /// `to` must be in the palette
+    pub fn replace_index(&mut self, from: u8, to: u8) -> Result<usize, IndexedImageError> {
+        check_index(self.get_palette(), to)?;
+        let count = replace_in(self.raw_pixels_mut(), from, to);
+        self.refresh_highest_palette_idx();
+        Ok(count)
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Set every pixel in frame `frame` using `from` to `to`, returns the number of pixels changed
+    ///
+    /// `to` must be in the palette
+    pub fn replace_index_in_frame(
+        &mut self,
+        frame: u16,
+        from: u8,
+        to: u8,
+    ) -> Result<usize, IndexedImageError> {
+        check_index(self.get_palette(), to)?;
+        let count = replace_in(self.frame_pixels_mut(frame)?, from, to);
+        self.refresh_highest_palette_idx();
+        Ok(count)
+    }
+}
+
+fn check_index(palette: &Palette, idx: u8) -> Result<(), IndexedImageError> {
+    if idx as usize >= palette.len() {
+        return Err(IndexOutOfRange(idx as usize, palette.len(), "palette"));
+    }
+    Ok(())
+}
+
+fn replace_in(pixels: &mut [u8], from: u8, to: u8) -> usize {
+    let mut count = 0;
+    for pixel in pixels.iter_mut().filter(|pixel| **pixel == from) {
+        *pixel = to;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replace() {
+        #[rustfmt::skip]
+        let mut image = IndexedImage::new(3, 3, vec![RED, Color::new(250, 5, 0, 255), BLUE], vec![
+            0, 1, 2,
+            1, 0, 1,
+            2, 1, 0,
+        ]).unwrap();
+        assert_eq!(image.replace_color(RED, GREEN, 0.0), 1);
+        assert_eq!(
+            image.get_palette(),
+            &[GREEN, Color::new(250, 5, 0, 255), BLUE]
+        );
+        assert_eq!(image.replace_color(RED, WHITE, 10.0), 1);
+        assert_eq!(image.get_palette(), &[GREEN, WHITE, BLUE]);
+
+        let changed = image
+            .replace_index_in_rect(IciRect::new(1, 1, 5, 5), 1, 2)
+            .unwrap();
+        assert_eq!(changed, 2);
+        assert_eq!(image.get_pixels(), &[0, 1, 2, 1, 0, 2, 2, 2, 0]);
+        assert_eq!(
+            image
+                .replace_index_in_rect(IciRect::new(10, 10, 1, 1), 0, 1)
+                .unwrap(),
+            0
+        );
+        assert!(image
+            .replace_index_in_rect(IciRect::new(0, 0, 1, 1), 0, 3)
+            .is_err());
+    }
+
+    #[test]
+    fn replace_indexes() {
+        let mut image = IndexedImage::new(2, 2, vec![RED, GREEN, BLUE], vec![2, 1, 2, 0]).unwrap();
+        assert_eq!(image.replace_index(2, 1).unwrap(), 2);
+        assert_eq!(image.get_pixels(), &[1, 1, 1, 0]);
+        assert_eq!(image.min_palette_size_supported(), 1);
+        assert!(image.replace_index(0, 3).is_err());
+
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 1],
+            PlayType::Loops,
+        )
+        .unwrap();
+        assert_eq!(image.replace_index_in_frame(1, 1, 0).unwrap(), 2);
+        assert_eq!(image.get_pixels(), &[0, 1, 0, 0]);
+        assert!(image.replace_index_in_frame(2, 1, 0).is_err());
+    }
+}
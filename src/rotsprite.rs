@@ -0,0 +1,141 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+use crate::scaling::scale2x_pixels;
+
+/// Upscale factor used by RotSprite (3 passes of Scale2x)
+const UPSCALE: usize = 8;
+/// Avoids rounding errors growing the output, i.e. for 90°
+const SIZE_EPSILON: f64 = 0.0001;
+
+impl IndexedImage {
+    /// Rotate clockwise by `degrees` using RotSprite, which keeps pixel art edges much cleaner than
+    /// nearest neighbour rotation
+    ///
+    /// The image is upscaled 8x with Scale2x, rotated around its center and sampled back down
+    /// The output is resized to fit the rotated image and empty space is filled with `fill_idx`
+    pub fn rotate_rotsprite(
+        &self,
+        degrees: f64,
+        fill_idx: u8,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        check_fill(self, fill_idx)?;
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let mut upscaled = self.get_pixels().to_vec();
+        let (mut up_width, mut up_height) = (width, height);
+        while up_width < width * UPSCALE {
+            upscaled = scale2x_pixels(up_width, up_height, &upscaled);
+            up_width *= 2;
+            up_height *= 2;
+        }
+        rotate_pixels(self, degrees, fill_idx, &upscaled, UPSCALE)
+    }
+
+    /// Rotate clockwise by `degrees` using nearest neighbour sampling
+    ///
+    /// The output is resized to fit the rotated image and empty space is filled with `fill_idx`
+    pub fn rotate(&self, degrees: f32, fill_idx: u8) -> Result<IndexedImage, IndexedImageError> {
+        check_fill(self, fill_idx)?;
+        rotate_pixels(self, degrees as f64, fill_idx, self.get_pixels(), 1)
+    }
+}
+
+fn check_fill(image: &IndexedImage, fill_idx: u8) -> Result<(), IndexedImageError> {
+    if fill_idx as usize >= image.get_palette().len() {
+        return Err(IndexOutOfRange(
+            fill_idx as usize,
+            image.get_palette().len(),
+            "palette",
+        ));
+    }
+    Ok(())
+}
+
+/// Rotate `image` around its center, sampling from `source` which is `image` upscaled by `scale`
+fn rotate_pixels(
+    image: &IndexedImage,
+    degrees: f64,
+    fill_idx: u8,
+    source: &[u8],
+    scale: usize,
+) -> Result<IndexedImage, IndexedImageError> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let (up_width, up_height) = (width * scale, height * scale);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let bounds = |w: f64, h: f64| (w * cos.abs() + h * sin.abs() - SIZE_EPSILON).ceil();
+    let new_width = bounds(width as f64, height as f64) as usize;
+    let new_height = bounds(height as f64, width as f64) as usize;
+    if new_width > 255 || new_height > 255 {
+        return Err(TooBigPostScale(new_width, new_height));
+    }
+    let (new_width, new_height) = (new_width.max(1), new_height.max(1));
+
+    let mut pixels = vec![fill_idx; new_width * new_height];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let dx = (i % new_width) as f64 + 0.5 - new_width as f64 / 2.0;
+        let dy = (i / new_width) as f64 + 0.5 - new_height as f64 / 2.0;
+        let sx = (dx * cos + dy * sin + width as f64 / 2.0) * scale as f64;
+        let sy = (-dx * sin + dy * cos + height as f64 / 2.0) * scale as f64;
+        if sx >= 0.0 && sy >= 0.0 && (sx as usize) < up_width && (sy as usize) < up_height {
+            *pixel = source[sx as usize + sy as usize * up_width];
+        }
+    }
+    IndexedImage::new(
+        new_width as u8,
+        new_height as u8,
+        image.get_palette().to_vec(),
+        pixels,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image() -> IndexedImage {
+        IndexedImage::new(
+            3,
+            2,
+            vec![RED, GREEN, BLUE, WHITE, BLACK, TRANSPARENT, MAGENTA],
+            vec![0, 1, 2, 3, 4, 5],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn right_angles() {
+        let image = image();
+        assert_eq!(image.rotate_rotsprite(0.0, 6).unwrap(), image);
+        assert_eq!(image.rotate_rotsprite(90.0, 6).unwrap(), image.rotate_cw());
+        assert_eq!(
+            image.rotate_rotsprite(-90.0, 6).unwrap(),
+            image.rotate_ccw()
+        );
+    }
+
+    #[test]
+    fn fills_corners() {
+        let image = IndexedImage::new(4, 4, vec![RED, BLUE], vec![0; 16]).unwrap();
+        let rotated = image.rotate_rotsprite(45.0, 1).unwrap();
+        assert_eq!(rotated.size(), (6, 6));
+        assert_eq!(rotated.get_pixels()[0], 1);
+        assert_eq!(rotated.get_pixels()[3 + 3 * 6], 0);
+        assert!(image.rotate_rotsprite(45.0, 2).is_err());
+    }
+
+    #[test]
+    fn nearest() {
+        let image = image();
+        assert_eq!(image.rotate(0.0, 6).unwrap(), image);
+        assert_eq!(image.rotate(90.0, 6).unwrap(), image.rotate_cw());
+        assert_eq!(image.rotate(270.0, 6).unwrap(), image.rotate_ccw());
+        assert!(image.rotate(45.0, 7).is_err());
+
+        let square = IndexedImage::new(4, 4, vec![RED, BLUE], vec![0; 16]).unwrap();
+        let rotated = square.rotate(45.0, 1).unwrap();
+        assert_eq!(rotated.size(), (6, 6));
+        assert_eq!(rotated.get_pixels()[0], 1);
+        assert_eq!(rotated.get_pixels()[3 + 3 * 6], 0);
+    }
+}
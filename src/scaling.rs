@@ -1,13 +1,24 @@
+use crate::color::{Color, TRANSPARENT};
+use crate::compat::*;
 use crate::errors::IndexedImageError;
 use crate::errors::IndexedImageError::{InvalidScaleParams, TooBigPostScale};
 use crate::image::IndexedImage;
+use crate::palette::{nearest_index_by, ColorDistance};
 use crate::scaling::Scaling::*;
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use core::fmt::Debug;
+use core::num::NonZeroUsize;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroUsize;
+
+/// Scaling algorithm, implement this to use other algorithms with [Scaling::Custom]
+pub trait Scaler: Debug + Send + Sync {
+    fn scale(&self, image: &IndexedImage) -> Result<IndexedImage, IndexedImageError>;
+}
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Scaling {
     /// Increase size of image by x_scale and y_scale
     /// Where {2,2} doubles the size
@@ -17,6 +28,53 @@ pub enum Scaling {
     },
     Epx2x,
     Epx4x,
+    /// Doubles the size, AdvMAME2x which is EPX with different edge handling
+    Scale2x,
+    /// Triples the size, AdvMAME3x
+    Scale3x,
+    /// Doubles the size, fills corners that match the three surrounding neighbours
+    Eagle,
+    /// Doubles the size, blends corners with similar looking (rather than identical) neighbours
+    ///
+    /// This is a simplified hq2x that only looks at the three neighbours of each corner instead of the full pattern table,
+    /// blended colors are matched to the closest palette color so the palette is unchanged
+    #[cfg(feature = "hq2x")]
+    Hq2x,
+    /// Use another algorithm, custom scalers can't be serialized
+    ///
+    /// Two custom scalings are only equal if they share the same scaler
+    ///
+    /// Only available on targets with atomic pointers, as it uses `Arc`
+    #[cfg(target_has_atomic = "ptr")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Arc<dyn Scaler>),
+}
+
+impl PartialEq for Scaling {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                NearestNeighbour { x_scale, y_scale },
+                NearestNeighbour {
+                    x_scale: other_x,
+                    y_scale: other_y,
+                },
+            ) => x_scale == other_x && y_scale == other_y,
+            #[cfg(target_has_atomic = "ptr")]
+            (Custom(scaler), Custom(other)) => Arc::ptr_eq(scaler, other),
+            #[cfg(target_has_atomic = "ptr")]
+            (Custom(_), _) | (_, Custom(_)) => false,
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Scaling {}
+
+impl Scaler for Scaling {
+    fn scale(&self, image: &IndexedImage) -> Result<IndexedImage, IndexedImageError> {
+        image.scale(self.clone())
+    }
 }
 
 impl Scaling {
@@ -27,27 +85,458 @@ impl Scaling {
         })
     }
 
+    /// How much the size is multiplied by, for algorithms with a fixed scale
+    pub(crate) fn fixed_factor(&self) -> Option<usize> {
+        match self {
+            NearestNeighbour { .. } => None,
+            #[cfg(target_has_atomic = "ptr")]
+            Custom(_) => None,
+            Epx2x | Scale2x | Eagle => Some(2),
+            #[cfg(feature = "hq2x")]
+            Hq2x => Some(2),
+            Scale3x => Some(3),
+            Epx4x => Some(4),
+        }
+    }
+
+    /// Short uppercase name used by [compare], e.g. `NN2X2`, `EPX4X` or `SCALE3X`
+    pub fn label(&self) -> String {
+        match self {
+            NearestNeighbour { x_scale, y_scale } => format!("NN{x_scale}X{y_scale}"),
+            Epx2x => "EPX2X".to_string(),
+            Epx4x => "EPX4X".to_string(),
+            Scale2x => "SCALE2X".to_string(),
+            Scale3x => "SCALE3X".to_string(),
+            Eagle => "EAGLE".to_string(),
+            #[cfg(feature = "hq2x")]
+            Hq2x => "HQ2X".to_string(),
+            #[cfg(target_has_atomic = "ptr")]
+            Custom(_) => "CUSTOM".to_string(),
+        }
+    }
+
     /// Double image size using nearest neighbour
     pub fn nn_double() -> Scaling {
         NearestNeighbour {
-            x_scale: NonZeroUsize::new(2).expect("2 is 0?"),
-            y_scale: NonZeroUsize::new(2).expect("2 is 0?"),
+            x_scale: NonZeroUsize::MIN.saturating_add(1),
+            y_scale: NonZeroUsize::MIN.saturating_add(1),
         }
     }
 }
 
-pub(crate) fn scale_nearest_neighbor(
+/// How pixels are chosen by [IndexedImage::resize]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum ResizeMode {
+    /// Use the source pixel under the center of each new pixel
+    #[default]
+    Nearest,
+    /// Use the most common index in the area of the source covered by each new pixel (ties use the lowest index)
+    ///
+    /// Keeps thin details when shrinking better than [ResizeMode::Nearest], the same as it when enlarging
+    DominantIndex,
+}
+
+/// 3x5 pixel glyphs for [compare] labels, each row uses the lowest 3 bits (left to right)
+#[rustfmt::skip]
+const GLYPHS: [(char, [u8; 5]); 26] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    (' ', [0; 5]),
+];
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+/// Label rows and the separator under them
+const LABEL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+/// Color of [compare] labels and separators
+const SEPARATOR: Color = Color::new(128, 128, 128, 255);
+
+/// Width in pixels of `text` drawn with [GLYPHS], with a 1px gap between characters
+fn label_width(text: &str) -> usize {
+    (text.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1)
+}
+
+/// Scale `image` with each of `algos` and place the results side by side, from left to right in the order of `algos`
+///
+/// Useful for choosing between scaling algorithms
+/// Each result has its algorithm's name (see [Scaling::label]) above it, and results and labels are separated by
+/// 1px gray lines. Tiles are as wide as the wider of the result and its label, so small images make wide sheets
+/// Empty space uses a transparent palette entry, gray and transparent are added to the palette if necessary and
+/// there's space
+pub fn compare(image: &IndexedImage, algos: &[Scaling]) -> Result<IndexedImage, IndexedImageError> {
+    let results = algos
+        .iter()
+        .map(|algo| Ok((algo.label(), image.scale(algo.clone())?)))
+        .collect::<Result<Vec<(String, IndexedImage)>, IndexedImageError>>()?;
+    let tile_widths: Vec<usize> = results
+        .iter()
+        .map(|(label, img)| (img.width() as usize).max(label_width(label)))
+        .collect();
+    let width = tile_widths.iter().map(|w| w + 1).sum::<usize>().max(2) - 1;
+    let height = LABEL_HEIGHT
+        + results
+            .iter()
+            .map(|(_, img)| img.height() as usize)
+            .max()
+            .unwrap_or(1);
+    if width > 255 || height > 255 {
+        return Err(TooBigPostScale(width, height));
+    }
+    let mut palette = image.get_palette().to_vec();
+    let background = match palette.iter().position(|c| c.a == 0) {
+        Some(idx) => idx,
+        None if palette.len() < 256 => {
+            palette.push(TRANSPARENT);
+            palette.len() - 1
+        }
+        None => 0,
+    } as u8;
+    let ink = match palette.iter().position(|c| *c == SEPARATOR) {
+        Some(idx) => idx as u8,
+        None if palette.len() < 256 => {
+            palette.push(SEPARATOR);
+            (palette.len() - 1) as u8
+        }
+        None => nearest_index_by(&palette, SEPARATOR, ColorDistance::EuclideanAlpha),
+    };
+
+    let mut pixels = vec![background; width * height];
+    pixels[GLYPH_HEIGHT * width..LABEL_HEIGHT * width].fill(ink);
+    let mut left = 0;
+    for ((label, result), tile_width) in results.iter().zip(&tile_widths) {
+        for (i, c) in label.chars().enumerate() {
+            let rows = GLYPHS
+                .iter()
+                .find(|(glyph, _)| *glyph == c)
+                .map(|(_, rows)| *rows)
+                .unwrap_or_default();
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if row >> (GLYPH_WIDTH - 1 - x) & 1 == 1 {
+                        pixels[left + i * (GLYPH_WIDTH + 1) + x + y * width] = ink;
+                    }
+                }
+            }
+        }
+        let result_width = result.width() as usize;
+        for (y, row) in result.get_pixels().chunks_exact(result_width).enumerate() {
+            let start = left + (y + LABEL_HEIGHT) * width;
+            pixels[start..start + result_width].copy_from_slice(row);
+        }
+        left += tile_width;
+        if left < width {
+            for y in 0..height {
+                pixels[left + y * width] = ink;
+            }
+        }
+        left += 1;
+    }
+    IndexedImage::new(width as u8, height as u8, palette, pixels)
+}
+
+/// Scale2x (AdvMAME2x) on raw palette indexes, output is `width * 2`x`height * 2`
+///
+/// Edge pixels treat missing neighbours as matching the center pixel
+pub(crate) fn scale2x_pixels(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let new_width = width * 2;
+    let mut output = vec![0; new_width * height * 2];
+    let get = |x: usize, y: usize| pixels[x + y * width];
+    for y in 0..height {
+        for x in 0..width {
+            let p = get(x, y);
+            let a = if y > 0 { get(x, y - 1) } else { p };
+            let b = if x + 1 < width { get(x + 1, y) } else { p };
+            let c = if x > 0 { get(x - 1, y) } else { p };
+            let d = if y + 1 < height { get(x, y + 1) } else { p };
+            let i = x * 2 + y * 2 * new_width;
+            output[i] = if c == a && c != d && a != b { a } else { p };
+            output[i + 1] = if a == b && a != c && b != d { b } else { p };
+            output[i + new_width] = if d == c && d != b && c != a { c } else { p };
+            output[i + new_width + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+    output
+}
+
+/// Scaled pixels for [Scaling::Scale2x], [Scaling::Scale3x], [Scaling::Eagle] and [Scaling::Hq2x]
+///
+/// Returns `None` for other algorithms
+pub(crate) fn filter_pixels(image: &IndexedImage, algo: Scaling) -> Option<Vec<u8>> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    match algo {
+        Scale2x => Some(scale2x_pixels(width, height, image.get_pixels())),
+        Scale3x => Some(scale3x_pixels(width, height, image.get_pixels())),
+        Eagle => Some(eagle_pixels(width, height, image.get_pixels())),
+        #[cfg(feature = "hq2x")]
+        Hq2x => Some(hq2x_pixels(image)),
+        NearestNeighbour { .. } | Epx2x | Epx4x => None,
+        #[cfg(target_has_atomic = "ptr")]
+        Custom(_) => None,
+    }
+}
+
+/// The 3x3 area around `x`,`y` (row by row), neighbours outside the image match the center pixel
+fn neighbourhood(width: usize, height: usize, pixels: &[u8], x: usize, y: usize) -> [u8; 9] {
+    let center = pixels[x + y * width];
+    let mut output = [center; 9];
+    for dy in 0..3 {
+        for dx in 0..3 {
+            let (nx, ny) = ((x + dx).wrapping_sub(1), (y + dy).wrapping_sub(1));
+            if nx < width && ny < height {
+                output[dx + dy * 3] = pixels[nx + ny * width];
+            }
+        }
+    }
+    output
+}
+
+/// Scale3x (AdvMAME3x) on raw palette indexes, output is `width * 3`x`height * 3`
+///
+/// Edge pixels treat missing neighbours as matching the center pixel
+pub(crate) fn scale3x_pixels(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let new_width = width * 3;
+    let mut output = vec![0; new_width * height * 3];
+    for y in 0..height {
+        for x in 0..width {
+            let [a, b, c, d, e, f, g, h, i] = neighbourhood(width, height, pixels, x, y);
+            let mut block = [e; 9];
+            if b != h && d != f {
+                block[0] = if d == b { d } else { e };
+                block[1] = if (d == b && e != c) || (b == f && e != a) {
+                    b
+                } else {
+                    e
+                };
+                block[2] = if b == f { f } else { e };
+                block[3] = if (d == b && e != g) || (d == h && e != a) {
+                    d
+                } else {
+                    e
+                };
+                block[5] = if (b == f && e != i) || (h == f && e != c) {
+                    f
+                } else {
+                    e
+                };
+                block[6] = if d == h { d } else { e };
+                block[7] = if (d == h && e != i) || (h == f && e != g) {
+                    h
+                } else {
+                    e
+                };
+                block[8] = if h == f { f } else { e };
+            }
+            let start = x * 3 + y * 3 * new_width;
+            for (row, values) in block.chunks_exact(3).enumerate() {
+                let start = start + row * new_width;
+                output[start..start + 3].copy_from_slice(values);
+            }
+        }
+    }
+    output
+}
+
+/// Eagle on raw palette indexes, output is `width * 2`x`height * 2`
+///
+/// Edge pixels treat missing neighbours as matching the center pixel
+pub(crate) fn eagle_pixels(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let new_width = width * 2;
+    let mut output = vec![0; new_width * height * 2];
+    for y in 0..height {
+        for x in 0..width {
+            let [s, t, u, v, c, w, x1, y1, z] = neighbourhood(width, height, pixels, x, y);
+            let i = x * 2 + y * 2 * new_width;
+            output[i] = if v == s && s == t { s } else { c };
+            output[i + 1] = if t == u && u == w { u } else { c };
+            output[i + new_width] = if v == x1 && x1 == y1 { x1 } else { c };
+            output[i + new_width + 1] = if w == z && z == y1 { z } else { c };
+        }
+    }
+    output
+}
+
+#[cfg(feature = "hq2x")]
+fn hq2x_pixels(image: &IndexedImage) -> Vec<u8> {
+    use alloc::collections::BTreeMap;
+
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let palette = image.get_palette();
+    let color = |idx: u8| palette.get(idx).unwrap_or(TRANSPARENT);
+    let yuva: Vec<[f32; 4]> = palette
+        .iter()
+        .map(|c| {
+            let (r, g, b) = (c.r as f32, c.g as f32, c.b as f32);
+            [
+                0.299 * r + 0.587 * g + 0.114 * b,
+                -0.169 * r - 0.331 * g + 0.5 * b,
+                0.5 * r - 0.419 * g - 0.081 * b,
+                c.a as f32,
+            ]
+        })
+        .collect();
+    // thresholds from hqx
+    let different = |lhs: u8, rhs: u8| {
+        if lhs == rhs {
+            return false;
+        }
+        let (Some(l), Some(r)) = (yuva.get(lhs as usize), yuva.get(rhs as usize)) else {
+            return true;
+        };
+        (l[0] - r[0]).abs() > 48.0
+            || (l[1] - r[1]).abs() > 7.0
+            || (l[2] - r[2]).abs() > 6.0
+            || (l[3] - r[3]).abs() > 48.0
+    };
+    let mut cache: BTreeMap<[u8; 4], u8> = BTreeMap::new();
+    let mut blend = |weights: &[(u8, u32)]| {
+        let total: u32 = weights.iter().map(|(_, w)| w).sum();
+        let channel = |get: fn(crate::color::Color) -> u8| {
+            let sum: u32 = weights
+                .iter()
+                .map(|(idx, w)| get(color(*idx)) as u32 * w)
+                .sum();
+            ((sum + total / 2) / total) as u8
+        };
+        let mixed = [
+            channel(|c| c.r),
+            channel(|c| c.g),
+            channel(|c| c.b),
+            channel(|c| c.a),
+        ];
+        *cache.entry(mixed).or_insert_with(|| {
+            nearest_index_by(
+                palette,
+                crate::color::Color::new(mixed[0], mixed[1], mixed[2], mixed[3]),
+                ColorDistance::EuclideanAlpha,
+            )
+        })
+    };
+
+    let new_width = width * 2;
+    let mut output = vec![0; new_width * height * 2];
+    for y in 0..height {
+        for x in 0..width {
+            let n = neighbourhood(width, height, image.get_pixels(), x, y);
+            let center = n[4];
+            // horizontal neighbour, vertical neighbour, diagonal neighbour and output offset for each corner
+            let corners = [
+                (n[3], n[1], n[0], 0),
+                (n[5], n[1], n[2], 1),
+                (n[3], n[7], n[6], new_width),
+                (n[5], n[7], n[8], new_width + 1),
+            ];
+            let start = x * 2 + y * 2 * new_width;
+            for (horz, vert, diag, offset) in corners {
+                output[start + offset] = if !different(horz, vert) && different(center, horz) {
+                    blend(&[(center, 2), (horz, 1), (vert, 1)])
+                } else if different(center, diag) {
+                    blend(&[(center, 3), (diag, 1)])
+                } else {
+                    center
+                };
+            }
+        }
+    }
+    output
+}
+
+/// Pixels of `image` resized to `new_width`x`new_height`, both must be > 0
+pub(crate) fn resize_pixels(
+    image: &IndexedImage,
+    new_width: u8,
+    new_height: u8,
+    mode: ResizeMode,
+) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let (new_width, new_height) = (new_width as usize, new_height as usize);
+    let pixels = image.get_pixels();
+    let mut output = Vec::with_capacity(new_width * new_height);
+    match mode {
+        ResizeMode::Nearest => {
+            for y in 0..new_height {
+                let sy = ((2 * y + 1) * height) / (2 * new_height);
+                for x in 0..new_width {
+                    let sx = ((2 * x + 1) * width) / (2 * new_width);
+                    output.push(pixels[sx + sy * width]);
+                }
+            }
+        }
+        ResizeMode::DominantIndex => {
+            // source pixels covered by new pixel `i` of `new_len`, always at least one
+            let span = |i: usize, len: usize, new_len: usize| {
+                let start = (i * len) / new_len;
+                let end = ((i + 1) * len).div_ceil(new_len).max(start + 1);
+                start..end
+            };
+            let mut counts = [0_usize; 256];
+            for y in 0..new_height {
+                let rows = span(y, height, new_height);
+                for x in 0..new_width {
+                    let columns = span(x, width, new_width);
+                    counts.fill(0);
+                    for sy in rows.clone() {
+                        for sx in columns.clone() {
+                            counts[pixels[sx + sy * width] as usize] += 1;
+                        }
+                    }
+                    let dominant = counts
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .max_by_key(|(_, count)| **count)
+                        .map(|(idx, _)| idx as u8)
+                        .unwrap_or_default();
+                    output.push(dominant);
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Size of `image` after scaling by `x_scale` and `y_scale`
+pub(crate) fn scaled_size(
     image: &IndexedImage,
     x_scale: usize,
     y_scale: usize,
-) -> Result<IndexedImage, IndexedImageError> {
+) -> Result<(u8, u8), IndexedImageError> {
     let new_width = image.width() as usize * x_scale;
     let new_height = image.height() as usize * y_scale;
     if new_height > 255 || new_width > 255 {
         return Err(TooBigPostScale(new_width, new_height));
     }
-    let new_width = new_width as u8;
-    let new_height = new_height as u8;
+    Ok((new_width as u8, new_height as u8))
+}
+
+pub(crate) fn scale_nearest_neighbor(
+    image: &IndexedImage,
+    x_scale: usize,
+    y_scale: usize,
+) -> Result<IndexedImage, IndexedImageError> {
+    let (new_width, new_height) = scaled_size(image, x_scale, y_scale)?;
     let mut new_image = IndexedImage::blank(new_width, new_height, image.get_palette().to_vec());
     let x_scale = 1.0 / x_scale as f32;
     let y_scale = 1.0 / y_scale as f32;
@@ -85,14 +574,26 @@ pub(crate) unsafe fn scale_nearest_neighbor_unchecked(
     new_image
 }
 
-pub(crate) fn scale_epx(image: &IndexedImage) -> Result<IndexedImage, IndexedImageError> {
-    let new_width = image.width() as usize * 2;
-    let new_height = image.height() as usize * 2;
-    if new_height > 255 || new_width > 255 {
-        return Err(TooBigPostScale(new_width, new_height));
+/// `target` must already be `x_scale`x`y_scale` the size of `image`
+pub(crate) fn scale_nearest_neighbor_into(
+    image: &IndexedImage,
+    x_scale: usize,
+    y_scale: usize,
+    target: &mut [u8],
+) {
+    let width = image.width() as usize;
+    let new_width = width * x_scale;
+    let pixels = image.get_pixels();
+    for (y, row) in target.chunks_exact_mut(new_width).enumerate() {
+        let source_row = &pixels[(y / y_scale) * width..];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = source_row[x / x_scale];
+        }
     }
-    let new_width = new_width as u8;
-    let new_height = new_height as u8;
+}
+
+pub(crate) fn scale_epx(image: &IndexedImage) -> Result<IndexedImage, IndexedImageError> {
+    let (new_width, new_height) = scaled_size(image, 2, 2)?;
     let mut new_image = IndexedImage::blank(new_width, new_height, image.get_palette().to_vec());
     for x in 0..image.width() {
         for y in 0..image.height() {
@@ -103,10 +604,10 @@ pub(crate) fn scale_epx(image: &IndexedImage) -> Result<IndexedImage, IndexedIma
             let a = image.get_pixel(image.get_pixel_index(x, if y > 0 { y - 1 } else { y })?)?;
             let c = image.get_pixel(image.get_pixel_index(if x > 0 { x - 1 } else { x }, y)?)?;
             let b = image.get_pixel(
-                image.get_pixel_index(if x < image.width() - 2 { x + 1 } else { x }, y)?,
+                image.get_pixel_index(if x + 2 < image.width() { x + 1 } else { x }, y)?,
             )?;
             let d = image.get_pixel(
-                image.get_pixel_index(x, if y < image.height() - 2 { y + 1 } else { y })?,
+                image.get_pixel_index(x, if y + 2 < image.height() { y + 1 } else { y })?,
             )?;
 
             if c == a && c != d && a != b {
@@ -133,6 +634,46 @@ pub(crate) fn scale_epx(image: &IndexedImage) -> Result<IndexedImage, IndexedIma
     Ok(new_image)
 }
 
+/// `target` must already be double the size of `image`
+pub(crate) fn scale_epx_into(image: &IndexedImage, target: &mut [u8]) {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let new_width = width * 2;
+    let pixels = image.get_pixels();
+    let get = |x: usize, y: usize| pixels[x + y * width];
+    for x in 0..width {
+        for y in 0..height {
+            let mut p1 = get(x, y);
+            let mut p2 = p1;
+            let mut p3 = p1;
+            let mut p4 = p1;
+            let a = get(x, if y > 0 { y - 1 } else { y });
+            let c = get(if x > 0 { x - 1 } else { x }, y);
+            let b = get(if x + 2 < width { x + 1 } else { x }, y);
+            let d = get(x, if y + 2 < height { y + 1 } else { y });
+
+            if c == a && c != d && a != b {
+                p1 = a
+            }
+            if a == b && a != c && b != d {
+                p2 = b
+            }
+            if d == c && d != b && c != a {
+                p3 = c
+            }
+            if b == d && b != a && d != c {
+                p4 = d
+            }
+
+            let i = x * 2 + y * 2 * new_width;
+            target[i] = p1;
+            target[i + 1] = p2;
+            target[i + new_width] = p3;
+            target[i + new_width + 1] = p4;
+        }
+    }
+}
+
 pub(crate) unsafe fn scale_epx_unchecked(image: &IndexedImage) -> IndexedImage {
     let new_width = (image.width() as usize * 2) as u8;
     let new_height = (image.height() as usize * 2) as u8;
@@ -150,10 +691,10 @@ pub(crate) unsafe fn scale_epx_unchecked(image: &IndexedImage) -> IndexedImage {
                 image.get_pixel_index_unchecked(if x > 0 { x - 1 } else { x }, y),
             );
             let b = image.get_pixel_unchecked(
-                image.get_pixel_index_unchecked(if x < image.width() - 2 { x + 1 } else { x }, y),
+                image.get_pixel_index_unchecked(if x + 2 < image.width() { x + 1 } else { x }, y),
             );
             let d = image.get_pixel_unchecked(
-                image.get_pixel_index_unchecked(x, if y < image.height() - 2 { y + 1 } else { y }),
+                image.get_pixel_index_unchecked(x, if y + 2 < image.height() { y + 1 } else { y }),
             );
 
             if c == a && c != d && a != b {
@@ -179,3 +720,175 @@ pub(crate) unsafe fn scale_epx_unchecked(image: &IndexedImage) -> IndexedImage {
     }
     new_image
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::color::*;
+    use crate::wrapper::IndexedWrapper;
+
+    #[test]
+    fn compare_sheet() {
+        let image = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        let algos = [Scaling::nearest_neighbour(1, 1).unwrap(), Epx2x];
+        assert_eq!(algos[0].label(), "NN1X1");
+        assert_eq!(algos[1].label(), "EPX2X");
+        let sheet = compare(&image, &algos).unwrap();
+        // two 19px wide labels, a separator column between them, label rows, a separator row and the results
+        assert_eq!(sheet.size(), (39, 8));
+        let gray = Color::new(128, 128, 128, 255);
+        assert_eq!(sheet.get_palette(), &[RED, BLUE, TRANSPARENT, gray]);
+        let row = |y: usize| &sheet.get_pixels()[y * 39..(y + 1) * 39];
+        // N
+        assert_eq!(&row(0)[..4], &[3, 3, 2, 2]);
+        assert_eq!(&row(1)[..4], &[3, 2, 3, 2]);
+        // E
+        assert_eq!(&row(0)[20..24], &[3, 3, 3, 2]);
+        assert_eq!(&row(2)[20..24], &[3, 3, 2, 2]);
+        assert!(row(5).iter().all(|idx| *idx == 3));
+        assert!((0..8).all(|y| row(y)[19] == 3));
+        assert_eq!(&row(6)[..3], &[0, 1, 2]);
+        assert_eq!(&row(6)[20..25], &[0, 0, 1, 1, 2]);
+        assert_eq!(&row(7)[..3], &[2, 2, 2]);
+        assert_eq!(&row(7)[20..25], &[0, 0, 1, 1, 2]);
+
+        let sheet = compare(&image, &[Epx2x]).unwrap();
+        assert_eq!(sheet.size(), (19, 8));
+        let big = IndexedImage::new(200, 1, vec![RED], vec![0; 200]).unwrap();
+        assert!(compare(&big, &[Epx2x]).is_err());
+    }
+
+    #[test]
+    fn pixel_art_scalers() {
+        #[rustfmt::skip]
+        let image = IndexedImage::new(3, 3, vec![RED, BLUE], vec![
+            1, 0, 0,
+            0, 1, 0,
+            0, 0, 1,
+        ]).unwrap();
+
+        let output = image.scale(Scale2x).unwrap();
+        assert_eq!(output.size(), (6, 6));
+        #[rustfmt::skip]
+        assert_eq!(output.get_pixels(), &[
+            1, 1, 0, 0, 0, 0,
+            1, 0, 1, 0, 0, 0,
+            0, 1, 1, 1, 0, 0,
+            0, 0, 1, 1, 1, 0,
+            0, 0, 0, 1, 0, 1,
+            0, 0, 0, 0, 1, 1,
+        ]);
+
+        let output = image.scale(Scale3x).unwrap();
+        assert_eq!(output.size(), (9, 9));
+        assert_eq!(
+            &output.get_pixels()[9 * 4..9 * 5],
+            &[0, 0, 0, 1, 1, 1, 0, 0, 0]
+        );
+        assert_eq!(
+            &output.get_pixels()[9 * 3..9 * 4],
+            &[0, 0, 1, 1, 1, 1, 0, 0, 0]
+        );
+
+        let output = image.scale(Eagle).unwrap();
+        assert_eq!(output.size(), (6, 6));
+        assert_eq!(&output.get_pixels()[..6], &[1, 1, 0, 0, 0, 0]);
+        assert_eq!(&output.get_pixels()[6..12], &[1, 1, 0, 0, 0, 0]);
+        assert_eq!(&output.get_pixels()[12..18], &[0, 0, 1, 0, 0, 0]);
+
+        let big = IndexedImage::new(100, 1, vec![RED], vec![0; 100]).unwrap();
+        assert!(big.scale(Scale3x).is_err());
+        let mut target = IndexedImage::blank(9, 9, vec![RED]);
+        image.scale_into(Scale3x, &mut target).unwrap();
+        assert_eq!(target, image.scale(Scale3x).unwrap());
+    }
+
+    #[test]
+    fn resize() {
+        #[rustfmt::skip]
+        let image = IndexedImage::new(4, 4, vec![RED, BLUE], vec![
+            0, 0, 1, 1,
+            0, 1, 1, 1,
+            0, 0, 0, 1,
+            0, 0, 1, 0,
+        ]).unwrap();
+        assert!(image.resize(0, 2, ResizeMode::Nearest).is_err());
+
+        let output = image.resize(2, 2, ResizeMode::DominantIndex).unwrap();
+        assert_eq!(output.get_pixels(), &[0, 1, 0, 0]);
+        assert_eq!(output.get_palette(), image.get_palette());
+        let output = image.resize(2, 2, ResizeMode::Nearest).unwrap();
+        assert_eq!(output.get_pixels(), &[1, 1, 0, 0]);
+
+        let output = image.resize(3, 1, ResizeMode::DominantIndex).unwrap();
+        assert_eq!(output.get_pixels(), &[0, 0, 1]);
+
+        let small = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        let output = small.resize(5, 2, ResizeMode::Nearest).unwrap();
+        assert_eq!(output.get_pixels(), &[0, 0, 1, 1, 1, 0, 0, 1, 1, 1]);
+        let output = small.resize(5, 2, ResizeMode::DominantIndex).unwrap();
+        assert_eq!(output.get_pixels(), &[0, 0, 0, 1, 1, 0, 0, 0, 1, 1]);
+        for mode in [ResizeMode::Nearest, ResizeMode::DominantIndex] {
+            assert_eq!(small.resize(2, 1, mode).unwrap(), small);
+        }
+    }
+
+    #[derive(Debug)]
+    struct Mirror;
+
+    impl Scaler for Mirror {
+        fn scale(&self, image: &IndexedImage) -> Result<IndexedImage, IndexedImageError> {
+            let doubled = image.scale(Scaling::nearest_neighbour(2, 1)?)?;
+            doubled.flip_horizontal()
+        }
+    }
+
+    #[test]
+    fn custom() {
+        let image = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        let mirror = Custom(Arc::new(Mirror));
+        let output = image.scale(mirror.clone()).unwrap();
+        assert_eq!(output.get_pixels(), &[1, 1, 0, 0]);
+        assert_eq!(unsafe { image.scale_unchecked(mirror.clone()) }, output);
+
+        let mut target = IndexedImage::new(4, 1, vec![RED], vec![0; 4]).unwrap();
+        image.scale_into(mirror.clone(), &mut target).unwrap();
+        assert_eq!(target, output);
+        let mut wrong = IndexedImage::new(2, 1, vec![RED], vec![0; 2]).unwrap();
+        assert!(image.scale_into(mirror.clone(), &mut wrong).is_err());
+
+        let wrapper = IndexedWrapper::from(image.clone())
+            .scale(mirror.clone())
+            .unwrap();
+        assert_eq!(wrapper.get_pixels(), output.get_pixels());
+
+        assert_eq!(mirror, mirror.clone());
+        assert_ne!(mirror, Custom(Arc::new(Mirror)));
+        assert_ne!(mirror, Epx2x);
+        assert_eq!(
+            Scaler::scale(&Epx2x, &image).unwrap(),
+            image.scale(Epx2x).unwrap()
+        );
+    }
+
+    #[cfg(feature = "hq2x")]
+    #[test]
+    fn hq2x() {
+        let gray = Color::gray(128);
+        let image = IndexedImage::new(2, 2, vec![BLACK, WHITE, gray], vec![1, 0, 0, 0]).unwrap();
+        let output = image.scale(Hq2x).unwrap();
+        assert_eq!(output.size(), (4, 4));
+        assert_eq!(output.get_palette(), image.get_palette());
+        // the white pixel's inner corner is blended with the black neighbours
+        assert_eq!(output.get_pixels()[5], 2);
+        assert_eq!(output.get_pixels()[0], 1);
+
+        // blending with transparency picks the translucent color rather than the opaque one
+        let translucent = Color::new(255, 0, 0, 128);
+        let image =
+            IndexedImage::new(2, 2, vec![TRANSPARENT, RED, translucent], vec![1, 0, 0, 0]).unwrap();
+        let output = image.scale(Hq2x).unwrap();
+        assert_eq!(output.get_pixels()[5], 2);
+        assert_eq!(output.get_pixels()[0], 1);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::palette::ColorDistance;
+use crate::prelude::*;
+
+impl IndexedImage {
+    /// Content aware resize, removes (or duplicates) the lowest energy paths through the image
+    /// so that detailed areas are kept intact
+    ///
+    /// Energy is calculated using the palette colors, the result uses the same palette and no new colors
+    pub fn seam_carve(
+        &self,
+        new_width: u8,
+        new_height: u8,
+    ) -> Result<IndexedImage, IndexedImageError> {
+        if new_width == 0 {
+            return Err(WidthIsZero);
+        }
+        if new_height == 0 {
+            return Err(HeightIsZero);
+        }
+        let palette = self.get_palette();
+        let mut rows: Vec<Vec<u8>> = self
+            .get_pixels()
+            .chunks_exact(self.width() as usize)
+            .map(|row| row.to_vec())
+            .collect();
+        carve_width(&mut rows, palette, new_width as usize);
+        let mut columns = transpose(&rows);
+        carve_width(&mut columns, palette, new_height as usize);
+        let rows = transpose(&columns);
+        IndexedImage::new(new_width, new_height, palette.to_vec(), rows.concat())
+    }
+}
+
+fn transpose(rows: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let width = rows.first().map(|row| row.len()).unwrap_or_default();
+    (0..width)
+        .map(|x| rows.iter().map(|row| row[x]).collect())
+        .collect()
+}
+
+/// Remove or duplicate vertical seams until every row is `new_width` long
+fn carve_width(rows: &mut [Vec<u8>], palette: &[Color], new_width: usize) {
+    let mut width = rows.first().map(|row| row.len()).unwrap_or_default();
+    while width > new_width {
+        let seam = find_seam(rows, palette);
+        for (row, x) in rows.iter_mut().zip(seam) {
+            row.remove(x);
+        }
+        width -= 1;
+    }
+    while width < new_width {
+        // find the seams to duplicate on a copy so the same seam isn't picked repeatedly
+        let count = (new_width - width).min(width);
+        let mut working = rows.to_vec();
+        let mut positions: Vec<Vec<usize>> =
+            rows.iter().map(|row| (0..row.len()).collect()).collect();
+        let mut chosen: Vec<Vec<usize>> = vec![vec![]; rows.len()];
+        for _ in 0..count {
+            let seam = find_seam(&working, palette);
+            for (y, x) in seam.into_iter().enumerate() {
+                working[y].remove(x);
+                chosen[y].push(positions[y].remove(x));
+            }
+        }
+        for (row, mut xs) in rows.iter_mut().zip(chosen) {
+            xs.sort_unstable_by(|a, b| b.cmp(a));
+            for x in xs {
+                row.insert(x, row[x]);
+            }
+        }
+        width += count;
+    }
+}
+
+/// Returns the x of the lowest energy vertical seam for each row
+fn find_seam(rows: &[Vec<u8>], palette: &[Color]) -> Vec<usize> {
+    let height = rows.len();
+    let width = rows[0].len();
+    let color = |x: usize, y: usize| {
+        palette
+            .get(rows[y][x] as usize)
+            .copied()
+            .unwrap_or(TRANSPARENT)
+    };
+    let metric = ColorDistance::EuclideanAlpha;
+    // difference between a pixel and its neighbours, so single pixel details aren't seen as flat
+    let energy = |x: usize, y: usize| {
+        let center = color(x, y);
+        [
+            color(x.saturating_sub(1), y),
+            color((x + 1).min(width - 1), y),
+            color(x, y.saturating_sub(1)),
+            color(x, (y + 1).min(height - 1)),
+        ]
+        .into_iter()
+        .map(|neighbour| metric.distance(center, neighbour))
+        .sum::<f32>()
+    };
+    let mut costs = vec![(0..width).map(|x| energy(x, 0)).collect::<Vec<f32>>()];
+    for y in 1..height {
+        let above = &costs[y - 1];
+        let row = (0..width)
+            .map(|x| {
+                let min = above[x.saturating_sub(1)..(x + 2).min(width)]
+                    .iter()
+                    .copied()
+                    .fold(f32::INFINITY, f32::min);
+                energy(x, y) + min
+            })
+            .collect();
+        costs.push(row);
+    }
+    let mut seam = vec![0; height];
+    seam[height - 1] = min_index(&costs[height - 1], 0);
+    for y in (0..height - 1).rev() {
+        let below = seam[y + 1];
+        let start = below.saturating_sub(1);
+        seam[y] = min_index(&costs[y][start..(below + 2).min(width)], start);
+    }
+    seam
+}
+
+fn min_index(values: &[f32], offset: usize) -> usize {
+    let mut best = 0;
+    for (i, value) in values.iter().enumerate() {
+        if *value < values[best] {
+            best = i;
+        }
+    }
+    best + offset
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn removes_flat_areas() {
+        #[rustfmt::skip]
+        let image = IndexedImage::new(5, 2, vec![WHITE, BLACK], vec![
+            0, 0, 1, 0, 0,
+            0, 0, 1, 0, 0,
+        ]).unwrap();
+        let output = image.seam_carve(3, 2).unwrap();
+        assert_eq!(output.get_pixels().iter().filter(|i| **i == 1).count(), 2);
+        let output = image.seam_carve(7, 3).unwrap();
+        assert_eq!(output.size(), (7, 3));
+        assert_eq!(output.get_palette(), image.get_palette());
+        assert_eq!(output.get_pixels().iter().filter(|i| **i == 1).count(), 3);
+        assert_eq!(image.seam_carve(5, 2).unwrap(), image);
+        assert!(image.seam_carve(0, 2).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Undo history for images
+//!
+//! Snapshots store pixels as rows, rows that haven't changed since the previous snapshot are shared
+//! so long histories of large animations only use memory for the rows that were edited
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    id: usize,
+    /// image without pixels
+    shell: IndexedWrapper,
+    rows: Vec<Rc<[u8]>>,
+}
+
+/// Memory used by the pixels of a [SnapshotImage]'s snapshots
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct SnapshotMemory {
+    pub snapshots: usize,
+    /// Bytes of pixel data stored, shared rows are only counted once
+    pub stored_bytes: usize,
+    /// Bytes of pixel data if every snapshot was a full copy
+    pub unshared_bytes: usize,
+}
+
+/// Image with a history of versions
+///
+/// ```
+///# use ici_files::prelude::*;
+///# fn main() -> Result<(), IndexedImageError> {
+/// let mut history = SnapshotImage::new(IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 0])?);
+/// let original = history.snapshot();
+/// history.image_mut().set_pixel(0, 1)?;
+/// history.restore(original)?;
+/// assert_eq!(history.image().get_pixels(), &[0, 0]);
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SnapshotImage {
+    image: IndexedWrapper,
+    snapshots: Vec<Snapshot>,
+    next_id: usize,
+    /// position in `snapshots` of the last snapshot taken or restored, new snapshots share rows with it
+    latest: Option<usize>,
+}
+
+impl SnapshotImage {
+    pub fn new<I: Into<IndexedWrapper>>(image: I) -> Self {
+        Self {
+            image: image.into(),
+            snapshots: vec![],
+            next_id: 0,
+            latest: None,
+        }
+    }
+
+    #[inline]
+    pub fn image(&self) -> &IndexedWrapper {
+        &self.image
+    }
+
+    #[inline]
+    pub fn image_mut(&mut self) -> &mut IndexedWrapper {
+        &mut self.image
+    }
+
+    pub fn into_image(self) -> IndexedWrapper {
+        self.image
+    }
+
+    /// Number of snapshots stored
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Ids of stored snapshots, oldest first
+    pub fn ids(&self) -> Vec<usize> {
+        self.snapshots.iter().map(|snapshot| snapshot.id).collect()
+    }
+
+    /// Store the current image, returns the id to pass to [SnapshotImage::restore]
+    pub fn snapshot(&mut self) -> usize {
+        let width = self.image.width() as usize;
+        let previous = self
+            .latest
+            .and_then(|idx| self.snapshots.get(idx))
+            .filter(|snapshot| snapshot.shell.width() == self.image.width());
+        let rows = self
+            .image
+            .get_pixels()
+            .chunks(width)
+            .enumerate()
+            .map(|(i, row)| {
+                previous
+                    .and_then(|snapshot| snapshot.rows.get(i))
+                    .filter(|prev| prev.as_ref() == row)
+                    .cloned()
+                    .unwrap_or_else(|| Rc::from(row))
+            })
+            .collect();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.snapshots.push(Snapshot {
+            id,
+            shell: self.image.clone_without_pixels(),
+            rows,
+        });
+        self.latest = Some(self.snapshots.len() - 1);
+        id
+    }
+
+    /// Replace the image with snapshot `id`, the snapshot is kept
+    pub fn restore(&mut self, id: usize) -> Result<(), IndexedImageError> {
+        let idx = self.position(id)?;
+        let snapshot = &self.snapshots[idx];
+        let mut image = snapshot.shell.clone();
+        image.set_raw_pixels(snapshot.rows.concat());
+        self.image = image;
+        self.latest = Some(idx);
+        Ok(())
+    }
+
+    /// Delete snapshot `id`, returns false if it doesn't exist
+    pub fn remove(&mut self, id: usize) -> bool {
+        let Ok(idx) = self.position(id) else {
+            return false;
+        };
+        self.snapshots.remove(idx);
+        self.latest = match self.latest {
+            Some(latest) if latest == idx => None,
+            Some(latest) if latest > idx => Some(latest - 1),
+            latest => latest,
+        };
+        true
+    }
+
+    /// Delete all snapshots
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.latest = None;
+    }
+
+    pub fn memory_usage(&self) -> SnapshotMemory {
+        let mut seen = BTreeSet::new();
+        let mut memory = SnapshotMemory {
+            snapshots: self.snapshots.len(),
+            ..SnapshotMemory::default()
+        };
+        for row in self.snapshots.iter().flat_map(|snapshot| &snapshot.rows) {
+            memory.unshared_bytes += row.len();
+            if seen.insert(Rc::as_ptr(row) as *const u8) {
+                memory.stored_bytes += row.len();
+            }
+        }
+        memory
+    }
+
+    fn position(&self, id: usize) -> Result<usize, IndexedImageError> {
+        self.snapshots
+            .iter()
+            .position(|snapshot| snapshot.id == id)
+            .ok_or(IndexOutOfRange(id, self.next_id, "snapshots"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn history() {
+        let image = AnimatedIndexedImage::new(
+            2,
+            2,
+            0.1,
+            2,
+            vec![RED, GREEN, BLUE],
+            vec![0, 0, 0, 0, 1, 1, 1, 1],
+            PlayType::Loops,
+        )
+        .unwrap();
+        let mut history = SnapshotImage::new(image.clone());
+        let first = history.snapshot();
+        history.image_mut().set_pixel(0, 2).unwrap();
+        let second = history.snapshot();
+        assert_eq!(
+            history.memory_usage(),
+            SnapshotMemory {
+                snapshots: 2,
+                stored_bytes: 10,
+                unshared_bytes: 16,
+            }
+        );
+
+        history.restore(first).unwrap();
+        assert_eq!(history.image(), &IndexedWrapper::from(image));
+        history.restore(second).unwrap();
+        assert_eq!(history.image().get_pixels(), &[2, 0, 0, 0, 1, 1, 1, 1]);
+
+        assert!(history.remove(first));
+        assert!(!history.remove(first));
+        assert!(history.restore(first).is_err());
+        assert_eq!(history.ids(), vec![second]);
+        assert_eq!(history.memory_usage().stored_bytes, 8);
+    }
+}
@@ -0,0 +1,268 @@
+//! Named frame ranges in animations, i.e. "walk" and "attack" in a character sprite sheet
+//!
+//! Tags are stored as a chunk after the pixel data (so older readers will ignore them)
+//! containing the count (u16) and then the name length (u8), name, start (u16) and end (u16) of each tag
+
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::metadata::{chunk_reader, write_chunk};
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+pub(crate) const TAGS_TAG: [u8; 4] = *b"TAGS";
+
+/// Frames `start..=end` of an animation
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FrameTag {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl FrameTag {
+    pub fn new(start: u16, end: u16) -> Self {
+        Self { start, end }
+    }
+
+    /// Number of frames in the tag
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.end as usize - self.start as usize + 1
+    }
+
+    /// Always false, tags have at least one frame
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    pub fn contains(&self, frame: u16) -> bool {
+        (self.start..=self.end).contains(&frame)
+    }
+
+    /// Update for a frame inserted before `idx`, frames inserted inside the tag are added to it
+    pub(crate) fn frame_inserted(&mut self, idx: u16) {
+        if self.start >= idx {
+            self.start += 1;
+        }
+        if self.end >= idx {
+            self.end += 1;
+        }
+    }
+
+    /// Update for frame `idx` being removed, returns false if the tag no longer has any frames
+    pub(crate) fn frame_removed(&mut self, idx: u16) -> bool {
+        if self.start == idx && self.end == idx {
+            return false;
+        }
+        if self.start > idx {
+            self.start -= 1;
+        }
+        if self.end >= idx {
+            self.end -= 1;
+        }
+        true
+    }
+}
+
+impl AnimatedIndexedImage {
+    /// Named frame ranges, saved with the file
+    pub fn tags(&self) -> &BTreeMap<String, FrameTag> {
+        self.tags_ref()
+    }
+
+    /// Name frames `start..=end`, returning the previous range for `name`
+    ///
+    /// `name` must be 1..=255 bytes and the frames must be in the animation
+    pub fn set_tag(
+        &mut self,
+        name: &str,
+        start: u16,
+        end: u16,
+    ) -> Result<Option<FrameTag>, IndexedImageError> {
+        if name.is_empty()
+            || name.len() > 255
+            || start > end
+            || end >= self.frame_count()
+            || (!self.tags().contains_key(name) && self.tags().len() >= u16::MAX as usize)
+        {
+            return Err(InvalidFrameTag(name.to_string()));
+        }
+        Ok(self
+            .tags_mut()
+            .insert(name.to_string(), FrameTag::new(start, end)))
+    }
+
+    /// Remove tag `name`, a segment that is playing isn't stopped
+    pub fn remove_tag(&mut self, name: &str) -> Option<FrameTag> {
+        self.tags_mut().remove(name)
+    }
+
+    /// Only play the frames tagged `name` and [reset], the animation is started for all play types
+    pub fn play_segment(&mut self, name: &str) -> Result<(), IndexedImageError> {
+        let Some(tag) = self.tags().get(name).copied() else {
+            return Err(InvalidFrameTag(name.to_string()));
+        };
+        self.set_segment(Some(tag));
+        self.reset();
+        self.set_animate(true);
+        Ok(())
+    }
+
+    /// Only play frames `start..=end` and [reset], the animation is started for all play types
+    pub fn play_frames(&mut self, start: u16, end: u16) -> Result<(), IndexedImageError> {
+        if end >= self.frame_count() {
+            return Err(IndexOutOfRange(
+                end as usize,
+                self.frame_count() as usize,
+                "frames",
+            ));
+        }
+        if start > end {
+            return Err(IndexOutOfRange(start as usize, end as usize + 1, "frames"));
+        }
+        self.set_segment(Some(FrameTag::new(start, end)));
+        self.reset();
+        self.set_animate(true);
+        Ok(())
+    }
+
+    /// Play every frame again and [reset]
+    pub fn play_all(&mut self) {
+        self.set_segment(None);
+        self.reset();
+    }
+
+    /// Frames currently being played, `None` if playing all frames
+    pub fn playing_segment(&self) -> Option<FrameTag> {
+        self.segment()
+    }
+}
+
+/// Writes the tags chunk, nothing is written if `tags` is empty
+pub(crate) fn write(tags: &BTreeMap<String, FrameTag>, output: &mut Vec<u8>) {
+    if tags.is_empty() {
+        return;
+    }
+    let mut payload = vec![];
+    payload.extend_from_slice(&(tags.len() as u16).to_be_bytes());
+    for (name, tag) in tags {
+        payload.push(name.len() as u8);
+        payload.extend_from_slice(name.as_bytes());
+        payload.extend_from_slice(&tag.start.to_be_bytes());
+        payload.extend_from_slice(&tag.end.to_be_bytes());
+    }
+    write_chunk(TAGS_TAG, &payload, output);
+}
+
+/// Reads tags from data after the pixels (`trailing`, which starts at `offset` in the file)
+pub(crate) fn read(
+    offset: usize,
+    trailing: &[u8],
+    frame_count: usize,
+) -> Result<BTreeMap<String, FrameTag>, IndexedImageError> {
+    let mut tags = BTreeMap::new();
+    let Some(mut reader) = chunk_reader(offset, trailing, TAGS_TAG) else {
+        return Ok(tags);
+    };
+    let count = reader.u16("tag count")?;
+    for _ in 0..count {
+        let name_len = reader.u8("tag name length")?;
+        let name = reader.string(name_len as usize, "tag name")?;
+        let frames_offset = reader.offset();
+        let start = reader.u16("tag start")?;
+        let end = reader.u16("tag end")?;
+        if start > end || end as usize >= frame_count {
+            return Err(reader.invalid(
+                frames_offset,
+                &format!("tag {name} frames"),
+                format!("within 0..{frame_count}"),
+                format!("{start}..={end}"),
+            ));
+        }
+        tags.insert(name, FrameTag::new(start, end));
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::FilePalette;
+
+    fn image() -> AnimatedIndexedImage {
+        AnimatedIndexedImage::new(
+            1,
+            1,
+            0.1,
+            5,
+            vec![RED, GREEN],
+            vec![0, 0, 1, 1, 1],
+            PlayType::Loops,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn segments() {
+        let mut image = image();
+        image.set_tag("walk", 1, 2).unwrap();
+        assert!(image.set_tag("run", 2, 5).is_err());
+        assert!(image.set_tag("", 0, 0).is_err());
+        assert!(image.play_segment("run").is_err());
+
+        image.play_segment("walk").unwrap();
+        assert_eq!(image.get_current_frame(), 1);
+        let mut frames = vec![];
+        for _ in 0..4 {
+            image.skip_to_next_frame();
+            image.update(0.0);
+            frames.push(image.get_current_frame());
+        }
+        assert_eq!(frames, vec![2, 1, 2, 1]);
+
+        image.set_play_type(PlayType::Once);
+        image.set_animate(true);
+        image.skip_to_next_frame();
+        image.update(0.0);
+        image.skip_to_next_frame();
+        assert_eq!(
+            image.update_with_events(0.0),
+            Some(AnimationEvent::Completed)
+        );
+        assert_eq!(image.get_current_frame(), 1);
+
+        image.play_all();
+        assert_eq!(image.playing_segment(), None);
+        assert_eq!(image.get_current_frame(), 0);
+
+        image.play_frames(3, 4).unwrap();
+        assert_eq!(image.playing_segment(), Some(FrameTag::new(3, 4)));
+        assert_eq!(image.get_current_frame(), 3);
+        assert!(image.play_frames(4, 5).is_err());
+        assert!(image.play_frames(2, 1).is_err());
+    }
+
+    #[test]
+    fn frame_changes() {
+        let mut image = image();
+        image.set_tag("first", 0, 0).unwrap();
+        image.set_tag("walk", 1, 3).unwrap();
+        image.insert_frame(2, &[0]).unwrap();
+        assert_eq!(image.tags()["walk"], FrameTag::new(1, 4));
+        image.remove_frame(0).unwrap();
+        assert_eq!(image.tags().get("first"), None);
+        assert_eq!(image.tags()["walk"], FrameTag::new(0, 3));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut image = image();
+        image.set_tag("walk", 0, 1).unwrap();
+        image.set_tag("attack", 2, 4).unwrap();
+        let bytes = image.to_file_contents(&FilePalette::Colors).unwrap();
+        let (read, _) = AnimatedIndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(read.tags(), image.tags());
+    }
+}
@@ -0,0 +1,331 @@
+//! Canonical ICI/ICA byte vectors and what they decode to
+//!
+//! Intended for validating other implementations of the format against this crate
+
+use crate::compat::*;
+use crate::palette::FilePalette;
+use crate::prelude::*;
+
+/// Encoded bytes and the expected result of decoding them
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase<T> {
+    pub name: &'static str,
+    pub bytes: &'static [u8],
+    pub expected: T,
+}
+
+/// 2x2 image, no palette data
+pub const IMAGE_NO_DATA: &[u8] = &[b'I', b'C', b'I', 1, 1, 0, 2, 2, 0, 0, 1, 2];
+/// 2x2 image, palette ID 15
+pub const IMAGE_ID: &[u8] = &[b'I', b'C', b'I', 1, 1, 1, 0, 15, 2, 2, 0, 0, 1, 2];
+/// 2x2 image, palette name "Test"
+pub const IMAGE_NAME: &[u8] = &[
+    b'I', b'C', b'I', 1, 1, 2, 4, b'T', b'e', b's', b't', 2, 2, 0, 0, 1, 2,
+];
+/// 2x2 image, 3 palette colors
+pub const IMAGE_COLORS: &[u8] = &[
+    b'I', b'C', b'I', 1, 1, 3, 3, 0, 0, 0, 0, 50, 51, 52, 53, 60, 61, 62, 63, 2, 2, 0, 0, 1, 2,
+];
+/// 2x1 image, 1 palette color, with trailing bytes that must be ignored
+pub const IMAGE_TRAILING_DATA: &[u8] = &[
+    b'I', b'C', b'I', 1, 1, 3, 1, 255, 0, 0, 255, 2, 1, 0, 0, 9, 9, 9,
+];
+/// 2x2 animation, 2 frames, 0.3s per frame, plays once, no palette data
+pub const ANIMATED_NO_DATA: &[u8] = &[
+    b'I', b'C', b'I', 1, 2, 0, 2, 2, 0, 2, 63, 211, 51, 51, 51, 51, 51, 51, 0, 0, 1, 2, 1, 2, 1, 0,
+];
+/// 1x1 animation, 3 frames, 0.5s per frame, loops both ways, 2 palette colors
+pub const ANIMATED_COLORS: &[u8] = &[
+    b'I', b'C', b'I', 1, 2, 3, 2, 0, 0, 0, 255, 255, 255, 255, 255, 1, 1, 4, 3, 63, 224, 0, 0, 0,
+    0, 0, 0, 0, 1, 0,
+];
+
+/// Too short to be an ICI file
+pub const INVALID_TOO_SHORT: &[u8] = &[b'I', b'C', b'I', 1, 1];
+/// Header doesn't start with ICI
+pub const INVALID_HEADER: &[u8] = &[b'P', b'N', b'G', 1, 1, 0, 2, 2, 0, 0, 1, 2];
+/// File type byte is not image or animated
+pub const INVALID_FILE_TYPE: &[u8] = &[b'I', b'C', b'I', 1, 9, 0, 2, 2, 0, 0, 1, 2];
+/// Palette type byte is not recognised
+pub const INVALID_PALETTE_TYPE: &[u8] = &[b'I', b'C', b'I', 1, 1, 7, 2, 2, 0, 0, 1, 2];
+/// Palette name is not UTF-8
+pub const INVALID_PALETTE_NAME: &[u8] = &[b'I', b'C', b'I', 1, 1, 2, 2, 0xC3, 0x28, 1, 1, 0];
+/// Palette says 4 colors but only contains 1
+pub const INVALID_PALETTE_TRUNCATED: &[u8] = &[b'I', b'C', b'I', 1, 1, 3, 4, 0, 0, 0, 255];
+/// Image is 3x3 but only has 4 pixels
+pub const INVALID_PIXELS_TRUNCATED: &[u8] = &[b'I', b'C', b'I', 1, 1, 0, 3, 3, 0, 0, 1, 2];
+/// Image has a width of 0
+pub const INVALID_ZERO_WIDTH: &[u8] = &[b'I', b'C', b'I', 1, 1, 0, 0, 2, 0, 0, 1, 2];
+/// Animation play type byte is not recognised
+pub const INVALID_PLAY_TYPE: &[u8] = &[
+    b'I', b'C', b'I', 1, 2, 0, 1, 1, 9, 1, 63, 224, 0, 0, 0, 0, 0, 0, 0,
+];
+/// Animation has 0 frames
+pub const INVALID_NO_FRAMES: &[u8] = &[
+    b'I', b'C', b'I', 1, 2, 0, 1, 1, 0, 0, 63, 224, 0, 0, 0, 0, 0, 0,
+];
+/// Animation has a negative per frame time
+pub const INVALID_PER_FRAME: &[u8] = &[
+    b'I', b'C', b'I', 1, 2, 0, 1, 1, 0, 1, 191, 224, 0, 0, 0, 0, 0, 0, 0,
+];
+/// Animation has a NaN per frame time
+pub const INVALID_PER_FRAME_NAN: &[u8] = &[
+    b'I', b'C', b'I', 1, 2, 0, 1, 1, 0, 1, 127, 248, 0, 0, 0, 0, 0, 0, 0,
+];
+/// Pixel uses color 1 but the palette only has 1 color
+pub const INVALID_PIXEL_OUTSIDE_PALETTE: &[u8] =
+    &[b'I', b'C', b'I', 1, 1, 3, 1, 0, 0, 0, 255, 1, 1, 1];
+/// Animation per frame time is cut short
+pub const INVALID_PER_FRAME_TRUNCATED: &[u8] = &[b'I', b'C', b'I', 1, 2, 0, 1, 1, 0, 1, 63, 224, 0];
+
+fn three_colors() -> Vec<Color> {
+    vec![
+        TRANSPARENT,
+        Color::new(50, 51, 52, 53),
+        Color::new(60, 61, 62, 63),
+    ]
+}
+
+fn image(palette: Vec<Color>) -> IndexedImage {
+    IndexedImage::new(2, 2, palette, vec![0, 0, 1, 2]).expect("test image is valid")
+}
+
+/// Valid single image files
+pub fn valid_images() -> Vec<TestCase<(IndexedImage, FilePalette)>> {
+    vec![
+        TestCase {
+            name: "image_no_data",
+            bytes: IMAGE_NO_DATA,
+            expected: (image(vec![TRANSPARENT; 3]), FilePalette::NoData),
+        },
+        TestCase {
+            name: "image_id",
+            bytes: IMAGE_ID,
+            expected: (image(vec![TRANSPARENT; 3]), FilePalette::ID(15)),
+        },
+        TestCase {
+            name: "image_name",
+            bytes: IMAGE_NAME,
+            expected: (
+                image(vec![TRANSPARENT; 3]),
+                FilePalette::Name("Test".to_string()),
+            ),
+        },
+        TestCase {
+            name: "image_colors",
+            bytes: IMAGE_COLORS,
+            expected: (image(three_colors()), FilePalette::Colors),
+        },
+        TestCase {
+            name: "image_trailing_data",
+            bytes: IMAGE_TRAILING_DATA,
+            expected: (
+                IndexedImage::new(2, 1, vec![RED], vec![0, 0]).expect("test image is valid"),
+                FilePalette::Colors,
+            ),
+        },
+    ]
+}
+
+/// Valid animated image files
+pub fn valid_animations() -> Vec<TestCase<(AnimatedIndexedImage, FilePalette)>> {
+    vec![
+        TestCase {
+            name: "animated_no_data",
+            bytes: ANIMATED_NO_DATA,
+            expected: (
+                AnimatedIndexedImage::new(
+                    2,
+                    2,
+                    0.3,
+                    2,
+                    vec![TRANSPARENT; 3],
+                    vec![0, 0, 1, 2, 1, 2, 1, 0],
+                    PlayType::Once,
+                )
+                .expect("test image is valid"),
+                FilePalette::NoData,
+            ),
+        },
+        TestCase {
+            name: "animated_colors",
+            bytes: ANIMATED_COLORS,
+            expected: (
+                AnimatedIndexedImage::new(
+                    1,
+                    1,
+                    0.5,
+                    3,
+                    vec![BLACK, WHITE],
+                    vec![0, 1, 0],
+                    PlayType::LoopsBoth,
+                )
+                .expect("test image is valid"),
+                FilePalette::Colors,
+            ),
+        },
+    ]
+}
+
+/// Files that must be rejected, `expected` describes why
+pub fn invalid_files() -> Vec<TestCase<&'static str>> {
+    vec![
+        TestCase {
+            name: "too_short",
+            bytes: INVALID_TOO_SHORT,
+            expected: "File is too short",
+        },
+        TestCase {
+            name: "header",
+            bytes: INVALID_HEADER,
+            expected: "Header is not ICI",
+        },
+        TestCase {
+            name: "file_type",
+            bytes: INVALID_FILE_TYPE,
+            expected: "Unknown file type",
+        },
+        TestCase {
+            name: "palette_type",
+            bytes: INVALID_PALETTE_TYPE,
+            expected: "Unknown palette type",
+        },
+        TestCase {
+            name: "palette_name",
+            bytes: INVALID_PALETTE_NAME,
+            expected: "Palette name is not UTF-8",
+        },
+        TestCase {
+            name: "palette_truncated",
+            bytes: INVALID_PALETTE_TRUNCATED,
+            expected: "Palette colors are incomplete",
+        },
+        TestCase {
+            name: "pixels_truncated",
+            bytes: INVALID_PIXELS_TRUNCATED,
+            expected: "Pixel data is incomplete",
+        },
+        TestCase {
+            name: "zero_width",
+            bytes: INVALID_ZERO_WIDTH,
+            expected: "Width is 0",
+        },
+        TestCase {
+            name: "play_type",
+            bytes: INVALID_PLAY_TYPE,
+            expected: "Unknown play type",
+        },
+        TestCase {
+            name: "no_frames",
+            bytes: INVALID_NO_FRAMES,
+            expected: "Animation has no frames",
+        },
+        TestCase {
+            name: "per_frame",
+            bytes: INVALID_PER_FRAME,
+            expected: "Per frame time is negative",
+        },
+        TestCase {
+            name: "per_frame_nan",
+            bytes: INVALID_PER_FRAME_NAN,
+            expected: "Per frame time is NaN",
+        },
+        TestCase {
+            name: "pixel_outside_palette",
+            bytes: INVALID_PIXEL_OUTSIDE_PALETTE,
+            expected: "Pixel uses a color outside the palette",
+        },
+        TestCase {
+            name: "per_frame_truncated",
+            bytes: INVALID_PER_FRAME_TRUNCATED,
+            expected: "Per frame time is incomplete",
+        },
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_images_decode() {
+        for case in valid_images() {
+            let output = IndexedImage::from_file_contents(case.bytes);
+            assert_eq!(output.ok(), Some(case.expected), "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn valid_animations_decode() {
+        for case in valid_animations() {
+            let output = AnimatedIndexedImage::from_file_contents(case.bytes);
+            assert_eq!(output.ok(), Some(case.expected), "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn valid_files_encode() {
+        for case in valid_images()
+            .into_iter()
+            .filter(|c| c.name != "image_trailing_data")
+        {
+            let (image, palette) = case.expected;
+            assert_eq!(
+                image.to_file_contents(&palette).unwrap(),
+                case.bytes,
+                "{}",
+                case.name
+            );
+        }
+        for case in valid_animations() {
+            let (image, palette) = case.expected;
+            assert_eq!(
+                image.to_file_contents(&palette).unwrap(),
+                case.bytes,
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_files_rejected() {
+        for case in invalid_files() {
+            assert!(
+                IndexedImage::from_file_contents(case.bytes).is_err(),
+                "{}",
+                case.name
+            );
+            assert!(
+                AnimatedIndexedImage::from_file_contents(case.bytes).is_err(),
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_files_rejected() {
+        for case in valid_images()
+            .into_iter()
+            .filter(|c| c.name != "image_trailing_data")
+        {
+            for len in 0..case.bytes.len() {
+                assert!(
+                    IndexedImage::from_file_contents(&case.bytes[..len]).is_err(),
+                    "{} {len}",
+                    case.name
+                );
+            }
+        }
+        for case in valid_animations() {
+            for len in 0..case.bytes.len() {
+                assert!(
+                    AnimatedIndexedImage::from_file_contents(&case.bytes[..len]).is_err(),
+                    "{} {len}",
+                    case.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Reference to a tile in a [TileSet], optionally flipped
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct TileRef {
+    pub tile: usize,
+    pub flip_h: bool,
+    pub flip_v: bool,
+}
+
+impl TileRef {
+    pub const fn new(tile: usize) -> Self {
+        TileRef {
+            tile,
+            flip_h: false,
+            flip_v: false,
+        }
+    }
+
+    pub const fn flipped(tile: usize, flip_h: bool, flip_v: bool) -> Self {
+        TileRef {
+            tile,
+            flip_h,
+            flip_v,
+        }
+    }
+}
+
+/// Grid of [TileRef]s, sizes are in tiles
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TileMap {
+    width: usize,
+    height: usize,
+    cells: Vec<TileRef>,
+}
+
+impl TileMap {
+    pub fn new(
+        width: usize,
+        height: usize,
+        cells: Vec<TileRef>,
+    ) -> Result<Self, IndexedImageError> {
+        if width == 0 {
+            return Err(WidthIsZero);
+        }
+        if height == 0 {
+            return Err(HeightIsZero);
+        }
+        if cells.len() != width * height {
+            return Err(MissingData(cells.len(), width * height));
+        }
+        Ok(TileMap {
+            width,
+            height,
+            cells,
+        })
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Cells left to right, top to bottom
+    #[inline]
+    pub fn cells(&self) -> &[TileRef] {
+        &self.cells
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<TileRef> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.cells[x + y * self.width])
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, tile: TileRef) -> Result<(), IndexedImageError> {
+        if x >= self.width {
+            return Err(IndexOutOfRange(x, self.width, "width"));
+        }
+        if y >= self.height {
+            return Err(IndexOutOfRange(y, self.height, "height"));
+        }
+        self.cells[x + y * self.width] = tile;
+        Ok(())
+    }
+}
+
+/// Same sized tiles sharing a palette and a [TileMap] describing how they are arranged
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TileSet {
+    tile_width: u8,
+    tile_height: u8,
+    palette: Vec<Color>,
+    tiles: Vec<Vec<u8>>,
+    map: TileMap,
+}
+
+impl TileSet {
+    /// Slice `image` into `tile_width`x`tile_height` tiles, left to right, top to bottom
+    ///
+    /// The image size must be a multiple of the tile size
+    pub fn from_image(
+        image: &IndexedImage,
+        tile_width: u8,
+        tile_height: u8,
+    ) -> Result<TileSet, IndexedImageError> {
+        if tile_width == 0 {
+            return Err(WidthIsZero);
+        }
+        if tile_height == 0 {
+            return Err(HeightIsZero);
+        }
+        if !image.width().is_multiple_of(tile_width) || !image.height().is_multiple_of(tile_height)
+        {
+            return Err(InvalidTileSize(tile_width, tile_height));
+        }
+        let columns = (image.width() / tile_width) as usize;
+        let rows = (image.height() / tile_height) as usize;
+        let (tw, th) = (tile_width as usize, tile_height as usize);
+        let width = image.width() as usize;
+        let pixels = image.get_pixels();
+        let mut tiles = vec![];
+        for row in 0..rows {
+            for column in 0..columns {
+                let mut tile = Vec::with_capacity(tw * th);
+                for y in 0..th {
+                    let start = column * tw + (row * th + y) * width;
+                    tile.extend_from_slice(&pixels[start..start + tw]);
+                }
+                tiles.push(tile);
+            }
+        }
+        let map = TileMap::new(columns, rows, (0..tiles.len()).map(TileRef::new).collect())?;
+        Ok(TileSet {
+            tile_width,
+            tile_height,
+            palette: image.get_palette().to_vec(),
+            tiles,
+            map,
+        })
+    }
+
+    #[inline]
+    pub fn tile_size(&self) -> (u8, u8) {
+        (self.tile_width, self.tile_height)
+    }
+
+    #[inline]
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &[Color] {
+        &self.palette
+    }
+
+    #[inline]
+    pub fn map(&self) -> &TileMap {
+        &self.map
+    }
+
+    /// Replace the map, every cell must refer to a tile in this set
+    pub fn set_map(&mut self, map: TileMap) -> Result<(), IndexedImageError> {
+        self.validate_map(&map)?;
+        self.map = map;
+        Ok(())
+    }
+
+    pub fn get_tile(&self, idx: usize) -> Result<IndexedImage, IndexedImageError> {
+        let pixels = self
+            .tiles
+            .get(idx)
+            .ok_or(IndexOutOfRange(idx, self.tiles.len(), "tiles"))?;
+        IndexedImage::new(
+            self.tile_width,
+            self.tile_height,
+            self.palette.clone(),
+            pixels.clone(),
+        )
+    }
+
+    /// Draw the tile set's map as an image
+    pub fn to_image(&self) -> Result<IndexedImage, IndexedImageError> {
+        self.render(&self.map)
+    }
+
+    /// Draw `map` using the tiles from this set
+    pub fn render(&self, map: &TileMap) -> Result<IndexedImage, IndexedImageError> {
+        self.validate_map(map)?;
+        let (tw, th) = (self.tile_width as usize, self.tile_height as usize);
+        let width = map.width * tw;
+        let height = map.height * th;
+        if width > 255 || height > 255 {
+            return Err(TooBigPostScale(width, height));
+        }
+        let mut pixels = vec![0; width * height];
+        for (i, cell) in map.cells.iter().enumerate() {
+            let tile = self.oriented(cell.tile, cell.flip_h, cell.flip_v);
+            let (column, row) = (i % map.width, i / map.width);
+            for (y, tile_row) in tile.chunks_exact(tw).enumerate() {
+                let start = column * tw + (row * th + y) * width;
+                pixels[start..start + tw].copy_from_slice(tile_row);
+            }
+        }
+        IndexedImage::new(width as u8, height as u8, self.palette.clone(), pixels)
+    }
+
+    /// Remove duplicate tiles and update the map to match
+    ///
+    /// If `detect_flips` is true then tiles that are horizontal and/or vertical mirrors of another tile
+    /// are also removed and the map entries flipped instead
+    ///
+    /// Returns the number of tiles removed
+    pub fn dedupe(&mut self, detect_flips: bool) -> usize {
+        let mut unique: Vec<Vec<u8>> = vec![];
+        let mut replacements = Vec::with_capacity(self.tiles.len());
+        for idx in 0..self.tiles.len() {
+            let orientations: &[(bool, bool)] = if detect_flips {
+                &[(false, false), (true, false), (false, true), (true, true)]
+            } else {
+                &[(false, false)]
+            };
+            let found = orientations.iter().find_map(|(flip_h, flip_v)| {
+                let oriented = self.oriented(idx, *flip_h, *flip_v);
+                unique
+                    .iter()
+                    .position(|tile| *tile == oriented)
+                    .map(|pos| TileRef::flipped(pos, *flip_h, *flip_v))
+            });
+            match found {
+                Some(tile) => replacements.push(tile),
+                None => {
+                    unique.push(self.tiles[idx].clone());
+                    replacements.push(TileRef::new(unique.len() - 1));
+                }
+            }
+        }
+        for cell in self.map.cells.iter_mut() {
+            let replacement = replacements[cell.tile];
+            cell.tile = replacement.tile;
+            cell.flip_h ^= replacement.flip_h;
+            cell.flip_v ^= replacement.flip_v;
+        }
+        let removed = self.tiles.len() - unique.len();
+        self.tiles = unique;
+        removed
+    }
+
+    fn validate_map(&self, map: &TileMap) -> Result<(), IndexedImageError> {
+        if let Some(cell) = map.cells.iter().find(|c| c.tile >= self.tiles.len()) {
+            return Err(IndexOutOfRange(cell.tile, self.tiles.len(), "tiles"));
+        }
+        Ok(())
+    }
+
+    fn oriented(&self, idx: usize, flip_h: bool, flip_v: bool) -> Vec<u8> {
+        let tw = self.tile_width as usize;
+        let mut rows: Vec<&[u8]> = self.tiles[idx].chunks_exact(tw).collect();
+        if flip_v {
+            rows.reverse();
+        }
+        rows.into_iter()
+            .flat_map(|row| {
+                let mut row = row.to_vec();
+                if flip_h {
+                    row.reverse();
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image() -> IndexedImage {
+        #[rustfmt::skip]
+        let pixels = vec![
+            0, 1, 1, 0, 0, 0, 2, 2,
+            0, 0, 0, 0, 0, 1, 3, 3,
+        ];
+        IndexedImage::new(8, 2, vec![RED, GREEN, BLUE, WHITE], pixels).unwrap()
+    }
+
+    #[test]
+    fn slice_and_render() {
+        let set = TileSet::from_image(&image(), 2, 2).unwrap();
+        assert_eq!(set.tile_count(), 4);
+        assert_eq!(set.get_tile(1).unwrap().get_pixels(), &[1, 0, 0, 0]);
+        assert_eq!(set.to_image().unwrap(), image());
+        assert!(TileSet::from_image(&image(), 3, 2).is_err());
+    }
+
+    #[test]
+    fn dedupe_exact() {
+        let mut set = TileSet::from_image(&image(), 2, 2).unwrap();
+        assert_eq!(set.dedupe(false), 0);
+        assert_eq!(set.tile_count(), 4);
+    }
+
+    #[test]
+    fn dedupe_flips() {
+        let mut set = TileSet::from_image(&image(), 2, 2).unwrap();
+        assert_eq!(set.dedupe(true), 2);
+        assert_eq!(set.tile_count(), 2);
+        assert_eq!(
+            set.map().cells(),
+            &[
+                TileRef::new(0),
+                TileRef::flipped(0, true, false),
+                TileRef::flipped(0, false, true),
+                TileRef::new(1),
+            ]
+        );
+        assert_eq!(set.to_image().unwrap(), image());
+    }
+
+    #[test]
+    fn map_validation() {
+        let mut set = TileSet::from_image(&image(), 2, 2).unwrap();
+        let map = TileMap::new(1, 1, vec![TileRef::new(10)]).unwrap();
+        assert!(set.set_map(map).is_err());
+        assert!(TileMap::new(2, 1, vec![TileRef::new(0)]).is_err());
+    }
+}
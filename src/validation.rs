@@ -0,0 +1,471 @@
+//! Lint style checking of files, unlike the readers every problem is reported instead of only the first
+//!
+//! ```
+//! # use ici_files::prelude::*;
+//! let report = ici_files::validate(&[b'I', b'C', b'I', 1, 1, 3, 1, 255, 0, 0, 255, 1, 1, 4]);
+//! assert!(!report.is_valid());
+//! println!("{report}");
+//! ```
+
+use crate::animated::PlayType;
+use crate::bundle::LazyBundle;
+use crate::compat::*;
+use crate::errors::IndexedImageError::*;
+use crate::errors::{FileSection, IndexedImageError};
+use crate::file::FileType::{Animated, Bundle, Image};
+use crate::file::{read_header, verify_checksum, FileType, CHECKSUM_CHUNK_LEN, CHECKSUM_TAG};
+use crate::file::{HEADER, HEADER_V2};
+use crate::lod::LOD_TAG;
+use crate::metadata::{read_chunks, CHUNK_HEADER_LEN, METADATA_TAG};
+use crate::playback::STATE_TAG;
+use crate::reader::ByteReader;
+use crate::tags::TAGS_TAG;
+use crate::{metadata, palette, playback, tags};
+use core::fmt::{Display, Formatter};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Severity {
+    /// The file is readable but contains data that will be ignored
+    Warning,
+    /// Reading the file will fail
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Problem found by [validate]
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    #[error(transparent)]
+    Invalid(#[from] IndexedImageError),
+    #[error("{1} pixels use colors outside the palette (len {2}), the first is at {0}")]
+    PixelsOutsidePalette(usize, usize, usize),
+    #[error("Unknown chunk {0}, it will be ignored")]
+    UnknownChunk(String),
+    #[error("Chunk {0} appears more than once, only the first is used")]
+    DuplicateChunk(String),
+    #[error("Checksum chunk must be 4 bytes and at the end of the file, it will be ignored")]
+    MisplacedChecksum,
+    #[error("{0} bytes of unrecognised data after the chunks, they will be ignored")]
+    TrailingData(usize),
+}
+
+impl ValidationIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidationIssue::Invalid(_) | ValidationIssue::PixelsOutsidePalette(_, _, _) => {
+                Severity::Error
+            }
+            ValidationIssue::UnknownChunk(_)
+            | ValidationIssue::DuplicateChunk(_)
+            | ValidationIssue::MisplacedChecksum
+            | ValidationIssue::TrailingData(_) => Severity::Warning,
+        }
+    }
+}
+
+/// Result of [validate]
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// File version and type, `None` if the header is invalid
+    pub header: Option<(u8, FileType)>,
+    /// Every problem found and its offset in the file, in file order
+    pub issues: Vec<(usize, ValidationIssue)>,
+}
+
+impl ValidationReport {
+    fn push<I: Into<ValidationIssue>>(&mut self, offset: usize, issue: I) {
+        self.issues.push((offset, issue.into()));
+    }
+
+    /// Adds a reader error, using its offset if it has one
+    fn push_error(&mut self, fallback: usize, error: IndexedImageError) {
+        self.push(error.offset().unwrap_or(fallback), error);
+    }
+
+    /// `true` if there are no errors, the file may still have warnings
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &(usize, ValidationIssue)> {
+        self.issues
+            .iter()
+            .filter(|(_, issue)| issue.severity() == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &(usize, ValidationIssue)> {
+        self.issues
+            .iter()
+            .filter(|(_, issue)| issue.severity() == Severity::Warning)
+    }
+}
+
+impl Display for ValidationReport {
+    /// One line per issue with the offset, severity and description
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if self.issues.is_empty() {
+            return writeln!(f, "No problems found");
+        }
+        for (offset, issue) in &self.issues {
+            writeln!(f, "{offset:08X}  {}: {issue}", issue.severity())?;
+        }
+        Ok(())
+    }
+}
+
+/// Check an image, animation or bundle file, including any chunks, levels of detail and bundle entries
+///
+/// Reading continues past problems where possible so the report lists everything wrong with the file,
+/// it only stops at data it can't find the end of (such as truncated pixels or an unknown palette format)
+pub fn validate(bytes: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    report.header =
+        check_file(bytes, 0, None, &mut report).map(|(version, file_type, _)| (version, file_type));
+    report
+}
+
+/// Checks the file in `bytes`, which starts at `base` in the outermost file
+///
+/// Images without palette colors are checked against `inherited` (the bundle or parent palette size)
+///
+/// Returns the version, type and, if the pixels could be read, the frame count
+fn check_file(
+    bytes: &[u8],
+    base: usize,
+    inherited: Option<usize>,
+    report: &mut ValidationReport,
+) -> Option<(u8, FileType, Option<usize>)> {
+    if bytes.len() < HEADER.len() + 1 || bytes[0..3] != HEADER[0..3] {
+        report.push(base, NotIciFile);
+        return None;
+    }
+    let version = bytes[3];
+    if version != HEADER[3] && version != HEADER_V2[3] {
+        report.push(base + 3, UnknownIciVersion(version));
+        return None;
+    }
+    let header = ByteReader::with_offset(bytes, base, FileSection::Header);
+    let Some(file_type) = FileType::from_byte(bytes[4]) else {
+        report.push(
+            base + 4,
+            header.invalid(base + 4, "file type", "1..=3", bytes[4]),
+        );
+        return None;
+    };
+    let mut content = bytes;
+    if let Err(e) = verify_checksum(bytes) {
+        let offset = bytes.len() - CHECKSUM_CHUNK_LEN;
+        report.push(base + offset, e);
+        content = &bytes[..offset];
+    }
+    // only the outermost file starts at 0
+    let result = match file_type {
+        Bundle if base == 0 => check_bundle(content, report).map(|_| 0),
+        Bundle => Err(header.invalid(base + 4, "file type", "an image", Bundle.name())),
+        Image | Animated => check_image(
+            content,
+            base,
+            version == HEADER_V2[3],
+            file_type == Animated,
+            inherited,
+            report,
+        ),
+    };
+    let frame_count = match result {
+        Ok(frame_count) => Some(frame_count),
+        Err(e) => {
+            report.push_error(base, e);
+            None
+        }
+    };
+    Some((version, file_type, frame_count))
+}
+
+fn check_bundle(bytes: &[u8], report: &mut ValidationReport) -> Result<(), IndexedImageError> {
+    let bundle = LazyBundle::from_file_contents(bytes)?;
+    let palette_len = bundle.get_palette().map(|palette| palette.len());
+    for (_, range) in bundle.entry_ranges() {
+        check_file(&bytes[range.clone()], range.start, palette_len, report);
+    }
+    Ok(())
+}
+
+/// Returns the frame count, or an error if the rest of the file can't be found
+fn check_image(
+    bytes: &[u8],
+    base: usize,
+    v2: bool,
+    animated: bool,
+    inherited: Option<usize>,
+    report: &mut ValidationReport,
+) -> Result<usize, IndexedImageError> {
+    let mut reader = ByteReader::with_offset(bytes, base, FileSection::Header);
+    read_header(&mut reader)?;
+    let (_, colors) = palette::read(&mut reader)?;
+    let palette_len = colors.map(|colors| colors.len()).or(inherited);
+
+    reader.set_section(FileSection::Dimensions);
+    let size_offset = reader.offset();
+    let width = reader.size(v2, "width")?;
+    let height = reader.size(v2, "height")?;
+    if width == 0 {
+        report.push(size_offset, WidthIsZero);
+    }
+    if height == 0 {
+        report.push(size_offset, HeightIsZero);
+    }
+    if animated && (width > u8::MAX as usize || height > u8::MAX as usize) {
+        report.push(size_offset, ImageTooLarge(width, height));
+    }
+
+    let mut frame_count = 1;
+    if animated {
+        reader.set_section(FileSection::Animation);
+        let play_offset = reader.offset();
+        let play_type = reader.u8("play type")?;
+        if PlayType::from_byte(play_type).is_none() {
+            report.push(
+                play_offset,
+                reader.invalid(play_offset, "play type", "0..=5", play_type),
+            );
+        }
+        let count_offset = reader.offset();
+        frame_count = reader.size(v2, "frame count")?;
+        if frame_count == 0 {
+            report.push(
+                count_offset,
+                reader.invalid(count_offset, "frame count", "at least 1", 0),
+            );
+        }
+        let per_frame_offset = reader.offset();
+        let per_frame = reader.f64("per frame time")?;
+        if !(per_frame > 0.0 && per_frame.is_finite()) {
+            let error = reader.invalid(
+                per_frame_offset,
+                "per frame time",
+                "finite and greater than 0",
+                per_frame,
+            );
+            report.push(per_frame_offset, error);
+        }
+    }
+
+    reader.set_section(FileSection::Pixels);
+    let pixels_offset = reader.offset();
+    let pixels = reader.bytes(width * height * frame_count, "pixels")?;
+    if let Some(len) = palette_len {
+        let mut outside = pixels
+            .iter()
+            .enumerate()
+            .filter(|(_, idx)| **idx as usize >= len);
+        if let Some((first, _)) = outside.next() {
+            report.push(
+                pixels_offset + first,
+                ValidationIssue::PixelsOutsidePalette(first, outside.count() + 1, len),
+            );
+        }
+    }
+
+    let chunk_frames = animated.then_some(frame_count);
+    check_chunks(
+        reader.remaining(),
+        reader.offset(),
+        chunk_frames,
+        palette_len,
+        report,
+    );
+    Ok(frame_count)
+}
+
+/// Checks the chunks after the pixels, `frame_count` is `None` for static images
+fn check_chunks(
+    trailing: &[u8],
+    offset: usize,
+    frame_count: Option<usize>,
+    palette_len: Option<usize>,
+    report: &mut ValidationReport,
+) {
+    let chunks = read_chunks(trailing);
+    let last = chunks.len().saturating_sub(1);
+    let mut seen = vec![];
+    let mut end = 0;
+    for (i, (chunk_offset, tag, payload)) in chunks.into_iter().enumerate() {
+        let at = offset + chunk_offset;
+        let chunk = &trailing[chunk_offset..];
+        let name = String::from_utf8_lossy(&tag).trim_end().to_string();
+        end = chunk_offset + CHUNK_HEADER_LEN + payload.len();
+        if tag != LOD_TAG {
+            if seen.contains(&tag) {
+                report.push(at, ValidationIssue::DuplicateChunk(name));
+                continue;
+            }
+            seen.push(tag);
+        }
+        let result = match (tag, frame_count) {
+            (METADATA_TAG, _) => metadata::read(at, chunk).map(|_| ()),
+            (TAGS_TAG, Some(count)) => tags::read(at, chunk, count).map(|_| ()),
+            (STATE_TAG, Some(count)) => playback::read(at, chunk, count as u16).map(|_| ()),
+            (LOD_TAG, Some(count)) => {
+                check_lod(payload, at + CHUNK_HEADER_LEN, count, palette_len, report);
+                Ok(())
+            }
+            (CHECKSUM_TAG, _) => {
+                if i != last || payload.len() != 4 {
+                    report.push(at, ValidationIssue::MisplacedChecksum);
+                }
+                Ok(())
+            }
+            _ => {
+                report.push(at, ValidationIssue::UnknownChunk(name));
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            report.push_error(at, e);
+        }
+    }
+    if end < trailing.len() {
+        report.push(
+            offset + end,
+            ValidationIssue::TrailingData(trailing.len() - end),
+        );
+    }
+}
+
+/// Checks a level of detail chunk payload (which starts at `offset`), it must be an animation with
+/// the same number of frames using the parent palette
+fn check_lod(
+    payload: &[u8],
+    offset: usize,
+    frame_count: usize,
+    palette_len: Option<usize>,
+    report: &mut ValidationReport,
+) {
+    let scale = payload.first().copied().unwrap_or_default();
+    if scale < 2 {
+        report.push(offset, InvalidLevelOfDetail(scale));
+        return;
+    }
+    match check_file(&payload[1..], offset + 1, palette_len, report) {
+        Some((_, Image, _)) => report.push(offset, InvalidLevelOfDetail(scale)),
+        Some((_, Animated, Some(count))) if count != frame_count => {
+            report.push(offset, InvalidLevelOfDetail(scale))
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metadata::write_chunk;
+    use crate::palette::FilePalette;
+    use crate::prelude::*;
+
+    #[test]
+    fn reports_every_problem() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 0],
+            PlayType::Loops,
+        )
+        .unwrap();
+        image.set_metadata("author", "test").unwrap();
+        image.set_tag("walk", 0, 1).unwrap();
+        let mut bytes = image.to_file_contents(&FilePalette::Colors).unwrap();
+        let report = validate(&bytes);
+        assert!(report.issues.is_empty(), "{report}");
+        assert_eq!(report.header, Some((1, Animated)));
+
+        // header, palette, size, play type, frame count and per frame
+        let pixels_start = 5 + 10 + 2 + 1 + 1 + 8;
+        bytes[pixels_start + 1] = 5;
+        bytes[pixels_start + 2] = 9;
+        bytes[17] = 99;
+        write_chunk(*b"TEST", &[1], &mut bytes);
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let report = validate(&bytes);
+        assert_eq!(
+            report.issues,
+            vec![
+                (
+                    17,
+                    ValidationIssue::Invalid(InvalidValue {
+                        section: FileSection::Animation,
+                        offset: 17,
+                        what: "play type".to_string(),
+                        expected: "0..=5".to_string(),
+                        found: "99".to_string(),
+                    })
+                ),
+                (
+                    pixels_start + 1,
+                    ValidationIssue::PixelsOutsidePalette(1, 2, 2)
+                ),
+                (
+                    bytes.len() - 12,
+                    ValidationIssue::UnknownChunk("TEST".to_string())
+                ),
+                (bytes.len() - 3, ValidationIssue::TrailingData(3)),
+            ],
+            "{report}"
+        );
+        assert!(!report.is_valid());
+        assert_eq!(report.warnings().count(), 2);
+
+        assert_eq!(validate(&bytes[..pixels_start + 1]).errors().count(), 2);
+        assert!(validate(b"PNG").header.is_none());
+
+        let mut bundle = IciBundle::new(vec![RED, GREEN]).unwrap();
+        bundle
+            .insert(
+                "image",
+                IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap(),
+            )
+            .unwrap();
+        let bytes = bundle
+            .to_file_contents_with_checksum(&FilePalette::Colors)
+            .unwrap();
+        let report = validate(&bytes);
+        assert!(report.issues.is_empty(), "{report}");
+        assert_eq!(report.header, Some((1, Bundle)));
+    }
+
+    #[test]
+    fn agrees_with_readers() {
+        let image = AnimatedIndexedImage::new(1, 1, 0.5, 1, vec![RED], vec![0], PlayType::Loops)
+            .unwrap()
+            .to_file_contents(&FilePalette::Colors)
+            .unwrap();
+        let per_frame = 5 + 2 + 4 + 2 + 2;
+        let mut files = vec![image.clone()];
+        for value in [f64::NAN, f64::INFINITY, -1.0] {
+            let mut bytes = image.clone();
+            bytes[per_frame..per_frame + 8].copy_from_slice(&value.to_be_bytes());
+            files.push(bytes);
+        }
+        let mut outside = image.clone();
+        outside[per_frame + 8] = 1;
+        files.push(outside);
+        for (i, bytes) in files.iter().enumerate() {
+            assert_eq!(validate(bytes).is_valid(), i == 0);
+            assert_eq!(
+                AnimatedIndexedImage::from_file_contents(bytes).is_ok(),
+                i == 0
+            );
+        }
+    }
+}
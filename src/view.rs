@@ -0,0 +1,276 @@
+//! Borrowed rectangular regions of an [IndexedImage], to work on part of an image (such as an atlas entry) in place
+//!
+//! Coordinates are relative to the top left of the view
+
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::palette::Palette;
+use crate::prelude::*;
+
+/// Read only region of an [IndexedImage], see [IndexedImage::view]
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a> {
+    image: &'a IndexedImage,
+    rect: IciRect,
+}
+
+/// Mutable region of an [IndexedImage], see [IndexedImage::view_mut]
+#[derive(Debug)]
+pub struct ImageViewMut<'a> {
+    image: &'a mut IndexedImage,
+    rect: IciRect,
+}
+
+impl IndexedImage {
+    /// View of `rect`, which must not be empty and must be entirely inside the image
+    pub fn view(&self, rect: IciRect) -> Result<ImageView<'_>, IndexedImageError> {
+        check_rect(self, rect)?;
+        Ok(ImageView { image: self, rect })
+    }
+
+    /// Mutable view of `rect`, which must not be empty and must be entirely inside the image
+    pub fn view_mut(&mut self, rect: IciRect) -> Result<ImageViewMut<'_>, IndexedImageError> {
+        check_rect(self, rect)?;
+        Ok(ImageViewMut { image: self, rect })
+    }
+}
+
+fn check_rect(image: &IndexedImage, rect: IciRect) -> Result<(), IndexedImageError> {
+    if rect.is_empty()
+        || rect.right() > image.width() as usize
+        || rect.bottom() > image.height() as usize
+    {
+        return Err(InvalidRect(rect.x, rect.y, rect.width, rect.height));
+    }
+    Ok(())
+}
+
+/// Index in the image pixels of `x`,`y` in `rect`
+fn pixel_index(
+    image: &IndexedImage,
+    rect: IciRect,
+    x: u8,
+    y: u8,
+) -> Result<usize, IndexedImageError> {
+    if x >= rect.width {
+        return Err(IndexOutOfRange(x as usize, rect.width as usize, "width"));
+    }
+    if y >= rect.height {
+        return Err(IndexOutOfRange(y as usize, rect.height as usize, "height"));
+    }
+    Ok((rect.x + x) as usize + (rect.y + y) as usize * image.width() as usize)
+}
+
+fn row(image: &IndexedImage, rect: IciRect, y: u8) -> Result<&[u8], IndexedImageError> {
+    let start = pixel_index(image, rect, 0, y)?;
+    Ok(&image.get_pixels()[start..start + rect.width as usize])
+}
+
+impl<'a> ImageView<'a> {
+    /// Area of the image this view covers
+    #[inline]
+    pub fn rect(&self) -> IciRect {
+        self.rect
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u8, u8) {
+        (self.rect.width, self.rect.height)
+    }
+
+    #[inline]
+    pub fn width(&self) -> u8 {
+        self.rect.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u8 {
+        self.rect.height
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &'a Palette {
+        self.image.get_palette()
+    }
+
+    pub fn get_pixel(&self, x: u8, y: u8) -> Result<u8, IndexedImageError> {
+        let i = pixel_index(self.image, self.rect, x, y)?;
+        Ok(self.image.get_pixels()[i])
+    }
+
+    pub fn get_color(&self, x: u8, y: u8) -> Result<Color, IndexedImageError> {
+        self.image.get_color(self.get_pixel(x, y)?)
+    }
+
+    /// Color indexes of row `y` of the view
+    pub fn row(&self, y: u8) -> Result<&'a [u8], IndexedImageError> {
+        row(self.image, self.rect, y)
+    }
+
+    /// Iterate over pixels as `(x, y, color index)`, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u8, u8, u8)> + 'a {
+        let (image, rect) = (self.image, self.rect);
+        (0..rect.height).flat_map(move |y| {
+            let row = row(image, rect, y).unwrap_or_default();
+            row.iter()
+                .enumerate()
+                .map(move |(x, idx)| (x as u8, y, *idx))
+        })
+    }
+
+    /// Copy the view into a new image, metadata is not copied
+    pub fn to_image(&self) -> IndexedImage {
+        to_image(self.image, self.rect)
+    }
+}
+
+fn to_image(image: &IndexedImage, rect: IciRect) -> IndexedImage {
+    let pixels = (0..rect.height)
+        .flat_map(|y| row(image, rect, y).unwrap_or_default())
+        .copied()
+        .collect();
+    IndexedImage::from_parts(rect.width, rect.height, image.get_palette().clone(), pixels)
+}
+
+impl ImageViewMut<'_> {
+    /// Area of the image this view covers
+    #[inline]
+    pub fn rect(&self) -> IciRect {
+        self.rect
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u8, u8) {
+        (self.rect.width, self.rect.height)
+    }
+
+    #[inline]
+    pub fn width(&self) -> u8 {
+        self.rect.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u8 {
+        self.rect.height
+    }
+
+    /// Read only view of the same area
+    pub fn as_view(&self) -> ImageView<'_> {
+        ImageView {
+            image: self.image,
+            rect: self.rect,
+        }
+    }
+
+    #[inline]
+    pub fn get_palette(&self) -> &Palette {
+        self.image.get_palette()
+    }
+
+    pub fn get_pixel(&self, x: u8, y: u8) -> Result<u8, IndexedImageError> {
+        self.as_view().get_pixel(x, y)
+    }
+
+    pub fn get_color(&self, x: u8, y: u8) -> Result<Color, IndexedImageError> {
+        self.as_view().get_color(x, y)
+    }
+
+    /// Set pixel at `x`,`y` of the view to `color_idx`, which must be in the palette
+    pub fn set_pixel(&mut self, x: u8, y: u8, color_idx: u8) -> Result<(), IndexedImageError> {
+        let i = pixel_index(self.image, self.rect, x, y)?;
+        self.image.set_pixel(i, color_idx)
+    }
+
+    /// Set every pixel in the view to `color_idx`, which must be in the palette
+    pub fn fill(&mut self, color_idx: u8) -> Result<(), IndexedImageError> {
+        let palette_len = self.image.get_palette().len();
+        if color_idx as usize >= palette_len {
+            return Err(IndexOutOfRange(color_idx as usize, palette_len, "palette"));
+        }
+        self.write(|_, _| color_idx);
+        Ok(())
+    }
+
+    /// Copy `image` into the view, it must be the same size as the view and only use indexes within this palette
+    ///
+    /// The palette of `image` is ignored
+    pub fn copy_from(&mut self, image: &IndexedImage) -> Result<(), IndexedImageError> {
+        if image.size() != self.size() {
+            return Err(InvalidImageSize);
+        }
+        let highest = image.min_palette_size_supported();
+        if highest as usize >= self.image.get_palette().len() {
+            return Err(PaletteTooFewColors(highest));
+        }
+        let source = image.get_pixels();
+        let width = image.width() as usize;
+        self.write(|x, y| source[x + y * width]);
+        Ok(())
+    }
+
+    /// Copy the view into a new image, metadata is not copied
+    pub fn to_image(&self) -> IndexedImage {
+        to_image(self.image, self.rect)
+    }
+
+    /// Set every pixel in the view using `value(x, y)`, the values must be valid palette indexes
+    fn write<F: Fn(usize, usize) -> u8>(&mut self, value: F) {
+        let rect = self.rect;
+        let image_width = self.image.width() as usize;
+        let pixels = self.image.raw_pixels_mut();
+        for y in 0..rect.height as usize {
+            let start = rect.x as usize + (rect.y as usize + y) * image_width;
+            for (x, pixel) in pixels[start..start + rect.width as usize]
+                .iter_mut()
+                .enumerate()
+            {
+                *pixel = value(x, y);
+            }
+        }
+        self.image.refresh_highest_palette_idx();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn views() {
+        let mut image = IndexedImage::new(
+            4,
+            3,
+            vec![RED, GREEN, BLUE],
+            vec![0, 0, 0, 0, 0, 1, 2, 0, 0, 2, 1, 0],
+        )
+        .unwrap();
+        assert!(image.view(IciRect::new(3, 0, 2, 1)).is_err());
+        assert!(image.view(IciRect::new(0, 0, 0, 1)).is_err());
+
+        let view = image.view(IciRect::new(1, 1, 2, 2)).unwrap();
+        assert_eq!(view.get_pixel(1, 0).unwrap(), 2);
+        assert_eq!(view.get_color(0, 1).unwrap(), BLUE);
+        assert!(view.get_pixel(2, 0).is_err());
+        assert_eq!(view.row(1).unwrap(), &[2, 1]);
+        assert_eq!(view.to_image().get_pixels(), &[1, 2, 2, 1]);
+        assert_eq!(
+            view.pixels().collect::<Vec<_>>(),
+            vec![(0, 0, 1), (1, 0, 2), (0, 1, 2), (1, 1, 1)]
+        );
+
+        let mut view = image.view_mut(IciRect::new(2, 0, 2, 2)).unwrap();
+        view.set_pixel(1, 1, 1).unwrap();
+        assert!(view.set_pixel(0, 0, 3).is_err());
+        assert!(view.fill(3).is_err());
+        view.copy_from(&IndexedImage::new(2, 2, vec![WHITE; 3], vec![2, 2, 0, 0]).unwrap())
+            .unwrap();
+        assert!(view
+            .copy_from(&IndexedImage::blank(1, 1, vec![RED]))
+            .is_err());
+        assert_eq!(image.get_pixels(), &[0, 0, 2, 2, 0, 1, 0, 0, 0, 2, 1, 0]);
+
+        let mut view = image.view_mut(IciRect::new(0, 0, 4, 3)).unwrap();
+        view.fill(0).unwrap();
+        assert_eq!(image.min_palette_size_supported(), 0);
+    }
+}
@@ -0,0 +1,144 @@
+//! Bindings for using images and animations from JavaScript
+//!
+//! ```js
+//! const image = new IciImage(bytes);
+//! // in requestAnimationFrame
+//! if (image.update(delta)) {
+//!     ctx.putImageData(new ImageData(image.rgba(), image.width, image.height), 0, 0);
+//! }
+//! ```
+
+use crate::palette::FilePalette;
+use crate::prelude::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+/// Image or animation loaded from ICI/ICA file contents
+#[wasm_bindgen(js_name = IciImage)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmImage {
+    image: IndexedWrapper,
+}
+
+#[wasm_bindgen(js_class = IciImage)]
+impl WasmImage {
+    /// Read an image or animation file, the palette must be included in the file
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<WasmImage, JsError> {
+        let (image, _) = IndexedWrapper::from_file_contents(bytes)?;
+        Ok(WasmImage { image })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u8 {
+        self.image.width()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u8 {
+        self.image.height()
+    }
+
+    #[wasm_bindgen(getter, js_name = frameCount)]
+    pub fn frame_count(&self) -> u16 {
+        self.image.frame_count()
+    }
+
+    #[wasm_bindgen(getter, js_name = isAnimation)]
+    pub fn is_animation(&self) -> bool {
+        self.image.is_animation()
+    }
+
+    #[wasm_bindgen(getter, js_name = currentFrame)]
+    pub fn current_frame(&self) -> u16 {
+        match &self.image {
+            IndexedWrapper::Static(_) => 0,
+            IndexedWrapper::Animated(img) => img.get_current_frame(),
+        }
+    }
+
+    /// Jump to frame `idx`, static images only have frame 0
+    #[wasm_bindgen(js_name = setCurrentFrame)]
+    pub fn set_current_frame(&mut self, idx: u16) -> Result<(), JsError> {
+        match &mut self.image {
+            IndexedWrapper::Static(_) if idx == 0 => Ok(()),
+            IndexedWrapper::Static(_) => {
+                Err(IndexedImageError::IndexOutOfRange(idx as usize, 1, "frames").into())
+            }
+            IndexedWrapper::Animated(img) => Ok(img.set_current_frame(idx)?),
+        }
+    }
+
+    /// Advance the animation by `delta` seconds, returns true if the frame changed and the image should be redrawn
+    pub fn update(&mut self, delta: f64) -> bool {
+        self.image.update_with_events(delta).is_some()
+    }
+
+    #[wasm_bindgen(js_name = setAnimate)]
+    pub fn set_animate(&mut self, animate: bool) {
+        self.image.set_animate(animate);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn animating(&self) -> bool {
+        self.image.animating()
+    }
+
+    pub fn reset(&mut self) {
+        self.image.reset();
+    }
+
+    /// RGBA pixels of the current frame, for `new ImageData(image.rgba(), image.width, image.height)`
+    pub fn rgba(&self) -> Clamped<Vec<u8>> {
+        Clamped(match &self.image {
+            IndexedWrapper::Static(img) => img.to_rgba_bytes(),
+            IndexedWrapper::Animated(img) => img.current_frame_rgba(),
+        })
+    }
+
+    /// File contents, including the palette colors
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, JsError> {
+        Ok(self.image.to_file_contents(&FilePalette::Colors)?)
+    }
+}
+
+impl From<IndexedWrapper> for WasmImage {
+    fn from(image: IndexedWrapper) -> Self {
+        WasmImage { image }
+    }
+}
+
+impl From<WasmImage> for IndexedWrapper {
+    fn from(image: WasmImage) -> Self {
+        image.image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn playback() {
+        let animation =
+            AnimatedIndexedImage::new(1, 1, 0.1, 2, vec![RED, BLUE], vec![0, 1], PlayType::Loops)
+                .unwrap();
+        let bytes = animation.to_file_contents(&FilePalette::Colors).unwrap();
+        let mut image = WasmImage::new(&bytes).unwrap();
+        assert!(image.is_animation());
+        assert_eq!(image.frame_count(), 2);
+        assert_eq!(image.rgba().0, vec![255, 0, 0, 255]);
+        image.set_animate(true);
+        assert!(!image.update(0.05));
+        assert!(!image.update(0.1));
+        assert!(image.update(0.01));
+        assert_eq!(image.current_frame(), 1);
+        assert_eq!(image.rgba().0, vec![0, 0, 255, 255]);
+        let copy = WasmImage::new(&image.to_bytes().unwrap()).unwrap();
+        assert_eq!(copy.frame_count(), 2);
+        assert_eq!(copy.rgba().0, vec![255, 0, 0, 255]);
+        image.set_current_frame(0).unwrap();
+        assert_eq!(IndexedWrapper::from(image).get_pixels(), &[0, 1]);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::compat::*;
+use crate::errors::IndexedImageError;
+use crate::errors::IndexedImageError::*;
+use crate::metadata::WATERMARK;
+use crate::prelude::*;
+
+/// Bits used to store the payload length
+const LENGTH_BITS: usize = 16;
+/// Largest payload for [IndexedImage::set_watermark_metadata], metadata values are at most 65535 bytes and
+/// each byte is stored as 2 hex digits
+const METADATA_CAPACITY: usize = u16::MAX as usize / 2;
+
+impl IndexedImage {
+    /// For each palette index returns the pair of indexes it can be swapped with
+    /// without changing how the image looks (the first two indexes with the same color)
+    fn watermark_pairs(&self) -> Vec<Option<(u8, u8)>> {
+        let palette = self.get_palette();
+        let mut pairs = vec![None; palette.len()];
+        for (lo, color) in palette.iter().enumerate() {
+            if pairs[lo].is_some() || palette[..lo].contains(color) {
+                continue;
+            }
+            if let Some(offset) = palette[lo + 1..].iter().position(|c| c == color) {
+                let hi = lo + 1 + offset;
+                pairs[lo] = Some((lo as u8, hi as u8));
+                pairs[hi] = Some((lo as u8, hi as u8));
+            }
+        }
+        pairs
+    }
+
+    fn watermark_pixels(&self) -> (Vec<Option<(u8, u8)>>, Vec<usize>) {
+        let pairs = self.watermark_pairs();
+        let pixels = self
+            .get_pixels()
+            .iter()
+            .enumerate()
+            .filter(|(_, idx)| pairs.get(**idx as usize).copied().flatten().is_some())
+            .map(|(i, _)| i)
+            .collect();
+        (pairs, pixels)
+    }
+
+    /// Number of bytes that can be hidden in this image with [IndexedImage::embed_watermark]
+    ///
+    /// Each pixel that uses a color duplicated in the palette can store one bit
+    pub fn watermark_capacity(&self) -> usize {
+        let (_, pixels) = self.watermark_pixels();
+        (pixels.len().saturating_sub(LENGTH_BITS) / 8).min(u16::MAX as usize)
+    }
+
+    /// Hide `payload` in the image by choosing between duplicate palette entries
+    ///
+    /// The image will look identical, see [IndexedImage::watermark_capacity] for how much can be stored
+    /// Changing the palette or pixels afterwards will likely destroy the watermark
+    pub fn embed_watermark(&mut self, payload: &[u8]) -> Result<(), IndexedImageError> {
+        let capacity = self.watermark_capacity();
+        if payload.len() > capacity {
+            return Err(WatermarkTooLarge(payload.len(), capacity));
+        }
+        let (pairs, pixels) = self.watermark_pixels();
+        let len = (payload.len() as u16).to_be_bytes();
+        let bits = len
+            .iter()
+            .chain(payload.iter())
+            .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1));
+        for (pixel_idx, bit) in pixels.into_iter().zip(bits) {
+            let current = self.get_pixel(pixel_idx)?;
+            if let Some((lo, hi)) = pairs[current as usize] {
+                self.set_pixel(pixel_idx, if bit { hi } else { lo })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a payload hidden with [IndexedImage::embed_watermark]
+    ///
+    /// Returns `None` if the image can't contain a watermark or it is invalid
+    pub fn extract_watermark(&self) -> Option<Vec<u8>> {
+        let (pairs, pixels) = self.watermark_pixels();
+        let image_pixels = self.get_pixels();
+        let mut bytes = pixels.chunks_exact(8).map(|chunk| {
+            chunk.iter().fold(0_u8, |acc, i| {
+                let idx = image_pixels[*i];
+                let bit = matches!(pairs[idx as usize], Some((_, hi)) if hi == idx);
+                (acc << 1) | bit as u8
+            })
+        });
+        let len = u16::from_be_bytes([bytes.next()?, bytes.next()?]) as usize;
+        if len > self.watermark_capacity() {
+            return None;
+        }
+        Some(bytes.take(len).collect())
+    }
+
+    /// Store `payload` as hex in the [WATERMARK] metadata, a plain alternative to [IndexedImage::embed_watermark]
+    ///
+    /// This survives palette and pixel changes and works for any image, but is easy to find and remove
+    pub fn set_watermark_metadata(&mut self, payload: &[u8]) -> Result<(), IndexedImageError> {
+        if payload.len() > METADATA_CAPACITY {
+            return Err(WatermarkTooLarge(payload.len(), METADATA_CAPACITY));
+        }
+        let hex: String = payload.iter().map(|byte| format!("{byte:02x}")).collect();
+        self.set_metadata(WATERMARK, &hex).map(|_| ())
+    }
+
+    /// Read a payload stored with [IndexedImage::set_watermark_metadata]
+    ///
+    /// Returns `None` if there isn't one or it isn't valid hex
+    pub fn watermark_metadata(&self) -> Option<Vec<u8>> {
+        let hex = self.metadata().get(WATERMARK)?;
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |b: u8| (b as char).to_digit(16).unwrap_or_default() as u8;
+        Some(
+            hex.as_bytes()
+                .chunks_exact(2)
+                .map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image() -> IndexedImage {
+        IndexedImage::new(
+            16,
+            8,
+            vec![RED, BLUE, RED, GREEN, BLUE],
+            (0..128).map(|i| (i % 4) as u8).collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn capacity() {
+        let image = image();
+        assert_eq!(image.watermark_capacity(), 10);
+        let no_dupes = IndexedImage::new(2, 1, vec![RED, BLUE], vec![0, 1]).unwrap();
+        assert_eq!(no_dupes.watermark_capacity(), 0);
+        assert_eq!(no_dupes.extract_watermark(), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut image = image();
+        let original = image.clone();
+        image.embed_watermark(b"hello").unwrap();
+        assert_eq!(image.extract_watermark(), Some(b"hello".to_vec()));
+        for (a, b) in image.get_pixels().iter().zip(original.get_pixels()) {
+            assert_eq!(
+                image.get_color(*a).unwrap(),
+                original.get_color(*b).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        let mut image = image();
+        assert_eq!(image.watermark_metadata(), None);
+        image.set_watermark_metadata(&[0, 1, 0xAB, 255]).unwrap();
+        assert_eq!(image.metadata().get(WATERMARK).unwrap(), "0001abff");
+        let bytes = image
+            .to_file_contents(&crate::palette::FilePalette::Colors)
+            .unwrap();
+        let (mut image, _) = IndexedImage::from_file_contents(&bytes).unwrap();
+        assert_eq!(image.watermark_metadata(), Some(vec![0, 1, 0xAB, 255]));
+
+        image.set_metadata(WATERMARK, "abc").unwrap();
+        assert_eq!(image.watermark_metadata(), None);
+        assert!(matches!(
+            image.set_watermark_metadata(&[0; 40000]),
+            Err(WatermarkTooLarge(40000, 32767))
+        ));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut image = image();
+        assert!(matches!(
+            image.embed_watermark(&[0; 11]),
+            Err(WatermarkTooLarge(11, 10))
+        ));
+    }
+}
@@ -1,5 +1,8 @@
+use crate::compat::*;
 use crate::errors::IndexedImageError;
+use crate::file::{verify_format_version, wrong_file_type, FileType};
 use crate::image::IndexedImage;
+use crate::palette::FilePalette;
 use crate::prelude::*;
 
 /// Store static or animated images in a generic way
@@ -25,7 +28,7 @@ impl From<AnimatedIndexedImage> for IndexedWrapper {
 
 impl IndexedWrapper {
     /// Replace palette for image
-    /// Will only return an error if the new palette has fewer colors than the image needs
+    /// Will return an error if the new palette has less colors than the image needs, is empty or has more than 256 colors
     pub fn set_palette(&mut self, palette: &[Color]) -> Result<(), IndexedImageError> {
         match self {
             IndexedWrapper::Static(img) => img.set_palette(palette),
@@ -34,7 +37,7 @@ impl IndexedWrapper {
     }
 
     /// Replace palette for image, any pixels outside the new palette will be replaced with `id`
-    /// Will only return an error if id is outside the new palette
+    /// Will return an error if id is outside the new palette, or the palette is empty or has more than 256 colors
     pub fn set_palette_replace_id(
         &mut self,
         palette: &[Color],
@@ -47,6 +50,7 @@ impl IndexedWrapper {
     }
 
     /// Replace palette for image, any color indexes outside the palette will be expanded with `color`
+    /// This never fails, colors after the first 256 are dropped
     pub fn set_palette_replace_color<C: Into<Color> + Copy>(
         &mut self,
         palette: &[Color],
@@ -79,6 +83,24 @@ impl IndexedWrapper {
         }
     }
 
+    /// Color index of pixel `pixel_idx`, for animations this is in the current frame
+    pub fn get_pixel(&self, pixel_idx: usize) -> Result<u8, IndexedImageError> {
+        match self {
+            IndexedWrapper::Static(img) => img.get_pixel(pixel_idx),
+            IndexedWrapper::Animated(img) => img.get_pixel(img.get_current_frame(), pixel_idx),
+        }
+    }
+
+    /// Set pixel `pixel_idx` to `color_idx`, for animations this is in the current frame
+    pub fn set_pixel(&mut self, pixel_idx: usize, color_idx: u8) -> Result<(), IndexedImageError> {
+        match self {
+            IndexedWrapper::Static(img) => img.set_pixel(pixel_idx, color_idx),
+            IndexedWrapper::Animated(img) => {
+                img.set_pixel(img.get_current_frame(), pixel_idx, color_idx)
+            }
+        }
+    }
+
     pub fn get_color(&self, idx: u8) -> Result<Color, IndexedImageError> {
         match self {
             IndexedWrapper::Static(img) => img.get_color(idx),
@@ -93,13 +115,29 @@ impl IndexedWrapper {
         }
     }
 
-    pub fn get_palette(&self) -> &[Color] {
+    /// Set several palette colors at once, if any index is outside the palette then no colors are changed
+    pub fn set_colors(&mut self, colors: &[(u8, Color)]) -> Result<(), IndexedImageError> {
+        match self {
+            IndexedWrapper::Static(img) => img.set_colors(colors),
+            IndexedWrapper::Animated(img) => img.set_colors(colors),
+        }
+    }
+
+    pub fn get_palette(&self) -> &Palette {
         match self {
             IndexedWrapper::Static(img) => img.get_palette(),
             IndexedWrapper::Animated(img) => img.get_palette(),
         }
     }
 
+    /// Remove palette colors that no pixel uses, returns the number of colors removed
+    pub fn prune_unused_colors(&mut self) -> usize {
+        match self {
+            IndexedWrapper::Static(img) => img.prune_unused_colors(),
+            IndexedWrapper::Animated(img) => img.prune_unused_colors(),
+        }
+    }
+
     pub fn min_palette_size_supported(&self) -> u8 {
         match self {
             IndexedWrapper::Static(img) => img.min_palette_size_supported(),
@@ -128,6 +166,14 @@ impl IndexedWrapper {
         }
     }
 
+    /// See [AnimatedIndexedImage::update_with_events], static images always return `None`
+    pub fn update_with_events(&mut self, delta: f64) -> Option<AnimationEvent> {
+        match self {
+            IndexedWrapper::Static(_) => None,
+            IndexedWrapper::Animated(img) => img.update_with_events(delta),
+        }
+    }
+
     pub fn reset(&mut self) {
         match self {
             IndexedWrapper::Static(_) => {}
@@ -151,7 +197,7 @@ impl IndexedWrapper {
         }
     }
 
-    pub fn frame_count(&self) -> u8 {
+    pub fn frame_count(&self) -> u16 {
         match self {
             IndexedWrapper::Static(_) => 1,
             IndexedWrapper::Animated(img) => img.frame_count(),
@@ -161,4 +207,186 @@ impl IndexedWrapper {
     pub fn is_animation(&self) -> bool {
         matches!(self, IndexedWrapper::Animated(_))
     }
+
+    /// Scale the image, or every frame of an animation
+    pub fn scale(&self, algo: Scaling) -> Result<IndexedWrapper, IndexedImageError> {
+        Ok(match self {
+            IndexedWrapper::Static(img) => img.scale(algo)?.into(),
+            IndexedWrapper::Animated(img) => img.scale(algo)?.into(),
+        })
+    }
+
+    /// Rotate the image, or every frame of an animation, 90° clockwise
+    pub fn rotate_cw(&self) -> IndexedWrapper {
+        match self {
+            IndexedWrapper::Static(img) => img.rotate_cw().into(),
+            IndexedWrapper::Animated(img) => img.rotate_cw().into(),
+        }
+    }
+
+    /// Rotate the image, or every frame of an animation, 90° counter clockwise
+    pub fn rotate_ccw(&self) -> IndexedWrapper {
+        match self {
+            IndexedWrapper::Static(img) => img.rotate_ccw().into(),
+            IndexedWrapper::Animated(img) => img.rotate_ccw().into(),
+        }
+    }
+
+    /// Flip the image, or every frame of an animation, vertically
+    pub fn flip_vertical(&self) -> Result<IndexedWrapper, IndexedImageError> {
+        Ok(match self {
+            IndexedWrapper::Static(img) => img.flip_vertical()?.into(),
+            IndexedWrapper::Animated(img) => img.flip_vertical()?.into(),
+        })
+    }
+
+    /// Flip the image, or every frame of an animation, horizontally
+    pub fn flip_horizontal(&self) -> Result<IndexedWrapper, IndexedImageError> {
+        Ok(match self {
+            IndexedWrapper::Static(img) => img.flip_horizontal()?.into(),
+            IndexedWrapper::Animated(img) => img.flip_horizontal()?.into(),
+        })
+    }
+}
+
+impl IndexedWrapper {
+    pub(crate) fn clone_without_pixels(&self) -> Self {
+        match self {
+            IndexedWrapper::Static(img) => img.clone_without_pixels().into(),
+            IndexedWrapper::Animated(img) => img.clone_without_pixels().into(),
+        }
+    }
+
+    pub(crate) fn set_raw_pixels(&mut self, pixels: Vec<u8>) {
+        match self {
+            IndexedWrapper::Static(img) => img.set_raw_pixels(pixels),
+            IndexedWrapper::Animated(img) => img.set_raw_pixels(pixels),
+        }
+    }
+}
+
+impl IndexedWrapper {
+    pub fn to_file_contents(&self, palette: &FilePalette) -> Result<Vec<u8>, IndexedImageError> {
+        match self {
+            IndexedWrapper::Static(img) => img.to_file_contents(palette),
+            IndexedWrapper::Animated(img) => img.to_file_contents(palette),
+        }
+    }
+
+    /// Read a static or animated image file
+    pub fn from_file_contents(
+        bytes: &[u8],
+    ) -> Result<(IndexedWrapper, FilePalette), IndexedImageError> {
+        match verify_format_version(bytes)?.1 {
+            FileType::Image => IndexedImage::from_file_contents(bytes)
+                .map(|(image, pal_type)| (image.into(), pal_type)),
+            FileType::Animated => AnimatedIndexedImage::from_file_contents(bytes)
+                .map(|(image, pal_type)| (image.into(), pal_type)),
+            file_type => Err(wrong_file_type("an image", &file_type)),
+        }
+    }
+}
+
+impl Tint for IndexedWrapper {
+    fn tint_add(&mut self, r_diff: isize, g_diff: isize, b_diff: isize, a_diff: isize) {
+        match self {
+            IndexedWrapper::Static(img) => Tint::tint_add(img, r_diff, g_diff, b_diff, a_diff),
+            IndexedWrapper::Animated(img) => img.tint_add(r_diff, g_diff, b_diff, a_diff),
+        }
+    }
+
+    fn tint_mul(&mut self, r_diff: f32, g_diff: f32, b_diff: f32, a_diff: f32) {
+        match self {
+            IndexedWrapper::Static(img) => Tint::tint_mul(img, r_diff, g_diff, b_diff, a_diff),
+            IndexedWrapper::Animated(img) => img.tint_mul(r_diff, g_diff, b_diff, a_diff),
+        }
+    }
+}
+
+impl HueShift for IndexedWrapper {
+    fn tint_hue_shift(&mut self, degrees: f32) {
+        match self {
+            IndexedWrapper::Static(img) => img.tint_hue_shift(degrees),
+            IndexedWrapper::Animated(img) => img.tint_hue_shift(degrees),
+        }
+    }
+}
+
+impl ChangeColors for IndexedWrapper {
+    fn with_saturate(&self, amount: f32) -> Self {
+        match self {
+            IndexedWrapper::Static(img) => img.with_saturate(amount).into(),
+            IndexedWrapper::Animated(img) => img.with_saturate(amount).into(),
+        }
+    }
+
+    fn with_brightness(&self, amount: f32) -> Self {
+        match self {
+            IndexedWrapper::Static(img) => img.with_brightness(amount).into(),
+            IndexedWrapper::Animated(img) => img.with_brightness(amount).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::IndexedImageError::*;
+    use crate::palette::FilePalette::*;
+
+    #[test]
+    fn parity() {
+        let mut image = AnimatedIndexedImage::new(
+            2,
+            1,
+            0.1,
+            2,
+            vec![RED, GREEN],
+            vec![0, 1, 1, 1],
+            PlayType::Loops,
+        )
+        .unwrap();
+        image.skip_to_next_frame();
+        image.update(0.0);
+        let mut wrapper = IndexedWrapper::from(image);
+        assert_eq!(wrapper.get_pixel(0).unwrap(), 1);
+        wrapper.set_pixel(0, 0).unwrap();
+        assert_eq!(wrapper.get_pixels(), &[0, 1, 0, 1]);
+        assert!(wrapper.set_pixel(2, 0).is_err());
+
+        let bytes = wrapper.to_file_contents(&Colors).unwrap();
+        let (read, _) = IndexedWrapper::from_file_contents(&bytes).unwrap();
+        assert!(read.is_animation());
+        assert_eq!(read.get_pixels(), wrapper.get_pixels());
+
+        let mut wrapper =
+            IndexedWrapper::from(IndexedImage::new(1, 1, vec![RED], vec![0]).unwrap());
+        wrapper.set_pixel(0, 0).unwrap();
+        let bytes = wrapper.to_file_contents(&Colors).unwrap();
+        let (read, _) = IndexedWrapper::from_file_contents(&bytes).unwrap();
+        assert_eq!(read, wrapper);
+        wrapper.tint_hue_shift(120.0);
+        assert_eq!(wrapper.get_color(0).unwrap(), GREEN);
+        assert!(IndexedWrapper::from_file_contents(&[0; 12]).is_err());
+    }
+
+    #[test]
+    fn set_palette_errors() {
+        let image = IndexedImage::new(2, 1, vec![RED, GREEN], vec![0, 1]).unwrap();
+        let animation =
+            AnimatedIndexedImage::new(2, 1, 0.1, 1, vec![RED, GREEN], vec![0, 1], PlayType::Once)
+                .unwrap();
+        for mut wrapper in [IndexedWrapper::from(image), IndexedWrapper::from(animation)] {
+            assert_eq!(wrapper.set_palette(&[]), Err(PaletteIsEmpty));
+            assert_eq!(wrapper.set_palette(&[BLUE]), Err(PaletteTooFewColors(1)));
+            assert_eq!(
+                wrapper.set_palette(&[TRANSPARENT; 257]),
+                Err(PaletteTooManyColors)
+            );
+            assert_eq!(wrapper.set_palette_replace_id(&[], 0), Err(PaletteIsEmpty));
+            wrapper.set_palette_replace_color(&[], BLUE);
+            assert_eq!(wrapper.get_palette(), &[BLUE, BLUE]);
+            wrapper.set_palette(&[RED, GREEN]).unwrap();
+        }
+    }
 }